@@ -14,8 +14,15 @@ enum PierBridge {
 
     // MARK: - Terminal
 
+    /// The user's login shell, as configured for their account.
+    static var defaultShell: String {
+        guard let shellPtr = pier_terminal_default_shell() else { return "/bin/zsh" }
+        defer { pier_string_free(shellPtr) }
+        return String(cString: shellPtr)
+    }
+
     /// Create a new terminal session.
-    static func createTerminal(cols: UInt16, rows: UInt16, shell: String = ProcessInfo.processInfo.environment["SHELL"] ?? "/bin/zsh") -> OpaquePointer? {
+    static func createTerminal(cols: UInt16, rows: UInt16, shell: String = PierBridge.defaultShell) -> OpaquePointer? {
         return shell.withCString { shellPtr in
             pier_terminal_create(cols, rows, shellPtr)
         }
@@ -109,7 +109,7 @@ class TerminalViewModel: ObservableObject {
 
     // MARK: - Tab Management
 
-    func addNewTab(title: String = "Terminal", shell: String = ProcessInfo.processInfo.environment["SHELL"] ?? "/bin/zsh", isSSH: Bool = false, profile: ConnectionProfile? = nil, preloadedPassword: String? = nil, sshProgram: String? = nil, sshArgs: [String]? = nil) {
+    func addNewTab(title: String = "Terminal", shell: String = PierBridge.defaultShell, isSSH: Bool = false, profile: ConnectionProfile? = nil, preloadedPassword: String? = nil, sshProgram: String? = nil, sshArgs: [String]? = nil) {
         let tab = TerminalTab(title: title, isSSH: isSSH, shellPath: shell)
         let session = TerminalSessionInfo(shellPath: shell, isSSH: isSSH, title: title)
         session.sshProgram = sshProgram
@@ -699,7 +699,7 @@ class TerminalNSView: NSView {
         cursorY = 0
     }
 
-    func startTerminal(shell: String = ProcessInfo.processInfo.environment["SHELL"] ?? "/bin/zsh") {
+    func startTerminal(shell: String = PierBridge.defaultShell) {
         // Use the scroll view's visible area for size, not document view bounds
         let visibleSize = enclosingScrollView?.contentView.bounds.size ?? bounds.size
         let cols = max(Int(80), Int(visibleSize.width / cellWidth))
@@ -3,28 +3,299 @@
 #include <stdint.h>
 #include <stdlib.h>
 
+/**
+ * Input mode flag: arrow keys use application sequences (`ESC O A`).
+ */
+#define PIER_INPUT_APP_CURSOR (1 << 0)
+
+/**
+ * Input mode flag: keypad uses application sequences (`ESC O p`...).
+ */
+#define PIER_INPUT_APP_KEYPAD (1 << 1)
+
+/**
+ * Key codes for pier_terminal_send_key. `PIER_KEY_CHAR` sends `codepoint`.
+ */
+#define PIER_KEY_CHAR 0
+
+#define PIER_KEY_ENTER 1
+
+#define PIER_KEY_TAB 2
+
+#define PIER_KEY_BACKSPACE 3
+
+#define PIER_KEY_ESCAPE 4
+
+#define PIER_KEY_UP 5
+
+#define PIER_KEY_DOWN 6
+
+#define PIER_KEY_LEFT 7
+
+#define PIER_KEY_RIGHT 8
+
+#define PIER_KEY_HOME 9
+
+#define PIER_KEY_END 10
+
+#define PIER_KEY_PAGE_UP 11
+
+#define PIER_KEY_PAGE_DOWN 12
+
+#define PIER_KEY_INSERT 13
+
+#define PIER_KEY_DELETE 14
+
+/**
+ * F1..F12 are `PIER_KEY_F1 + 0..=11`.
+ */
+#define PIER_KEY_F1 32
+
+/**
+ * Modifier flags for pier_terminal_send_key (same bits as xterm/kitty).
+ */
+#define PIER_MOD_SHIFT 1
+
+#define PIER_MOD_ALT 2
+
+#define PIER_MOD_CTRL 4
+
+#define PIER_MOD_SUPER 8
+
+/**
+ * Search option flag: interpret the pattern as a regular expression.
+ */
+#define PIER_SEARCH_REGEX (1 << 0)
+
+/**
+ * Search option flag: ignore case.
+ */
+#define PIER_SEARCH_CASE_INSENSITIVE (1 << 1)
+
+/**
+ * Largest decoded image accepted, to bound memory use.
+ */
+#define MAX_IMAGE_BYTES ((32 * 1024) * 1024)
+
+/**
+ * Modifier bits, as used by xterm and kitty (sent as `1 + bits`).
+ */
+#define MOD_SHIFT 1
+
+#define MOD_ALT 2
+
+#define MOD_CTRL 4
+
+#define MOD_SUPER 8
+
+/**
+ * Kitty keyboard protocol flag: disambiguate escape codes.
+ */
+#define KITTY_DISAMBIGUATE 1
+
+/**
+ * Kitty keyboard protocol flag: report all keys as escape codes.
+ */
+#define KITTY_ALL_KEYS 8
+
+/**
+ * Packet-mode status bits (`TIOCPKT_*`), the same on macOS and Linux.
+ * Output was stopped with XOFF (Ctrl-S).
+ */
+#define PACKET_STOP 4
+
+/**
+ * Output was restarted with XON (Ctrl-Q).
+ */
+#define PACKET_START 8
+
+/**
+ * The termios settings changed (where the platform reports it).
+ */
+#define PACKET_IOCTL 64
+
+/**
+ * Default number of lines retained in the scrollback buffer.
+ */
+#define DEFAULT_SCROLLBACK_LIMIT 10000
+
+/**
+ * Default memory budget for scrollback, in bytes.
+ */
+#define DEFAULT_SCROLLBACK_BYTES ((64 * 1024) * 1024)
+
+/**
+ * Upper bound on reported matches; the newest are kept.
+ */
+#define MAX_SEARCH_MATCHES 10000
+
+/**
+ * Commands running at once when no limit is given.
+ */
+#define DEFAULT_PARALLELISM 8
+
+/**
+ * Key flag: the authenticator must see a touch to sign.
+ */
+#define FLAG_USER_PRESENCE 1
+
+/**
+ * Key flag: the authenticator must verify the user, e.g. by PIN.
+ */
+#define FLAG_USER_VERIFICATION 4
+
+/**
+ * Reply codes sent back to the client.
+ */
+#define REPLY_SUCCEEDED 0
+
+#define REPLY_GENERAL_FAILURE 1
+
+#define REPLY_NOT_ALLOWED 2
+
+#define REPLY_CONNECTION_REFUSED 5
+
+#define REPLY_COMMAND_NOT_SUPPORTED 7
+
+#define REPLY_ADDRESS_NOT_SUPPORTED 8
+
+/**
+ * Pings kept in the round-trip history.
+ */
+#define RTT_HISTORY 120
+
+/**
+ * Plays a cast recording into an emulator with pause, speed and seek.
+ */
+typedef struct CastPlayer CastPlayer;
+
+/**
+ * A remote directory being listed in batches, see `DirListing::start`.
+ */
+typedef struct DirListing DirListing;
+
+/**
+ * The profiles of one file. Changes are written right away.
+ */
+typedef struct ProfileStore ProfileStore;
+
+/**
+ * A command started with `SshSession::exec_channel`.
+ */
+typedef struct RemoteExec RemoteExec;
+
+/**
+ * A remote file being followed, see `SftpClient::tail_follow`.
+ */
+typedef struct RemoteTail RemoteTail;
+
+/**
+ * Remote paths polled for changes, see `RemoteWatch::start`.
+ */
+typedef struct RemoteWatch RemoteWatch;
+
+/**
+ * SFTP operations wrapper.
+ */
+typedef struct SftpClient SftpClient;
+
 /**
  * SSH session manager.
  */
 typedef struct SshSession SshSession;
 
+/**
+ * A command streaming its output to callbacks from the runtime.
+ */
+typedef struct StreamingExec StreamingExec;
+
 /**
  * Represents a terminal session with a PTY backend and VT parser.
+ *
+ * A background thread drains the PTY into the emulator as output arrives;
+ * callers read parsed state through [`TerminalSession::lock`].
  */
 typedef struct TerminalSession TerminalSession;
 
+/**
+ * Time limits for connecting and for commands, in milliseconds; 0 for
+ * none, e.g. for backups that run for hours.
+ */
+typedef struct Timeouts Timeouts;
+
+/**
+ * Runs queued transfers over an SFTP session. Dropping it cancels the
+ * transfers still running.
+ */
+typedef struct TransferManager TransferManager;
+
 /**
  * Opaque pointer to a TerminalSession.
  */
 typedef struct TerminalSession *PierTerminalHandle;
 
+/**
+ * Opaque pointer to a cast recording player.
+ */
+typedef struct CastPlayer *PierPlayerHandle;
+
 /**
  * Opaque pointer to an SSH session.
  */
 typedef struct SshSession *PierSshHandle;
 
+/**
+ * Opaque pointer to a remote command with streamed input.
+ */
+typedef struct RemoteExec *PierExecHandle;
+
+/**
+ * Opaque pointer to a command streaming its output to callbacks.
+ */
+typedef struct StreamingExec *PierStreamingExecHandle;
+
+/**
+ * Opaque pointer to an SFTP session.
+ */
+typedef struct SftpClient *PierSftpHandle;
+
+/**
+ * Opaque pointer to a remote directory being listed in batches.
+ */
+typedef struct DirListing *PierSftpListingHandle;
+
+/**
+ * Opaque pointer to a remote file being followed.
+ */
+typedef struct RemoteTail *PierSftpTailHandle;
+
+/**
+ * Opaque pointer to a set of remote paths watched for changes.
+ */
+typedef struct RemoteWatch *PierSftpWatchHandle;
+
+/**
+ * Opaque pointer to a transfer queue.
+ */
+typedef struct TransferManager *PierTransfersHandle;
+
+/**
+ * Opaque pointer to a connection profile store.
+ */
+typedef struct ProfileStore *PierProfileStoreHandle;
+
+
+
+/**
+ * The user's login shell, validated against /etc/shells, for when no
+ * shell is configured in the app.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_default_shell(void);
+
 /**
  * Create a new terminal session.
+ * A null `shell` starts the user's login shell.
  * Returns null on failure.
  */
 PierTerminalHandle pier_terminal_create(uint16_t cols, uint16_t rows, const char *shell);
@@ -40,6 +311,21 @@ PierTerminalHandle pier_terminal_create_with_args(uint16_t cols,
                                                   const char *const *args,
                                                   uint32_t argc);
 
+/**
+ * Create a new terminal session running a command with extra environment
+ * variables. `args`/`argc` are as for pier_terminal_create_with_args; `env`
+ * is a C array of `envc` `"NAME=value"` strings, applied after Pier's
+ * defaults so they can override TERM or LANG.
+ * Returns null on failure or a malformed entry.
+ */
+PierTerminalHandle pier_terminal_create_with_env(uint16_t cols,
+                                                 uint16_t rows,
+                                                 const char *program,
+                                                 const char *const *args,
+                                                 uint32_t argc,
+                                                 const char *const *env,
+                                                 uint32_t envc);
+
 /**
  * Destroy a terminal session.
  */
@@ -51,92 +337,1663 @@ void pier_terminal_destroy(PierTerminalHandle handle);
  */
 int32_t pier_terminal_write(PierTerminalHandle handle, const uint8_t *data, uintptr_t len);
 
+/**
+ * Paste text into the terminal. Like pier_terminal_write, but sent in
+ * chunks when pier_terminal_set_paste_pacing is on.
+ * Returns 0 on success, -1 on failure.
+ */
+int32_t pier_terminal_paste(PierTerminalHandle handle, const uint8_t *data, uintptr_t len);
+
+/**
+ * Send pastes in chunks of `chunk_bytes`, pausing `interval_ms` after
+ * each, for programs that drop input arriving too fast. A `chunk_bytes` of
+ * 0 sends pastes all at once (the default).
+ */
+void pier_terminal_set_paste_pacing(PierTerminalHandle handle,
+                                    uint32_t chunk_bytes,
+                                    uint32_t interval_ms);
+
+/**
+ * Bytes of input queued that the program hasn't read yet, e.g. to show
+ * progress of a large paste. Returns 0 on invalid handle.
+ */
+uintptr_t pier_terminal_pending_input(PierTerminalHandle handle);
+
 /**
  * Read output from the terminal.
- * Writes data into the provided buffer and returns the number of bytes read.
- * Returns -1 on failure.
+ * A background thread parses PTY output as it arrives; prefer the parsed
+ * state (pier_terminal_snapshot, pier_terminal_get_dirty_rows). This copies
+ * the raw bytes already parsed, for front-ends still interpreting them.
+ * Returns the number of bytes copied (0 if none are pending), or -1 on
+ * invalid arguments or once the PTY has closed and all output was read.
  */
 int64_t pier_terminal_read(PierTerminalHandle handle, uint8_t *buffer, uintptr_t buffer_len);
 
+/**
+ * Like pier_terminal_read, but block up to `timeout_ms` for output to
+ * arrive instead of returning 0 immediately, so a front-end reading raw
+ * bytes needn't poll. Returns early when the PTY closes.
+ */
+int64_t pier_terminal_read_timeout(PierTerminalHandle handle,
+                                   uint8_t *buffer,
+                                   uintptr_t buffer_len,
+                                   uint32_t timeout_ms);
+
 /**
  * Resize the terminal.
  */
 int32_t pier_terminal_resize(PierTerminalHandle handle, uint16_t cols, uint16_t rows);
 
 /**
- * Get the PTY file descriptor for polling.
+ * Get the PTY file descriptor for polling (-1 for remote sessions).
  */
 int32_t pier_terminal_fd(PierTerminalHandle handle);
 
 /**
- * Search result returned via FFI as a JSON string.
- * Caller must free the returned string with pier_string_free.
+ * Set the maximum number of scrollback lines kept by the terminal.
+ * Returns 0 on success, -1 on invalid handle.
  */
-char *pier_search_files(const char *root, const char *pattern, uintptr_t max_results);
+int32_t pier_terminal_set_scrollback_limit(PierTerminalHandle handle, uint32_t lines);
 
 /**
- * List directory contents. Returns JSON string.
- * Caller must free the returned string with pier_string_free.
+ * Set the scrollback memory budget in bytes; the oldest lines are
+ * discarded once compacted history exceeds it. Applies alongside the
+ * line limit. Returns 0 on success, -1 on invalid handle.
  */
-char *pier_list_directory(const char *path);
+int32_t pier_terminal_set_scrollback_memory_limit(PierTerminalHandle handle, uint64_t bytes);
 
 /**
- * Connect to an SSH server.
- * auth_type: 0 = password, 1 = key file
- * credential: password string (auth_type=0) or key file path (auth_type=1)
- * Returns null on failure.
+ * Approximate memory used by the terminal's scrollback, in bytes.
+ * Returns -1 on invalid handle.
  */
-PierSshHandle pier_ssh_connect(const char *host,
-                               uint16_t port,
-                               const char *username,
-                               int32_t auth_type,
-                               const char *credential);
+int64_t pier_terminal_scrollback_memory_usage(PierTerminalHandle handle);
 
 /**
- * Disconnect an SSH session and free the handle.
+ * Number of lines currently in the terminal's scrollback.
+ * Returns -1 on invalid handle.
  */
-int32_t pier_ssh_disconnect(PierSshHandle handle);
+int64_t pier_terminal_scrollback_len(PierTerminalHandle handle);
 
 /**
- * Check if SSH session is connected.
- * Returns 1 if connected, 0 if not, -1 on invalid handle.
+ * Get a page of scrollback history as JSON: an array of lines, each an
+ * array of attributed cells. `start` indexes from the oldest line.
+ * Caller must free with pier_string_free.
  */
-int32_t pier_ssh_is_connected(PierSshHandle handle);
+char *pier_terminal_get_scrollback(PierTerminalHandle handle, uint32_t start, uint32_t count);
 
 /**
- * Detect services installed on the remote server.
- * Returns a JSON array of DetectedService.
+ * Get the window title set by the shell or running program (OSC 0/2).
+ * Returns an empty string if no title has been set, null on invalid handle.
  * Caller must free with pier_string_free.
  */
-char *pier_ssh_detect_services(PierSshHandle handle);
+char *pier_terminal_get_title(PierTerminalHandle handle);
 
 /**
- * Execute a command on the remote server.
- * Returns JSON: {"exit_code": N, "stdout": "..."}
+ * Configure which OSC 52 clipboard operations programs may perform.
+ * Reads are denied by default. Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_terminal_set_clipboard_policy(PierTerminalHandle handle,
+                                           bool allow_write,
+                                           bool allow_read);
+
+/**
+ * Get the session's colors as JSON `{colors: ["#rrggbb" x256], foreground,
+ * background, cursor}`, including changes made by the program via OSC
+ * 4/10/11/12. `cursor` is null when the cursor uses the foreground color.
  * Caller must free with pier_string_free.
  */
-char *pier_ssh_exec(PierSshHandle handle, const char *command);
+char *pier_terminal_get_palette(PierTerminalHandle handle);
 
 /**
- * Start local port forwarding: 127.0.0.1:local_port → remote_host:remote_port.
+ * Set the color theme from JSON `{colors?: [spec...], foreground?,
+ * background?, cursor?}`, with colors as `#rrggbb` or `rgb:rr/gg/bb`.
+ * Omitted entries use xterm defaults. Replaces colors set by the program;
+ * OSC 104/110/111/112 reset to this theme.
+ * Returns 0 on success, -1 on invalid handle or theme.
+ */
+int32_t pier_terminal_set_theme(PierTerminalHandle handle, const char *theme_json);
+
+/**
+ * Set the answerback string written back to the PTY when the host sends
+ * ENQ (0x05). An empty string disables the reply (the default).
+ * Returns 0 on success, -1 on invalid arguments.
+ */
+int32_t pier_terminal_set_answerback(PierTerminalHandle handle, const char *answerback);
+
+/**
+ * Take pending OSC 52 clipboard events as a JSON array of
+ * `{"kind":"write","selection":"c","data":"..."}` / `{"kind":"read","selection":"c"}`.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_take_clipboard_events(PierTerminalHandle handle);
+
+/**
+ * Take bells and desktop notifications as a JSON array of
+ * `{"kind":"bell","timestamp_ms":N}` / `{"kind":"notify","title":"...","body":"...","timestamp_ms":N}`.
+ * Poll periodically to flash the tab or post a notification.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_take_notifications(PierTerminalHandle handle);
+
+/**
+ * Get the terminal's foreground process group straight from the PTY
+ * (`tcgetpgrp`). It equals the shell's pid while the shell sits at its
+ * prompt. Returns -1 on invalid handle or if it can't be determined.
+ */
+int32_t pier_terminal_foreground_pgid(PierTerminalHandle handle);
+
+/**
+ * Get the working directory of the foreground job (the shell while idle),
+ * as reported by the OS rather than OSC 7. Returns null on invalid handle
+ * or if it can't be read.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_foreground_cwd(PierTerminalHandle handle);
+
+/**
+ * Get the session's foreground job as JSON `{pgid, name, args, is_shell}`, or
+ * `null` if unknown. `is_shell` is true while the shell sits at its prompt.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_foreground_process(PierTerminalHandle handle);
+
+/**
+ * Take foreground job changes as a JSON array of `{pgid, name, args, is_shell}`,
+ * oldest first, to update the tab's "running: ..." label.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_take_process_events(PierTerminalHandle handle);
+
+/**
+ * Whether a command other than the shell is running, for confirm-on-close.
+ * Returns false on invalid handle.
+ */
+bool pier_terminal_is_busy(PierTerminalHandle handle);
+
+/**
+ * Take inline images received via OSC 1337 (imgcat) as a JSON array of
+ * `{id, name, width, height, preserve_aspect_ratio, inline, row, col, data}`.
+ * `row` is absolute, `data` is base64 file contents; sizes are `"auto"`,
+ * `{"cells":N}`, `{"pixels":N}` or `{"percent":N}`.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_take_images(PierTerminalHandle handle);
+
+/**
+ * Answer a clipboard read event with the current clipboard contents.
  * Returns 0 on success, -1 on failure.
  */
-int32_t pier_ssh_forward_port(PierSshHandle handle,
-                              uint16_t local_port,
-                              const char *remote_host,
-                              uint16_t remote_port);
+int32_t pier_terminal_clipboard_reply(PierTerminalHandle handle,
+                                      const char *selection,
+                                      const char *data);
 
 /**
- * Stop a local port forward.
- * Returns 0 on success, -1 if no such forward.
+ * Get the shell-integration (OSC 133) command history as a JSON array of
+ * `{command, exit_code, prompt_row, output_start_row, output_end_row}`.
+ * Rows are absolute; see pier_terminal_screen_base_row.
+ * Caller must free with pier_string_free.
  */
-int32_t pier_ssh_stop_forward(PierSshHandle handle, uint16_t local_port);
+char *pier_terminal_get_command_history(PierTerminalHandle handle);
 
 /**
- * List active forward ports as a JSON array.
+ * Absolute row number of the first visible screen row.
+ * Scrollback index `i` maps to absolute row `base - scrollback_len + i`.
+ * Returns -1 on invalid handle.
+ */
+int64_t pier_terminal_screen_base_row(PierTerminalHandle handle);
+
+/**
+ * Current keyboard input modes as a bitmask of `PIER_INPUT_*` flags.
+ * Returns 0 on invalid handle.
+ */
+uint32_t pier_terminal_input_modes(PierTerminalHandle handle);
+
+/**
+ * Encode a key press for the running program's keyboard modes (DECCKM,
+ * modifyOtherKeys, kitty keyboard protocol) and write it to the PTY.
+ * `modifiers` takes `PIER_MOD_*` flags.
+ * Returns 0 on success, -1 on invalid handle, key, or write failure.
+ */
+int32_t pier_terminal_send_key(PierTerminalHandle handle,
+                               uint32_t key,
+                               uint32_t codepoint,
+                               uint32_t modifiers);
+
+/**
+ * Serialize the full visible screen as JSON:
+ * `{cols, rows, cursor_x, cursor_y, cursor_visible, cursor_style, reverse_video, title,
+ * alternate_screen, lines: [[cell...]...], line_renditions: [...]}`.
+ * Line renditions are `"single"`, `"double_width"`, `"double_height_top"` or
+ * `"double_height_bottom"`.
+ * Cells omit default colors and unset attributes.
  * Caller must free with pier_string_free.
  */
-char *pier_ssh_list_forwards(PierSshHandle handle);
+char *pier_terminal_snapshot(PierTerminalHandle handle);
+
+/**
+ * Get the screen rows changed since the previous call as a JSON array of
+ * `{"row": N, "cells": [...]}`. The first call returns every row.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_get_dirty_rows(PierTerminalHandle handle);
+
+/**
+ * Get changes since the previous call as JSON:
+ * `{generation, cols, rows, cursor: {x, y, visible, style}, reverse_video,
+ * alternate_screen, scrolled, lines: [{row, cells, rendition?}...]}`; `rendition`
+ * is omitted for single-size lines.
+ * `lines` holds only rows whose content or attributes changed (all rows on
+ * the first call or after a resize); `generation` stays the same when
+ * nothing changed. Shares dirty tracking with pier_terminal_get_dirty_rows.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_take_updates(PierTerminalHandle handle);
+
+/**
+ * Extract the text between two absolute (row, col) points, inclusive.
+ * `block` selects the rectangle between them instead of running text.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_get_selection_text(PierTerminalHandle handle,
+                                       int64_t start_row,
+                                       uint32_t start_col,
+                                       int64_t end_row,
+                                       uint32_t end_col,
+                                       bool block);
+
+/**
+ * Get the range of the word at an absolute (row, col) as JSON
+ * `{start_row, start_col, end_row, end_col}`, or null if out of range.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_word_at(PierTerminalHandle handle, int64_t row, uint32_t col);
+
+/**
+ * Get the range covering a whole absolute row as JSON
+ * `{start_row, start_col, end_row, end_col}`, or null if out of range.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_line_range(PierTerminalHandle handle, int64_t row);
+
+/**
+ * Search the screen and scrollback for `pattern` (`PIER_SEARCH_*` flags in
+ * `options`). Returns a JSON array of `{start_row, start_col, end_row, end_col}`
+ * with absolute rows, oldest first; null on invalid handle or bad regex.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_search(PierTerminalHandle handle, const char *pattern, uint32_t options);
+
+/**
+ * Enter keyboard copy mode at the terminal cursor (restarting it if
+ * active). Returns the copy-mode state as JSON
+ * `{cursor_row, cursor_col, viewport_top, visual, selection, search_prompt}`
+ * with absolute rows, or null on invalid handle.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_copy_mode_enter(PierTerminalHandle handle);
+
+/**
+ * Leave copy mode, dropping any selection.
+ */
+void pier_terminal_copy_mode_exit(PierTerminalHandle handle);
+
+/**
+ * Get the copy-mode state as JSON (see pier_terminal_copy_mode_enter), or
+ * null if copy mode is not active.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_copy_mode_state(PierTerminalHandle handle);
+
+/**
+ * Feed a key to copy mode (`PIER_KEY_*` code, `PIER_MOD_*` modifiers, as
+ * for pier_terminal_send_key). Returns JSON `{action, text, state}`:
+ * `action` is `"continue"`, `"exit"` or `"copy"` with the selected `text`
+ * for the clipboard; copy mode ends on the latter two and `state` is null.
+ * Returns null on invalid handle or key, or if copy mode is not active.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_copy_mode_key(PierTerminalHandle handle,
+                                  uint32_t key,
+                                  uint32_t codepoint,
+                                  uint32_t modifiers);
+
+/**
+ * Search from the copy-mode cursor for `pattern` (`PIER_SEARCH_*` flags in
+ * `options`) and move to the next match, or the previous one if
+ * `backwards`, wrapping around. `n`/`N` then repeat it. Returns the
+ * copy-mode state as JSON; null on invalid handle, bad regex, or if copy
+ * mode is not active.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_copy_mode_search(PierTerminalHandle handle,
+                                     const char *pattern,
+                                     uint32_t options,
+                                     bool backwards);
+
+/**
+ * Register a regex trigger on terminal output. `options` takes the
+ * `PIER_SEARCH_*` flags. Returns the trigger id (> 0), or -1 on invalid
+ * handle or pattern.
+ */
+int64_t pier_terminal_add_trigger(PierTerminalHandle handle, const char *pattern, uint32_t options);
+
+/**
+ * Remove a trigger registered with pier_terminal_add_trigger.
+ * Returns 0 on success, -1 if the handle or id is invalid.
+ */
+int32_t pier_terminal_remove_trigger(PierTerminalHandle handle, uint32_t trigger_id);
+
+/**
+ * Set the callback receiving trigger matches, or clear it with null.
+ * The callback runs on the session's reader thread, with
+ * `user_data` and a JSON event `{trigger_id, text, start_row, start_col,
+ * end_row, end_col}` that is only valid during the call.
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_terminal_set_trigger_callback(PierTerminalHandle handle,
+                                           void (*callback)(void *user_data, const char *event_json),
+                                           void *user_data);
+
+/**
+ * Set the callback notified when new PTY output has been parsed, or clear
+ * it with null. It runs on the session's reader thread with `user_data`,
+ * once per parsed chunk and once more when the PTY closes; fetch the new
+ * state with pier_terminal_take_updates (dispatching to the UI thread as
+ * needed) instead of polling.
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_terminal_set_output_callback(PierTerminalHandle handle,
+                                          void (*callback)(void *user_data),
+                                          void *user_data);
+
+/**
+ * Send signal `sig` (SIGINT, SIGTERM, SIGHUP, SIGTSTP, SIGCONT, ...) to the
+ * terminal's foreground process group, i.e. the running job or the shell.
+ * Returns 0 on success, -1 on invalid handle or signal, or if the process
+ * has exited.
+ */
+int32_t pier_terminal_signal(PierTerminalHandle handle, int32_t sig);
+
+/**
+ * Pause (`paused = true`) or resume reading the session's output. While
+ * paused the program blocks once the kernel buffer fills, which keeps a
+ * runaway command from flooding the renderer.
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_terminal_set_output_paused(PierTerminalHandle handle, bool paused);
+
+/**
+ * Limit output consumed to `bytes_per_second`; 0 removes the limit.
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_terminal_set_output_throttle(PierTerminalHandle handle, uint64_t bytes_per_second);
+
+/**
+ * Whether the program turned off echo while keeping line editing, as
+ * password prompts (`sudo`, `ssh`, `read -s`) do, so the UI can show a
+ * secure-input indicator. Returns false on invalid handle.
+ */
+bool pier_terminal_is_password_input(PierTerminalHandle handle);
+
+/**
+ * Whether output is held back, either paused with
+ * pier_terminal_set_output_paused or stopped by the user's XOFF (Ctrl-S).
+ * Returns false on invalid handle.
+ */
+bool pier_terminal_is_output_stopped(PierTerminalHandle handle);
+
+/**
+ * Add (`registered = true`) or remove the session's utmpx login record so
+ * `who`, `w` and `last` list it. Sessions from pier_terminal_create are
+ * registered by default; command sessions are not. Registration is best
+ * effort and silently skipped without permission to write the database.
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_terminal_set_login_record(PierTerminalHandle handle, bool registered);
+
+/**
+ * Suspend the foreground job with SIGSTOP until pier_terminal_resume, e.g.
+ * to pause an output-heavy command. Returns 0 on success, -1 on invalid
+ * handle or if the process has exited.
+ */
+int32_t pier_terminal_suspend(PierTerminalHandle handle);
+
+/**
+ * Resume the job stopped by pier_terminal_suspend with SIGCONT.
+ * Returns 0 on success (or if nothing was suspended), -1 on failure.
+ */
+int32_t pier_terminal_resume(PierTerminalHandle handle);
+
+/**
+ * Whether a job suspended with pier_terminal_suspend is waiting to be
+ * resumed. Returns false on invalid handle.
+ */
+bool pier_terminal_is_suspended(PierTerminalHandle handle);
+
+/**
+ * Set the callback notified when the shell or command exits, or clear it
+ * with null. It runs once on the session's reader thread with `user_data`,
+ * the exit code (-1 if killed by a signal) and the signal (0 if it exited).
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_terminal_set_exit_callback(PierTerminalHandle handle,
+                                        void (*callback)(void *user_data,
+                                                         int32_t exit_code,
+                                                         int32_t signal),
+                                        void *user_data);
+
+/**
+ * Whether the shell or command behind the session is still running.
+ * Returns false for a null handle.
+ */
+bool pier_terminal_is_alive(PierTerminalHandle handle);
+
+/**
+ * Get how the child process ended as JSON, `{"kind":"exited","code":N}` or
+ * `{"kind":"signaled","signal":N}`; null while it is running.
+ * Caller must free with pier_string_free.
+ */
+char *pier_terminal_exit_status(PierTerminalHandle handle);
+
+/**
+ * Open an asciinema v2 `.cast` recording for playback.
+ * Returns null on failure.
+ */
+PierPlayerHandle pier_player_open(const char *path);
+
+/**
+ * Destroy a player.
+ */
+void pier_player_destroy(PierPlayerHandle handle);
+
+/**
+ * Advance playback by `elapsed` wall-clock seconds.
+ * Returns 1 if the screen changed, 0 if not, -1 on invalid handle.
+ */
+int32_t pier_player_advance(PierPlayerHandle handle, double elapsed);
+
+/**
+ * Jump to `seconds` into the recording.
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_player_seek(PierPlayerHandle handle, double seconds);
+
+/**
+ * Pause or resume playback.
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_player_set_paused(PierPlayerHandle handle, bool paused);
+
+/**
+ * Set the playback speed multiplier (1.0 = original). Non-positive values are ignored.
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_player_set_speed(PierPlayerHandle handle, double speed);
+
+/**
+ * Current playback position in seconds, or -1 on invalid handle.
+ */
+double pier_player_position(PierPlayerHandle handle);
+
+/**
+ * Total recording length in seconds, or -1 on invalid handle.
+ */
+double pier_player_duration(PierPlayerHandle handle);
+
+/**
+ * Serialize the replayed screen as JSON, in the same format as
+ * pier_terminal_snapshot.
+ * Caller must free with pier_string_free.
+ */
+char *pier_player_snapshot(PierPlayerHandle handle);
+
+/**
+ * Search result returned via FFI as a JSON string.
+ * Caller must free the returned string with pier_string_free.
+ */
+char *pier_search_files(const char *root, const char *pattern, uintptr_t max_results);
+
+/**
+ * List directory contents. Returns JSON string.
+ * Caller must free the returned string with pier_string_free.
+ */
+char *pier_list_directory(const char *path);
+
+/**
+ * Connect to an SSH server.
+ * auth_type: 0 = password, 1 = key file
+ * credential: password string (auth_type=0) or key file path (auth_type=1)
+ * Returns null on failure.
+ */
+PierSshHandle pier_ssh_connect(const char *host,
+                               uint16_t port,
+                               const char *username,
+                               int32_t auth_type,
+                               const char *credential);
+
+/**
+ * Connect to an SSH server that may ask questions while authenticating
+ * (OTP codes, Duo, PAM prompts).
+ * auth_type: 0 = password, 1 = key file, 2 = keyboard-interactive only
+ * (credential may be null). With a password or key, a second factor the
+ * server asks for afterwards goes through the callback too.
+ *
+ * `prompt_callback` runs on a background thread and may block while the
+ * user answers. It receives JSON
+ * `{"name": "...", "instructions": "...", "prompts": [{"prompt": "...", "echo": false}]}`
+ * and returns a JSON array with one answer string per prompt, allocated
+ * with malloc (e.g. strdup) for Pier to free, or null to cancel.
+ * Returns null on failure.
+ */
+PierSshHandle pier_ssh_connect_interactive(const char *host,
+                                           uint16_t port,
+                                           const char *username,
+                                           int32_t auth_type,
+                                           const char *credential,
+                                           char *(*prompt_callback)(void *user_data,
+                                                                    const char *prompts_json),
+                                           void *user_data);
+
+/**
+ * Connect to an SSH server through jump hosts. `proxy_jump` is an
+ * OpenSSH ProxyJump value (`[user@]host[:port]`, comma separated, first
+ * hop first); each hop is looked up in ~/.ssh/config for its host name,
+ * user, port and key. Other arguments are as for
+ * pier_ssh_connect_interactive, except that `prompt_callback` may be null.
+ * Returns null on failure.
+ */
+PierSshHandle pier_ssh_connect_via(const char *host,
+                                   uint16_t port,
+                                   const char *username,
+                                   int32_t auth_type,
+                                   const char *credential,
+                                   const char *proxy_jump,
+                                   char *(*prompt_callback)(void *user_data,
+                                                            const char *prompts_json),
+                                   void *user_data);
+
+/**
+ * Connect to an SSH server, sharing the connection with other pooled
+ * sessions for the same host, port and user: the first one connects and
+ * authenticates, later ones open their channels on the same transport.
+ * Disconnecting a pooled session only releases it; the connection closes
+ * once no session has used it for the pool's idle timeout.
+ * `proxy_jump` and `prompt_callback` may be null; other arguments are as
+ * for pier_ssh_connect_via, and only matter when a connection is made.
+ * Returns null on failure.
+ */
+PierSshHandle pier_ssh_connect_pooled(const char *host,
+                                      uint16_t port,
+                                      const char *username,
+                                      int32_t auth_type,
+                                      const char *credential,
+                                      const char *proxy_jump,
+                                      char *(*prompt_callback)(void *user_data,
+                                                               const char *prompts_json),
+                                      void *user_data);
+
+/**
+ * Set how long a pooled connection stays open after its last session is
+ * disconnected (default 60 seconds).
+ */
+void pier_ssh_pool_set_idle_timeout(uint32_t seconds);
+
+/**
+ * List pooled connections as a JSON array of
+ * `{"host", "port", "username", "sessions"}`; `sessions` is 0 while a
+ * connection idles.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_pool_list(void);
+
+/**
+ * Set the time limits for connections made from now on (and their
+ * reconnects): connecting to each hop, and commands run with
+ * pier_ssh_exec and pier_ssh_exec_with_input. 0 means no limit. The
+ * defaults are 10 and 60 seconds.
+ */
+void pier_ssh_set_default_timeouts(uint64_t connect_timeout_ms, uint64_t exec_timeout_ms);
+
+/**
+ * Compress connections made from now on with zlib (zlib@openssh.com)
+ * when the server supports it, like OpenSSH's Compression option. Helps
+ * SFTP transfers and verbose output over slow links; off by default.
+ */
+void pier_ssh_set_default_compression(bool enabled);
+
+/**
+ * Set the ciphers, key exchange algorithms and MACs offered to the hosts
+ * connected to from now on (not their jump hosts), in ssh_config syntax:
+ * e.g. "+diffie-hellman-group1-sha1" to also allow it for a legacy
+ * appliance, or "aes256-gcm@openssh.com" to allow only it. Null keeps the
+ * defaults. A connect fails if no algorithm of a list is supported.
+ */
+void pier_ssh_set_default_algorithms(const char *ciphers,
+                                     const char *kex_algorithms,
+                                     const char *macs);
+
+/**
+ * Renew the session keys of connections made from now on after
+ * `max_bytes` sent or received (at most 1 GiB) or `interval_secs`, like
+ * OpenSSH's RekeyLimit. 0 keeps the default of 1 GiB or one hour.
+ */
+void pier_ssh_set_default_rekey_limit(uint64_t max_bytes, uint64_t interval_secs);
+
+/**
+ * Open the TCP connections of connects made from now on through a proxy
+ * (the first jump host's, with jump hosts): `kind` 1 for SOCKS5, 2 for
+ * HTTP CONNECT, 0 or a null host for direct connections. `username` and
+ * `password` may be null for a proxy without authentication.
+ * Returns 0, or -1 for an unknown kind.
+ */
+int32_t pier_ssh_set_default_proxy(int32_t kind,
+                                   const char *host,
+                                   uint16_t port,
+                                   const char *username,
+                                   const char *password);
+
+/**
+ * Begin a cancellable operation on the calling thread: until
+ * pier_ssh_operation_end, the connect, exec, service detection, remote
+ * search and archive download calls it makes stop as soon as pier_ssh_cancel is called with the
+ * returned id, from any thread, failing as they would on a timeout.
+ */
+uint64_t pier_ssh_operation_begin(void);
+
+/**
+ * End the calling thread's operation; later calls aren't cancellable.
+ */
+void pier_ssh_operation_end(void);
+
+/**
+ * Cancel operation `op_id`: its running call returns now, and the ones
+ * after it fail right away. Returns 0, or -1 if there is no such
+ * operation (e.g. it already ended).
+ */
+int32_t pier_ssh_cancel(uint64_t op_id);
+
+/**
+ * The outcome of the last pier_ssh_connect* call made on this thread,
+ * successful or not: `{"connected", "error", "banner", "remaining_methods",
+ * "partial_success", "password_changed"}`. `banner` is the servers'
+ * pre-login message (legal notices some environments require showing),
+ * or null; after a failed login, `remaining_methods` lists the methods
+ * the server would still accept and `partial_success` tells whether it
+ * wanted another one on top of an accepted method. `password_changed`
+ * tells that an expired password was replaced (see
+ * pier_ssh_set_password_change_callback), so the app can store the new
+ * one. Returns null if this thread hasn't connected.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_last_connect_result(void);
+
+/**
+ * Hosts configured in ~/.ssh/config (aliases without wildcards), with
+ * their resolved settings. Returns a JSON array of
+ * `{"alias", "host_name", "user", "port", "identity_files", "proxy_jump", "forward_agent",
+ * "server_alive_interval", "server_alive_count_max", "send_env", "set_env", "compression", "ciphers",
+ * "kex_algorithms", "macs", "rekey_limit"}`;
+ * `set_env` is an array of `[name, value]` pairs.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_config_hosts(void);
+
+/**
+ * Resolve what ~/.ssh/config says for `alias`, the way ssh(1) would.
+ * Returns a JSON object as for pier_ssh_config_hosts; `host_name` is the
+ * alias itself when nothing is configured.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_config_resolve(const char *alias);
+
+/**
+ * Check and save host keys in the file at `path` instead of
+ * ~/.ssh/known_hosts, for an app-managed trust store kept apart from
+ * OpenSSH's; null goes back to ~/.ssh/known_hosts. With `hash_hosts`,
+ * keys saved from now on get hashed host names (like OpenSSH's
+ * HashKnownHosts), so the file doesn't reveal the hosts connected to.
+ * Hashed entries are always read. Applies to connections made from now
+ * on and to the pier_ssh_known_hosts_* functions.
+ */
+void pier_ssh_set_known_hosts(const char *path, bool hash_hosts);
+
+/**
+ * Set the callback deciding about host keys that aren't in
+ * the known_hosts file or differ from the recorded one, for all connections
+ * made afterwards; null clears it, and such hosts are then refused.
+ *
+ * The callback runs on a background thread and may block while the user
+ * decides. It receives JSON
+ * `{"host", "port", "key_type", "fingerprint": "SHA256:...", "status": "new" | "changed"}`
+ * and returns 0 to reject, 1 to accept for this connection only, or 2 to
+ * accept and record the key (replacing the old one if it changed).
+ */
+void pier_ssh_set_host_key_callback(int32_t (*callback)(void *user_data, const char *key_json),
+                                    void *user_data);
+
+/**
+ * Set the callback asked for the old and new passwords when a server
+ * requires changing an expired password while logging in, for all
+ * connections made afterwards; null clears it, and the server's prompts
+ * then go to the connection's prompt callback, if any. With it set, a
+ * password login the server refuses is retried with keyboard-interactive
+ * authentication, which is where PAM servers ask for the change.
+ *
+ * The callback runs on a background thread and may block while the user
+ * answers. It receives JSON `{"host", "port", "username", "message"}`,
+ * `message` being the server's explanation (also why it rejected the
+ * last new password, when it asks again), and returns JSON
+ * `{"old_password": "...", "new_password": "..."}` allocated with malloc
+ * for Pier to free, or null to cancel the login.
+ */
+void pier_ssh_set_password_change_callback(char *(*callback)(void *user_data,
+                                                             const char *request_json),
+                                           void *user_data);
+
+/**
+ * Set the callback that signs with security keys (FIDO2, `ed25519-sk` and
+ * `ecdsa-sk` key files), for all connections made afterwards; null
+ * clears it, and such keys then fail to log in.
+ *
+ * The callback runs on a background thread and may block until the user
+ * touches the key: it should show a "touch your security key" prompt,
+ * then get an assertion from the authenticator. It receives JSON
+ * `{"host", "port", "username", "algorithm", "application", "key_handle",
+ * "flags", "challenge"}`: the relying party id, the credential id
+ * (base64), the key's flags (1: touch required, 4: PIN required) and the
+ * client data hash to sign (base64). It returns JSON
+ * `{"signature": base64, "flags": N, "counter": N}` from the assertion
+ * (the signature as the authenticator gives it, DER for ECDSA) allocated
+ * with malloc for Pier to free, or null to cancel the login.
+ */
+void pier_ssh_set_security_key_callback(char *(*callback)(void *user_data, const char *request_json),
+                                        void *user_data);
+
+/**
+ * List the known_hosts file (see pier_ssh_set_known_hosts). Returns a JSON array of
+ * `{"line", "hosts": [...], "hashed", "marker", "key_type", "fingerprint"}`;
+ * hashed entries have no readable host names.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_known_hosts_list(void);
+
+/**
+ * Forget the keys recorded for `host:port` in the known_hosts file,
+ * hashed entries included.
+ * Returns how many entries were removed, or -1 on error.
+ */
+int32_t pier_ssh_known_hosts_remove(const char *host, uint16_t port);
+
+/**
+ * Record `public_key` (OpenSSH format, `ssh-ed25519 AAAA... [comment]`)
+ * as the key of `host:port` in the known_hosts file, replacing any keys
+ * recorded for it. The host name is hashed if set up so.
+ * Returns 0 on success, -1 on error.
+ */
+int32_t pier_ssh_known_hosts_update(const char *host, uint16_t port, const char *public_key);
+
+/**
+ * Disconnect an SSH session and free the handle.
+ */
+int32_t pier_ssh_disconnect(PierSshHandle handle);
+
+/**
+ * Check if SSH session is connected, as of the last keepalive; a closed
+ * or dead connection, or one being re-established, is not.
+ * Returns 1 if connected, 0 if not, -1 on invalid handle.
+ */
+int32_t pier_ssh_is_connected(PierSshHandle handle);
+
+/**
+ * Connection state: 0 = disconnected, 1 = connecting (or reconnecting),
+ * 2 = connected, 3 = degraded (keepalives going unanswered).
+ * Returns -1 on invalid handle.
+ */
+int32_t pier_ssh_state(PierSshHandle handle);
+
+/**
+ * Check that the server still answers, waiting at most `timeout_ms` for
+ * a keepalive reply. A failed probe also starts reconnecting if a
+ * reconnect policy is set.
+ * Returns 1 if alive, 0 if not, -1 on invalid handle.
+ */
+int32_t pier_ssh_probe(PierSshHandle handle, uint32_t timeout_ms);
+
+/**
+ * Probe the server every `interval_secs` seconds (0 to only notice
+ * connections the OS reports closed), and treat the connection as dead
+ * after `count_max` unanswered probes in a row, like OpenSSH's
+ * ServerAliveInterval and ServerAliveCountMax.
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_ssh_set_keepalive(PierSshHandle handle, uint32_t interval_secs, uint32_t count_max);
+
+/**
+ * Log the session's commands (with exit codes), shell channels and
+ * disconnect to the file at `path`, one timestamped line each, for an
+ * audit trail of what ran on the server. With `shell_io`, shells' raw
+ * input and output are logged too; that includes anything typed at a
+ * prompt, passwords included. Past `max_bytes` (0 for no limit) the file
+ * is rotated to `path.1`, keeping `keep_files` old ones. Applies to
+ * shells opened from now on. Null `path` stops logging.
+ * Returns 0 on success, -1 on invalid handle or if the file can't be
+ * opened.
+ */
+int32_t pier_ssh_set_transcript(PierSshHandle handle,
+                                const char *path,
+                                uint64_t max_bytes,
+                                uint32_t keep_files,
+                                bool shell_io);
+
+/**
+ * Set environment variables for shells and commands started from now on,
+ * like OpenSSH's SetEnv and SendEnv: `set_env_json` is an object of
+ * names and values, `send_env_json` an array of names or `*`/`?`
+ * patterns of local variables to pass on. Null clears either. The server
+ * only applies the names its AcceptEnv allows.
+ * Returns 0 on success, -1 on invalid handle or JSON.
+ */
+int32_t pier_ssh_set_env(PierSshHandle handle, const char *set_env_json, const char *send_env_json);
+
+/**
+ * Reconnect automatically when the connection dies, re-authenticating
+ * with the same credentials (prompting again through the prompt callback
+ * if the server asks) and keeping port forwards. The delay before each
+ * attempt doubles from `initial_delay_ms` up to `max_delay_ms`; after
+ * `max_retries` failed attempts (0 = no limit) the session stays
+ * disconnected. `enabled` false turns reconnecting off.
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_ssh_set_reconnect_policy(PierSshHandle handle,
+                                      bool enabled,
+                                      uint32_t max_retries,
+                                      uint64_t initial_delay_ms,
+                                      uint64_t max_delay_ms);
+
+/**
+ * Set the callback notified of connection state changes, or clear it
+ * with null. It runs on a background thread with `user_data` and a JSON
+ * event `{state: "connecting" | "connected" | "degraded" | "disconnected",
+ * attempt, error}` that is only valid during the call.
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_ssh_set_state_callback(PierSshHandle handle,
+                                    void (*callback)(void *user_data, const char *event_json),
+                                    void *user_data);
+
+/**
+ * Set the callback told of the session's events, or clear it with null.
+ * It runs on a background thread with `user_data` and a JSON event that
+ * is only valid during the call, by `type`:
+ * `{"type": "connected", attempt}`, `{"type": "reconnecting", attempt,
+ * reason}`, `{"type": "auth_prompt", name, instructions, prompts}` (before
+ * the prompt callback is asked), `{"type": "keepalive_timeout"}`,
+ * `{"type": "disconnected", reason}` (reason null if closed by the app),
+ * `{"type": "forward_died", local_port, error}` (the listener failed and
+ * is being restarted), `{"type": "forward_restarted", local_port}`,
+ * `{"type": "forward_failing", local_port, error}` (a connection through
+ * the forward couldn't be served; told once until one is).
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_ssh_set_event_callback(PierSshHandle handle,
+                                    void (*callback)(void *user_data, const char *event_json),
+                                    void *user_data);
+
+/**
+ * Renew the session keys now instead of at the rekey limits.
+ * Returns 0 once requested, -1 on invalid handle or when not connected.
+ */
+int32_t pier_ssh_rekey(PierSshHandle handle);
+
+/**
+ * The connection's negotiated algorithms as JSON: `{"kex", "host_key",
+ * "cipher", "mac_client_to_server", "mac_server_to_client", "compression",
+ * "exchanges", "last_exchange"}`; `exchanges` counts key exchanges
+ * including the first, `last_exchange` is in Unix seconds. Returns null
+ * on invalid handle or before connecting.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_key_exchange_info(PierSshHandle handle);
+
+/**
+ * Traffic statistics as JSON: `{"bytes_sent", "bytes_received",
+ * "channels_open", "channels_opened", "rtt_ms", "tunnels": [{"local_port",
+ * "bytes_sent", "bytes_received", "connections", "active_connections",
+ * "listening", "last_connection_ms", "errors", "last_error", "restarts"}]}`.
+ * Connection bytes are what the link carries (encrypted, with SSH
+ * framing), tunnel bytes what the forwards relay; `rtt_ms` is the last
+ * keepalive or probe round trip, null until one is measured. A tunnel's
+ * `listening` is false while its failed listener is being restarted;
+ * `errors` counts connections it couldn't serve and listener failures.
+ * Sessions sharing a pooled connection see the same numbers.
+ * Returns null on invalid handle.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_stats(PierSshHandle handle);
+
+/**
+ * Ping every `interval_secs` to measure round trips while keepalives are
+ * off (keepalive pings are measured anyway); 0 stops. Unanswered pings
+ * are recorded as lost but don't drop the connection.
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_ssh_set_latency_interval(PierSshHandle handle, uint32_t interval_secs);
+
+/**
+ * The connection's last 120 pings (keepalive, latency and probe), for a
+ * latency chart, as JSON: `{"samples": [{"at_ms", "rtt_ms"}], "min_ms",
+ * "avg_ms", "max_ms", "loss"}`. Samples are oldest first, `at_ms` in Unix
+ * milliseconds and `rtt_ms` null for unanswered pings; `loss` is the
+ * share of those, from 0 to 1. Summary times are null without answers.
+ * Returns null on invalid handle.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_latency(PierSshHandle handle);
+
+/**
+ * Check the connection now rather than at the next keepalive, and retry
+ * a pending reconnect without waiting. Call on network changes and after
+ * waking from sleep.
+ * Returns 0 on success, -1 on invalid handle.
+ */
+int32_t pier_ssh_check_connection(PierSshHandle handle);
+
+/**
+ * Find up to `max_results` files and directories under `root` on the
+ * server whose names contain `pattern`, ignoring case, like
+ * pier_search_files does locally.
+ * Returns a JSON array of entries (name, path, is_dir, size, modified,
+ * permissions), or null on error, timeout (60s) or cancellation.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_search_files(PierSshHandle handle,
+                            const char *root,
+                            const char *pattern,
+                            uintptr_t max_results);
+
+/**
+ * Detect services installed on the remote server.
+ * Returns a JSON array of DetectedService, empty if detection timed out
+ * or was cancelled.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_detect_services(PierSshHandle handle);
+
+/**
+ * Execute a command on the remote server, within the exec timeout set
+ * with pier_ssh_set_default_timeouts when connecting.
+ * Returns JSON: {"exit_code": N, "stdout": "..."}
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_exec(PierSshHandle handle, const char *command);
+
+/**
+ * Execute a command as root with sudo, within the exec timeout, feeding
+ * `password` to sudo's prompt if it asks (null if sudo needs none). The
+ * prompt is removed from the output.
+ * Returns JSON: {"exit_code": N, "output": "...", "elevated": bool,
+ * "error": "..."}; `elevated` tells whether sudo let the command run,
+ * `error` why not (wrong password, not in sudoers). Null on invalid
+ * arguments, or if the command couldn't be run or timed out.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_exec_sudo(PierSshHandle handle, const char *command, const char *password);
+
+/**
+ * Run every command of `commands_json` (a JSON array of strings) on each
+ * of the `handle_count` sessions in `handles`, at most `parallelism` at
+ * once (0 for 8), e.g. a health check on all servers in one go. Each
+ * command gets its session's exec timeout.
+ * Returns a JSON array in session order, then command order:
+ * `[{"session": index, "command", "exit_code", "output", "error",
+ * "elapsed_ms"}]`; `exit_code` is null and `error` set when a command
+ * couldn't run or timed out. Null on invalid arguments or if cancelled.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_exec_batch(const PierSshHandle *handles,
+                          uintptr_t handle_count,
+                          const char *commands_json,
+                          uint32_t parallelism);
+
+/**
+ * Execute a command on the remote server, waiting at most `timeout_ms`
+ * for it to exit; 0 waits as long as it takes (backups and the like).
+ * Returns JSON: {"exit_code": N, "stdout": "..."}
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_exec_with_timeout(PierSshHandle handle, const char *command, uint64_t timeout_ms);
+
+/**
+ * Execute a command with `input_len` bytes at `input` as its stdin
+ * (closed after them), for `cat > file` and the like. Input is sent while
+ * output is read, with the same time limit as pier_ssh_exec; use
+ * pier_ssh_exec_start for longer or streamed input.
+ * Returns JSON: {"exit_code": N, "stdout": "..."}
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_exec_with_input(PierSshHandle handle,
+                               const char *command,
+                               const uint8_t *input,
+                               uintptr_t input_len);
+
+/**
+ * Start a command whose stdin is fed with pier_ssh_exec_write and closed
+ * with pier_ssh_exec_close_stdin, e.g. `psql < dump.sql` with the dump
+ * read in pieces. Read output with pier_ssh_exec_read or at the end with
+ * pier_ssh_exec_finish, and free with pier_ssh_exec_free. The SSH handle
+ * must outlive it.
+ * Returns null on failure.
+ */
+PierExecHandle pier_ssh_exec_start(PierSshHandle handle, const char *command);
+
+/**
+ * Queue `len` bytes for the command's stdin. Doesn't wait for them to be
+ * sent.
+ * Returns 0 on success, -1 on error or once the command has ended.
+ */
+int32_t pier_ssh_exec_write(PierExecHandle exec, const uint8_t *data, uintptr_t len);
+
+/**
+ * Close the command's stdin after the input queued so far.
+ * Returns 0 on success, -1 on error.
+ */
+int32_t pier_ssh_exec_close_stdin(PierExecHandle exec);
+
+/**
+ * Read up to `cap` bytes of output (stdout and stderr) into `buf`,
+ * waiting up to `timeout_ms` for some.
+ * Returns the bytes read, 0 at the end of output, or -1 if none arrived
+ * in time or on invalid arguments.
+ */
+intptr_t pier_ssh_exec_read(PierExecHandle exec, uint8_t *buf, uintptr_t cap, uint32_t timeout_ms);
+
+/**
+ * Wait up to `timeout_ms` for the command to exit, and return the output
+ * not read yet. Close stdin first if the command reads it to the end.
+ * Returns JSON: {"exit_code": N, "stdout": "..."} (exit_code is -1 if
+ * unknown, 128 + N if killed by signal N), or null on timeout.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_exec_finish(PierExecHandle exec, uint32_t timeout_ms);
+
+/**
+ * Close the command's channel, if still open, and free the handle.
+ */
+void pier_ssh_exec_free(PierExecHandle exec);
+
+/**
+ * Run a command and pass its output to `output_callback` as it arrives,
+ * for long-running commands (builds, `tail -f`) whose output should show
+ * live. The callback gets `user_data`, the stream (1 stdout, 2 stderr)
+ * and a chunk only valid during the call. When the command ends,
+ * `exit_callback` (if not null) gets its exit code: -1 if the server
+ * didn't report one, 128 + N if signal N killed it. Both run on a
+ * background thread. There is no time limit; free with
+ * pier_ssh_exec_streaming_free, which stops the command if still running.
+ * The SSH handle must outlive it.
+ * Returns null on failure.
+ */
+PierStreamingExecHandle pier_ssh_exec_streaming(PierSshHandle handle,
+                                                const char *command,
+                                                void (*output_callback)(void *user_data,
+                                                                        int32_t stream,
+                                                                        const uint8_t *data,
+                                                                        uintptr_t len),
+                                                void (*exit_callback)(void *user_data,
+                                                                      int32_t exit_code),
+                                                void *user_data);
+
+/**
+ * Stop the command if still running and free the handle. No callback
+ * runs once this returns.
+ */
+void pier_ssh_exec_streaming_free(PierStreamingExecHandle exec);
+
+/**
+ * Open an interactive shell on the SSH connection, as a terminal session.
+ * The returned handle works with the pier_terminal_* functions like a
+ * local one (write, read, resize, snapshot, callbacks) and is freed with
+ * pier_terminal_destroy; process inspection and job control only apply
+ * to local sessions. The SSH handle must outlive it.
+ * Returns null on failure.
+ */
+PierTerminalHandle pier_ssh_open_shell(PierSshHandle handle, uint16_t cols, uint16_t rows);
+
+/**
+ * Start local port forwarding: 127.0.0.1:local_port → remote_host:remote_port.
+ * Returns 0 on success, -1 on failure.
+ */
+int32_t pier_ssh_forward_port(PierSshHandle handle,
+                              uint16_t local_port,
+                              const char *remote_host,
+                              uint16_t remote_port);
+
+/**
+ * Stop a local port forward.
+ * Returns 0 on success, -1 if no such forward.
+ */
+int32_t pier_ssh_stop_forward(PierSshHandle handle, uint16_t local_port);
+
+/**
+ * List active forward ports as a JSON array.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_list_forwards(PierSshHandle handle);
+
+/**
+ * Start dynamic forwarding: a SOCKS5 proxy on 127.0.0.1:local_port
+ * tunneling each connection through the server (`ssh -D`).
+ * Returns 0 on success, -1 on failure.
+ */
+int32_t pier_ssh_start_dynamic_forward(PierSshHandle handle, uint16_t local_port);
+
+/**
+ * Stop a dynamic forward.
+ * Returns 0 on success, -1 if no such forward.
+ */
+int32_t pier_ssh_stop_dynamic_forward(PierSshHandle handle, uint16_t local_port);
+
+/**
+ * List ports with an active SOCKS proxy as a JSON array.
+ * Caller must free with pier_string_free.
+ */
+char *pier_ssh_list_dynamic_forwards(PierSshHandle handle);
+
+/**
+ * Open an SFTP session over an SSH connection. The SSH handle must
+ * outlive it; free with pier_sftp_close.
+ * Returns null on failure.
+ */
+PierSftpHandle pier_sftp_open(PierSshHandle handle);
+
+/**
+ * End an SFTP session and free its handle.
+ */
+void pier_sftp_close(PierSftpHandle handle);
+
+/**
+ * List a remote directory as a JSON array of entries (name, path, is_dir,
+ * size, modified, permissions), directories first.
+ * Returns null on error. Caller must free with pier_string_free.
+ */
+char *pier_sftp_list_dir(PierSftpHandle handle, const char *path);
+
+/**
+ * List a remote directory in batches, for directories too big to list
+ * in one go. Runs on an SFTP channel of its own over the SSH connection,
+ * so other SFTP work isn't held up. Entries go to `batch_callback` with
+ * `user_data` as a JSON array like pier_sftp_list_dir's, but unsorted,
+ * as the server sends them. Then `done_callback` (if not null) gets the
+ * number of entries listed, or -1 on failure. Both run on a background
+ * thread, with strings only valid during the call. Free with
+ * pier_sftp_list_dir_streaming_free, which stops the listing if still
+ * going. The SSH handle must outlive it.
+ * Returns null on failure.
+ */
+PierSftpListingHandle pier_sftp_list_dir_streaming(PierSshHandle handle,
+                                                   const char *path,
+                                                   void (*batch_callback)(void *user_data,
+                                                                          const char *entries_json),
+                                                   void (*done_callback)(void *user_data,
+                                                                         int64_t count),
+                                                   void *user_data);
+
+/**
+ * Stop the listing if still going and free the handle. No callback runs
+ * once this returns.
+ */
+void pier_sftp_list_dir_streaming_free(PierSftpListingHandle listing);
+
+/**
+ * Have uploads and downloads on this SFTP session (and transfer queues
+ * created for it afterwards) carry over mode bits and modification times,
+ * as `scp -p` does. Off by default.
+ */
+void pier_sftp_set_preserve_attributes(PierSftpHandle handle, bool preserve);
+
+/**
+ * Limit each upload, download and copy on this SFTP session to
+ * `bytes_per_sec`, 0 for no limit. Transfer queues created for it
+ * afterwards start their transfers with this limit.
+ */
+void pier_sftp_set_rate_limit(PierSftpHandle handle, uint64_t bytes_per_sec);
+
+/**
+ * Limit all SFTP transfers together to `bytes_per_sec`, 0 for no limit,
+ * so they leave room on the link for shells and calls. Running transfers
+ * slow down at once.
+ */
+void pier_sftp_set_global_rate_limit(uint64_t bytes_per_sec);
+
+/**
+ * Download `remote_path` to `local_path`, streamed to disk in chunks.
+ * `progress_callback`, if not null, is called with `user_data`, the bytes
+ * done, the file size, the average rate in bytes per second and the
+ * seconds left (-1 until known), up to ten times a second and once at the
+ * end, on an SSH runtime thread while this call blocks.
+ * Returns 0 on success, -1 on failure.
+ */
+int32_t pier_sftp_download(PierSftpHandle handle,
+                           const char *remote_path,
+                           const char *local_path,
+                           void (*progress_callback)(void *user_data,
+                                                     uint64_t done,
+                                                     uint64_t total,
+                                                     uint64_t bytes_per_sec,
+                                                     int64_t eta_secs),
+                           void *user_data);
+
+/**
+ * Upload `local_path` to `remote_path`, read from disk in chunks.
+ * `progress_callback` is as for pier_sftp_download.
+ * Returns 0 on success, -1 on failure.
+ */
+int32_t pier_sftp_upload(PierSftpHandle handle,
+                         const char *local_path,
+                         const char *remote_path,
+                         void (*progress_callback)(void *user_data,
+                                                   uint64_t done,
+                                                   uint64_t total,
+                                                   uint64_t bytes_per_sec,
+                                                   int64_t eta_secs),
+                         void *user_data);
+
+/**
+ * Rename or move a remote file or directory. With `overwrite`, a file
+ * already at `new_path` is replaced.
+ * Returns 0 on success, -1 on failure.
+ */
+int32_t pier_sftp_rename(PierSftpHandle handle,
+                         const char *old_path,
+                         const char *new_path,
+                         bool overwrite);
+
+/**
+ * Copy a remote file to another remote path without a local download,
+ * replacing any file there. `progress_callback` is as for
+ * pier_sftp_download.
+ * Returns 0 on success, -1 on failure.
+ */
+int32_t pier_sftp_copy(PierSftpHandle handle,
+                       const char *from_path,
+                       const char *to_path,
+                       void (*progress_callback)(void *user_data,
+                                                 uint64_t done,
+                                                 uint64_t total,
+                                                 uint64_t bytes_per_sec,
+                                                 int64_t eta_secs),
+                       void *user_data);
+
+/**
+ * Set the permission bits of a remote entry, e.g. 0o644.
+ * Returns 0 on success, -1 on failure.
+ */
+int32_t pier_sftp_chmod(PierSftpHandle handle, const char *path, uint32_t mode);
+
+/**
+ * Change the owner and group of a remote entry; -1 for either keeps it,
+ * as with chown(2).
+ * Returns 0 on success, -1 on failure.
+ */
+int32_t pier_sftp_chown(PierSftpHandle handle, const char *path, int64_t uid, int64_t gid);
+
+/**
+ * Set the access and modification times of a remote entry, in Unix
+ * seconds; -1 for either keeps it.
+ * Returns 0 on success, -1 on failure.
+ */
+int32_t pier_sftp_set_times(PierSftpHandle handle, const char *path, int64_t atime, int64_t mtime);
+
+/**
+ * Remove a remote file.
+ * Returns 0 on success, -1 on failure.
+ */
+int32_t pier_sftp_remove_file(PierSftpHandle handle, const char *path);
+
+/**
+ * Expand a remote path with wildcards (`*`, `?`, `[...]`, and `**` for
+ * any depth of directories) as a shell would, giving up to `max_results`
+ * matches as a JSON array of entries like pier_sftp_list_dir's, sorted
+ * by path. Names starting with `.` only match a pattern that does too.
+ * Returns null on error. Caller must free with pier_string_free.
+ */
+char *pier_sftp_glob(PierSftpHandle handle, const char *pattern, uint32_t max_results);
+
+/**
+ * Download the remote files matching a wildcard pattern (as for
+ * pier_sftp_glob) into `local_dir`, keeping their layout below the
+ * pattern's first wildcard, so `etc/**/*.conf` lands as
+ * `local_dir/nginx/nginx.conf` and so on. Directories matched are
+ * skipped. For progress, queue the pier_sftp_glob matches with
+ * pier_transfers_add_download instead.
+ * Returns the number of files downloaded, or -1 on failure.
+ */
+int32_t pier_sftp_download_glob(PierSftpHandle handle, const char *pattern, const char *local_dir);
+
+/**
+ * Download a remote directory into `local_dir` (as
+ * `local_dir/<directory name>`) as one gzipped tar stream: `tar` runs on
+ * the server over exec and locally to unpack, which is much faster than
+ * per-file SFTP for trees of many small files. `progress_callback`, if
+ * not null, gets `user_data` and the compressed bytes received so far, on
+ * an SSH runtime thread while this call blocks. Cancellable like exec.
+ * Returns 0 on success, 1 if the server has no tar or gzip (nothing was
+ * downloaded; fall back to SFTP), -1 on failure or cancellation.
+ */
+int32_t pier_ssh_download_dir_archive(PierSshHandle handle,
+                                      const char *remote_dir,
+                                      const char *local_dir,
+                                      void (*progress_callback)(void *user_data, uint64_t received),
+                                      void *user_data);
+
+/**
+ * Remove the remote files matching a wildcard pattern (as for
+ * pier_sftp_glob), like `rm` without `-r`: directories matched are left.
+ * Files that can't be removed don't stop the rest.
+ * Returns the number of files removed, or -1 if any couldn't be.
+ */
+int32_t pier_sftp_remove_glob(PierSftpHandle handle, const char *pattern);
+
+/**
+ * Create a remote directory.
+ * Returns 0 on success, -1 on failure.
+ */
+int32_t pier_sftp_create_dir(PierSftpHandle handle, const char *path);
+
+/**
+ * Read up to `cap` bytes of a remote file from `offset` into `buf`.
+ * Returns the bytes read (fewer than `cap` at the end of the file, 0 at
+ * or past it), or -1 on error.
+ */
+intptr_t pier_sftp_read_range(PierSftpHandle handle,
+                              const char *path,
+                              uint64_t offset,
+                              uint8_t *buf,
+                              uintptr_t cap);
+
+/**
+ * Read a whole remote file, up to `max_size` bytes, to preview or edit it.
+ * Returns JSON: {"size": N, "encoding": "utf-8", "utf-16le", "utf-16be" or
+ * null, "bom": bool, "binary": bool, "content": the text, or base64 of
+ * the bytes if binary}, or null on error or if the file is over
+ * `max_size`.
+ * Caller must free with pier_string_free.
+ */
+char *pier_sftp_read_file(PierSftpHandle handle, const char *path, uint64_t max_size);
+
+/**
+ * Replace a remote file with `len` bytes of `data`, or create it, without
+ * ever leaving it truncated: the data is written to a temporary file that
+ * then takes the old file's place and permissions.
+ * Returns 0 on success, -1 on failure.
+ */
+int32_t pier_sftp_write_file(PierSftpHandle handle,
+                             const char *path,
+                             const uint8_t *data,
+                             uintptr_t len);
+
+/**
+ * Follow a remote file as it grows, like `tail -F`, for live logs. Bytes
+ * appended from `offset` on (-1 for the current end) are passed to
+ * `data_callback` with `user_data`, in chunks only valid during the call,
+ * on a background thread. The file is checked every `interval_ms` (1000
+ * if 0); if it shrinks, as when a log is rotated, it's followed from its
+ * start again. Stop with pier_sftp_tail_stop before closing the SFTP
+ * handle.
+ * Returns null on failure.
+ */
+PierSftpTailHandle pier_sftp_tail_follow(PierSftpHandle handle,
+                                         const char *path,
+                                         int64_t offset,
+                                         uint32_t interval_ms,
+                                         void (*data_callback)(void *user_data,
+                                                               const uint8_t *data,
+                                                               uintptr_t len),
+                                         void *user_data);
+
+/**
+ * Stop following a file and free the handle. No callback runs once this
+ * returns.
+ */
+void pier_sftp_tail_stop(PierSftpTailHandle tail);
+
+/**
+ * Watch remote paths for changes by polling them every `interval_ms`
+ * (2000 if 0), so open directory views and previews can refresh. Add
+ * paths with pier_sftp_watch_add. Each change is passed to
+ * `change_callback` with `user_data` as JSON, only valid during the call,
+ * on a background thread:
+ * {"path": "...", "kind": "created"|"modified"|"deleted"}
+ * A directory is modified when an entry in it is added, removed, or
+ * changes size or time. Stop with pier_sftp_watch_stop before closing
+ * the SFTP handle.
+ * Returns null on failure.
+ */
+PierSftpWatchHandle pier_sftp_watch_start(PierSftpHandle handle,
+                                          uint32_t interval_ms,
+                                          void (*change_callback)(void *user_data,
+                                                                  const char *change_json),
+                                          void *user_data);
+
+/**
+ * Start watching a remote file or directory. Changes are reported from
+ * the poll after the next one; a path that doesn't exist yet is reported
+ * when created.
+ * Returns 0 on success, -1 on invalid arguments.
+ */
+int32_t pier_sftp_watch_add(PierSftpWatchHandle watch, const char *path);
+
+/**
+ * Stop watching a remote path.
+ * Returns 0 on success, -1 if it wasn't watched.
+ */
+int32_t pier_sftp_watch_remove(PierSftpWatchHandle watch, const char *path);
+
+/**
+ * Stop watching and free the handle. No callback runs once this returns.
+ */
+void pier_sftp_watch_stop(PierSftpWatchHandle watch);
+
+/**
+ * Space on the filesystem holding a remote path, to check an upload
+ * fits. Asks the SFTP server, and if it can't tell, `df` over the SSH
+ * connection; either handle may be null, not both.
+ * Returns JSON: {"total": N, "used": N, "available": N} in bytes, with
+ * "available" what unprivileged users may fill, or null on error.
+ * Caller must free with pier_string_free.
+ */
+char *pier_sftp_filesystem_space(PierSftpHandle handle, PierSshHandle ssh_handle, const char *path);
+
+/**
+ * Whether anything exists at a remote path.
+ * Returns 1 if it does, 0 if not, -1 on error.
+ */
+int32_t pier_sftp_exists(PierSftpHandle handle, const char *path);
+
+/**
+ * Create a remote directory and any missing parents, like `mkdir -p`.
+ * Returns 0 on success (also if it already exists), -1 on failure.
+ */
+int32_t pier_sftp_create_dir_all(PierSftpHandle handle, const char *path);
+
+/**
+ * The session's working directory on the server, usually the home
+ * directory.
+ * Returns null on error. Caller must free with pier_string_free.
+ */
+char *pier_sftp_pwd(PierSftpHandle handle);
+
+/**
+ * The absolute path a remote path stands for, with `.`, `..` and
+ * symlinks resolved, e.g. to find the release a `current` link points at.
+ * Relative paths are from the working directory.
+ * Returns null on error (including, on most servers, a missing path).
+ * Caller must free with pier_string_free.
+ */
+char *pier_sftp_canonicalize(PierSftpHandle handle, const char *path);
+
+/**
+ * Make `link_path` a hard link to the remote file `target`.
+ * Returns 0 on success, 1 if the server doesn't support hard links
+ * (OpenSSH does), -1 on failure.
+ */
+int32_t pier_sftp_hard_link(PierSftpHandle handle, const char *target, const char *link_path);
+
+/**
+ * Create a transfer queue running up to `max_concurrent` transfers (at
+ * least 1) over an SFTP session. Free it with pier_transfers_free before
+ * closing the SFTP handle.
+ * Returns null on failure.
+ */
+PierTransfersHandle pier_transfers_new(PierSftpHandle sftp, uint32_t max_concurrent);
+
+/**
+ * Open `channels` more SFTP channels on the SSH connection the queue's
+ * SFTP session runs on, and spread transfers across them, which speeds up
+ * many small files on high-latency links. Raise the concurrency limit to
+ * match.
+ * Returns how many channels the queue has, or -1 on failure.
+ */
+int32_t pier_transfers_open_channels(PierTransfersHandle handle,
+                                     PierSshHandle ssh_handle,
+                                     uint32_t channels);
+
+/**
+ * Cancel the transfers still running and free the queue.
+ */
+void pier_transfers_free(PierTransfersHandle handle);
+
+/**
+ * Queue a download of `remote_path` to `local_path`.
+ * Returns the transfer id, or 0 on invalid arguments.
+ */
+uint64_t pier_transfers_add_download(PierTransfersHandle handle,
+                                     const char *remote_path,
+                                     const char *local_path);
+
+/**
+ * Queue an upload of `local_path` to `remote_path`.
+ * Returns the transfer id, or 0 on invalid arguments.
+ */
+uint64_t pier_transfers_add_upload(PierTransfersHandle handle,
+                                   const char *local_path,
+                                   const char *remote_path);
+
+/**
+ * Pause a queued or running transfer; it keeps what it has moved.
+ * Returns 0 on success, -1 if there's no such transfer or it can't pause.
+ */
+int32_t pier_transfers_pause(PierTransfersHandle handle, uint64_t id);
+
+/**
+ * Queue a paused or failed transfer again, to carry on where it stopped.
+ * Returns 0 on success, -1 if there's no such transfer or it can't resume.
+ */
+int32_t pier_transfers_resume(PierTransfersHandle handle, uint64_t id);
+
+/**
+ * Cancel a transfer, leaving any partial file behind.
+ * Returns 0 on success, -1 if there's no such transfer.
+ */
+int32_t pier_transfers_cancel(PierTransfersHandle handle, uint64_t id);
+
+/**
+ * Move a transfer to `index` in the queue (the end if past it); queued
+ * transfers start in queue order.
+ * Returns 0 on success, -1 if there's no such transfer.
+ */
+int32_t pier_transfers_move(PierTransfersHandle handle, uint64_t id, uint32_t index);
+
+/**
+ * Hold a transfer to `bytes_per_sec`, 0 for no limit, at once if it's
+ * running. The global limit applies as well.
+ * Returns 0 on success, -1 if there's no such transfer.
+ */
+int32_t pier_transfers_set_rate_limit(PierTransfersHandle handle,
+                                      uint64_t id,
+                                      uint64_t bytes_per_sec);
+
+/**
+ * Change how many transfers run at once (at least 1).
+ */
+void pier_transfers_set_max_concurrent(PierTransfersHandle handle, uint32_t max_concurrent);
+
+/**
+ * The queue as a JSON array in queue order: [{id, direction ("upload" or
+ * "download"), local_path, remote_path, state ("queued", "running",
+ * "paused", "completed", "failed" or "cancelled"), done, total,
+ * bytes_per_sec, eta_secs, rate_limit, error}]. Poll it to show progress.
+ * Caller must free with pier_string_free.
+ */
+char *pier_transfers_list(PierTransfersHandle handle);
+
+/**
+ * Remove completed and cancelled transfers from the queue.
+ */
+void pier_transfers_clear_finished(PierTransfersHandle handle);
+
+/**
+ * Open the profile file at `path` (created on the first save), sealed
+ * with the 32-byte `key` the app keeps, e.g. in the Keychain. With
+ * `keep_secrets`, saved profiles keep their passwords and passphrases;
+ * otherwise these are dropped on save.
+ * Returns null on failure, e.g. a wrong key.
+ */
+PierProfileStoreHandle pier_profiles_open(const char *path,
+                                          const uint8_t *key,
+                                          uintptr_t key_len,
+                                          bool keep_secrets);
+
+/**
+ * Close a profile store.
+ */
+void pier_profiles_destroy(PierProfileStoreHandle handle);
+
+/**
+ * All profiles as a JSON array of
+ * `{"id", "name", "group", "config": {...}, "updated_at"}`, `config`
+ * being the connection settings (host, port, username, auth, jump_hosts,
+ * keepalive, ...).
+ * Returns null on invalid handle.
+ * Caller must free with pier_string_free.
+ */
+char *pier_profiles_list(PierProfileStoreHandle handle);
+
+/**
+ * The profile with `id` as JSON (see pier_profiles_list).
+ * Returns null if there is none.
+ * Caller must free with pier_string_free.
+ */
+char *pier_profiles_get(PierProfileStoreHandle handle, const char *id);
+
+/**
+ * Save a profile given as JSON (see pier_profiles_list): a new one
+ * without `id`, or replacing the one with its id. `updated_at` is set.
+ * Returns the profile's id, or null on failure.
+ * Caller must free with pier_string_free.
+ */
+char *pier_profiles_save(PierProfileStoreHandle handle, const char *profile_json);
+
+/**
+ * Delete the profile with `id`.
+ * Returns 1 if deleted, 0 if there was none, -1 on error.
+ */
+int32_t pier_profiles_remove(PierProfileStoreHandle handle, const char *id);
+
+/**
+ * All profiles as plain JSON for pier_profiles_import, with passwords
+ * and passphrases only if `include_secrets`.
+ * Returns null on invalid handle.
+ * Caller must free with pier_string_free.
+ */
+char *pier_profiles_export(PierProfileStoreHandle handle, bool include_secrets);
+
+/**
+ * Import profiles exported with pier_profiles_export: those with the id
+ * of a saved profile replace it, the others are added.
+ * Returns how many were imported, or -1 on error.
+ */
+int32_t pier_profiles_import(PierProfileStoreHandle handle, const char *json);
 
 /**
  * Load commit graph data. Returns JSON string.
@@ -3,14 +3,43 @@
 //! All functions exported here are callable from Swift via the C bridge.
 //! Naming convention: pier_<module>_<action>
 
-use std::ffi::{CStr, CString};
+use std::ffi::{c_void, CStr, CString};
 use std::os::raw::c_char;
 use crate::terminal::TerminalSession;
+use crate::terminal::copy_mode::{CopyMode, CopyModeAction};
+use crate::terminal::emulator::ClipboardPolicy;
+use crate::terminal::keys::Key;
+use crate::terminal::palette::ThemeSpec;
+use crate::terminal::playback::CastPlayer;
+use crate::terminal::search::{self as terminal_search, SearchOptions};
+use crate::terminal::selection::SelectionMode;
+use crate::terminal::writer::PastePacing;
 use crate::search;
+use crate::profiles::{Profile, ProfileStore};
+use crate::ssh::archive;
+use crate::ssh::batch;
+use crate::ssh::config_file::SshConfigFile;
+use crate::ssh::disk_space;
+use crate::ssh::known_hosts::{self, HostKeyDecision, HostKeyHandler, HostKeyPrompt};
+use crate::ssh::password_change::{self, NewPassword, PasswordChangeHandler, PasswordChangeRequest};
+use crate::ssh::pool;
+use crate::ssh::proxy::{ProxyConfig, ProxyKind};
+use crate::ssh::remote_glob;
+use crate::ssh::remote_search;
+use crate::ssh::remote_watch::{self, RemoteWatch};
+use crate::ssh::security_key::{self, SecurityKeyHandler, SecurityKeyRequest, SecurityKeySignature};
 use crate::ssh::session::SshSession;
-use crate::ssh::{SshConfig, SshAuth};
+use crate::ssh::exec::{OutputStream, RemoteExec, StreamingExec};
+use crate::ssh::sftp::{self, DirListing, FileContent, RemoteTail, SftpClient, TransferProgress};
+use crate::ssh::shell::RemoteShell;
+use crate::ssh::transcript::TranscriptOptions;
+use crate::ssh::transfers::TransferManager;
+use crate::ssh::{
+    AlgorithmPreferences, AuthPrompts, ConnectionEvent, ConnectionState, EventHandler, KeepalivePolicy,
+    PromptHandler, ReconnectPolicy, RekeyLimits, SessionEvent, SshAuth, SshConfig, StateHandler, Timeouts,
+};
 use crate::ssh::service_detector;
-use std::sync::OnceLock;
+use std::sync::{Arc, OnceLock};
 
 /// Global tokio runtime for async SSH operations.
 fn ssh_runtime() -> &'static tokio::runtime::Runtime {
@@ -52,6 +81,7 @@ unsafe impl<T> Sync for SendPtr<T> {}
 impl<T> SendPtr<T> {
     fn as_ref(&self) -> &T { unsafe { &*self.0 } }
     fn as_mut(&self) -> &mut T { unsafe { &mut *self.0 } }
+    fn get(&self) -> *mut T { self.0 }
 }
 
 // ═══════════════════════════════════════════════════════════
@@ -61,7 +91,17 @@ impl<T> SendPtr<T> {
 /// Opaque pointer to a TerminalSession.
 pub type PierTerminalHandle = *mut TerminalSession;
 
+/// The user's login shell, validated against /etc/shells, for when no
+/// shell is configured in the app.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_default_shell() -> *mut c_char {
+    let shell = crate::terminal::shell::default_shell();
+    CString::new(shell).unwrap_or_default().into_raw()
+}
+
 /// Create a new terminal session.
+/// A null `shell` starts the user's login shell.
 /// Returns null on failure.
 #[no_mangle]
 pub extern "C" fn pier_terminal_create(
@@ -70,12 +110,12 @@ pub extern "C" fn pier_terminal_create(
     shell: *const c_char,
 ) -> PierTerminalHandle {
     let shell_str = if shell.is_null() {
-        "/bin/zsh"
+        crate::terminal::shell::default_shell()
     } else {
-        unsafe { CStr::from_ptr(shell).to_str().unwrap_or("/bin/zsh") }
+        unsafe { CStr::from_ptr(shell).to_string_lossy().into_owned() }
     };
 
-    match TerminalSession::new(cols, rows, shell_str) {
+    match TerminalSession::new(cols, rows, &shell_str) {
         Ok(session) => Box::into_raw(Box::new(session)),
         Err(e) => {
             log::error!("Failed to create terminal: {}", e);
@@ -101,31 +141,79 @@ pub extern "C" fn pier_terminal_create_with_args(
 
     let program_str = unsafe { CStr::from_ptr(program).to_str().unwrap_or("/bin/zsh") };
 
-    let mut arg_strings: Vec<String> = Vec::new();
-    if !args.is_null() && argc > 0 {
-        for i in 0..argc as usize {
-            unsafe {
-                let arg_ptr = *args.add(i);
-                if !arg_ptr.is_null() {
-                    if let Ok(s) = CStr::from_ptr(arg_ptr).to_str() {
-                        arg_strings.push(s.to_string());
-                    }
-                }
-            }
+    let arg_strings = c_string_array(args, argc);
+    let arg_refs: Vec<&str> = arg_strings.iter().map(|s| s.as_str()).collect();
+
+    match TerminalSession::new_with_command(cols, rows, program_str, &arg_refs) {
+        Ok(session) => Box::into_raw(Box::new(session)),
+        Err(e) => {
+            log::error!("Failed to create terminal with args: {}", e);
+            std::ptr::null_mut()
         }
     }
+}
+
+/// Create a new terminal session running a command with extra environment
+/// variables. `args`/`argc` are as for pier_terminal_create_with_args; `env`
+/// is a C array of `envc` `"NAME=value"` strings, applied after Pier's
+/// defaults so they can override TERM or LANG.
+/// Returns null on failure or a malformed entry.
+#[no_mangle]
+pub extern "C" fn pier_terminal_create_with_env(
+    cols: u16,
+    rows: u16,
+    program: *const c_char,
+    args: *const *const c_char,
+    argc: u32,
+    env: *const *const c_char,
+    envc: u32,
+) -> PierTerminalHandle {
+    if program.is_null() {
+        return std::ptr::null_mut();
+    }
 
+    let program_str = unsafe { CStr::from_ptr(program).to_str().unwrap_or("/bin/zsh") };
+    let arg_strings = c_string_array(args, argc);
     let arg_refs: Vec<&str> = arg_strings.iter().map(|s| s.as_str()).collect();
 
-    match TerminalSession::new_with_command(cols, rows, program_str, &arg_refs) {
+    let env_strings = c_string_array(env, envc);
+    let mut env_pairs = Vec::with_capacity(env_strings.len());
+    for entry in &env_strings {
+        let Some(pair) = entry.split_once('=') else {
+            log::error!("Invalid environment entry: {}", entry);
+            return std::ptr::null_mut();
+        };
+        env_pairs.push(pair);
+    }
+
+    match TerminalSession::new_with_env(cols, rows, program_str, &arg_refs, &env_pairs) {
         Ok(session) => Box::into_raw(Box::new(session)),
         Err(e) => {
-            log::error!("Failed to create terminal with args: {}", e);
+            log::error!("Failed to create terminal with env: {}", e);
             std::ptr::null_mut()
         }
     }
 }
 
+/// Collect a C array of `count` string pointers, skipping null and
+/// non-UTF-8 entries.
+fn c_string_array(items: *const *const c_char, count: u32) -> Vec<String> {
+    let mut strings = Vec::new();
+    if !items.is_null() && count > 0 {
+        for i in 0..count as usize {
+            unsafe {
+                let item = *items.add(i);
+                if !item.is_null() {
+                    if let Ok(s) = CStr::from_ptr(item).to_str() {
+                        strings.push(s.to_string());
+                    }
+                }
+            }
+        }
+    }
+    strings
+}
+
 /// Destroy a terminal session.
 #[no_mangle]
 pub extern "C" fn pier_terminal_destroy(handle: PierTerminalHandle) {
@@ -157,9 +245,65 @@ pub extern "C" fn pier_terminal_write(
     }
 }
 
+/// Paste text into the terminal. Like pier_terminal_write, but sent in
+/// chunks when pier_terminal_set_paste_pacing is on.
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_terminal_paste(
+    handle: PierTerminalHandle,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    if handle.is_null() || data.is_null() {
+        return -1;
+    }
+
+    let session = unsafe { &mut *handle };
+    let bytes = unsafe { std::slice::from_raw_parts(data, len) };
+
+    match session.paste(bytes) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Send pastes in chunks of `chunk_bytes`, pausing `interval_ms` after
+/// each, for programs that drop input arriving too fast. A `chunk_bytes` of
+/// 0 sends pastes all at once (the default).
+#[no_mangle]
+pub extern "C" fn pier_terminal_set_paste_pacing(
+    handle: PierTerminalHandle,
+    chunk_bytes: u32,
+    interval_ms: u32,
+) {
+    if handle.is_null() {
+        return;
+    }
+    let session = unsafe { &mut *handle };
+    let pacing = (chunk_bytes > 0).then(|| PastePacing {
+        chunk: chunk_bytes as usize,
+        interval: std::time::Duration::from_millis(interval_ms as u64),
+    });
+    session.set_paste_pacing(pacing);
+}
+
+/// Bytes of input queued that the program hasn't read yet, e.g. to show
+/// progress of a large paste. Returns 0 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_pending_input(handle: PierTerminalHandle) -> usize {
+    if handle.is_null() {
+        return 0;
+    }
+    let session = unsafe { &*handle };
+    session.pending_input()
+}
+
 /// Read output from the terminal.
-/// Writes data into the provided buffer and returns the number of bytes read.
-/// Returns -1 on failure.
+/// A background thread parses PTY output as it arrives; prefer the parsed
+/// state (pier_terminal_snapshot, pier_terminal_get_dirty_rows). This copies
+/// the raw bytes already parsed, for front-ends still interpreting them.
+/// Returns the number of bytes copied (0 if none are pending), or -1 on
+/// invalid arguments or once the PTY has closed and all output was read.
 #[no_mangle]
 pub extern "C" fn pier_terminal_read(
     handle: PierTerminalHandle,
@@ -171,17 +315,39 @@ pub extern "C" fn pier_terminal_read(
     }
 
     let session = unsafe { &mut *handle };
+    let data = session.take_output(buffer_len);
+    if data.is_empty() && session.lock().closed {
+        return -1;
+    }
+    unsafe {
+        std::ptr::copy_nonoverlapping(data.as_ptr(), buffer, data.len());
+    }
+    data.len() as i64
+}
 
-    match session.read() {
-        Ok(data) => {
-            let copy_len = data.len().min(buffer_len);
-            unsafe {
-                std::ptr::copy_nonoverlapping(data.as_ptr(), buffer, copy_len);
-            }
-            copy_len as i64
-        }
-        Err(_) => -1,
+/// Like pier_terminal_read, but block up to `timeout_ms` for output to
+/// arrive instead of returning 0 immediately, so a front-end reading raw
+/// bytes needn't poll. Returns early when the PTY closes.
+#[no_mangle]
+pub extern "C" fn pier_terminal_read_timeout(
+    handle: PierTerminalHandle,
+    buffer: *mut u8,
+    buffer_len: usize,
+    timeout_ms: u32,
+) -> i64 {
+    if handle.is_null() || buffer.is_null() {
+        return -1;
     }
+
+    let session = unsafe { &mut *handle };
+    let data = session.take_output_timeout(buffer_len, std::time::Duration::from_millis(timeout_ms as u64));
+    if data.is_empty() && session.lock().closed {
+        return -1;
+    }
+    unsafe {
+        std::ptr::copy_nonoverlapping(data.as_ptr(), buffer, data.len());
+    }
+    data.len() as i64
 }
 
 /// Resize the terminal.
@@ -195,330 +361,4163 @@ pub extern "C" fn pier_terminal_resize(
         return -1;
     }
 
-    let session = unsafe { &mut *handle };
-    match session.resize(cols, rows) {
+    let session = unsafe { &mut *handle };
+    match session.resize(cols, rows) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Get the PTY file descriptor for polling (-1 for remote sessions).
+#[no_mangle]
+pub extern "C" fn pier_terminal_fd(handle: PierTerminalHandle) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &*handle };
+    session.raw_fd().unwrap_or(-1)
+}
+
+/// Set the maximum number of scrollback lines kept by the terminal.
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_set_scrollback_limit(handle: PierTerminalHandle, lines: u32) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    session.lock().emulator.set_scrollback_limit(lines as usize);
+    0
+}
+
+/// Set the scrollback memory budget in bytes; the oldest lines are
+/// discarded once compacted history exceeds it. Applies alongside the
+/// line limit. Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_set_scrollback_memory_limit(handle: PierTerminalHandle, bytes: u64) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    session.lock().emulator.set_scrollback_memory_limit(bytes as usize);
+    0
+}
+
+/// Approximate memory used by the terminal's scrollback, in bytes.
+/// Returns -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_scrollback_memory_usage(handle: PierTerminalHandle) -> i64 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &*handle };
+    session.lock().emulator.scrollback_memory_usage() as i64
+}
+
+/// Number of lines currently in the terminal's scrollback.
+/// Returns -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_scrollback_len(handle: PierTerminalHandle) -> i64 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &*handle };
+    session.lock().emulator.scrollback_len() as i64
+}
+
+/// Get a page of scrollback history as JSON: an array of lines, each an
+/// array of attributed cells. `start` indexes from the oldest line.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_get_scrollback(
+    handle: PierTerminalHandle,
+    start: u32,
+    count: u32,
+) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+    let lines = session.lock().emulator.scrollback_lines(start as usize, count as usize);
+
+    match serde_json::to_string(&lines) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Get the window title set by the shell or running program (OSC 0/2).
+/// Returns an empty string if no title has been set, null on invalid handle.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_get_title(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+    CString::new(session.lock().emulator.title()).unwrap_or_default().into_raw()
+}
+
+/// Configure which OSC 52 clipboard operations programs may perform.
+/// Reads are denied by default. Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_set_clipboard_policy(
+    handle: PierTerminalHandle,
+    allow_write: bool,
+    allow_read: bool,
+) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    session.lock().emulator.set_clipboard_policy(ClipboardPolicy { allow_write, allow_read });
+    0
+}
+
+/// Get the session's colors as JSON `{colors: ["#rrggbb" x256], foreground,
+/// background, cursor}`, including changes made by the program via OSC
+/// 4/10/11/12. `cursor` is null when the cursor uses the foreground color.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_get_palette(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+
+    match serde_json::to_string(session.lock().emulator.palette()) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Set the color theme from JSON `{colors?: [spec...], foreground?,
+/// background?, cursor?}`, with colors as `#rrggbb` or `rgb:rr/gg/bb`.
+/// Omitted entries use xterm defaults. Replaces colors set by the program;
+/// OSC 104/110/111/112 reset to this theme.
+/// Returns 0 on success, -1 on invalid handle or theme.
+#[no_mangle]
+pub extern "C" fn pier_terminal_set_theme(handle: PierTerminalHandle, theme_json: *const c_char) -> i32 {
+    if handle.is_null() || theme_json.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    let json = match unsafe { CStr::from_ptr(theme_json) }.to_str() {
+        Ok(s) => s,
+        Err(_) => return -1,
+    };
+    let palette = match serde_json::from_str::<ThemeSpec>(json) {
+        Ok(spec) => spec.to_palette(),
+        Err(e) => Err(e.to_string()),
+    };
+    match palette {
+        Ok(palette) => {
+            session.lock().emulator.set_theme(palette);
+            0
+        }
+        Err(e) => {
+            log::warn!("Invalid terminal theme: {}", e);
+            -1
+        }
+    }
+}
+
+/// Set the answerback string written back to the PTY when the host sends
+/// ENQ (0x05). An empty string disables the reply (the default).
+/// Returns 0 on success, -1 on invalid arguments.
+#[no_mangle]
+pub extern "C" fn pier_terminal_set_answerback(handle: PierTerminalHandle, answerback: *const c_char) -> i32 {
+    if handle.is_null() || answerback.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    let answerback = unsafe { CStr::from_ptr(answerback) };
+    match answerback.to_str() {
+        Ok(s) => {
+            session.lock().emulator.set_answerback(s.to_string());
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+/// Take pending OSC 52 clipboard events as a JSON array of
+/// `{"kind":"write","selection":"c","data":"..."}` / `{"kind":"read","selection":"c"}`.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_take_clipboard_events(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &mut *handle };
+    let events = session.lock().emulator.take_clipboard_events();
+
+    match serde_json::to_string(&events) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Take bells and desktop notifications as a JSON array of
+/// `{"kind":"bell","timestamp_ms":N}` / `{"kind":"notify","title":"...","body":"...","timestamp_ms":N}`.
+/// Poll periodically to flash the tab or post a notification.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_take_notifications(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &mut *handle };
+    let notifications = session.lock().emulator.take_notifications();
+
+    match serde_json::to_string(&notifications) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Get the terminal's foreground process group straight from the PTY
+/// (`tcgetpgrp`). It equals the shell's pid while the shell sits at its
+/// prompt. Returns -1 on invalid handle or if it can't be determined.
+#[no_mangle]
+pub extern "C" fn pier_terminal_foreground_pgid(handle: PierTerminalHandle) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &*handle };
+    session.foreground_pgid().unwrap_or(-1)
+}
+
+/// Get the working directory of the foreground job (the shell while idle),
+/// as reported by the OS rather than OSC 7. Returns null on invalid handle
+/// or if it can't be read.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_foreground_cwd(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+    match session.foreground_cwd() {
+        Some(path) => CString::new(path.to_string_lossy().into_owned()).unwrap_or_default().into_raw(),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Get the session's foreground job as JSON `{pgid, name, args, is_shell}`, or
+/// `null` if unknown. `is_shell` is true while the shell sits at its prompt.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_foreground_process(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+    let foreground = session.lock().foreground.clone();
+
+    match serde_json::to_string(&foreground) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Take foreground job changes as a JSON array of `{pgid, name, args, is_shell}`,
+/// oldest first, to update the tab's "running: ..." label.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_take_process_events(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+    let events = session.lock().take_process_events();
+
+    match serde_json::to_string(&events) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Whether a command other than the shell is running, for confirm-on-close.
+/// Returns false on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_is_busy(handle: PierTerminalHandle) -> bool {
+    if handle.is_null() {
+        return false;
+    }
+    let session = unsafe { &*handle };
+    session.lock().is_busy()
+}
+
+/// Take inline images received via OSC 1337 (imgcat) as a JSON array of
+/// `{id, name, width, height, preserve_aspect_ratio, inline, row, col, data}`.
+/// `row` is absolute, `data` is base64 file contents; sizes are `"auto"`,
+/// `{"cells":N}`, `{"pixels":N}` or `{"percent":N}`.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_take_images(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &mut *handle };
+    let images = session.lock().emulator.take_images();
+
+    match serde_json::to_string(&images) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Answer a clipboard read event with the current clipboard contents.
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_terminal_clipboard_reply(
+    handle: PierTerminalHandle,
+    selection: *const c_char,
+    data: *const c_char,
+) -> i32 {
+    if handle.is_null() || data.is_null() {
+        return -1;
+    }
+    let selection_str = if selection.is_null() {
+        "c"
+    } else {
+        unsafe { CStr::from_ptr(selection).to_str().unwrap_or("c") }
+    };
+    let data_str = unsafe { CStr::from_ptr(data).to_str().unwrap_or("") };
+
+    let session = unsafe { &mut *handle };
+    match session.reply_clipboard(selection_str, data_str) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Get the shell-integration (OSC 133) command history as a JSON array of
+/// `{command, exit_code, prompt_row, output_start_row, output_end_row}`.
+/// Rows are absolute; see pier_terminal_screen_base_row.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_get_command_history(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+    let history = session.lock().emulator.command_history();
+
+    match serde_json::to_string(&history) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Absolute row number of the first visible screen row.
+/// Scrollback index `i` maps to absolute row `base - scrollback_len + i`.
+/// Returns -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_screen_base_row(handle: PierTerminalHandle) -> i64 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &*handle };
+    session.lock().emulator.screen_base_row() as i64
+}
+
+/// Input mode flag: arrow keys use application sequences (`ESC O A`).
+pub const PIER_INPUT_APP_CURSOR: u32 = 1 << 0;
+/// Input mode flag: keypad uses application sequences (`ESC O p`...).
+pub const PIER_INPUT_APP_KEYPAD: u32 = 1 << 1;
+
+/// Current keyboard input modes as a bitmask of `PIER_INPUT_*` flags.
+/// Returns 0 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_input_modes(handle: PierTerminalHandle) -> u32 {
+    if handle.is_null() {
+        return 0;
+    }
+    let session = unsafe { &*handle };
+    let modes = session.lock().emulator.input_modes();
+    let mut flags = 0;
+    if modes.application_cursor {
+        flags |= PIER_INPUT_APP_CURSOR;
+    }
+    if modes.application_keypad {
+        flags |= PIER_INPUT_APP_KEYPAD;
+    }
+    flags
+}
+
+/// Key codes for pier_terminal_send_key. `PIER_KEY_CHAR` sends `codepoint`.
+pub const PIER_KEY_CHAR: u32 = 0;
+pub const PIER_KEY_ENTER: u32 = 1;
+pub const PIER_KEY_TAB: u32 = 2;
+pub const PIER_KEY_BACKSPACE: u32 = 3;
+pub const PIER_KEY_ESCAPE: u32 = 4;
+pub const PIER_KEY_UP: u32 = 5;
+pub const PIER_KEY_DOWN: u32 = 6;
+pub const PIER_KEY_LEFT: u32 = 7;
+pub const PIER_KEY_RIGHT: u32 = 8;
+pub const PIER_KEY_HOME: u32 = 9;
+pub const PIER_KEY_END: u32 = 10;
+pub const PIER_KEY_PAGE_UP: u32 = 11;
+pub const PIER_KEY_PAGE_DOWN: u32 = 12;
+pub const PIER_KEY_INSERT: u32 = 13;
+pub const PIER_KEY_DELETE: u32 = 14;
+/// F1..F12 are `PIER_KEY_F1 + 0..=11`.
+pub const PIER_KEY_F1: u32 = 32;
+
+/// Modifier flags for pier_terminal_send_key (same bits as xterm/kitty).
+pub const PIER_MOD_SHIFT: u32 = 1;
+pub const PIER_MOD_ALT: u32 = 2;
+pub const PIER_MOD_CTRL: u32 = 4;
+pub const PIER_MOD_SUPER: u32 = 8;
+
+/// Map a `PIER_KEY_*` code (and `codepoint` for `PIER_KEY_CHAR`) to a key.
+fn key_from_code(key: u32, codepoint: u32) -> Option<Key> {
+    Some(match key {
+        PIER_KEY_CHAR => Key::Char(char::from_u32(codepoint)?),
+        PIER_KEY_ENTER => Key::Enter,
+        PIER_KEY_TAB => Key::Tab,
+        PIER_KEY_BACKSPACE => Key::Backspace,
+        PIER_KEY_ESCAPE => Key::Escape,
+        PIER_KEY_UP => Key::Up,
+        PIER_KEY_DOWN => Key::Down,
+        PIER_KEY_LEFT => Key::Left,
+        PIER_KEY_RIGHT => Key::Right,
+        PIER_KEY_HOME => Key::Home,
+        PIER_KEY_END => Key::End,
+        PIER_KEY_PAGE_UP => Key::PageUp,
+        PIER_KEY_PAGE_DOWN => Key::PageDown,
+        PIER_KEY_INSERT => Key::Insert,
+        PIER_KEY_DELETE => Key::Delete,
+        k if (PIER_KEY_F1..PIER_KEY_F1 + 12).contains(&k) => Key::F((k - PIER_KEY_F1 + 1) as u8),
+        _ => return None,
+    })
+}
+
+/// Encode a key press for the running program's keyboard modes (DECCKM,
+/// modifyOtherKeys, kitty keyboard protocol) and write it to the PTY.
+/// `modifiers` takes `PIER_MOD_*` flags.
+/// Returns 0 on success, -1 on invalid handle, key, or write failure.
+#[no_mangle]
+pub extern "C" fn pier_terminal_send_key(
+    handle: PierTerminalHandle,
+    key: u32,
+    codepoint: u32,
+    modifiers: u32,
+) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let Some(key) = key_from_code(key, codepoint) else {
+        return -1;
+    };
+
+    let session = unsafe { &mut *handle };
+    match session.send_key(key, (modifiers & 0x0f) as u8) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Serialize the full visible screen as JSON:
+/// `{cols, rows, cursor_x, cursor_y, cursor_visible, cursor_style, reverse_video, title,
+/// alternate_screen, lines: [[cell...]...], line_renditions: [...]}`.
+/// Line renditions are `"single"`, `"double_width"`, `"double_height_top"` or
+/// `"double_height_bottom"`.
+/// Cells omit default colors and unset attributes.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_snapshot(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+
+    match serde_json::to_string(&session.lock().emulator.snapshot()) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Get the screen rows changed since the previous call as a JSON array of
+/// `{"row": N, "cells": [...]}`. The first call returns every row.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_get_dirty_rows(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &mut *handle };
+    let mut state = session.lock();
+    let emulator = &mut state.emulator;
+    let rows: Vec<serde_json::Value> = emulator
+        .take_dirty_rows()
+        .into_iter()
+        .map(|row| serde_json::json!({ "row": row, "cells": emulator.cells[row] }))
+        .collect();
+
+    match serde_json::to_string(&rows) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Get changes since the previous call as JSON:
+/// `{generation, cols, rows, cursor: {x, y, visible, style}, reverse_video,
+/// alternate_screen, scrolled, lines: [{row, cells, rendition?}...]}`; `rendition`
+/// is omitted for single-size lines.
+/// `lines` holds only rows whose content or attributes changed (all rows on
+/// the first call or after a resize); `generation` stays the same when
+/// nothing changed. Shares dirty tracking with pier_terminal_get_dirty_rows.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_take_updates(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &mut *handle };
+    let mut state = session.lock();
+
+    match serde_json::to_string(&state.emulator.take_updates()) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Extract the text between two absolute (row, col) points, inclusive.
+/// `block` selects the rectangle between them instead of running text.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_get_selection_text(
+    handle: PierTerminalHandle,
+    start_row: i64,
+    start_col: u32,
+    end_row: i64,
+    end_col: u32,
+    block: bool,
+) -> *mut c_char {
+    if handle.is_null() || start_row < 0 || end_row < 0 {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+    let mode = if block { SelectionMode::Block } else { SelectionMode::Stream };
+    let text = session.lock().emulator.selection_text(
+        (start_row as u64, start_col as usize),
+        (end_row as u64, end_col as usize),
+        mode,
+    );
+    CString::new(text).unwrap_or_default().into_raw()
+}
+
+/// Get the range of the word at an absolute (row, col) as JSON
+/// `{start_row, start_col, end_row, end_col}`, or null if out of range.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_word_at(handle: PierTerminalHandle, row: i64, col: u32) -> *mut c_char {
+    if handle.is_null() || row < 0 {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+    let Some(range) = session.lock().emulator.word_at(row as u64, col as usize) else {
+        return std::ptr::null_mut();
+    };
+
+    match serde_json::to_string(&range) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Get the range covering a whole absolute row as JSON
+/// `{start_row, start_col, end_row, end_col}`, or null if out of range.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_line_range(handle: PierTerminalHandle, row: i64) -> *mut c_char {
+    if handle.is_null() || row < 0 {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+    let Some(range) = session.lock().emulator.line_range(row as u64) else {
+        return std::ptr::null_mut();
+    };
+
+    match serde_json::to_string(&range) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Search option flag: interpret the pattern as a regular expression.
+pub const PIER_SEARCH_REGEX: u32 = 1 << 0;
+/// Search option flag: ignore case.
+pub const PIER_SEARCH_CASE_INSENSITIVE: u32 = 1 << 1;
+
+/// Search the screen and scrollback for `pattern` (`PIER_SEARCH_*` flags in
+/// `options`). Returns a JSON array of `{start_row, start_col, end_row, end_col}`
+/// with absolute rows, oldest first; null on invalid handle or bad regex.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_search(
+    handle: PierTerminalHandle,
+    pattern: *const c_char,
+    options: u32,
+) -> *mut c_char {
+    if handle.is_null() || pattern.is_null() {
+        return std::ptr::null_mut();
+    }
+    let pattern_str = unsafe { CStr::from_ptr(pattern).to_str().unwrap_or("") };
+    let options = SearchOptions {
+        regex: options & PIER_SEARCH_REGEX != 0,
+        case_insensitive: options & PIER_SEARCH_CASE_INSENSITIVE != 0,
+    };
+    let Ok(regex) = terminal_search::build_pattern(pattern_str, options) else {
+        return std::ptr::null_mut();
+    };
+    let session = unsafe { &*handle };
+    let matches = session.lock().emulator.search(&regex);
+
+    match serde_json::to_string(&matches) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Enter keyboard copy mode at the terminal cursor (restarting it if
+/// active). Returns the copy-mode state as JSON
+/// `{cursor_row, cursor_col, viewport_top, visual, selection, search_prompt}`
+/// with absolute rows, or null on invalid handle.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_copy_mode_enter(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &mut *handle };
+    let mut state = session.lock();
+    let copy_mode = CopyMode::new(&state.emulator);
+    let json = serde_json::to_string(&copy_mode.state(&state.emulator));
+    state.copy_mode = Some(copy_mode);
+
+    match json {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Leave copy mode, dropping any selection.
+#[no_mangle]
+pub extern "C" fn pier_terminal_copy_mode_exit(handle: PierTerminalHandle) {
+    if handle.is_null() {
+        return;
+    }
+    let session = unsafe { &mut *handle };
+    session.lock().copy_mode = None;
+}
+
+/// Get the copy-mode state as JSON (see pier_terminal_copy_mode_enter), or
+/// null if copy mode is not active.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_copy_mode_state(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+    let state = session.lock();
+    let Some(copy_mode) = &state.copy_mode else {
+        return std::ptr::null_mut();
+    };
+
+    match serde_json::to_string(&copy_mode.state(&state.emulator)) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Feed a key to copy mode (`PIER_KEY_*` code, `PIER_MOD_*` modifiers, as
+/// for pier_terminal_send_key). Returns JSON `{action, text, state}`:
+/// `action` is `"continue"`, `"exit"` or `"copy"` with the selected `text`
+/// for the clipboard; copy mode ends on the latter two and `state` is null.
+/// Returns null on invalid handle or key, or if copy mode is not active.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_copy_mode_key(
+    handle: PierTerminalHandle,
+    key: u32,
+    codepoint: u32,
+    modifiers: u32,
+) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let Some(key) = key_from_code(key, codepoint) else {
+        return std::ptr::null_mut();
+    };
+    let session = unsafe { &mut *handle };
+    let mut guard = session.lock();
+    let state = &mut *guard;
+    let Some(copy_mode) = state.copy_mode.as_mut() else {
+        return std::ptr::null_mut();
+    };
+
+    let reply = match copy_mode.handle_key(&state.emulator, key, (modifiers & 0x0f) as u8) {
+        CopyModeAction::Continue => serde_json::json!({
+            "action": "continue",
+            "text": null,
+            "state": copy_mode.state(&state.emulator),
+        }),
+        CopyModeAction::Exit => {
+            state.copy_mode = None;
+            serde_json::json!({ "action": "exit", "text": null, "state": null })
+        }
+        CopyModeAction::Copy(text) => {
+            state.copy_mode = None;
+            serde_json::json!({ "action": "copy", "text": text, "state": null })
+        }
+    };
+    CString::new(reply.to_string()).unwrap_or_default().into_raw()
+}
+
+/// Search from the copy-mode cursor for `pattern` (`PIER_SEARCH_*` flags in
+/// `options`) and move to the next match, or the previous one if
+/// `backwards`, wrapping around. `n`/`N` then repeat it. Returns the
+/// copy-mode state as JSON; null on invalid handle, bad regex, or if copy
+/// mode is not active.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_copy_mode_search(
+    handle: PierTerminalHandle,
+    pattern: *const c_char,
+    options: u32,
+    backwards: bool,
+) -> *mut c_char {
+    if handle.is_null() || pattern.is_null() {
+        return std::ptr::null_mut();
+    }
+    let pattern_str = unsafe { CStr::from_ptr(pattern).to_str().unwrap_or("") };
+    let options = SearchOptions {
+        regex: options & PIER_SEARCH_REGEX != 0,
+        case_insensitive: options & PIER_SEARCH_CASE_INSENSITIVE != 0,
+    };
+    let Ok(regex) = terminal_search::build_pattern(pattern_str, options) else {
+        return std::ptr::null_mut();
+    };
+    let session = unsafe { &mut *handle };
+    let mut guard = session.lock();
+    let state = &mut *guard;
+    let Some(copy_mode) = state.copy_mode.as_mut() else {
+        return std::ptr::null_mut();
+    };
+    copy_mode.search(&state.emulator, regex, !backwards);
+
+    match serde_json::to_string(&copy_mode.state(&state.emulator)) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Register a regex trigger on terminal output. `options` takes the
+/// `PIER_SEARCH_*` flags. Returns the trigger id (> 0), or -1 on invalid
+/// handle or pattern.
+#[no_mangle]
+pub extern "C" fn pier_terminal_add_trigger(
+    handle: PierTerminalHandle,
+    pattern: *const c_char,
+    options: u32,
+) -> i64 {
+    if handle.is_null() || pattern.is_null() {
+        return -1;
+    }
+    let pattern_str = unsafe { CStr::from_ptr(pattern).to_str().unwrap_or("") };
+    let options = SearchOptions {
+        regex: options & PIER_SEARCH_REGEX != 0,
+        case_insensitive: options & PIER_SEARCH_CASE_INSENSITIVE != 0,
+    };
+    let Ok(regex) = terminal_search::build_pattern(pattern_str, options) else {
+        return -1;
+    };
+    let session = unsafe { &mut *handle };
+    session.lock().triggers.add(regex) as i64
+}
+
+/// Remove a trigger registered with pier_terminal_add_trigger.
+/// Returns 0 on success, -1 if the handle or id is invalid.
+#[no_mangle]
+pub extern "C" fn pier_terminal_remove_trigger(handle: PierTerminalHandle, trigger_id: u32) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    if session.lock().triggers.remove(trigger_id) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Set the callback receiving trigger matches, or clear it with null.
+/// The callback runs on the session's reader thread, with
+/// `user_data` and a JSON event `{trigger_id, text, start_row, start_col,
+/// end_row, end_col}` that is only valid during the call.
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_set_trigger_callback(
+    handle: PierTerminalHandle,
+    callback: Option<extern "C" fn(user_data: *mut c_void, event_json: *const c_char)>,
+    user_data: *mut c_void,
+) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    session.set_trigger_callback(callback, user_data);
+    0
+}
+
+/// Set the callback notified when new PTY output has been parsed, or clear
+/// it with null. It runs on the session's reader thread with `user_data`,
+/// once per parsed chunk and once more when the PTY closes; fetch the new
+/// state with pier_terminal_take_updates (dispatching to the UI thread as
+/// needed) instead of polling.
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_set_output_callback(
+    handle: PierTerminalHandle,
+    callback: Option<extern "C" fn(user_data: *mut c_void)>,
+    user_data: *mut c_void,
+) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    session.set_output_callback(callback, user_data);
+    0
+}
+
+/// Send signal `sig` (SIGINT, SIGTERM, SIGHUP, SIGTSTP, SIGCONT, ...) to the
+/// terminal's foreground process group, i.e. the running job or the shell.
+/// Returns 0 on success, -1 on invalid handle or signal, or if the process
+/// has exited.
+#[no_mangle]
+pub extern "C" fn pier_terminal_signal(handle: PierTerminalHandle, sig: i32) -> i32 {
+    if handle.is_null() || sig <= 0 {
+        return -1;
+    }
+    let session = unsafe { &*handle };
+    match session.signal(sig) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Pause (`paused = true`) or resume reading the session's output. While
+/// paused the program blocks once the kernel buffer fills, which keeps a
+/// runaway command from flooding the renderer.
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_set_output_paused(handle: PierTerminalHandle, paused: bool) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    session.set_output_paused(paused);
+    0
+}
+
+/// Limit output consumed to `bytes_per_second`; 0 removes the limit.
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_set_output_throttle(handle: PierTerminalHandle, bytes_per_second: u64) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    session.set_output_throttle(Some(bytes_per_second as usize));
+    0
+}
+
+/// Whether the program turned off echo while keeping line editing, as
+/// password prompts (`sudo`, `ssh`, `read -s`) do, so the UI can show a
+/// secure-input indicator. Returns false on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_is_password_input(handle: PierTerminalHandle) -> bool {
+    if handle.is_null() {
+        return false;
+    }
+    let session = unsafe { &*handle };
+    session.is_password_input()
+}
+
+/// Whether output is held back, either paused with
+/// pier_terminal_set_output_paused or stopped by the user's XOFF (Ctrl-S).
+/// Returns false on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_is_output_stopped(handle: PierTerminalHandle) -> bool {
+    if handle.is_null() {
+        return false;
+    }
+    let session = unsafe { &*handle };
+    session.is_output_stopped()
+}
+
+/// Add (`registered = true`) or remove the session's utmpx login record so
+/// `who`, `w` and `last` list it. Sessions from pier_terminal_create are
+/// registered by default; command sessions are not. Registration is best
+/// effort and silently skipped without permission to write the database.
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_set_login_record(handle: PierTerminalHandle, registered: bool) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    session.set_login_record(registered);
+    0
+}
+
+/// Suspend the foreground job with SIGSTOP until pier_terminal_resume, e.g.
+/// to pause an output-heavy command. Returns 0 on success, -1 on invalid
+/// handle or if the process has exited.
+#[no_mangle]
+pub extern "C" fn pier_terminal_suspend(handle: PierTerminalHandle) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &*handle };
+    match session.suspend() {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Resume the job stopped by pier_terminal_suspend with SIGCONT.
+/// Returns 0 on success (or if nothing was suspended), -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_terminal_resume(handle: PierTerminalHandle) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &*handle };
+    match session.resume() {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Whether a job suspended with pier_terminal_suspend is waiting to be
+/// resumed. Returns false on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_is_suspended(handle: PierTerminalHandle) -> bool {
+    if handle.is_null() {
+        return false;
+    }
+    let session = unsafe { &*handle };
+    session.is_suspended()
+}
+
+/// Set the callback notified when the shell or command exits, or clear it
+/// with null. It runs once on the session's reader thread with `user_data`,
+/// the exit code (-1 if killed by a signal) and the signal (0 if it exited).
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_set_exit_callback(
+    handle: PierTerminalHandle,
+    callback: Option<extern "C" fn(user_data: *mut c_void, exit_code: i32, signal: i32)>,
+    user_data: *mut c_void,
+) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    session.set_exit_callback(callback, user_data);
+    0
+}
+
+/// Whether the shell or command behind the session is still running.
+/// Returns false for a null handle.
+#[no_mangle]
+pub extern "C" fn pier_terminal_is_alive(handle: PierTerminalHandle) -> bool {
+    if handle.is_null() {
+        return false;
+    }
+    let session = unsafe { &*handle };
+    session.is_alive()
+}
+
+/// Get how the child process ended as JSON, `{"kind":"exited","code":N}` or
+/// `{"kind":"signaled","signal":N}`; null while it is running.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_terminal_exit_status(handle: PierTerminalHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+    let Some(status) = session.exit_status() else {
+        return std::ptr::null_mut();
+    };
+
+    match serde_json::to_string(&status) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+// ═══════════════════════════════════════════════════════════
+// Session Playback FFI
+// ═══════════════════════════════════════════════════════════
+
+/// Opaque pointer to a cast recording player.
+pub type PierPlayerHandle = *mut CastPlayer;
+
+/// Open an asciinema v2 `.cast` recording for playback.
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn pier_player_open(path: *const c_char) -> PierPlayerHandle {
+    if path.is_null() {
+        return std::ptr::null_mut();
+    }
+    let path_str = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") };
+
+    match CastPlayer::open(path_str) {
+        Ok(player) => Box::into_raw(Box::new(player)),
+        Err(e) => {
+            log::error!("Failed to open recording {}: {}", path_str, e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Destroy a player.
+#[no_mangle]
+pub extern "C" fn pier_player_destroy(handle: PierPlayerHandle) {
+    if !handle.is_null() {
+        unsafe {
+            drop(Box::from_raw(handle));
+        }
+    }
+}
+
+/// Advance playback by `elapsed` wall-clock seconds.
+/// Returns 1 if the screen changed, 0 if not, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_player_advance(handle: PierPlayerHandle, elapsed: f64) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let player = unsafe { &mut *handle };
+    player.advance(elapsed) as i32
+}
+
+/// Jump to `seconds` into the recording.
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_player_seek(handle: PierPlayerHandle, seconds: f64) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let player = unsafe { &mut *handle };
+    player.seek(seconds);
+    0
+}
+
+/// Pause or resume playback.
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_player_set_paused(handle: PierPlayerHandle, paused: bool) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let player = unsafe { &mut *handle };
+    player.set_paused(paused);
+    0
+}
+
+/// Set the playback speed multiplier (1.0 = original). Non-positive values are ignored.
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_player_set_speed(handle: PierPlayerHandle, speed: f64) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let player = unsafe { &mut *handle };
+    player.set_speed(speed);
+    0
+}
+
+/// Current playback position in seconds, or -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_player_position(handle: PierPlayerHandle) -> f64 {
+    if handle.is_null() {
+        return -1.0;
+    }
+    let player = unsafe { &*handle };
+    player.position()
+}
+
+/// Total recording length in seconds, or -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_player_duration(handle: PierPlayerHandle) -> f64 {
+    if handle.is_null() {
+        return -1.0;
+    }
+    let player = unsafe { &*handle };
+    player.duration()
+}
+
+/// Serialize the replayed screen as JSON, in the same format as
+/// pier_terminal_snapshot.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_player_snapshot(handle: PierPlayerHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let player = unsafe { &*handle };
+
+    match serde_json::to_string(&player.emulator().snapshot()) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+// ═══════════════════════════════════════════════════════════
+// File Search FFI
+// ═══════════════════════════════════════════════════════════
+
+/// Search result returned via FFI as a JSON string.
+/// Caller must free the returned string with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_search_files(
+    root: *const c_char,
+    pattern: *const c_char,
+    max_results: usize,
+) -> *mut c_char {
+    if root.is_null() || pattern.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let root_str = unsafe { CStr::from_ptr(root).to_str().unwrap_or("") };
+    let pattern_str = unsafe { CStr::from_ptr(pattern).to_str().unwrap_or("") };
+
+    let results = search::search_files(root_str, pattern_str, max_results);
+
+    match serde_json::to_string(&results) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// List directory contents. Returns JSON string.
+/// Caller must free the returned string with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_list_directory(path: *const c_char) -> *mut c_char {
+    if path.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let path_str = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") };
+
+    match search::list_directory(path_str) {
+        Ok(entries) => {
+            match serde_json::to_string(&entries) {
+                Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+                Err(_) => std::ptr::null_mut(),
+            }
+        }
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+// ═══════════════════════════════════════════════════════════
+// SSH FFI
+// ═══════════════════════════════════════════════════════════
+
+/// Opaque pointer to an SSH session.
+pub type PierSshHandle = *mut SshSession;
+
+/// Connect to an SSH server.
+/// auth_type: 0 = password, 1 = key file
+/// credential: password string (auth_type=0) or key file path (auth_type=1)
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn pier_ssh_connect(
+    host: *const c_char,
+    port: u16,
+    username: *const c_char,
+    auth_type: i32,
+    credential: *const c_char,
+) -> PierSshHandle {
+    if credential.is_null() {
+        return std::ptr::null_mut();
+    }
+    let config = ssh_config(host, port, username, auth_type, credential, std::ptr::null(), false);
+    ssh_connect(config, None, false)
+}
+
+/// Connect to an SSH server that may ask questions while authenticating
+/// (OTP codes, Duo, PAM prompts).
+/// auth_type: 0 = password, 1 = key file, 2 = keyboard-interactive only
+/// (credential may be null). With a password or key, a second factor the
+/// server asks for afterwards goes through the callback too.
+///
+/// `prompt_callback` runs on a background thread and may block while the
+/// user answers. It receives JSON
+/// `{"name": "...", "instructions": "...", "prompts": [{"prompt": "...", "echo": false}]}`
+/// and returns a JSON array with one answer string per prompt, allocated
+/// with malloc (e.g. strdup) for Pier to free, or null to cancel.
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn pier_ssh_connect_interactive(
+    host: *const c_char,
+    port: u16,
+    username: *const c_char,
+    auth_type: i32,
+    credential: *const c_char,
+    prompt_callback: Option<extern "C" fn(user_data: *mut c_void, prompts_json: *const c_char) -> *mut c_char>,
+    user_data: *mut c_void,
+) -> PierSshHandle {
+    let Some(callback) = prompt_callback else {
+        return std::ptr::null_mut();
+    };
+    if credential.is_null() && auth_type != 2 {
+        return std::ptr::null_mut();
+    }
+    let handler = prompt_handler(callback, user_data);
+    let config = ssh_config(host, port, username, auth_type, credential, std::ptr::null(), true);
+    ssh_connect(config, Some(handler), false)
+}
+
+/// Connect to an SSH server through jump hosts. `proxy_jump` is an
+/// OpenSSH ProxyJump value (`[user@]host[:port]`, comma separated, first
+/// hop first); each hop is looked up in ~/.ssh/config for its host name,
+/// user, port and key. Other arguments are as for
+/// pier_ssh_connect_interactive, except that `prompt_callback` may be null.
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn pier_ssh_connect_via(
+    host: *const c_char,
+    port: u16,
+    username: *const c_char,
+    auth_type: i32,
+    credential: *const c_char,
+    proxy_jump: *const c_char,
+    prompt_callback: Option<extern "C" fn(user_data: *mut c_void, prompts_json: *const c_char) -> *mut c_char>,
+    user_data: *mut c_void,
+) -> PierSshHandle {
+    if proxy_jump.is_null() || (credential.is_null() && auth_type != 2) {
+        return std::ptr::null_mut();
+    }
+    let handler = prompt_callback.map(|callback| prompt_handler(callback, user_data));
+    let config = ssh_config(host, port, username, auth_type, credential, proxy_jump, handler.is_some());
+    ssh_connect(config, handler, false)
+}
+
+/// Connect to an SSH server, sharing the connection with other pooled
+/// sessions for the same host, port and user: the first one connects and
+/// authenticates, later ones open their channels on the same transport.
+/// Disconnecting a pooled session only releases it; the connection closes
+/// once no session has used it for the pool's idle timeout.
+/// `proxy_jump` and `prompt_callback` may be null; other arguments are as
+/// for pier_ssh_connect_via, and only matter when a connection is made.
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn pier_ssh_connect_pooled(
+    host: *const c_char,
+    port: u16,
+    username: *const c_char,
+    auth_type: i32,
+    credential: *const c_char,
+    proxy_jump: *const c_char,
+    prompt_callback: Option<extern "C" fn(user_data: *mut c_void, prompts_json: *const c_char) -> *mut c_char>,
+    user_data: *mut c_void,
+) -> PierSshHandle {
+    if credential.is_null() && auth_type != 2 {
+        return std::ptr::null_mut();
+    }
+    let handler = prompt_callback.map(|callback| prompt_handler(callback, user_data));
+    let config = ssh_config(host, port, username, auth_type, credential, proxy_jump, handler.is_some());
+    ssh_connect(config, handler, true)
+}
+
+/// Set how long a pooled connection stays open after its last session is
+/// disconnected (default 60 seconds).
+#[no_mangle]
+pub extern "C" fn pier_ssh_pool_set_idle_timeout(seconds: u32) {
+    pool::set_idle_timeout(std::time::Duration::from_secs(seconds as u64));
+}
+
+/// List pooled connections as a JSON array of
+/// `{"host", "port", "username", "sessions"}`; `sessions` is 0 while a
+/// connection idles.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_pool_list() -> *mut c_char {
+    match serde_json::to_string(&pool::connections()) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Timeouts for connections made from now on.
+static DEFAULT_TIMEOUTS: std::sync::Mutex<Timeouts> = std::sync::Mutex::new(Timeouts::DEFAULT);
+
+/// Set the time limits for connections made from now on (and their
+/// reconnects): connecting to each hop, and commands run with
+/// pier_ssh_exec and pier_ssh_exec_with_input. 0 means no limit. The
+/// defaults are 10 and 60 seconds.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_default_timeouts(connect_timeout_ms: u64, exec_timeout_ms: u64) {
+    *DEFAULT_TIMEOUTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) =
+        Timeouts { connect_ms: connect_timeout_ms, exec_ms: exec_timeout_ms };
+}
+
+/// Whether connections made from now on are compressed.
+static DEFAULT_COMPRESSION: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Compress connections made from now on with zlib (zlib@openssh.com)
+/// when the server supports it, like OpenSSH's Compression option. Helps
+/// SFTP transfers and verbose output over slow links; off by default.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_default_compression(enabled: bool) {
+    DEFAULT_COMPRESSION.store(enabled, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Algorithm preferences for connections made from now on.
+static DEFAULT_ALGORITHMS: std::sync::Mutex<AlgorithmPreferences> =
+    std::sync::Mutex::new(AlgorithmPreferences { ciphers: None, kex: None, macs: None });
+
+/// Set the ciphers, key exchange algorithms and MACs offered to the hosts
+/// connected to from now on (not their jump hosts), in ssh_config syntax:
+/// e.g. "+diffie-hellman-group1-sha1" to also allow it for a legacy
+/// appliance, or "aes256-gcm@openssh.com" to allow only it. Null keeps the
+/// defaults. A connect fails if no algorithm of a list is supported.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_default_algorithms(
+    ciphers: *const c_char,
+    kex_algorithms: *const c_char,
+    macs: *const c_char,
+) {
+    let list = |spec: *const c_char| {
+        (!spec.is_null()).then(|| unsafe { CStr::from_ptr(spec).to_str().unwrap_or("") }.to_string())
+    };
+    *DEFAULT_ALGORITHMS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) =
+        AlgorithmPreferences { ciphers: list(ciphers), kex: list(kex_algorithms), macs: list(macs) };
+}
+
+/// Rekey limits for connections made from now on.
+static DEFAULT_REKEY_LIMITS: std::sync::Mutex<RekeyLimits> =
+    std::sync::Mutex::new(RekeyLimits { max_bytes: 0, interval_secs: 0 });
+
+/// Renew the session keys of connections made from now on after
+/// `max_bytes` sent or received (at most 1 GiB) or `interval_secs`, like
+/// OpenSSH's RekeyLimit. 0 keeps the default of 1 GiB or one hour.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_default_rekey_limit(max_bytes: u64, interval_secs: u64) {
+    *DEFAULT_REKEY_LIMITS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) =
+        RekeyLimits { max_bytes, interval_secs };
+}
+
+/// Proxy for connections made from now on.
+static DEFAULT_PROXY: std::sync::Mutex<Option<ProxyConfig>> = std::sync::Mutex::new(None);
+
+/// Open the TCP connections of connects made from now on through a proxy
+/// (the first jump host's, with jump hosts): `kind` 1 for SOCKS5, 2 for
+/// HTTP CONNECT, 0 or a null host for direct connections. `username` and
+/// `password` may be null for a proxy without authentication.
+/// Returns 0, or -1 for an unknown kind.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_default_proxy(
+    kind: i32,
+    host: *const c_char,
+    port: u16,
+    username: *const c_char,
+    password: *const c_char,
+) -> i32 {
+    let text = |value: *const c_char| {
+        (!value.is_null()).then(|| unsafe { CStr::from_ptr(value).to_str().unwrap_or("") }.to_string())
+    };
+    let kind = match kind {
+        0 => None,
+        1 => Some(ProxyKind::Socks5),
+        2 => Some(ProxyKind::Http),
+        _ => return -1,
+    };
+    let proxy = kind.zip(text(host)).map(|(kind, host)| ProxyConfig {
+        kind,
+        host,
+        port,
+        username: text(username),
+        password: text(password),
+    });
+    *DEFAULT_PROXY.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = proxy;
+    0
+}
+
+/// Wrap an FFI prompt callback as a PromptHandler: prompts go out as JSON,
+/// answers come back as a malloc'd JSON array (null to cancel).
+fn prompt_handler(
+    callback: extern "C" fn(user_data: *mut c_void, prompts_json: *const c_char) -> *mut c_char,
+    user_data: *mut c_void,
+) -> PromptHandler {
+    let user_data = SendPtr(user_data);
+    Arc::new(move |prompts: &AuthPrompts| {
+        let json = CString::new(serde_json::to_string(prompts).ok()?).ok()?;
+        let reply = callback(user_data.get(), json.as_ptr());
+        if reply.is_null() {
+            return None;
+        }
+        let answers = unsafe { CStr::from_ptr(reply) }
+            .to_str()
+            .ok()
+            .and_then(|json| serde_json::from_str::<Vec<String>>(json).ok());
+        unsafe {
+            // Answers are secrets: wipe them before handing the memory back
+            std::ptr::write_bytes(reply, 0, libc::strlen(reply));
+            libc::free(reply as *mut c_void);
+        }
+        answers
+    })
+}
+
+/// Connection settings from the connect arguments; `None` if they're
+/// invalid. Keyboard-interactive auth (2) needs `interactive`.
+fn ssh_config(
+    host: *const c_char,
+    port: u16,
+    username: *const c_char,
+    auth_type: i32,
+    credential: *const c_char,
+    proxy_jump: *const c_char,
+    interactive: bool,
+) -> Option<SshConfig> {
+    if host.is_null() || username.is_null() {
+        return None;
+    }
+
+    let host_str = unsafe { CStr::from_ptr(host).to_str().unwrap_or("") };
+    let username_str = unsafe { CStr::from_ptr(username).to_str().unwrap_or("") };
+    let credential_str = if credential.is_null() {
+        ""
+    } else {
+        unsafe { CStr::from_ptr(credential).to_str().unwrap_or("") }
+    };
+
+    let auth = match auth_type {
+        0 => SshAuth::Password(credential_str.to_string()),
+        1 => SshAuth::KeyFile {
+            path: credential_str.to_string(),
+            passphrase: None,
+        },
+        2 if interactive => SshAuth::KeyboardInteractive,
+        _ => {
+            log::error!("Unknown SSH auth type: {}", auth_type);
+            return None;
+        }
+    };
+
+    let timeouts = *DEFAULT_TIMEOUTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut jump_hosts = if proxy_jump.is_null() {
+        Vec::new()
+    } else {
+        let spec = unsafe { CStr::from_ptr(proxy_jump).to_str().unwrap_or("") };
+        SshConfigFile::load().jump_hosts(spec, username_str)
+    };
+    let compression = DEFAULT_COMPRESSION.load(std::sync::atomic::Ordering::Relaxed);
+    for jump in &mut jump_hosts {
+        jump.timeouts = timeouts;
+        jump.compression |= compression;
+    }
+    Some(SshConfig {
+        host: host_str.to_string(),
+        port,
+        username: username_str.to_string(),
+        auth,
+        jump_hosts,
+        keepalive: KeepalivePolicy::default(),
+        reconnect: None,
+        timeouts,
+        set_env: Vec::new(),
+        send_env: Vec::new(),
+        compression,
+        algorithms: DEFAULT_ALGORITHMS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone(),
+        rekey: *DEFAULT_REKEY_LIMITS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()),
+        proxy: DEFAULT_PROXY.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone(),
+    })
+}
+
+// ═══════════════════════════════════════════════════════════
+// SSH Cancellation FFI
+// ═══════════════════════════════════════════════════════════
+
+/// Cancel signals of the operations begun and not yet ended.
+static OPERATIONS: std::sync::Mutex<Option<std::collections::HashMap<u64, tokio::sync::watch::Sender<bool>>>> =
+    std::sync::Mutex::new(None);
+static NEXT_OPERATION: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+
+thread_local! {
+    /// The operation the calling thread's SSH calls belong to, 0 for none.
+    static CURRENT_OPERATION: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+}
+
+/// Begin a cancellable operation on the calling thread: until
+/// pier_ssh_operation_end, the connect, exec, service detection, remote
+/// search and archive download calls it makes stop as soon as pier_ssh_cancel is called with the
+/// returned id, from any thread, failing as they would on a timeout.
+#[no_mangle]
+pub extern "C" fn pier_ssh_operation_begin() -> u64 {
+    let id = NEXT_OPERATION.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let (cancel, _) = tokio::sync::watch::channel(false);
+    OPERATIONS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get_or_insert_with(Default::default)
+        .insert(id, cancel);
+    CURRENT_OPERATION.with(|current| current.set(id));
+    id
+}
+
+/// End the calling thread's operation; later calls aren't cancellable.
+#[no_mangle]
+pub extern "C" fn pier_ssh_operation_end() {
+    let id = CURRENT_OPERATION.with(|current| current.replace(0));
+    if let Some(operations) = OPERATIONS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).as_mut() {
+        operations.remove(&id);
+    }
+}
+
+/// Cancel operation `op_id`: its running call returns now, and the ones
+/// after it fail right away. Returns 0, or -1 if there is no such
+/// operation (e.g. it already ended).
+#[no_mangle]
+pub extern "C" fn pier_ssh_cancel(op_id: u64) -> i32 {
+    let operations = OPERATIONS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    match operations.as_ref().and_then(|operations| operations.get(&op_id)) {
+        Some(cancel) => {
+            cancel.send_replace(true);
+            0
+        }
+        None => -1,
+    }
+}
+
+/// `future`, unless the calling thread's operation is cancelled first, in
+/// which case `None`. Must be called on the FFI caller's thread, before
+/// handing the result to `ffi_block_on`.
+fn until_cancelled<F: std::future::Future>(future: F) -> impl std::future::Future<Output = Option<F::Output>> {
+    let id = CURRENT_OPERATION.with(std::cell::Cell::get);
+    let cancelled = OPERATIONS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .as_ref()
+        .and_then(|operations| operations.get(&id))
+        .map(tokio::sync::watch::Sender::subscribe);
+    async move {
+        let Some(mut cancelled) = cancelled else {
+            return Some(future.await);
+        };
+        tokio::select! {
+            output = future => Some(output),
+            Ok(_) = cancelled.wait_for(|cancelled| *cancelled) => None,
+        }
+    }
+}
+
+thread_local! {
+    /// The outcome of the calling thread's last connect, as JSON.
+    static LAST_CONNECT: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Connect as `config` says, on a pooled connection if `pooled`.
+fn ssh_connect(config: Option<SshConfig>, prompt_handler: Option<PromptHandler>, pooled: bool) -> PierSshHandle {
+    let Some(config) = config else {
+        return std::ptr::null_mut();
+    };
+    let (host, port) = (config.host.clone(), config.port);
+
+    // Use ffi_block_on to safely run async connect on a fresh thread
+    let (result, login) = ffi_block_on(until_cancelled(async move {
+        if pooled {
+            // A failed pooled connect doesn't keep its session around
+            let result = pool::acquire(config, prompt_handler).await;
+            let login = result.as_ref().map(|session| session.login_info().clone()).unwrap_or_default();
+            return (result, login);
+        }
+        let mut session = SshSession::new(config);
+        session.set_prompt_handler(prompt_handler);
+        let result = session.connect().await;
+        let login = session.login_info().clone();
+        (result.map(|()| session), login)
+    }))
+    .unwrap_or_else(|| (Err(anyhow::anyhow!("Cancelled")), Default::default()));
+    let outcome = serde_json::json!({
+        "connected": result.is_ok(),
+        "error": result.as_ref().err().map(|e| e.to_string()),
+        "banner": login.banner,
+        "remaining_methods": login.remaining_methods,
+        "partial_success": login.partial_success,
+        "password_changed": login.password_changed,
+    });
+    LAST_CONNECT.with(|last| *last.borrow_mut() = Some(outcome.to_string()));
+
+    match result {
+        Ok(connected_session) => {
+            log::info!("SSH connected to {}:{}", host, port);
+            Box::into_raw(Box::new(connected_session))
+        }
+        Err(e) => {
+            log::error!("SSH connect failed: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// The outcome of the last pier_ssh_connect* call made on this thread,
+/// successful or not: `{"connected", "error", "banner", "remaining_methods",
+/// "partial_success", "password_changed"}`. `banner` is the servers'
+/// pre-login message (legal notices some environments require showing),
+/// or null; after a failed login, `remaining_methods` lists the methods
+/// the server would still accept and `partial_success` tells whether it
+/// wanted another one on top of an accepted method. `password_changed`
+/// tells that an expired password was replaced (see
+/// pier_ssh_set_password_change_callback), so the app can store the new
+/// one. Returns null if this thread hasn't connected.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_last_connect_result() -> *mut c_char {
+    match LAST_CONNECT.with(|last| last.borrow().clone()) {
+        Some(json) => CString::new(json).unwrap_or_default().into_raw(),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Hosts configured in ~/.ssh/config (aliases without wildcards), with
+/// their resolved settings. Returns a JSON array of
+/// `{"alias", "host_name", "user", "port", "identity_files", "proxy_jump", "forward_agent",
+/// "server_alive_interval", "server_alive_count_max", "send_env", "set_env", "compression", "ciphers",
+/// "kex_algorithms", "macs", "rekey_limit"}`;
+/// `set_env` is an array of `[name, value]` pairs.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_config_hosts() -> *mut c_char {
+    let config = SshConfigFile::load();
+    let hosts: Vec<_> = config.hosts().iter().map(|alias| config.resolve(alias)).collect();
+
+    match serde_json::to_string(&hosts) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Resolve what ~/.ssh/config says for `alias`, the way ssh(1) would.
+/// Returns a JSON object as for pier_ssh_config_hosts; `host_name` is the
+/// alias itself when nothing is configured.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_config_resolve(alias: *const c_char) -> *mut c_char {
+    if alias.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let alias_str = unsafe { CStr::from_ptr(alias).to_str().unwrap_or("") };
+    let host = SshConfigFile::load().resolve(alias_str);
+
+    match serde_json::to_string(&host) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Check and save host keys in the file at `path` instead of
+/// ~/.ssh/known_hosts, for an app-managed trust store kept apart from
+/// OpenSSH's; null goes back to ~/.ssh/known_hosts. With `hash_hosts`,
+/// keys saved from now on get hashed host names (like OpenSSH's
+/// HashKnownHosts), so the file doesn't reveal the hosts connected to.
+/// Hashed entries are always read. Applies to connections made from now
+/// on and to the pier_ssh_known_hosts_* functions.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_known_hosts(path: *const c_char, hash_hosts: bool) {
+    let path = if path.is_null() {
+        None
+    } else {
+        let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") };
+        (!path.is_empty()).then(|| std::path::PathBuf::from(path))
+    };
+    known_hosts::set_path(path);
+    known_hosts::set_hash_hosts(hash_hosts);
+}
+
+/// Set the callback deciding about host keys that aren't in
+/// the known_hosts file or differ from the recorded one, for all connections
+/// made afterwards; null clears it, and such hosts are then refused.
+///
+/// The callback runs on a background thread and may block while the user
+/// decides. It receives JSON
+/// `{"host", "port", "key_type", "fingerprint": "SHA256:...", "status": "new" | "changed"}`
+/// and returns 0 to reject, 1 to accept for this connection only, or 2 to
+/// accept and record the key (replacing the old one if it changed).
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_host_key_callback(
+    callback: Option<extern "C" fn(user_data: *mut c_void, key_json: *const c_char) -> i32>,
+    user_data: *mut c_void,
+) {
+    let user_data = SendPtr(user_data);
+    known_hosts::set_handler(callback.map(|callback| {
+        Arc::new(move |prompt: &HostKeyPrompt| {
+            let Some(json) = serde_json::to_string(prompt).ok().and_then(|json| CString::new(json).ok()) else {
+                return HostKeyDecision::Reject;
+            };
+            match callback(user_data.get(), json.as_ptr()) {
+                1 => HostKeyDecision::AcceptOnce,
+                2 => HostKeyDecision::AcceptAndSave,
+                _ => HostKeyDecision::Reject,
+            }
+        }) as HostKeyHandler
+    }));
+}
+
+/// Set the callback asked for the old and new passwords when a server
+/// requires changing an expired password while logging in, for all
+/// connections made afterwards; null clears it, and the server's prompts
+/// then go to the connection's prompt callback, if any. With it set, a
+/// password login the server refuses is retried with keyboard-interactive
+/// authentication, which is where PAM servers ask for the change.
+///
+/// The callback runs on a background thread and may block while the user
+/// answers. It receives JSON `{"host", "port", "username", "message"}`,
+/// `message` being the server's explanation (also why it rejected the
+/// last new password, when it asks again), and returns JSON
+/// `{"old_password": "...", "new_password": "..."}` allocated with malloc
+/// for Pier to free, or null to cancel the login.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_password_change_callback(
+    callback: Option<extern "C" fn(user_data: *mut c_void, request_json: *const c_char) -> *mut c_char>,
+    user_data: *mut c_void,
+) {
+    let user_data = SendPtr(user_data);
+    password_change::set_handler(callback.map(|callback| {
+        Arc::new(move |request: &PasswordChangeRequest| {
+            let json = CString::new(serde_json::to_string(request).ok()?).ok()?;
+            let reply = callback(user_data.get(), json.as_ptr());
+            if reply.is_null() {
+                return None;
+            }
+            let answer = unsafe { CStr::from_ptr(reply) }
+                .to_str()
+                .ok()
+                .and_then(|json| serde_json::from_str::<NewPassword>(json).ok());
+            unsafe {
+                // Passwords: wipe them before handing the memory back
+                std::ptr::write_bytes(reply, 0, libc::strlen(reply));
+                libc::free(reply as *mut c_void);
+            }
+            answer
+        }) as PasswordChangeHandler
+    }));
+}
+
+/// Set the callback that signs with security keys (FIDO2, `ed25519-sk` and
+/// `ecdsa-sk` key files), for all connections made afterwards; null
+/// clears it, and such keys then fail to log in.
+///
+/// The callback runs on a background thread and may block until the user
+/// touches the key: it should show a "touch your security key" prompt,
+/// then get an assertion from the authenticator. It receives JSON
+/// `{"host", "port", "username", "algorithm", "application", "key_handle",
+/// "flags", "challenge"}`: the relying party id, the credential id
+/// (base64), the key's flags (1: touch required, 4: PIN required) and the
+/// client data hash to sign (base64). It returns JSON
+/// `{"signature": base64, "flags": N, "counter": N}` from the assertion
+/// (the signature as the authenticator gives it, DER for ECDSA) allocated
+/// with malloc for Pier to free, or null to cancel the login.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_security_key_callback(
+    callback: Option<extern "C" fn(user_data: *mut c_void, request_json: *const c_char) -> *mut c_char>,
+    user_data: *mut c_void,
+) {
+    let user_data = SendPtr(user_data);
+    security_key::set_handler(callback.map(|callback| {
+        Arc::new(move |request: &SecurityKeyRequest| {
+            let json = CString::new(serde_json::to_string(request).ok()?).ok()?;
+            let reply = callback(user_data.get(), json.as_ptr());
+            if reply.is_null() {
+                return None;
+            }
+            let signature = unsafe { CStr::from_ptr(reply) }
+                .to_str()
+                .ok()
+                .and_then(|json| serde_json::from_str::<SecurityKeySignature>(json).ok());
+            unsafe { libc::free(reply as *mut c_void) };
+            signature
+        }) as SecurityKeyHandler
+    }));
+}
+
+/// List the known_hosts file (see pier_ssh_set_known_hosts). Returns a JSON array of
+/// `{"line", "hosts": [...], "hashed", "marker", "key_type", "fingerprint"}`;
+/// hashed entries have no readable host names.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_known_hosts_list() -> *mut c_char {
+    let Some(path) = known_hosts::path() else {
+        return std::ptr::null_mut();
+    };
+    match known_hosts::list(&path).map(|entries| serde_json::to_string(&entries)) {
+        Ok(Ok(json)) => CString::new(json).unwrap_or_default().into_raw(),
+        Ok(Err(_)) => std::ptr::null_mut(),
+        Err(e) => {
+            log::error!("Reading known_hosts failed: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Forget the keys recorded for `host:port` in the known_hosts file,
+/// hashed entries included.
+/// Returns how many entries were removed, or -1 on error.
+#[no_mangle]
+pub extern "C" fn pier_ssh_known_hosts_remove(host: *const c_char, port: u16) -> i32 {
+    if host.is_null() {
+        return -1;
+    }
+    let host_str = unsafe { CStr::from_ptr(host).to_str().unwrap_or("") };
+    let Some(path) = known_hosts::path() else {
+        return -1;
+    };
+    match known_hosts::remove(&path, host_str, port) {
+        Ok(removed) => removed as i32,
+        Err(e) => {
+            log::error!("Removing known host failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Record `public_key` (OpenSSH format, `ssh-ed25519 AAAA... [comment]`)
+/// as the key of `host:port` in the known_hosts file, replacing any keys
+/// recorded for it. The host name is hashed if set up so.
+/// Returns 0 on success, -1 on error.
+#[no_mangle]
+pub extern "C" fn pier_ssh_known_hosts_update(host: *const c_char, port: u16, public_key: *const c_char) -> i32 {
+    if host.is_null() || public_key.is_null() {
+        return -1;
+    }
+    let host_str = unsafe { CStr::from_ptr(host).to_str().unwrap_or("") };
+    let key_str = unsafe { CStr::from_ptr(public_key).to_str().unwrap_or("") };
+    let Some(path) = known_hosts::path() else {
+        return -1;
+    };
+    let result = russh::keys::PublicKey::from_openssh(key_str.trim())
+        .map_err(anyhow::Error::from)
+        .and_then(|key| known_hosts::update(&path, host_str, port, &key, known_hosts::hash_hosts()));
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("Updating known host failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Disconnect an SSH session and free the handle.
+#[no_mangle]
+pub extern "C" fn pier_ssh_disconnect(handle: PierSshHandle) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+
+    let mut session = unsafe { Box::from_raw(handle) };
+    match ffi_block_on(async move { session.disconnect().await }) {
+        Ok(()) => {
+            log::info!("SSH disconnected");
+            0
+        }
+        Err(e) => {
+            log::error!("SSH disconnect error: {}", e);
+            -1
+        }
+    }
+}
+
+/// Check if SSH session is connected, as of the last keepalive; a closed
+/// or dead connection, or one being re-established, is not.
+/// Returns 1 if connected, 0 if not, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_ssh_is_connected(handle: PierSshHandle) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    // Safety: we only read, handle is valid
+    let session = unsafe { &*handle };
+    if session.is_connected() { 1 } else { 0 }
+}
+
+/// Connection state: 0 = disconnected, 1 = connecting (or reconnecting),
+/// 2 = connected, 3 = degraded (keepalives going unanswered).
+/// Returns -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_ssh_state(handle: PierSshHandle) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &*handle };
+    match session.state() {
+        ConnectionState::Disconnected => 0,
+        ConnectionState::Connecting => 1,
+        ConnectionState::Connected => 2,
+        ConnectionState::Degraded => 3,
+    }
+}
+
+/// Check that the server still answers, waiting at most `timeout_ms` for
+/// a keepalive reply. A failed probe also starts reconnecting if a
+/// reconnect policy is set.
+/// Returns 1 if alive, 0 if not, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_ssh_probe(handle: PierSshHandle, timeout_ms: u32) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session_ptr = SendPtr(handle);
+    let timeout = std::time::Duration::from_millis(timeout_ms as u64);
+    let alive = ffi_block_on(async move { session_ptr.as_ref().probe(timeout).await });
+    if alive { 1 } else { 0 }
+}
+
+/// Probe the server every `interval_secs` seconds (0 to only notice
+/// connections the OS reports closed), and treat the connection as dead
+/// after `count_max` unanswered probes in a row, like OpenSSH's
+/// ServerAliveInterval and ServerAliveCountMax.
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_keepalive(handle: PierSshHandle, interval_secs: u32, count_max: u32) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    session.set_keepalive(KeepalivePolicy { interval_secs, count_max });
+    0
+}
+
+/// Log the session's commands (with exit codes), shell channels and
+/// disconnect to the file at `path`, one timestamped line each, for an
+/// audit trail of what ran on the server. With `shell_io`, shells' raw
+/// input and output are logged too; that includes anything typed at a
+/// prompt, passwords included. Past `max_bytes` (0 for no limit) the file
+/// is rotated to `path.1`, keeping `keep_files` old ones. Applies to
+/// shells opened from now on. Null `path` stops logging.
+/// Returns 0 on success, -1 on invalid handle or if the file can't be
+/// opened.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_transcript(
+    handle: PierSshHandle,
+    path: *const c_char,
+    max_bytes: u64,
+    keep_files: u32,
+    shell_io: bool,
+) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let options = if path.is_null() {
+        None
+    } else {
+        let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") };
+        if path.is_empty() {
+            return -1;
+        }
+        Some(TranscriptOptions { path: path.into(), max_bytes, keep: keep_files, shell_io })
+    };
+    let session = unsafe { &mut *handle };
+    match session.set_transcript(options) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("SSH transcript failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Set environment variables for shells and commands started from now on,
+/// like OpenSSH's SetEnv and SendEnv: `set_env_json` is an object of
+/// names and values, `send_env_json` an array of names or `*`/`?`
+/// patterns of local variables to pass on. Null clears either. The server
+/// only applies the names its AcceptEnv allows.
+/// Returns 0 on success, -1 on invalid handle or JSON.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_env(
+    handle: PierSshHandle,
+    set_env_json: *const c_char,
+    send_env_json: *const c_char,
+) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let set_env: Option<std::collections::BTreeMap<String, String>> = if set_env_json.is_null() {
+        Some(Default::default())
+    } else {
+        let json = unsafe { CStr::from_ptr(set_env_json).to_str().unwrap_or("") };
+        serde_json::from_str(json).ok()
+    };
+    let send_env: Option<Vec<String>> = if send_env_json.is_null() {
+        Some(Vec::new())
+    } else {
+        let json = unsafe { CStr::from_ptr(send_env_json).to_str().unwrap_or("") };
+        serde_json::from_str(json).ok()
+    };
+    let (Some(set_env), Some(send_env)) = (set_env, send_env) else {
+        return -1;
+    };
+    let session = unsafe { &mut *handle };
+    session.set_environment(set_env.into_iter().collect(), send_env);
+    0
+}
+
+/// Reconnect automatically when the connection dies, re-authenticating
+/// with the same credentials (prompting again through the prompt callback
+/// if the server asks) and keeping port forwards. The delay before each
+/// attempt doubles from `initial_delay_ms` up to `max_delay_ms`; after
+/// `max_retries` failed attempts (0 = no limit) the session stays
+/// disconnected. `enabled` false turns reconnecting off.
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_reconnect_policy(
+    handle: PierSshHandle,
+    enabled: bool,
+    max_retries: u32,
+    initial_delay_ms: u64,
+    max_delay_ms: u64,
+) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    session.set_reconnect_policy(enabled.then_some(ReconnectPolicy { max_retries, initial_delay_ms, max_delay_ms }));
+    0
+}
+
+/// Set the callback notified of connection state changes, or clear it
+/// with null. It runs on a background thread with `user_data` and a JSON
+/// event `{state: "connecting" | "connected" | "degraded" | "disconnected",
+/// attempt, error}` that is only valid during the call.
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_state_callback(
+    handle: PierSshHandle,
+    callback: Option<extern "C" fn(user_data: *mut c_void, event_json: *const c_char)>,
+    user_data: *mut c_void,
+) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    let user_data = SendPtr(user_data);
+    session.set_state_handler(callback.map(|callback| {
+        Arc::new(move |event: &ConnectionEvent| {
+            let Ok(json) = serde_json::to_string(event) else { return };
+            if let Ok(json) = CString::new(json) {
+                callback(user_data.get(), json.as_ptr());
+            }
+        }) as StateHandler
+    }));
+    0
+}
+
+/// Set the callback told of the session's events, or clear it with null.
+/// It runs on a background thread with `user_data` and a JSON event that
+/// is only valid during the call, by `type`:
+/// `{"type": "connected", attempt}`, `{"type": "reconnecting", attempt,
+/// reason}`, `{"type": "auth_prompt", name, instructions, prompts}` (before
+/// the prompt callback is asked), `{"type": "keepalive_timeout"}`,
+/// `{"type": "disconnected", reason}` (reason null if closed by the app),
+/// `{"type": "forward_died", local_port, error}` (the listener failed and
+/// is being restarted), `{"type": "forward_restarted", local_port}`,
+/// `{"type": "forward_failing", local_port, error}` (a connection through
+/// the forward couldn't be served; told once until one is).
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_event_callback(
+    handle: PierSshHandle,
+    callback: Option<extern "C" fn(user_data: *mut c_void, event_json: *const c_char)>,
+    user_data: *mut c_void,
+) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    let user_data = SendPtr(user_data);
+    session.set_event_handler(callback.map(|callback| {
+        Arc::new(move |event: &SessionEvent| {
+            let Ok(json) = serde_json::to_string(event) else { return };
+            if let Ok(json) = CString::new(json) {
+                callback(user_data.get(), json.as_ptr());
+            }
+        }) as EventHandler
+    }));
+    0
+}
+
+/// Renew the session keys now instead of at the rekey limits.
+/// Returns 0 once requested, -1 on invalid handle or when not connected.
+#[no_mangle]
+pub extern "C" fn pier_ssh_rekey(handle: PierSshHandle) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session_ptr = SendPtr(handle);
+    match ffi_block_on(async move { session_ptr.as_ref().rekey().await }) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::warn!("SSH rekey failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// The connection's negotiated algorithms as JSON: `{"kex", "host_key",
+/// "cipher", "mac_client_to_server", "mac_server_to_client", "compression",
+/// "exchanges", "last_exchange"}`; `exchanges` counts key exchanges
+/// including the first, `last_exchange` is in Unix seconds. Returns null
+/// on invalid handle or before connecting.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_key_exchange_info(handle: PierSshHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+    match session.key_exchange_info().and_then(|info| serde_json::to_string(&info).ok()) {
+        Some(json) => CString::new(json).unwrap_or_default().into_raw(),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Traffic statistics as JSON: `{"bytes_sent", "bytes_received",
+/// "channels_open", "channels_opened", "rtt_ms", "tunnels": [{"local_port",
+/// "bytes_sent", "bytes_received", "connections", "active_connections",
+/// "listening", "last_connection_ms", "errors", "last_error", "restarts"}]}`.
+/// Connection bytes are what the link carries (encrypted, with SSH
+/// framing), tunnel bytes what the forwards relay; `rtt_ms` is the last
+/// keepalive or probe round trip, null until one is measured. A tunnel's
+/// `listening` is false while its failed listener is being restarted;
+/// `errors` counts connections it couldn't serve and listener failures.
+/// Sessions sharing a pooled connection see the same numbers.
+/// Returns null on invalid handle.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_stats(handle: PierSshHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+    match serde_json::to_string(&session.stats()) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Ping every `interval_secs` to measure round trips while keepalives are
+/// off (keepalive pings are measured anyway); 0 stops. Unanswered pings
+/// are recorded as lost but don't drop the connection.
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_ssh_set_latency_interval(handle: PierSshHandle, interval_secs: u32) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &mut *handle };
+    session.set_latency_interval((interval_secs > 0).then(|| std::time::Duration::from_secs(interval_secs as u64)));
+    0
+}
+
+/// The connection's last 120 pings (keepalive, latency and probe), for a
+/// latency chart, as JSON: `{"samples": [{"at_ms", "rtt_ms"}], "min_ms",
+/// "avg_ms", "max_ms", "loss"}`. Samples are oldest first, `at_ms` in Unix
+/// milliseconds and `rtt_ms` null for unanswered pings; `loss` is the
+/// share of those, from 0 to 1. Summary times are null without answers.
+/// Returns null on invalid handle.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_latency(handle: PierSshHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let session = unsafe { &*handle };
+    match serde_json::to_string(&session.latency()) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Check the connection now rather than at the next keepalive, and retry
+/// a pending reconnect without waiting. Call on network changes and after
+/// waking from sleep.
+/// Returns 0 on success, -1 on invalid handle.
+#[no_mangle]
+pub extern "C" fn pier_ssh_check_connection(handle: PierSshHandle) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let session = unsafe { &*handle };
+    session.check_connection();
+    0
+}
+
+/// Find up to `max_results` files and directories under `root` on the
+/// server whose names contain `pattern`, ignoring case, like
+/// pier_search_files does locally.
+/// Returns a JSON array of entries (name, path, is_dir, size, modified,
+/// permissions), or null on error, timeout (60s) or cancellation.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_search_files(
+    handle: PierSshHandle,
+    root: *const c_char,
+    pattern: *const c_char,
+    max_results: usize,
+) -> *mut c_char {
+    if handle.is_null() || root.is_null() || pattern.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let root = unsafe { CStr::from_ptr(root).to_str().unwrap_or("") }.to_string();
+    let pattern = unsafe { CStr::from_ptr(pattern).to_str().unwrap_or("") }.to_string();
+    let session_ptr = SendPtr(handle);
+
+    let results = match ffi_block_on(until_cancelled(async move {
+        let session = session_ptr.as_ref();
+        tokio::time::timeout(
+            std::time::Duration::from_secs(60),
+            remote_search::search_remote(session, &root, &pattern, max_results),
+        ).await
+    })) {
+        Some(Ok(Ok(results))) => results,
+        Some(Ok(Err(e))) => {
+            log::error!("Remote search failed: {}", e);
+            return std::ptr::null_mut();
+        }
+        Some(Err(_)) => {
+            log::warn!("Remote search timed out after 60s");
+            return std::ptr::null_mut();
+        }
+        None => return std::ptr::null_mut(),
+    };
+
+    match serde_json::to_string(&results) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Detect services installed on the remote server.
+/// Returns a JSON array of DetectedService, empty if detection timed out
+/// or was cancelled.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_detect_services(handle: PierSshHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let session_ptr = SendPtr(handle);
+
+    // 30-second overall timeout for service detection to prevent blocking
+    // when the SSH connection is dead (e.g. network change).
+    let services = match ffi_block_on(until_cancelled(async move {
+        let session = session_ptr.as_ref();
+        tokio::time::timeout(
+            std::time::Duration::from_secs(30),
+            service_detector::detect_all(session),
+        ).await
+    })) {
+        Some(Ok(services)) => services,
+        Some(Err(_)) => {
+            log::warn!("Service detection timed out after 30s");
+            Vec::new()
+        }
+        None => Vec::new(),
+    };
+
+    match serde_json::to_string(&services) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(e) => {
+            log::error!("Failed to serialize services: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Execute a command on the remote server, within the exec timeout set
+/// with pier_ssh_set_default_timeouts when connecting.
+/// Returns JSON: {"exit_code": N, "stdout": "..."}
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_exec(
+    handle: PierSshHandle,
+    command: *const c_char,
+) -> *mut c_char {
+    if handle.is_null() || command.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let cmd_str = unsafe { CStr::from_ptr(command).to_str().unwrap_or("") };
+    let session_ptr = SendPtr(handle as *mut SshSession);
+    let cmd_string = cmd_str.to_string();
+
+    // Time-limited by default, to avoid blocking the FFI thread indefinitely
+    // when the SSH connection is dead (e.g. network change).
+    let result = ffi_block_on(until_cancelled(async move {
+        let session = session_ptr.as_ref();
+        session.exec_command(&cmd_string).await
+    }));
+    exec_result_json(result.unwrap_or_else(|| Err(anyhow::anyhow!("Cancelled"))))
+}
+
+/// Execute a command as root with sudo, within the exec timeout, feeding
+/// `password` to sudo's prompt if it asks (null if sudo needs none). The
+/// prompt is removed from the output.
+/// Returns JSON: {"exit_code": N, "output": "...", "elevated": bool,
+/// "error": "..."}; `elevated` tells whether sudo let the command run,
+/// `error` why not (wrong password, not in sudoers). Null on invalid
+/// arguments, or if the command couldn't be run or timed out.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_exec_sudo(
+    handle: PierSshHandle,
+    command: *const c_char,
+    password: *const c_char,
+) -> *mut c_char {
+    if handle.is_null() || command.is_null() {
+        return std::ptr::null_mut();
+    }
+    let cmd_string = unsafe { CStr::from_ptr(command).to_str().unwrap_or("") }.to_string();
+    let password_string = if password.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(password).to_str().unwrap_or("") }.to_string()
+    };
+    let session_ptr = SendPtr(handle);
+
+    let result = ffi_block_on(until_cancelled(async move {
+        let session = session_ptr.as_ref();
+        let result = session.exec_sudo(&cmd_string, &password_string).await;
+        password_string.into_bytes().fill(0);
+        result
+    }))
+    .unwrap_or_else(|| Err(anyhow::anyhow!("Cancelled")));
+    match result.map(|output| serde_json::to_string(&output)) {
+        Ok(Ok(json)) => CString::new(json).unwrap_or_default().into_raw(),
+        Ok(Err(_)) => std::ptr::null_mut(),
+        Err(e) => {
+            log::error!("SSH sudo exec failed: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Run every command of `commands_json` (a JSON array of strings) on each
+/// of the `handle_count` sessions in `handles`, at most `parallelism` at
+/// once (0 for 8), e.g. a health check on all servers in one go. Each
+/// command gets its session's exec timeout.
+/// Returns a JSON array in session order, then command order:
+/// `[{"session": index, "command", "exit_code", "output", "error",
+/// "elapsed_ms"}]`; `exit_code` is null and `error` set when a command
+/// couldn't run or timed out. Null on invalid arguments or if cancelled.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_exec_batch(
+    handles: *const PierSshHandle,
+    handle_count: usize,
+    commands_json: *const c_char,
+    parallelism: u32,
+) -> *mut c_char {
+    if handles.is_null() || handle_count == 0 || commands_json.is_null() {
+        return std::ptr::null_mut();
+    }
+    let handles = unsafe { std::slice::from_raw_parts(handles, handle_count) };
+    if handles.iter().any(|handle| handle.is_null()) {
+        return std::ptr::null_mut();
+    }
+    let json = unsafe { CStr::from_ptr(commands_json).to_str().unwrap_or("") };
+    let Ok(commands) = serde_json::from_str::<Vec<String>>(json) else {
+        return std::ptr::null_mut();
+    };
+    let jobs: Vec<(usize, String)> = (0..handle_count)
+        .flat_map(|index| commands.iter().map(move |command| (index, command.clone())))
+        .collect();
+    let session_ptrs: Vec<SendPtr<SshSession>> = handles.iter().map(|handle| SendPtr(*handle)).collect();
+
+    let Some(results) = ffi_block_on(until_cancelled(async move {
+        let sessions: Vec<&SshSession> = session_ptrs.iter().map(SendPtr::as_ref).collect();
+        batch::run(&sessions, &jobs, parallelism as usize).await
+    })) else {
+        return std::ptr::null_mut();
+    };
+    match serde_json::to_string(&results) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Execute a command on the remote server, waiting at most `timeout_ms`
+/// for it to exit; 0 waits as long as it takes (backups and the like).
+/// Returns JSON: {"exit_code": N, "stdout": "..."}
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_exec_with_timeout(
+    handle: PierSshHandle,
+    command: *const c_char,
+    timeout_ms: u64,
+) -> *mut c_char {
+    if handle.is_null() || command.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let cmd_string = unsafe { CStr::from_ptr(command).to_str().unwrap_or("") }.to_string();
+    let session_ptr = SendPtr(handle);
+    let timeout = (timeout_ms > 0).then(|| std::time::Duration::from_millis(timeout_ms));
+
+    let result = ffi_block_on(until_cancelled(async move {
+        let session = session_ptr.as_ref();
+        session.exec_command_with_timeout(&cmd_string, timeout).await
+    }));
+    exec_result_json(result.unwrap_or_else(|| Err(anyhow::anyhow!("Cancelled"))))
+}
+
+/// Execute a command with `input_len` bytes at `input` as its stdin
+/// (closed after them), for `cat > file` and the like. Input is sent while
+/// output is read, with the same time limit as pier_ssh_exec; use
+/// pier_ssh_exec_start for longer or streamed input.
+/// Returns JSON: {"exit_code": N, "stdout": "..."}
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_exec_with_input(
+    handle: PierSshHandle,
+    command: *const c_char,
+    input: *const u8,
+    input_len: usize,
+) -> *mut c_char {
+    if handle.is_null() || command.is_null() || (input.is_null() && input_len > 0) {
+        return std::ptr::null_mut();
+    }
+
+    let cmd_str = unsafe { CStr::from_ptr(command).to_str().unwrap_or("") };
+    let input = if input_len == 0 {
+        Vec::new()
+    } else {
+        unsafe { std::slice::from_raw_parts(input, input_len) }.to_vec()
+    };
+    let session_ptr = SendPtr(handle);
+    let cmd_string = cmd_str.to_string();
+
+    let result = ffi_block_on(until_cancelled(async move {
+        let session = session_ptr.as_ref();
+        session.exec_with_input(&cmd_string, &input).await
+    }));
+    exec_result_json(result.unwrap_or_else(|| Err(anyhow::anyhow!("Cancelled"))))
+}
+
+/// The JSON pier_ssh_exec returns for an exec's outcome.
+fn exec_result_json(result: Result<(i32, String), anyhow::Error>) -> *mut c_char {
+    match result {
+        Ok((exit_code, stdout)) => {
+            let result = serde_json::json!({
+                "exit_code": exit_code,
+                "stdout": stdout,
+            });
+            match CString::new(result.to_string()) {
+                Ok(cs) => cs.into_raw(),
+                Err(_) => std::ptr::null_mut(),
+            }
+        }
+        Err(e) => {
+            log::error!("SSH exec failed: {}", e);
+            let err = serde_json::json!({
+                "exit_code": -1,
+                "stdout": format!("Error: {}", e),
+            });
+            CString::new(err.to_string()).unwrap_or_default().into_raw()
+        }
+    }
+}
+
+/// Opaque pointer to a remote command with streamed input.
+pub type PierExecHandle = *mut RemoteExec;
+
+/// Start a command whose stdin is fed with pier_ssh_exec_write and closed
+/// with pier_ssh_exec_close_stdin, e.g. `psql < dump.sql` with the dump
+/// read in pieces. Read output with pier_ssh_exec_read or at the end with
+/// pier_ssh_exec_finish, and free with pier_ssh_exec_free. The SSH handle
+/// must outlive it.
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn pier_ssh_exec_start(handle: PierSshHandle, command: *const c_char) -> PierExecHandle {
+    if handle.is_null() || command.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let cmd_string = unsafe { CStr::from_ptr(command).to_str().unwrap_or("") }.to_string();
+    let session_ptr = SendPtr(handle);
+
+    // 10-second timeout: channel open + exec request
+    let channel = match ffi_block_on(async move {
+        let session = session_ptr.as_ref();
+        tokio::time::timeout(std::time::Duration::from_secs(10), session.exec_channel(&cmd_string)).await
+    }) {
+        Ok(Ok(channel)) => channel,
+        Ok(Err(e)) => {
+            log::error!("SSH exec failed: {}", e);
+            return std::ptr::null_mut();
+        }
+        Err(_) => {
+            log::warn!("SSH exec start timed out after 10s");
+            return std::ptr::null_mut();
+        }
+    };
+    Box::into_raw(Box::new(RemoteExec::start(ssh_runtime().handle(), channel)))
+}
+
+/// Queue `len` bytes for the command's stdin. Doesn't wait for them to be
+/// sent.
+/// Returns 0 on success, -1 on error or once the command has ended.
+#[no_mangle]
+pub extern "C" fn pier_ssh_exec_write(exec: PierExecHandle, data: *const u8, len: usize) -> i32 {
+    if exec.is_null() || (data.is_null() && len > 0) {
+        return -1;
+    }
+    if len == 0 {
+        return 0;
+    }
+    let exec = unsafe { &*exec };
+    let data = unsafe { std::slice::from_raw_parts(data, len) };
+    match exec.write(data) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Close the command's stdin after the input queued so far.
+/// Returns 0 on success, -1 on error.
+#[no_mangle]
+pub extern "C" fn pier_ssh_exec_close_stdin(exec: PierExecHandle) -> i32 {
+    if exec.is_null() {
+        return -1;
+    }
+    let exec = unsafe { &*exec };
+    match exec.close_stdin() {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Read up to `cap` bytes of output (stdout and stderr) into `buf`,
+/// waiting up to `timeout_ms` for some.
+/// Returns the bytes read, 0 at the end of output, or -1 if none arrived
+/// in time or on invalid arguments.
+#[no_mangle]
+pub extern "C" fn pier_ssh_exec_read(exec: PierExecHandle, buf: *mut u8, cap: usize, timeout_ms: u32) -> isize {
+    if exec.is_null() || buf.is_null() || cap == 0 {
+        return -1;
+    }
+    let exec_ptr = SendPtr(exec);
+    let buf_ptr = SendPtr(buf);
+    let timeout = std::time::Duration::from_millis(timeout_ms as u64);
+    let result = ffi_block_on(async move {
+        let buf = unsafe { std::slice::from_raw_parts_mut(buf_ptr.get(), cap) };
+        exec_ptr.as_mut().read(buf, timeout).await
+    });
+    match result {
+        Ok(len) => len as isize,
+        Err(_) => -1,
+    }
+}
+
+/// Wait up to `timeout_ms` for the command to exit, and return the output
+/// not read yet. Close stdin first if the command reads it to the end.
+/// Returns JSON: {"exit_code": N, "stdout": "..."} (exit_code is -1 if
+/// unknown, 128 + N if killed by signal N), or null on timeout.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_exec_finish(exec: PierExecHandle, timeout_ms: u32) -> *mut c_char {
+    if exec.is_null() {
+        return std::ptr::null_mut();
+    }
+    let exec_ptr = SendPtr(exec);
+    let timeout = std::time::Duration::from_millis(timeout_ms as u64);
+    match ffi_block_on(async move { exec_ptr.as_mut().finish(timeout).await }) {
+        Ok((exit_code, output)) => {
+            let result = serde_json::json!({
+                "exit_code": exit_code,
+                "stdout": String::from_utf8_lossy(&output),
+            });
+            CString::new(result.to_string()).unwrap_or_default().into_raw()
+        }
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Close the command's channel, if still open, and free the handle.
+#[no_mangle]
+pub extern "C" fn pier_ssh_exec_free(exec: PierExecHandle) {
+    if !exec.is_null() {
+        unsafe { drop(Box::from_raw(exec)) };
+    }
+}
+
+/// Opaque pointer to a command streaming its output to callbacks.
+pub type PierStreamingExecHandle = *mut StreamingExec;
+
+/// Run a command and pass its output to `output_callback` as it arrives,
+/// for long-running commands (builds, `tail -f`) whose output should show
+/// live. The callback gets `user_data`, the stream (1 stdout, 2 stderr)
+/// and a chunk only valid during the call. When the command ends,
+/// `exit_callback` (if not null) gets its exit code: -1 if the server
+/// didn't report one, 128 + N if signal N killed it. Both run on a
+/// background thread. There is no time limit; free with
+/// pier_ssh_exec_streaming_free, which stops the command if still running.
+/// The SSH handle must outlive it.
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn pier_ssh_exec_streaming(
+    handle: PierSshHandle,
+    command: *const c_char,
+    output_callback: Option<extern "C" fn(user_data: *mut c_void, stream: i32, data: *const u8, len: usize)>,
+    exit_callback: Option<extern "C" fn(user_data: *mut c_void, exit_code: i32)>,
+    user_data: *mut c_void,
+) -> PierStreamingExecHandle {
+    if handle.is_null() || command.is_null() {
+        return std::ptr::null_mut();
+    }
+    let Some(output_callback) = output_callback else {
+        return std::ptr::null_mut();
+    };
+
+    let cmd_string = unsafe { CStr::from_ptr(command).to_str().unwrap_or("") }.to_string();
+    let session_ptr = SendPtr(handle);
+
+    // 10-second timeout: channel open + exec request
+    let channel = match ffi_block_on(async move {
+        let session = session_ptr.as_ref();
+        tokio::time::timeout(std::time::Duration::from_secs(10), session.exec_channel(&cmd_string)).await
+    }) {
+        Ok(Ok(channel)) => channel,
+        Ok(Err(e)) => {
+            log::error!("SSH exec failed: {}", e);
+            return std::ptr::null_mut();
+        }
+        Err(_) => {
+            log::warn!("SSH exec start timed out after 10s");
+            return std::ptr::null_mut();
+        }
+    };
+
+    let output_data = SendPtr(user_data);
+    let exit_data = SendPtr(user_data);
+    let exec = StreamingExec::start(
+        ssh_runtime().handle(),
+        channel,
+        move |stream, data| {
+            let stream = match stream {
+                OutputStream::Stdout => 1,
+                OutputStream::Stderr => 2,
+            };
+            output_callback(output_data.get(), stream, data.as_ptr(), data.len());
+        },
+        move |exit_code| {
+            if let Some(callback) = exit_callback {
+                callback(exit_data.get(), exit_code);
+            }
+        },
+    );
+    Box::into_raw(Box::new(exec))
+}
+
+/// Stop the command if still running and free the handle. No callback
+/// runs once this returns.
+#[no_mangle]
+pub extern "C" fn pier_ssh_exec_streaming_free(exec: PierStreamingExecHandle) {
+    if exec.is_null() {
+        return;
+    }
+    let exec = unsafe { Box::from_raw(exec) };
+    ffi_block_on(exec.stop());
+}
+
+/// Open an interactive shell on the SSH connection, as a terminal session.
+/// The returned handle works with the pier_terminal_* functions like a
+/// local one (write, read, resize, snapshot, callbacks) and is freed with
+/// pier_terminal_destroy; process inspection and job control only apply
+/// to local sessions. The SSH handle must outlive it.
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn pier_ssh_open_shell(handle: PierSshHandle, cols: u16, rows: u16) -> PierTerminalHandle {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let session_ptr = SendPtr(handle);
+
+    // 10-second timeout: channel open + pty/shell requests
+    let channel = match ffi_block_on(async move {
+        let session = session_ptr.as_ref();
+        tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            session.open_shell(cols as u32, rows as u32),
+        ).await
+    }) {
+        Ok(Ok(channel)) => channel,
+        Ok(Err(e)) => {
+            log::error!("SSH shell failed: {}", e);
+            return std::ptr::null_mut();
+        }
+        Err(_) => {
+            log::warn!("SSH shell timed out after 10s");
+            return std::ptr::null_mut();
+        }
+    };
+
+    let transcript = unsafe { &*handle }.transcript();
+    let (shell, output) = RemoteShell::start(ssh_runtime().handle(), channel, transcript);
+    match TerminalSession::new_remote(cols, rows, shell, output) {
+        Ok(session) => Box::into_raw(Box::new(session)),
+        Err(e) => {
+            log::error!("Failed to create remote terminal: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+// ═══════════════════════════════════════════════════════════
+// SSH Port Forwarding FFI
+// ═══════════════════════════════════════════════════════════
+
+/// Start local port forwarding: 127.0.0.1:local_port → remote_host:remote_port.
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_ssh_forward_port(
+    handle: PierSshHandle,
+    local_port: u16,
+    remote_host: *const c_char,
+    remote_port: u16,
+) -> i32 {
+    if handle.is_null() || remote_host.is_null() {
+        return -1;
+    }
+
+    let host_str = unsafe { CStr::from_ptr(remote_host).to_str().unwrap_or("") };
+    let session_ptr = SendPtr(handle);
+    let host_string = host_str.to_string();
+
+    // 10-second timeout: TcpListener::bind + SSH channel setup
+    match ffi_block_on(async move {
+        let session = session_ptr.as_mut();
+        tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            session.start_port_forward(local_port, &host_string, remote_port),
+        ).await
+    }) {
+        Ok(Ok(())) => 0,
+        Ok(Err(e)) => {
+            log::error!("Port forward failed: {}", e);
+            -1
+        }
+        Err(_) => {
+            log::warn!("Port forward timed out after 10s for port {}", local_port);
+            -1
+        }
+    }
+}
+
+/// Stop a local port forward.
+/// Returns 0 on success, -1 if no such forward.
+#[no_mangle]
+pub extern "C" fn pier_ssh_stop_forward(handle: PierSshHandle, local_port: u16) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+
+    let session = unsafe { &mut *handle };
+    match session.stop_port_forward(local_port) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("Stop forward failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// List active forward ports as a JSON array.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_list_forwards(handle: PierSshHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let session = unsafe { &*handle };
+    let ports = session.active_forwards();
+
+    match serde_json::to_string(&ports) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Start dynamic forwarding: a SOCKS5 proxy on 127.0.0.1:local_port
+/// tunneling each connection through the server (`ssh -D`).
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_ssh_start_dynamic_forward(handle: PierSshHandle, local_port: u16) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+
+    let session_ptr = SendPtr(handle);
+    match ffi_block_on(async move {
+        let session = session_ptr.as_mut();
+        tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            session.start_dynamic_forward(local_port),
+        ).await
+    }) {
+        Ok(Ok(())) => 0,
+        Ok(Err(e)) => {
+            log::error!("Dynamic forward failed: {}", e);
+            -1
+        }
+        Err(_) => {
+            log::warn!("Dynamic forward timed out after 10s for port {}", local_port);
+            -1
+        }
+    }
+}
+
+/// Stop a dynamic forward.
+/// Returns 0 on success, -1 if no such forward.
+#[no_mangle]
+pub extern "C" fn pier_ssh_stop_dynamic_forward(handle: PierSshHandle, local_port: u16) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+
+    let session = unsafe { &mut *handle };
+    match session.stop_dynamic_forward(local_port) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("Stop dynamic forward failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// List ports with an active SOCKS proxy as a JSON array.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_ssh_list_dynamic_forwards(handle: PierSshHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let session = unsafe { &*handle };
+    let ports = session.active_dynamic_forwards();
+
+    match serde_json::to_string(&ports) {
+        Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+// ═══════════════════════════════════════════════════════════
+// SFTP FFI
+// ═══════════════════════════════════════════════════════════
+
+/// Opaque pointer to an SFTP session.
+pub type PierSftpHandle = *mut SftpClient;
+
+/// Open an SFTP session over an SSH connection. The SSH handle must
+/// outlive it; free with pier_sftp_close.
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_open(handle: PierSshHandle) -> PierSftpHandle {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let session_ptr = SendPtr(handle);
+
+    // 10-second timeout: channel open + subsystem request + SFTP init
+    match ffi_block_on(async move {
+        let session = session_ptr.as_ref();
+        tokio::time::timeout(std::time::Duration::from_secs(10), session.open_sftp()).await
+    }) {
+        Ok(Ok(sftp)) => Box::into_raw(Box::new(sftp)),
+        Ok(Err(e)) => {
+            log::error!("SFTP open failed: {}", e);
+            std::ptr::null_mut()
+        }
+        Err(_) => {
+            log::warn!("SFTP open timed out after 10s");
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// End an SFTP session and free its handle.
+#[no_mangle]
+pub extern "C" fn pier_sftp_close(handle: PierSftpHandle) {
+    if handle.is_null() {
+        return;
+    }
+
+    let mut sftp = unsafe { Box::from_raw(handle) };
+    ffi_block_on(async move {
+        if let Err(e) = sftp.close().await {
+            log::warn!("SFTP close failed: {}", e);
+        }
+    });
+}
+
+/// List a remote directory as a JSON array of entries (name, path, is_dir,
+/// size, modified, permissions), directories first.
+/// Returns null on error. Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_sftp_list_dir(handle: PierSftpHandle, path: *const c_char) -> *mut c_char {
+    if handle.is_null() || path.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().list_dir(&path).await }) {
+        Ok(entries) => match serde_json::to_string(&entries) {
+            Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+            Err(_) => std::ptr::null_mut(),
+        },
+        Err(e) => {
+            log::error!("SFTP list failed: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Opaque pointer to a remote directory being listed in batches.
+pub type PierSftpListingHandle = *mut DirListing;
+
+/// List a remote directory in batches, for directories too big to list
+/// in one go. Runs on an SFTP channel of its own over the SSH connection,
+/// so other SFTP work isn't held up. Entries go to `batch_callback` with
+/// `user_data` as a JSON array like pier_sftp_list_dir's, but unsorted,
+/// as the server sends them. Then `done_callback` (if not null) gets the
+/// number of entries listed, or -1 on failure. Both run on a background
+/// thread, with strings only valid during the call. Free with
+/// pier_sftp_list_dir_streaming_free, which stops the listing if still
+/// going. The SSH handle must outlive it.
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_list_dir_streaming(
+    handle: PierSshHandle,
+    path: *const c_char,
+    batch_callback: Option<extern "C" fn(user_data: *mut c_void, entries_json: *const c_char)>,
+    done_callback: Option<extern "C" fn(user_data: *mut c_void, count: i64)>,
+    user_data: *mut c_void,
+) -> PierSftpListingHandle {
+    if handle.is_null() || path.is_null() {
+        return std::ptr::null_mut();
+    }
+    let Some(batch_callback) = batch_callback else {
+        return std::ptr::null_mut();
+    };
+
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") }.to_string();
+    let session_ptr = SendPtr(handle);
+
+    // 10-second timeout: channel open
+    let channel = match ffi_block_on(async move {
+        tokio::time::timeout(std::time::Duration::from_secs(10), session_ptr.as_ref().sftp_channel()).await
+    }) {
+        Ok(Ok(channel)) => channel,
+        Ok(Err(e)) => {
+            log::error!("SFTP listing failed: {}", e);
+            return std::ptr::null_mut();
+        }
+        Err(_) => {
+            log::warn!("SFTP listing start timed out after 10s");
+            return std::ptr::null_mut();
+        }
+    };
+
+    let batch_data = SendPtr(user_data);
+    let done_data = SendPtr(user_data);
+    let listing = DirListing::start(
+        ssh_runtime().handle(),
+        channel,
+        path,
+        move |entries| {
+            let json = CString::new(serde_json::to_string(&entries).unwrap_or_default()).unwrap_or_default();
+            batch_callback(batch_data.get(), json.as_ptr());
+        },
+        move |result| {
+            let count = match result {
+                Ok(count) => i64::try_from(count).unwrap_or(i64::MAX),
+                Err(e) => {
+                    log::error!("SFTP listing failed: {}", e);
+                    -1
+                }
+            };
+            if let Some(callback) = done_callback {
+                callback(done_data.get(), count);
+            }
+        },
+    );
+    Box::into_raw(Box::new(listing))
+}
+
+/// Stop the listing if still going and free the handle. No callback runs
+/// once this returns.
+#[no_mangle]
+pub extern "C" fn pier_sftp_list_dir_streaming_free(listing: PierSftpListingHandle) {
+    if listing.is_null() {
+        return;
+    }
+    let listing = unsafe { Box::from_raw(listing) };
+    ffi_block_on(listing.stop());
+}
+
+/// Have uploads and downloads on this SFTP session (and transfer queues
+/// created for it afterwards) carry over mode bits and modification times,
+/// as `scp -p` does. Off by default.
+#[no_mangle]
+pub extern "C" fn pier_sftp_set_preserve_attributes(handle: PierSftpHandle, preserve: bool) {
+    if handle.is_null() {
+        return;
+    }
+
+    let sftp = unsafe { &mut *handle };
+    sftp.set_preserve_attributes(preserve);
+}
+
+/// Limit each upload, download and copy on this SFTP session to
+/// `bytes_per_sec`, 0 for no limit. Transfer queues created for it
+/// afterwards start their transfers with this limit.
+#[no_mangle]
+pub extern "C" fn pier_sftp_set_rate_limit(handle: PierSftpHandle, bytes_per_sec: u64) {
+    if handle.is_null() {
+        return;
+    }
+
+    let sftp = unsafe { &mut *handle };
+    sftp.set_transfer_rate_limit(bytes_per_sec);
+}
+
+/// Limit all SFTP transfers together to `bytes_per_sec`, 0 for no limit,
+/// so they leave room on the link for shells and calls. Running transfers
+/// slow down at once.
+#[no_mangle]
+pub extern "C" fn pier_sftp_set_global_rate_limit(bytes_per_sec: u64) {
+    sftp::set_global_rate_limit(bytes_per_sec);
+}
+
+/// Forward transfer progress to `callback`, if there is one.
+fn progress_reporter(
+    callback: Option<extern "C" fn(user_data: *mut c_void, done: u64, total: u64, bytes_per_sec: u64, eta_secs: i64)>,
+    user_data: *mut c_void,
+) -> impl FnMut(TransferProgress) + Send {
+    let user_data = SendPtr(user_data);
+    move |progress| {
+        if let Some(callback) = callback {
+            let eta_secs = progress.eta_secs.map_or(-1, |eta| eta.min(i64::MAX as u64) as i64);
+            callback(user_data.get(), progress.done, progress.total, progress.bytes_per_sec, eta_secs);
+        }
+    }
+}
+
+/// Download `remote_path` to `local_path`, streamed to disk in chunks.
+/// `progress_callback`, if not null, is called with `user_data`, the bytes
+/// done, the file size, the average rate in bytes per second and the
+/// seconds left (-1 until known), up to ten times a second and once at the
+/// end, on an SSH runtime thread while this call blocks.
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_download(
+    handle: PierSftpHandle,
+    remote_path: *const c_char,
+    local_path: *const c_char,
+    progress_callback: Option<extern "C" fn(user_data: *mut c_void, done: u64, total: u64, bytes_per_sec: u64, eta_secs: i64)>,
+    user_data: *mut c_void,
+) -> i32 {
+    if handle.is_null() || remote_path.is_null() || local_path.is_null() {
+        return -1;
+    }
+
+    let remote = unsafe { CStr::from_ptr(remote_path).to_str().unwrap_or("") }.to_string();
+    let local = unsafe { CStr::from_ptr(local_path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    let on_progress = progress_reporter(progress_callback, user_data);
+    match ffi_block_on(async move {
+        sftp_ptr.as_ref().download_with_progress(&remote, std::path::Path::new(&local), on_progress).await
+    }) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("SFTP download failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Upload `local_path` to `remote_path`, read from disk in chunks.
+/// `progress_callback` is as for pier_sftp_download.
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_upload(
+    handle: PierSftpHandle,
+    local_path: *const c_char,
+    remote_path: *const c_char,
+    progress_callback: Option<extern "C" fn(user_data: *mut c_void, done: u64, total: u64, bytes_per_sec: u64, eta_secs: i64)>,
+    user_data: *mut c_void,
+) -> i32 {
+    if handle.is_null() || local_path.is_null() || remote_path.is_null() {
+        return -1;
+    }
+
+    let local = unsafe { CStr::from_ptr(local_path).to_str().unwrap_or("") }.to_string();
+    let remote = unsafe { CStr::from_ptr(remote_path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    let on_progress = progress_reporter(progress_callback, user_data);
+    match ffi_block_on(async move {
+        sftp_ptr.as_ref().upload_with_progress(std::path::Path::new(&local), &remote, on_progress).await
+    }) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("SFTP upload failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Rename or move a remote file or directory. With `overwrite`, a file
+/// already at `new_path` is replaced.
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_rename(
+    handle: PierSftpHandle,
+    old_path: *const c_char,
+    new_path: *const c_char,
+    overwrite: bool,
+) -> i32 {
+    if handle.is_null() || old_path.is_null() || new_path.is_null() {
+        return -1;
+    }
+
+    let old_path = unsafe { CStr::from_ptr(old_path).to_str().unwrap_or("") }.to_string();
+    let new_path = unsafe { CStr::from_ptr(new_path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().rename(&old_path, &new_path, overwrite).await }) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("SFTP rename failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Copy a remote file to another remote path without a local download,
+/// replacing any file there. `progress_callback` is as for
+/// pier_sftp_download.
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_copy(
+    handle: PierSftpHandle,
+    from_path: *const c_char,
+    to_path: *const c_char,
+    progress_callback: Option<extern "C" fn(user_data: *mut c_void, done: u64, total: u64, bytes_per_sec: u64, eta_secs: i64)>,
+    user_data: *mut c_void,
+) -> i32 {
+    if handle.is_null() || from_path.is_null() || to_path.is_null() {
+        return -1;
+    }
+
+    let from_path = unsafe { CStr::from_ptr(from_path).to_str().unwrap_or("") }.to_string();
+    let to_path = unsafe { CStr::from_ptr(to_path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    let on_progress = progress_reporter(progress_callback, user_data);
+    match ffi_block_on(async move { sftp_ptr.as_ref().copy(&from_path, &to_path, on_progress).await }) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("SFTP copy failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Set the permission bits of a remote entry, e.g. 0o644.
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_chmod(handle: PierSftpHandle, path: *const c_char, mode: u32) -> i32 {
+    if handle.is_null() || path.is_null() {
+        return -1;
+    }
+
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().chmod(&path, mode).await }) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("SFTP chmod failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Change the owner and group of a remote entry; -1 for either keeps it,
+/// as with chown(2).
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_chown(handle: PierSftpHandle, path: *const c_char, uid: i64, gid: i64) -> i32 {
+    if handle.is_null() || path.is_null() {
+        return -1;
+    }
+
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") }.to_string();
+    let uid = u32::try_from(uid).ok();
+    let gid = u32::try_from(gid).ok();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().chown(&path, uid, gid).await }) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("SFTP chown failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Set the access and modification times of a remote entry, in Unix
+/// seconds; -1 for either keeps it.
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_set_times(handle: PierSftpHandle, path: *const c_char, atime: i64, mtime: i64) -> i32 {
+    if handle.is_null() || path.is_null() {
+        return -1;
+    }
+
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") }.to_string();
+    let atime = u32::try_from(atime).ok();
+    let mtime = u32::try_from(mtime).ok();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().set_times(&path, atime, mtime).await }) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("SFTP set times failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Remove a remote file.
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_remove_file(handle: PierSftpHandle, path: *const c_char) -> i32 {
+    if handle.is_null() || path.is_null() {
+        return -1;
+    }
+
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().remove_file(&path).await }) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("SFTP remove failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Expand a remote path with wildcards (`*`, `?`, `[...]`, and `**` for
+/// any depth of directories) as a shell would, giving up to `max_results`
+/// matches as a JSON array of entries like pier_sftp_list_dir's, sorted
+/// by path. Names starting with `.` only match a pattern that does too.
+/// Returns null on error. Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_sftp_glob(handle: PierSftpHandle, pattern: *const c_char, max_results: u32) -> *mut c_char {
+    if handle.is_null() || pattern.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let pattern = unsafe { CStr::from_ptr(pattern).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { remote_glob::expand(sftp_ptr.as_ref(), &pattern, max_results as usize).await }) {
+        Ok(entries) => match serde_json::to_string(&entries) {
+            Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+            Err(_) => std::ptr::null_mut(),
+        },
+        Err(e) => {
+            log::error!("SFTP glob failed: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Download the remote files matching a wildcard pattern (as for
+/// pier_sftp_glob) into `local_dir`, keeping their layout below the
+/// pattern's first wildcard, so `etc/**/*.conf` lands as
+/// `local_dir/nginx/nginx.conf` and so on. Directories matched are
+/// skipped. For progress, queue the pier_sftp_glob matches with
+/// pier_transfers_add_download instead.
+/// Returns the number of files downloaded, or -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_download_glob(
+    handle: PierSftpHandle,
+    pattern: *const c_char,
+    local_dir: *const c_char,
+) -> i32 {
+    if handle.is_null() || pattern.is_null() || local_dir.is_null() {
+        return -1;
+    }
+
+    let pattern = unsafe { CStr::from_ptr(pattern).to_str().unwrap_or("") }.to_string();
+    let local_dir = unsafe { CStr::from_ptr(local_dir).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move {
+        remote_glob::download_matches(sftp_ptr.as_ref(), &pattern, std::path::Path::new(&local_dir)).await
+    }) {
+        Ok(count) => i32::try_from(count).unwrap_or(i32::MAX),
+        Err(e) => {
+            log::error!("SFTP glob download failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Download a remote directory into `local_dir` (as
+/// `local_dir/<directory name>`) as one gzipped tar stream: `tar` runs on
+/// the server over exec and locally to unpack, which is much faster than
+/// per-file SFTP for trees of many small files. `progress_callback`, if
+/// not null, gets `user_data` and the compressed bytes received so far, on
+/// an SSH runtime thread while this call blocks. Cancellable like exec.
+/// Returns 0 on success, 1 if the server has no tar or gzip (nothing was
+/// downloaded; fall back to SFTP), -1 on failure or cancellation.
+#[no_mangle]
+pub extern "C" fn pier_ssh_download_dir_archive(
+    handle: PierSshHandle,
+    remote_dir: *const c_char,
+    local_dir: *const c_char,
+    progress_callback: Option<extern "C" fn(user_data: *mut c_void, received: u64)>,
+    user_data: *mut c_void,
+) -> i32 {
+    if handle.is_null() || remote_dir.is_null() || local_dir.is_null() {
+        return -1;
+    }
+
+    let remote_dir = unsafe { CStr::from_ptr(remote_dir).to_str().unwrap_or("") }.to_string();
+    let local_dir = unsafe { CStr::from_ptr(local_dir).to_str().unwrap_or("") }.to_string();
+    let session_ptr = SendPtr(handle);
+    let user_data = SendPtr(user_data);
+    let on_progress = move |received: u64| {
+        if let Some(callback) = progress_callback {
+            callback(user_data.get(), received);
+        }
+    };
+    match ffi_block_on(until_cancelled(async move {
+        archive::download_dir(session_ptr.as_ref(), &remote_dir, std::path::Path::new(&local_dir), on_progress).await
+    })) {
+        Some(Ok(true)) => 0,
+        Some(Ok(false)) => 1,
+        Some(Err(e)) => {
+            log::error!("Archive download failed: {}", e);
+            -1
+        }
+        None => -1,
+    }
+}
+
+/// Remove the remote files matching a wildcard pattern (as for
+/// pier_sftp_glob), like `rm` without `-r`: directories matched are left.
+/// Files that can't be removed don't stop the rest.
+/// Returns the number of files removed, or -1 if any couldn't be.
+#[no_mangle]
+pub extern "C" fn pier_sftp_remove_glob(handle: PierSftpHandle, pattern: *const c_char) -> i32 {
+    if handle.is_null() || pattern.is_null() {
+        return -1;
+    }
+
+    let pattern = unsafe { CStr::from_ptr(pattern).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { remote_glob::remove_matches(sftp_ptr.as_ref(), &pattern).await }) {
+        Ok(count) => i32::try_from(count).unwrap_or(i32::MAX),
+        Err(e) => {
+            log::error!("SFTP glob remove failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Create a remote directory.
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_create_dir(handle: PierSftpHandle, path: *const c_char) -> i32 {
+    if handle.is_null() || path.is_null() {
+        return -1;
+    }
+
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().create_dir(&path).await }) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("SFTP mkdir failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Read up to `cap` bytes of a remote file from `offset` into `buf`.
+/// Returns the bytes read (fewer than `cap` at the end of the file, 0 at
+/// or past it), or -1 on error.
+#[no_mangle]
+pub extern "C" fn pier_sftp_read_range(
+    handle: PierSftpHandle,
+    path: *const c_char,
+    offset: u64,
+    buf: *mut u8,
+    cap: usize,
+) -> isize {
+    if handle.is_null() || path.is_null() || (buf.is_null() && cap > 0) {
+        return -1;
+    }
+
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().read_range(&path, offset, cap).await }) {
+        Ok(data) => {
+            let buf = unsafe { std::slice::from_raw_parts_mut(buf, data.len()) };
+            buf.copy_from_slice(&data);
+            data.len() as isize
+        }
+        Err(e) => {
+            log::error!("SFTP ranged read failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Read a whole remote file, up to `max_size` bytes, to preview or edit it.
+/// Returns JSON: {"size": N, "encoding": "utf-8", "utf-16le", "utf-16be" or
+/// null, "bom": bool, "binary": bool, "content": the text, or base64 of
+/// the bytes if binary}, or null on error or if the file is over
+/// `max_size`.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_sftp_read_file(handle: PierSftpHandle, path: *const c_char, max_size: u64) -> *mut c_char {
+    if handle.is_null() || path.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().read_to_bytes(&path, max_size).await }) {
+        Ok(data) => match serde_json::to_string(&FileContent::decode(&data)) {
+            Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+            Err(_) => std::ptr::null_mut(),
+        },
+        Err(e) => {
+            log::error!("SFTP read failed: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Replace a remote file with `len` bytes of `data`, or create it, without
+/// ever leaving it truncated: the data is written to a temporary file that
+/// then takes the old file's place and permissions.
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_write_file(
+    handle: PierSftpHandle,
+    path: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    if handle.is_null() || path.is_null() || (data.is_null() && len > 0) {
+        return -1;
+    }
+
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") }.to_string();
+    let data = if len == 0 { Vec::new() } else { unsafe { std::slice::from_raw_parts(data, len) }.to_vec() };
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().write_bytes(&path, &data).await }) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("SFTP write failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Opaque pointer to a remote file being followed.
+pub type PierSftpTailHandle = *mut RemoteTail;
+
+/// Follow a remote file as it grows, like `tail -F`, for live logs. Bytes
+/// appended from `offset` on (-1 for the current end) are passed to
+/// `data_callback` with `user_data`, in chunks only valid during the call,
+/// on a background thread. The file is checked every `interval_ms` (1000
+/// if 0); if it shrinks, as when a log is rotated, it's followed from its
+/// start again. Stop with pier_sftp_tail_stop before closing the SFTP
+/// handle.
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_tail_follow(
+    handle: PierSftpHandle,
+    path: *const c_char,
+    offset: i64,
+    interval_ms: u32,
+    data_callback: Option<extern "C" fn(user_data: *mut c_void, data: *const u8, len: usize)>,
+    user_data: *mut c_void,
+) -> PierSftpTailHandle {
+    if handle.is_null() || path.is_null() {
+        return std::ptr::null_mut();
+    }
+    let Some(data_callback) = data_callback else {
+        return std::ptr::null_mut();
+    };
+
+    let sftp = unsafe { &*handle };
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") };
+    let offset = u64::try_from(offset).ok();
+    let interval = std::time::Duration::from_millis(if interval_ms == 0 { 1000 } else { interval_ms as u64 });
+    let user_data = SendPtr(user_data);
+    let on_data = move |data: &[u8]| data_callback(user_data.get(), data.as_ptr(), data.len());
+    match sftp.tail_follow(ssh_runtime().handle(), path, offset, interval, on_data) {
+        Ok(tail) => Box::into_raw(Box::new(tail)),
+        Err(e) => {
+            log::error!("SFTP tail failed: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Stop following a file and free the handle. No callback runs once this
+/// returns.
+#[no_mangle]
+pub extern "C" fn pier_sftp_tail_stop(tail: PierSftpTailHandle) {
+    if tail.is_null() {
+        return;
+    }
+    let tail = unsafe { Box::from_raw(tail) };
+    ffi_block_on(tail.stop());
+}
+
+/// Opaque pointer to a set of remote paths watched for changes.
+pub type PierSftpWatchHandle = *mut RemoteWatch;
+
+/// Watch remote paths for changes by polling them every `interval_ms`
+/// (2000 if 0), so open directory views and previews can refresh. Add
+/// paths with pier_sftp_watch_add. Each change is passed to
+/// `change_callback` with `user_data` as JSON, only valid during the call,
+/// on a background thread:
+/// {"path": "...", "kind": "created"|"modified"|"deleted"}
+/// A directory is modified when an entry in it is added, removed, or
+/// changes size or time. Stop with pier_sftp_watch_stop before closing
+/// the SFTP handle.
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_watch_start(
+    handle: PierSftpHandle,
+    interval_ms: u32,
+    change_callback: Option<extern "C" fn(user_data: *mut c_void, change_json: *const c_char)>,
+    user_data: *mut c_void,
+) -> PierSftpWatchHandle {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+    let Some(change_callback) = change_callback else {
+        return std::ptr::null_mut();
+    };
+
+    let sftp = unsafe { &*handle };
+    let interval = std::time::Duration::from_millis(if interval_ms == 0 { 2000 } else { interval_ms as u64 });
+    let user_data = SendPtr(user_data);
+    let on_change = move |change: &remote_watch::RemoteChange| {
+        let json = CString::new(serde_json::to_string(change).unwrap_or_default()).unwrap_or_default();
+        change_callback(user_data.get(), json.as_ptr());
+    };
+    match RemoteWatch::start(sftp, ssh_runtime().handle(), interval, on_change) {
+        Ok(watch) => Box::into_raw(Box::new(watch)),
+        Err(e) => {
+            log::error!("SFTP watch failed: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Start watching a remote file or directory. Changes are reported from
+/// the poll after the next one; a path that doesn't exist yet is reported
+/// when created.
+/// Returns 0 on success, -1 on invalid arguments.
+#[no_mangle]
+pub extern "C" fn pier_sftp_watch_add(watch: PierSftpWatchHandle, path: *const c_char) -> i32 {
+    if watch.is_null() || path.is_null() {
+        return -1;
+    }
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") };
+    unsafe { &*watch }.watch(path);
+    0
+}
+
+/// Stop watching a remote path.
+/// Returns 0 on success, -1 if it wasn't watched.
+#[no_mangle]
+pub extern "C" fn pier_sftp_watch_remove(watch: PierSftpWatchHandle, path: *const c_char) -> i32 {
+    if watch.is_null() || path.is_null() {
+        return -1;
+    }
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") };
+    if unsafe { &*watch }.unwatch(path) { 0 } else { -1 }
+}
+
+/// Stop watching and free the handle. No callback runs once this returns.
+#[no_mangle]
+pub extern "C" fn pier_sftp_watch_stop(watch: PierSftpWatchHandle) {
+    if watch.is_null() {
+        return;
+    }
+    let watch = unsafe { Box::from_raw(watch) };
+    ffi_block_on(watch.stop());
+}
+
+/// Space on the filesystem holding a remote path, to check an upload
+/// fits. Asks the SFTP server, and if it can't tell, `df` over the SSH
+/// connection; either handle may be null, not both.
+/// Returns JSON: {"total": N, "used": N, "available": N} in bytes, with
+/// "available" what unprivileged users may fill, or null on error.
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_sftp_filesystem_space(
+    handle: PierSftpHandle,
+    ssh_handle: PierSshHandle,
+    path: *const c_char,
+) -> *mut c_char {
+    if (handle.is_null() && ssh_handle.is_null()) || path.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    let session_ptr = SendPtr(ssh_handle);
+    match ffi_block_on(async move {
+        let sftp = (!sftp_ptr.get().is_null()).then(|| sftp_ptr.as_ref());
+        let session = (!session_ptr.get().is_null()).then(|| session_ptr.as_ref());
+        disk_space::filesystem_space(session, sftp, &path).await
+    }) {
+        Ok(space) => match serde_json::to_string(&space) {
+            Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
+            Err(_) => std::ptr::null_mut(),
+        },
+        Err(e) => {
+            log::error!("Filesystem space check failed: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Whether anything exists at a remote path.
+/// Returns 1 if it does, 0 if not, -1 on error.
+#[no_mangle]
+pub extern "C" fn pier_sftp_exists(handle: PierSftpHandle, path: *const c_char) -> i32 {
+    if handle.is_null() || path.is_null() {
+        return -1;
+    }
+
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().exists(&path).await }) {
+        Ok(exists) => exists as i32,
+        Err(e) => {
+            log::error!("SFTP exists check failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Create a remote directory and any missing parents, like `mkdir -p`.
+/// Returns 0 on success (also if it already exists), -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_create_dir_all(handle: PierSftpHandle, path: *const c_char) -> i32 {
+    if handle.is_null() || path.is_null() {
+        return -1;
+    }
+
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().create_dir_all(&path).await }) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("SFTP mkdir -p failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// The session's working directory on the server, usually the home
+/// directory.
+/// Returns null on error. Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_sftp_pwd(handle: PierSftpHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().pwd().await }) {
+        Ok(path) => CString::new(path).unwrap_or_default().into_raw(),
+        Err(e) => {
+            log::error!("SFTP pwd failed: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// The absolute path a remote path stands for, with `.`, `..` and
+/// symlinks resolved, e.g. to find the release a `current` link points at.
+/// Relative paths are from the working directory.
+/// Returns null on error (including, on most servers, a missing path).
+/// Caller must free with pier_string_free.
+#[no_mangle]
+pub extern "C" fn pier_sftp_canonicalize(handle: PierSftpHandle, path: *const c_char) -> *mut c_char {
+    if handle.is_null() || path.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let path = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().canonicalize(&path).await }) {
+        Ok(path) => CString::new(path).unwrap_or_default().into_raw(),
+        Err(e) => {
+            log::error!("SFTP canonicalize failed: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Make `link_path` a hard link to the remote file `target`.
+/// Returns 0 on success, 1 if the server doesn't support hard links
+/// (OpenSSH does), -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_sftp_hard_link(
+    handle: PierSftpHandle,
+    target: *const c_char,
+    link_path: *const c_char,
+) -> i32 {
+    if handle.is_null() || target.is_null() || link_path.is_null() {
+        return -1;
+    }
+
+    let target = unsafe { CStr::from_ptr(target).to_str().unwrap_or("") }.to_string();
+    let link_path = unsafe { CStr::from_ptr(link_path).to_str().unwrap_or("") }.to_string();
+    let sftp_ptr = SendPtr(handle);
+    match ffi_block_on(async move { sftp_ptr.as_ref().hard_link(&target, &link_path).await }) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            log::error!("SFTP hard link failed: {}", e);
+            -1
+        }
+    }
+}
+
+// ═══════════════════════════════════════════════════════════
+// Transfer Queue FFI
+// ═══════════════════════════════════════════════════════════
+
+/// Opaque pointer to a transfer queue.
+pub type PierTransfersHandle = *mut TransferManager;
+
+/// Create a transfer queue running up to `max_concurrent` transfers (at
+/// least 1) over an SFTP session. Free it with pier_transfers_free before
+/// closing the SFTP handle.
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn pier_transfers_new(sftp: PierSftpHandle, max_concurrent: u32) -> PierTransfersHandle {
+    if sftp.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let sftp = unsafe { &*sftp };
+    match TransferManager::new(sftp, ssh_runtime().handle(), max_concurrent as usize) {
+        Ok(manager) => Box::into_raw(Box::new(manager)),
+        Err(e) => {
+            log::error!("Failed to create transfer queue: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Open `channels` more SFTP channels on the SSH connection the queue's
+/// SFTP session runs on, and spread transfers across them, which speeds up
+/// many small files on high-latency links. Raise the concurrency limit to
+/// match.
+/// Returns how many channels the queue has, or -1 on failure.
+#[no_mangle]
+pub extern "C" fn pier_transfers_open_channels(
+    handle: PierTransfersHandle,
+    ssh_handle: PierSshHandle,
+    channels: u32,
+) -> i32 {
+    if handle.is_null() || ssh_handle.is_null() {
+        return -1;
+    }
+
+    let manager_ptr = SendPtr(handle);
+    let session_ptr = SendPtr(ssh_handle);
+
+    // 10-second timeout per channel: open + subsystem request + SFTP init
+    let timeout = std::time::Duration::from_secs(10 * channels.max(1) as u64);
+    match ffi_block_on(async move {
+        let session = session_ptr.as_ref();
+        tokio::time::timeout(timeout, manager_ptr.as_ref().open_channels(session, channels as usize)).await
+    }) {
+        Ok(Ok(count)) => count as i32,
+        Ok(Err(e)) => {
+            log::error!("Opening SFTP channels failed: {}", e);
+            -1
+        }
+        Err(_) => {
+            log::warn!("Opening SFTP channels timed out");
+            -1
+        }
+    }
+}
+
+/// Cancel the transfers still running and free the queue.
+#[no_mangle]
+pub extern "C" fn pier_transfers_free(handle: PierTransfersHandle) {
+    if !handle.is_null() {
+        unsafe { drop(Box::from_raw(handle)) };
+    }
+}
+
+/// Queue a download of `remote_path` to `local_path`.
+/// Returns the transfer id, or 0 on invalid arguments.
+#[no_mangle]
+pub extern "C" fn pier_transfers_add_download(
+    handle: PierTransfersHandle,
+    remote_path: *const c_char,
+    local_path: *const c_char,
+) -> u64 {
+    if handle.is_null() || remote_path.is_null() || local_path.is_null() {
+        return 0;
+    }
+
+    let manager = unsafe { &*handle };
+    let remote = unsafe { CStr::from_ptr(remote_path).to_str().unwrap_or("") };
+    let local = unsafe { CStr::from_ptr(local_path).to_str().unwrap_or("") };
+    manager.download(remote, local)
+}
+
+/// Queue an upload of `local_path` to `remote_path`.
+/// Returns the transfer id, or 0 on invalid arguments.
+#[no_mangle]
+pub extern "C" fn pier_transfers_add_upload(
+    handle: PierTransfersHandle,
+    local_path: *const c_char,
+    remote_path: *const c_char,
+) -> u64 {
+    if handle.is_null() || local_path.is_null() || remote_path.is_null() {
+        return 0;
+    }
+
+    let manager = unsafe { &*handle };
+    let local = unsafe { CStr::from_ptr(local_path).to_str().unwrap_or("") };
+    let remote = unsafe { CStr::from_ptr(remote_path).to_str().unwrap_or("") };
+    manager.upload(local, remote)
+}
+
+/// Pause a queued or running transfer; it keeps what it has moved.
+/// Returns 0 on success, -1 if there's no such transfer or it can't pause.
+#[no_mangle]
+pub extern "C" fn pier_transfers_pause(handle: PierTransfersHandle, id: u64) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+
+    let manager = unsafe { &*handle };
+    match manager.pause(id) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::warn!("Pause transfer failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Queue a paused or failed transfer again, to carry on where it stopped.
+/// Returns 0 on success, -1 if there's no such transfer or it can't resume.
+#[no_mangle]
+pub extern "C" fn pier_transfers_resume(handle: PierTransfersHandle, id: u64) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+
+    let manager = unsafe { &*handle };
+    match manager.resume(id) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::warn!("Resume transfer failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Cancel a transfer, leaving any partial file behind.
+/// Returns 0 on success, -1 if there's no such transfer.
+#[no_mangle]
+pub extern "C" fn pier_transfers_cancel(handle: PierTransfersHandle, id: u64) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+
+    let manager = unsafe { &*handle };
+    match manager.cancel(id) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::warn!("Cancel transfer failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Move a transfer to `index` in the queue (the end if past it); queued
+/// transfers start in queue order.
+/// Returns 0 on success, -1 if there's no such transfer.
+#[no_mangle]
+pub extern "C" fn pier_transfers_move(handle: PierTransfersHandle, id: u64, index: u32) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+
+    let manager = unsafe { &*handle };
+    match manager.move_to(id, index as usize) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::warn!("Move transfer failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// Hold a transfer to `bytes_per_sec`, 0 for no limit, at once if it's
+/// running. The global limit applies as well.
+/// Returns 0 on success, -1 if there's no such transfer.
+#[no_mangle]
+pub extern "C" fn pier_transfers_set_rate_limit(handle: PierTransfersHandle, id: u64, bytes_per_sec: u64) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+
+    let manager = unsafe { &*handle };
+    match manager.set_rate_limit(id, bytes_per_sec) {
         Ok(()) => 0,
-        Err(_) => -1,
+        Err(e) => {
+            log::warn!("Set transfer rate limit failed: {}", e);
+            -1
+        }
     }
 }
 
-/// Get the PTY file descriptor for polling.
+/// Change how many transfers run at once (at least 1).
 #[no_mangle]
-pub extern "C" fn pier_terminal_fd(handle: PierTerminalHandle) -> i32 {
+pub extern "C" fn pier_transfers_set_max_concurrent(handle: PierTransfersHandle, max_concurrent: u32) {
     if handle.is_null() {
-        return -1;
+        return;
     }
-    let session = unsafe { &*handle };
-    session.pty.raw_fd()
-}
 
-// ═══════════════════════════════════════════════════════════
-// File Search FFI
-// ═══════════════════════════════════════════════════════════
+    let manager = unsafe { &*handle };
+    manager.set_max_concurrent(max_concurrent as usize);
+}
 
-/// Search result returned via FFI as a JSON string.
-/// Caller must free the returned string with pier_string_free.
+/// The queue as a JSON array in queue order: [{id, direction ("upload" or
+/// "download"), local_path, remote_path, state ("queued", "running",
+/// "paused", "completed", "failed" or "cancelled"), done, total,
+/// bytes_per_sec, eta_secs, rate_limit, error}]. Poll it to show progress.
+/// Caller must free with pier_string_free.
 #[no_mangle]
-pub extern "C" fn pier_search_files(
-    root: *const c_char,
-    pattern: *const c_char,
-    max_results: usize,
-) -> *mut c_char {
-    if root.is_null() || pattern.is_null() {
+pub extern "C" fn pier_transfers_list(handle: PierTransfersHandle) -> *mut c_char {
+    if handle.is_null() {
         return std::ptr::null_mut();
     }
 
-    let root_str = unsafe { CStr::from_ptr(root).to_str().unwrap_or("") };
-    let pattern_str = unsafe { CStr::from_ptr(pattern).to_str().unwrap_or("") };
-
-    let results = search::search_files(root_str, pattern_str, max_results);
-
-    match serde_json::to_string(&results) {
+    let manager = unsafe { &*handle };
+    match serde_json::to_string(&manager.list()) {
         Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
         Err(_) => std::ptr::null_mut(),
     }
 }
 
-/// List directory contents. Returns JSON string.
-/// Caller must free the returned string with pier_string_free.
+/// Remove completed and cancelled transfers from the queue.
 #[no_mangle]
-pub extern "C" fn pier_list_directory(path: *const c_char) -> *mut c_char {
-    if path.is_null() {
-        return std::ptr::null_mut();
+pub extern "C" fn pier_transfers_clear_finished(handle: PierTransfersHandle) {
+    if handle.is_null() {
+        return;
     }
 
-    let path_str = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") };
-
-    match search::list_directory(path_str) {
-        Ok(entries) => {
-            match serde_json::to_string(&entries) {
-                Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
-                Err(_) => std::ptr::null_mut(),
-            }
-        }
-        Err(_) => std::ptr::null_mut(),
-    }
+    let manager = unsafe { &*handle };
+    manager.clear_finished();
 }
 
 // ═══════════════════════════════════════════════════════════
-// SSH FFI
+// Connection Profiles FFI
 // ═══════════════════════════════════════════════════════════
 
-/// Opaque pointer to an SSH session.
-pub type PierSshHandle = *mut SshSession;
+/// Opaque pointer to a connection profile store.
+pub type PierProfileStoreHandle = *mut ProfileStore;
 
-/// Connect to an SSH server.
-/// auth_type: 0 = password, 1 = key file
-/// credential: password string (auth_type=0) or key file path (auth_type=1)
-/// Returns null on failure.
+/// Open the profile file at `path` (created on the first save), sealed
+/// with the 32-byte `key` the app keeps, e.g. in the Keychain. With
+/// `keep_secrets`, saved profiles keep their passwords and passphrases;
+/// otherwise these are dropped on save.
+/// Returns null on failure, e.g. a wrong key.
 #[no_mangle]
-pub extern "C" fn pier_ssh_connect(
-    host: *const c_char,
-    port: u16,
-    username: *const c_char,
-    auth_type: i32,
-    credential: *const c_char,
-) -> PierSshHandle {
-    if host.is_null() || username.is_null() || credential.is_null() {
+pub extern "C" fn pier_profiles_open(
+    path: *const c_char,
+    key: *const u8,
+    key_len: usize,
+    keep_secrets: bool,
+) -> PierProfileStoreHandle {
+    if path.is_null() || key.is_null() || key_len != 32 {
         return std::ptr::null_mut();
     }
+    let path_str = unsafe { CStr::from_ptr(path).to_str().unwrap_or("") };
+    let mut key_bytes = [0u8; 32];
+    key_bytes.copy_from_slice(unsafe { std::slice::from_raw_parts(key, key_len) });
 
-    let host_str = unsafe { CStr::from_ptr(host).to_str().unwrap_or("") };
-    let username_str = unsafe { CStr::from_ptr(username).to_str().unwrap_or("") };
-    let credential_str = unsafe { CStr::from_ptr(credential).to_str().unwrap_or("") };
-
-    let auth = match auth_type {
-        0 => SshAuth::Password(credential_str.to_string()),
-        1 => SshAuth::KeyFile {
-            path: credential_str.to_string(),
-            passphrase: None,
-        },
-        _ => {
-            log::error!("Unknown SSH auth type: {}", auth_type);
-            return std::ptr::null_mut();
-        }
-    };
-
-    let config = SshConfig {
-        host: host_str.to_string(),
-        port,
-        username: username_str.to_string(),
-        auth,
-    };
-
-    let mut session = SshSession::new(config);
-
-    // Use ffi_block_on to safely run async connect on a fresh thread
-    match ffi_block_on(async move { session.connect().await.map(|()| session) }) {
-        Ok(connected_session) => {
-            log::info!("SSH connected to {}:{}", host_str, port);
-            Box::into_raw(Box::new(connected_session))
-        }
+    match ProfileStore::open(path_str, key_bytes, keep_secrets) {
+        Ok(store) => Box::into_raw(Box::new(store)),
         Err(e) => {
-            log::error!("SSH connect failed: {}", e);
+            log::error!("Failed to open profiles {}: {}", path_str, e);
             std::ptr::null_mut()
         }
     }
 }
 
-/// Disconnect an SSH session and free the handle.
+/// Close a profile store.
 #[no_mangle]
-pub extern "C" fn pier_ssh_disconnect(handle: PierSshHandle) -> i32 {
-    if handle.is_null() {
-        return -1;
-    }
-
-    let mut session = unsafe { Box::from_raw(handle) };
-    match ffi_block_on(async move { session.disconnect().await }) {
-        Ok(()) => {
-            log::info!("SSH disconnected");
-            0
-        }
-        Err(e) => {
-            log::error!("SSH disconnect error: {}", e);
-            -1
+pub extern "C" fn pier_profiles_destroy(handle: PierProfileStoreHandle) {
+    if !handle.is_null() {
+        unsafe {
+            drop(Box::from_raw(handle));
         }
     }
 }
 
-/// Check if SSH session is connected.
-/// Returns 1 if connected, 0 if not, -1 on invalid handle.
-#[no_mangle]
-pub extern "C" fn pier_ssh_is_connected(handle: PierSshHandle) -> i32 {
-    if handle.is_null() {
-        return -1;
-    }
-    // Safety: we only read, handle is valid
-    let session = unsafe { &*handle };
-    if session.is_connected() { 1 } else { 0 }
-}
-
-/// Detect services installed on the remote server.
-/// Returns a JSON array of DetectedService.
+/// All profiles as a JSON array of
+/// `{"id", "name", "group", "config": {...}, "updated_at"}`, `config`
+/// being the connection settings (host, port, username, auth, jump_hosts,
+/// keepalive, ...).
+/// Returns null on invalid handle.
 /// Caller must free with pier_string_free.
 #[no_mangle]
-pub extern "C" fn pier_ssh_detect_services(handle: PierSshHandle) -> *mut c_char {
+pub extern "C" fn pier_profiles_list(handle: PierProfileStoreHandle) -> *mut c_char {
     if handle.is_null() {
         return std::ptr::null_mut();
     }
-
-    let session_ptr = SendPtr(handle);
-
-    // 30-second overall timeout for service detection to prevent blocking
-    // when the SSH connection is dead (e.g. network change).
-    let services = match ffi_block_on(async move {
-        let session = session_ptr.as_ref();
-        tokio::time::timeout(
-            std::time::Duration::from_secs(30),
-            service_detector::detect_all(session),
-        ).await
-    }) {
-        Ok(services) => services,
-        Err(_) => {
-            log::warn!("Service detection timed out after 30s");
-            Vec::new()
-        }
-    };
-
-    match serde_json::to_string(&services) {
+    let store = unsafe { &*handle };
+    match serde_json::to_string(store.profiles()) {
         Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
-        Err(e) => {
-            log::error!("Failed to serialize services: {}", e);
-            std::ptr::null_mut()
-        }
+        Err(_) => std::ptr::null_mut(),
     }
 }
 
-/// Execute a command on the remote server.
-/// Returns JSON: {"exit_code": N, "stdout": "..."}
+/// The profile with `id` as JSON (see pier_profiles_list).
+/// Returns null if there is none.
 /// Caller must free with pier_string_free.
 #[no_mangle]
-pub extern "C" fn pier_ssh_exec(
-    handle: PierSshHandle,
-    command: *const c_char,
-) -> *mut c_char {
-    if handle.is_null() || command.is_null() {
+pub extern "C" fn pier_profiles_get(handle: PierProfileStoreHandle, id: *const c_char) -> *mut c_char {
+    if handle.is_null() || id.is_null() {
         return std::ptr::null_mut();
     }
-
-    let cmd_str = unsafe { CStr::from_ptr(command).to_str().unwrap_or("") };
-    let session_ptr = SendPtr(handle as *mut SshSession);
-    let cmd_string = cmd_str.to_string();
-
-    // 60-second overall timeout to prevent blocking the FFI thread indefinitely
-    // when the SSH connection is dead (e.g. network change).
-    match ffi_block_on(async move {
-        let session = session_ptr.as_ref();
-        tokio::time::timeout(
-            std::time::Duration::from_secs(60),
-            session.exec_command(&cmd_string),
-        ).await
-    }) {
-        Ok(Ok((exit_code, stdout))) => {
-            let result = serde_json::json!({
-                "exit_code": exit_code,
-                "stdout": stdout,
-            });
-            match CString::new(result.to_string()) {
-                Ok(cs) => cs.into_raw(),
-                Err(_) => std::ptr::null_mut(),
-            }
-        }
-        Ok(Err(e)) => {
-            log::error!("SSH exec failed: {}", e);
-            let err = serde_json::json!({
-                "exit_code": -1,
-                "stdout": format!("Error: {}", e),
-            });
-            CString::new(err.to_string()).unwrap_or_default().into_raw()
-        }
-        Err(_) => {
-            log::warn!("SSH exec timed out after 60s for command: {}", cmd_str);
-            let err = serde_json::json!({
-                "exit_code": -1,
-                "stdout": "Error: command timed out after 60s",
-            });
-            CString::new(err.to_string()).unwrap_or_default().into_raw()
-        }
+    let store = unsafe { &*handle };
+    let id_str = unsafe { CStr::from_ptr(id).to_str().unwrap_or("") };
+    match store.get(id_str).map(serde_json::to_string) {
+        Some(Ok(json)) => CString::new(json).unwrap_or_default().into_raw(),
+        _ => std::ptr::null_mut(),
     }
 }
 
-// ═══════════════════════════════════════════════════════════
-// SSH Port Forwarding FFI
-// ═══════════════════════════════════════════════════════════
-
-/// Start local port forwarding: 127.0.0.1:local_port → remote_host:remote_port.
-/// Returns 0 on success, -1 on failure.
+/// Save a profile given as JSON (see pier_profiles_list): a new one
+/// without `id`, or replacing the one with its id. `updated_at` is set.
+/// Returns the profile's id, or null on failure.
+/// Caller must free with pier_string_free.
 #[no_mangle]
-pub extern "C" fn pier_ssh_forward_port(
-    handle: PierSshHandle,
-    local_port: u16,
-    remote_host: *const c_char,
-    remote_port: u16,
-) -> i32 {
-    if handle.is_null() || remote_host.is_null() {
-        return -1;
+pub extern "C" fn pier_profiles_save(handle: PierProfileStoreHandle, profile_json: *const c_char) -> *mut c_char {
+    if handle.is_null() || profile_json.is_null() {
+        return std::ptr::null_mut();
     }
-
-    let host_str = unsafe { CStr::from_ptr(remote_host).to_str().unwrap_or("") };
-    let session_ptr = SendPtr(handle);
-    let host_string = host_str.to_string();
-
-    // 10-second timeout: TcpListener::bind + SSH channel setup
-    match ffi_block_on(async move {
-        let session = session_ptr.as_mut();
-        tokio::time::timeout(
-            std::time::Duration::from_secs(10),
-            session.start_port_forward(local_port, &host_string, remote_port),
-        ).await
-    }) {
-        Ok(Ok(())) => 0,
-        Ok(Err(e)) => {
-            log::error!("Port forward failed: {}", e);
-            -1
-        }
-        Err(_) => {
-            log::warn!("Port forward timed out after 10s for port {}", local_port);
-            -1
+    let store = unsafe { &mut *handle };
+    let json = unsafe { CStr::from_ptr(profile_json).to_str().unwrap_or("") };
+    let result = serde_json::from_str::<Profile>(json)
+        .map_err(anyhow::Error::from)
+        .and_then(|profile| store.save(profile));
+    match result {
+        Ok(id) => CString::new(id).unwrap_or_default().into_raw(),
+        Err(e) => {
+            log::error!("Saving profile failed: {}", e);
+            std::ptr::null_mut()
         }
     }
 }
 
-/// Stop a local port forward.
-/// Returns 0 on success, -1 if no such forward.
+/// Delete the profile with `id`.
+/// Returns 1 if deleted, 0 if there was none, -1 on error.
 #[no_mangle]
-pub extern "C" fn pier_ssh_stop_forward(handle: PierSshHandle, local_port: u16) -> i32 {
-    if handle.is_null() {
+pub extern "C" fn pier_profiles_remove(handle: PierProfileStoreHandle, id: *const c_char) -> i32 {
+    if handle.is_null() || id.is_null() {
         return -1;
     }
-
-    let session = unsafe { &mut *handle };
-    match session.stop_port_forward(local_port) {
-        Ok(()) => 0,
+    let store = unsafe { &mut *handle };
+    let id_str = unsafe { CStr::from_ptr(id).to_str().unwrap_or("") };
+    match store.remove(id_str) {
+        Ok(removed) => removed as i32,
         Err(e) => {
-            log::error!("Stop forward failed: {}", e);
+            log::error!("Removing profile failed: {}", e);
             -1
         }
     }
 }
 
-/// List active forward ports as a JSON array.
+/// All profiles as plain JSON for pier_profiles_import, with passwords
+/// and passphrases only if `include_secrets`.
+/// Returns null on invalid handle.
 /// Caller must free with pier_string_free.
 #[no_mangle]
-pub extern "C" fn pier_ssh_list_forwards(handle: PierSshHandle) -> *mut c_char {
+pub extern "C" fn pier_profiles_export(handle: PierProfileStoreHandle, include_secrets: bool) -> *mut c_char {
     if handle.is_null() {
         return std::ptr::null_mut();
     }
-
-    let session = unsafe { &*handle };
-    let ports = session.active_forwards();
-
-    match serde_json::to_string(&ports) {
+    let store = unsafe { &*handle };
+    match store.export(include_secrets) {
         Ok(json) => CString::new(json).unwrap_or_default().into_raw(),
         Err(_) => std::ptr::null_mut(),
     }
 }
 
+/// Import profiles exported with pier_profiles_export: those with the id
+/// of a saved profile replace it, the others are added.
+/// Returns how many were imported, or -1 on error.
+#[no_mangle]
+pub extern "C" fn pier_profiles_import(handle: PierProfileStoreHandle, json: *const c_char) -> i32 {
+    if handle.is_null() || json.is_null() {
+        return -1;
+    }
+    let store = unsafe { &mut *handle };
+    let json_str = unsafe { CStr::from_ptr(json).to_str().unwrap_or("") };
+    match store.import(json_str) {
+        Ok(count) => count as i32,
+        Err(e) => {
+            log::error!("Importing profiles failed: {}", e);
+            -1
+        }
+    }
+}
+
 // ═══════════════════════════════════════════════════════════
 // Git Graph FFI — direct .git access via libgit2
 // ═══════════════════════════════════════════════════════════
@@ -797,3 +4796,22 @@ pub extern "C" fn pier_init() {
     let _ = env_logger::try_init();
     log::info!("Pier Core initialized");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_cancel() {
+        let id = pier_ssh_operation_begin();
+        let pending = until_cancelled(std::future::pending::<()>());
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            pier_ssh_cancel(id)
+        });
+        assert_eq!(ffi_block_on(pending), None);
+        assert_eq!(canceller.join().unwrap(), 0);
+        pier_ssh_operation_end();
+        assert_eq!(pier_ssh_cancel(id), -1);
+    }
+}
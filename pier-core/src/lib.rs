@@ -1,7 +1,7 @@
 //! Pier Core — high-performance engine for Pier Terminal
 //!
-//! Provides terminal emulation, SSH/SFTP, file search, git graph, and crypto
-//! through a C FFI interface consumed by Swift.
+//! Provides terminal emulation, SSH/SFTP, file search, git graph, connection
+//! profiles and crypto through a C FFI interface consumed by Swift.
 
 pub mod ffi;
 pub mod terminal;
@@ -9,3 +9,4 @@ pub mod ssh;
 pub mod search;
 pub mod crypto;
 pub mod git_graph;
+pub mod profiles;
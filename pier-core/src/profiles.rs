@@ -0,0 +1,253 @@
+//! Saved connection profiles.
+//!
+//! Connection bookmarks are kept in one file, sealed with AES-256-GCM under
+//! a key the app holds (e.g. in the Keychain), so the file can be synced
+//! without exposing host names and users. Passwords and key passphrases are
+//! only stored if the store is opened to keep them; otherwise they are
+//! dropped when a profile is saved and the app supplies them at connect
+//! time. Export produces plain JSON, without secrets unless asked, for
+//! moving profiles to another store.
+
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::crypto;
+use crate::ssh::{SshAuth, SshConfig};
+
+/// Start of a profile file, before the sealed contents.
+const MAGIC: &[u8] = b"PIERPROF1\n";
+
+/// A saved connection.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Profile {
+    /// Assigned when first saved
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    /// Folder to show the profile in, if any
+    #[serde(default)]
+    pub group: Option<String>,
+    pub config: SshConfig,
+    /// Seconds since the Unix epoch of the last save
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+/// What the file holds once opened.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ProfileFile {
+    version: u32,
+    profiles: Vec<Profile>,
+}
+
+/// The profiles of one file. Changes are written right away.
+pub struct ProfileStore {
+    path: PathBuf,
+    key: [u8; 32],
+    keep_secrets: bool,
+    profiles: Vec<Profile>,
+}
+
+impl ProfileStore {
+    /// Open the store at `path`, sealed with `key`; empty if the file
+    /// doesn't exist yet. With `keep_secrets`, saved profiles keep their
+    /// passwords and passphrases.
+    pub fn open(path: impl Into<PathBuf>, key: [u8; 32], keep_secrets: bool) -> Result<Self, anyhow::Error> {
+        let path = path.into();
+        let profiles = match std::fs::read(&path) {
+            Ok(data) => {
+                let sealed = data
+                    .strip_prefix(MAGIC)
+                    .ok_or_else(|| anyhow::anyhow!("{} is not a profile file", path.display()))?;
+                let json = crypto::decrypt(&key, sealed)
+                    .map_err(|_| anyhow::anyhow!("Wrong key for {}, or the file is damaged", path.display()))?;
+                let file: ProfileFile = serde_json::from_slice(&json)?;
+                file.profiles
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, key, keep_secrets, profiles })
+    }
+
+    pub fn profiles(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.id == id)
+    }
+
+    /// Add `profile`, or replace the one with its id. Returns the id.
+    pub fn save(&mut self, mut profile: Profile) -> Result<String, anyhow::Error> {
+        if profile.name.trim().is_empty() {
+            return Err(anyhow::anyhow!("A profile needs a name"));
+        }
+        if profile.id.is_empty() {
+            profile.id = new_id()?;
+        }
+        if !self.keep_secrets {
+            strip_secrets(&mut profile.config);
+        }
+        profile.updated_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let id = profile.id.clone();
+        match self.profiles.iter_mut().find(|saved| saved.id == id) {
+            Some(saved) => *saved = profile,
+            None => self.profiles.push(profile),
+        }
+        self.write()?;
+        Ok(id)
+    }
+
+    /// Delete the profile with `id`. Returns whether there was one.
+    pub fn remove(&mut self, id: &str) -> Result<bool, anyhow::Error> {
+        let count = self.profiles.len();
+        self.profiles.retain(|profile| profile.id != id);
+        if self.profiles.len() == count {
+            return Ok(false);
+        }
+        self.write()?;
+        Ok(true)
+    }
+
+    /// All profiles as a JSON array, secrets removed unless
+    /// `include_secrets`.
+    pub fn export(&self, include_secrets: bool) -> Result<String, anyhow::Error> {
+        let mut profiles = self.profiles.clone();
+        if !include_secrets {
+            for profile in &mut profiles {
+                strip_secrets(&mut profile.config);
+            }
+        }
+        Ok(serde_json::to_string_pretty(&profiles)?)
+    }
+
+    /// Add the profiles of an export: those with the id of a saved one
+    /// replace it, the others are added. Returns how many were imported.
+    pub fn import(&mut self, json: &str) -> Result<usize, anyhow::Error> {
+        let imported: Vec<Profile> = serde_json::from_str(json)?;
+        let count = imported.len();
+        for mut profile in imported {
+            if profile.id.is_empty() {
+                profile.id = new_id()?;
+            }
+            if !self.keep_secrets {
+                strip_secrets(&mut profile.config);
+            }
+            match self.profiles.iter_mut().find(|saved| saved.id == profile.id) {
+                Some(saved) => *saved = profile,
+                None => self.profiles.push(profile),
+            }
+        }
+        self.write()?;
+        Ok(count)
+    }
+
+    /// Seal the profiles into the file, replacing it in one step so a
+    /// crash can't leave half a file.
+    fn write(&self) -> Result<(), anyhow::Error> {
+        let json = serde_json::to_vec(&ProfileFile { version: 1, profiles: self.profiles.clone() })?;
+        let mut data = MAGIC.to_vec();
+        data.extend(crypto::encrypt(&self.key, &json)?);
+
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        std::fs::write(&temp, data)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&temp, std::fs::Permissions::from_mode(0o600))?;
+        }
+        std::fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Forget `config`'s password, key passphrase and proxy password, and its
+/// jump hosts'.
+fn strip_secrets(config: &mut SshConfig) {
+    match &mut config.auth {
+        SshAuth::Password(password) => password.clear(),
+        SshAuth::KeyFile { passphrase, .. } => *passphrase = None,
+        SshAuth::Agent | SshAuth::KeyboardInteractive => {}
+    }
+    if let Some(proxy) = &mut config.proxy {
+        proxy.password = None;
+    }
+    for jump in &mut config.jump_hosts {
+        strip_secrets(jump);
+    }
+}
+
+/// A random profile id, 32 hex digits.
+fn new_id() -> Result<String, anyhow::Error> {
+    use ring::rand::SecureRandom;
+
+    let mut id = [0u8; 16];
+    ring::rand::SystemRandom::new()
+        .fill(&mut id)
+        .map_err(|_| anyhow::anyhow!("RNG failed"))?;
+    Ok(data_encoding::HEXLOWER.encode(&id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str, password: &str) -> Profile {
+        Profile {
+            id: String::new(),
+            name: name.to_string(),
+            group: Some("prod".to_string()),
+            config: SshConfig {
+                host: "db1.example.com".to_string(),
+                auth: SshAuth::Password(password.to_string()),
+                ..SshConfig::default()
+            },
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_profile_store() {
+        let path = std::env::temp_dir().join(format!("pier-profiles-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let key = [7u8; 32];
+
+        let mut store = ProfileStore::open(&path, key, false).unwrap();
+        assert!(store.profiles().is_empty());
+        let id = store.save(profile("db1", "hunter2")).unwrap();
+        assert!(store.save(profile(" ", "")).is_err());
+
+        // Sealed on disk, secrets dropped
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.starts_with(MAGIC));
+        assert!(!String::from_utf8_lossy(&data).contains("db1.example.com"));
+        let store = ProfileStore::open(&path, key, false).unwrap();
+        let saved = store.get(&id).unwrap();
+        assert_eq!((saved.name.as_str(), saved.group.as_deref()), ("db1", Some("prod")));
+        assert!(matches!(&saved.config.auth, SshAuth::Password(password) if password.is_empty()));
+        assert!(saved.updated_at > 0);
+        assert!(ProfileStore::open(&path, [8u8; 32], false).is_err());
+
+        // Kept secrets stay out of exports unless asked for
+        let mut store = ProfileStore::open(&path, key, true).unwrap();
+        let mut renamed = profile("db1 primary", "hunter2");
+        renamed.id = id.clone();
+        store.save(renamed).unwrap();
+        assert_eq!(store.profiles().len(), 1);
+        assert!(!store.export(false).unwrap().contains("hunter2"));
+        let export = store.export(true).unwrap();
+        assert!(export.contains("hunter2"));
+
+        assert!(store.remove(&id).unwrap());
+        assert!(!store.remove(&id).unwrap());
+        assert_eq!(store.import(&export).unwrap(), 1);
+        assert_eq!(store.get(&id).unwrap().name, "db1 primary");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
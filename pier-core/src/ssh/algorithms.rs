@@ -0,0 +1,128 @@
+//! Cipher, key exchange and MAC preferences.
+//!
+//! Lists are written as in ssh_config's `Ciphers`, `KexAlgorithms` and
+//! `MACs`: comma separated names replacing the defaults, or with a leading
+//! `+` appended to them, `-` removed from them (`*`/`?` patterns allowed)
+//! or `^` put first. That way a legacy appliance can be reached with
+//! `+diffie-hellman-group1-sha1` without spelling out the whole list, and
+//! a hardened policy can name exactly what is allowed. Names the SSH
+//! library doesn't implement are skipped.
+
+use russh::{kex, Preferred};
+
+use super::config_file::wildcard_match;
+use super::AlgorithmPreferences;
+
+/// Key exchange extensions the client always offers: they aren't
+/// algorithms, and strict key exchange guards against prefix truncation
+/// (Terrapin).
+const KEX_EXTENSIONS: &[kex::Name] = &[kex::EXTENSION_SUPPORT_AS_CLIENT, kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT];
+
+/// The library's preferences with `prefs` applied.
+pub fn preferred(prefs: &AlgorithmPreferences) -> Result<Preferred, anyhow::Error> {
+    let defaults = Preferred::DEFAULT;
+    let mut preferred = Preferred::DEFAULT;
+    if let Some(spec) = &prefs.ciphers {
+        preferred.cipher = apply(spec, &defaults.cipher, "ciphers")?.into();
+    }
+    if let Some(spec) = &prefs.kex {
+        let mut names = apply(spec, &defaults.kex, "key exchange algorithms")?;
+        for extension in KEX_EXTENSIONS {
+            if !names.contains(extension) {
+                names.push(*extension);
+            }
+        }
+        preferred.kex = names.into();
+    }
+    if let Some(spec) = &prefs.macs {
+        preferred.mac = apply(spec, &defaults.mac, "MACs")?.into();
+    }
+    Ok(preferred)
+}
+
+/// Apply the list `spec` to `defaults`. Fails if nothing usable is left.
+fn apply<N>(spec: &str, defaults: &[N], what: &str) -> Result<Vec<N>, anyhow::Error>
+where
+    N: Copy + PartialEq + AsRef<str> + for<'a> TryFrom<&'a str>,
+{
+    let spec = spec.trim();
+    let known = |list: &str| -> Vec<N> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| match N::try_from(name) {
+                Ok(name) => Some(name),
+                Err(_) => {
+                    log::warn!("Unsupported SSH algorithm {} ignored", name);
+                    None
+                }
+            })
+            .collect()
+    };
+
+    let names: Vec<N> = if let Some(list) = spec.strip_prefix('+') {
+        let mut names = defaults.to_vec();
+        names.extend(known(list).into_iter().filter(|name| !defaults.contains(name)));
+        names
+    } else if let Some(list) = spec.strip_prefix('-') {
+        let patterns: Vec<&str> = list.split(',').map(str::trim).collect();
+        defaults
+            .iter()
+            .filter(|name| !patterns.iter().any(|pattern| wildcard_match(pattern, name.as_ref())))
+            .copied()
+            .collect()
+    } else if let Some(list) = spec.strip_prefix('^') {
+        let mut names = known(list);
+        names.extend(defaults.iter().filter(|name| !names.contains(name)).copied().collect::<Vec<_>>());
+        names
+    } else {
+        known(spec)
+    };
+
+    if names.is_empty() {
+        return Err(anyhow::anyhow!("None of the configured SSH {} are supported: {}", what, spec));
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh::mac;
+
+    fn names<N: AsRef<str>>(list: &[N]) -> Vec<&str> {
+        list.iter().map(AsRef::as_ref).collect()
+    }
+
+    #[test]
+    fn test_apply() {
+        let defaults = [mac::HMAC_SHA256, mac::HMAC_SHA1];
+        assert_eq!(names(&apply("hmac-sha1,bogus", &defaults, "MACs").unwrap()), vec!["hmac-sha1"]);
+        assert_eq!(
+            names(&apply("+hmac-sha2-512,hmac-sha1", &defaults, "MACs").unwrap()),
+            vec!["hmac-sha2-256", "hmac-sha1", "hmac-sha2-512"]
+        );
+        assert_eq!(names(&apply("-hmac-sha2-*", &defaults, "MACs").unwrap()), vec!["hmac-sha1"]);
+        assert_eq!(
+            names(&apply("^hmac-sha1", &defaults, "MACs").unwrap()),
+            vec!["hmac-sha1", "hmac-sha2-256"]
+        );
+        assert!(apply("bogus", &defaults, "MACs").is_err());
+    }
+
+    #[test]
+    fn test_preferred() {
+        let prefs = AlgorithmPreferences {
+            ciphers: Some("aes128-ctr".to_string()),
+            kex: Some("diffie-hellman-group14-sha1".to_string()),
+            macs: None,
+        };
+        let preferred = preferred(&prefs).unwrap();
+        assert_eq!(names(&preferred.cipher), vec!["aes128-ctr"]);
+        assert_eq!(
+            names(&preferred.kex),
+            vec!["diffie-hellman-group14-sha1", "ext-info-c", "kex-strict-c-v00@openssh.com"]
+        );
+        assert_eq!(preferred.mac, Preferred::DEFAULT.mac);
+    }
+}
@@ -0,0 +1,119 @@
+//! Downloading a remote directory as one compressed tar stream, for trees
+//! of many small files where a round trip per file over SFTP is slow.
+//!
+//! The server runs `tar czf -` over exec, and a local `tar xzf -` unpacks
+//! the stream as it arrives. Local tar refuses absolute and `..` member
+//! paths, so a hostile archive can't write outside the destination.
+
+use std::path::Path;
+use std::process::Stdio;
+
+use russh::ChannelMsg;
+use tokio::io::AsyncWriteExt;
+
+use super::session::SshSession;
+use super::sudo::quote;
+
+/// Most of the server's error output kept for the error message.
+const MAX_STDERR: usize = 4096;
+
+/// Download the remote directory `remote_dir` into `local_dir`, as
+/// `local_dir/<name of remote_dir>`, through tar. `on_progress` gets the
+/// compressed bytes received so far. Returns `false`, having downloaded
+/// nothing, if the server has no `tar` or `gzip`.
+pub async fn download_dir(
+    session: &SshSession,
+    remote_dir: &str,
+    local_dir: &Path,
+    mut on_progress: impl FnMut(u64) + Send,
+) -> Result<bool, anyhow::Error> {
+    let (probe, _) = session.exec_command("command -v tar && command -v gzip").await?;
+    if probe != 0 {
+        return Ok(false);
+    }
+
+    std::fs::create_dir_all(local_dir)?;
+    let mut unpack = tokio::process::Command::new("tar")
+        .arg("-xzf")
+        .arg("-")
+        .arg("-C")
+        .arg(local_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut stdin = unpack.stdin.take().ok_or_else(|| anyhow::anyhow!("No stdin for local tar"))?;
+
+    let mut channel = session.exec_channel(&tar_command(remote_dir)).await?;
+    let mut received = 0u64;
+    let mut remote_error = Vec::new();
+    let mut exit_code = None;
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Data { data } => {
+                // Waiting on local tar keeps the channel's window from
+                // running ahead of unpacking
+                if let Err(e) = stdin.write_all(&data).await {
+                    let output = unpack.wait_with_output().await?;
+                    return Err(anyhow::anyhow!(
+                        "Local tar failed ({}): {}",
+                        e,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                }
+                received += data.len() as u64;
+                on_progress(received);
+            }
+            ChannelMsg::ExtendedData { data, .. } => {
+                let room = MAX_STDERR.saturating_sub(remote_error.len());
+                remote_error.extend_from_slice(&data[..data.len().min(room)]);
+            }
+            ChannelMsg::ExitStatus { exit_status } => exit_code = Some(exit_status),
+            ChannelMsg::Close => break,
+            _ => {}
+        }
+    }
+    drop(stdin);
+
+    let output = unpack.wait_with_output().await?;
+    if exit_code != Some(0) {
+        let message = String::from_utf8_lossy(&remote_error);
+        return Err(match exit_code {
+            Some(code) => anyhow::anyhow!("Remote tar failed with exit code {}: {}", code, message.trim()),
+            None => anyhow::anyhow!("Remote tar ended without an exit code: {}", message.trim()),
+        });
+    }
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("Local tar failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(true)
+}
+
+/// A command writing `remote_dir` as a gzipped tar to stdout, with members
+/// named from the directory's own name down.
+fn tar_command(remote_dir: &str) -> String {
+    let trimmed = remote_dir.trim_end_matches('/');
+    let (parent, name) = match trimmed.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((parent, name)) => (parent, name),
+        // The root itself, or a path relative to the home directory
+        None if trimmed.is_empty() => ("/", "."),
+        None => (".", trimmed),
+    };
+    // `./` keeps a name starting with `-` from reading as an option
+    format!("cd {} && tar -czf - {}", quote(parent), quote(&format!("./{}", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tar_command() {
+        assert_eq!(tar_command("/var/log/"), "cd '/var' && tar -czf - './log'");
+        assert_eq!(tar_command("/srv"), "cd '/' && tar -czf - './srv'");
+        assert_eq!(tar_command("-web"), "cd '.' && tar -czf - './-web'");
+        assert_eq!(tar_command("/"), "cd '/' && tar -czf - './.'");
+    }
+}
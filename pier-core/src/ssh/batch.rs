@@ -0,0 +1,118 @@
+//! Commands run in parallel on one or many sessions.
+//!
+//! Each command runs on its own channel, so a batch costs about as long as
+//! its slowest command rather than the sum of all round trips. At most
+//! `parallelism` commands run at once, across all sessions, to stay under
+//! servers' `MaxSessions` and not flood slow links.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Instant;
+
+use tokio::sync::Semaphore;
+
+use super::session::SshSession;
+
+/// Commands running at once when no limit is given.
+pub const DEFAULT_PARALLELISM: usize = 8;
+
+/// How one command of a batch went.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct BatchResult {
+    /// Index of the session the command ran on
+    pub session: usize,
+    pub command: String,
+    /// `None` if the command couldn't be run or timed out
+    pub exit_code: Option<i32>,
+    /// Stdout and stderr
+    pub output: String,
+    pub error: Option<String>,
+    pub elapsed_ms: u64,
+}
+
+/// Run each of `jobs`, a session index into `sessions` and a command, with
+/// at most `parallelism` at once (0 for the default), within each session's
+/// exec timeout. Results come in the order of `jobs`.
+pub async fn run(sessions: &[&SshSession], jobs: &[(usize, String)], parallelism: usize) -> Vec<BatchResult> {
+    let permits = Semaphore::new(if parallelism == 0 { DEFAULT_PARALLELISM } else { parallelism });
+    let permits = &permits;
+    let runs = jobs.iter().map(|(index, command)| async move {
+        let _permit = permits.acquire().await.ok();
+        let started = Instant::now();
+        let result = match sessions.get(*index) {
+            Some(session) => session.exec_command(command).await,
+            None => Err(anyhow::anyhow!("No session {}", index)),
+        };
+        let (exit_code, output, error) = match result {
+            Ok((exit_code, output)) => (Some(exit_code), output, None),
+            Err(e) => (None, String::new(), Some(e.to_string())),
+        };
+        BatchResult {
+            session: *index,
+            command: command.clone(),
+            exit_code,
+            output,
+            error,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+        }
+    });
+    join_all(runs.collect()).await
+}
+
+/// Poll `futures` together until all are done; their outputs in order.
+async fn join_all<F: Future>(futures: Vec<F>) -> Vec<F::Output> {
+    let mut pending: Vec<Pin<Box<F>>> = futures.into_iter().map(Box::pin).collect();
+    let mut outputs: Vec<Option<F::Output>> = pending.iter().map(|_| None).collect();
+    std::future::poll_fn(|cx| {
+        let mut done = true;
+        for (future, output) in pending.iter_mut().zip(outputs.iter_mut()) {
+            if output.is_none() {
+                match future.as_mut().poll(cx) {
+                    Poll::Ready(value) => *output = Some(value),
+                    Poll::Pending => done = false,
+                }
+            }
+        }
+        if done {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+    outputs.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::SshConfig;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_join_all_runs_concurrently() {
+        let permits = Semaphore::new(4);
+        let permits = &permits;
+        let started = Instant::now();
+        let runs = (0..8u64).map(|n| async move {
+            let _permit = permits.acquire().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            n
+        });
+        assert_eq!(join_all(runs.collect()).await, (0..8).collect::<Vec<_>>());
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(100) && elapsed < Duration::from_millis(300), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_run_reports_failures() {
+        let session = SshSession::new(SshConfig::default());
+        let jobs = vec![(0, "uptime".to_string()), (1, "uptime".to_string())];
+        let results = run(&[&session], &jobs, 0).await;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].error.as_deref(), Some("Not connected"));
+        assert_eq!(results[1].error.as_deref(), Some("No session 1"));
+        assert!(results.iter().all(|result| result.exit_code.is_none()));
+    }
+}
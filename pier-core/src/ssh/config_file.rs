@@ -0,0 +1,696 @@
+//! OpenSSH client configuration (`~/.ssh/config`).
+//!
+//! Resolves the options Pier uses for a host alias the way ssh(1) does:
+//! blocks apply when a `Host` pattern matches the alias, the first value
+//! found for an option wins, and `IdentityFile` accumulates. `Include` is
+//! followed (relative paths are under `~/.ssh`, with `*`/`?` wildcards).
+//! `Match` blocks other than `Match all` can't be evaluated here and are
+//! skipped. `ProxyJump` hosts are resolved through the same file.
+
+use std::path::{Path, PathBuf};
+
+use super::{AlgorithmPreferences, KeepalivePolicy, RekeyLimits, SshAuth, SshConfig, Timeouts};
+
+/// How deep `Include` may nest, against include loops.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Most jump hosts followed for one connection, against `ProxyJump` loops.
+const MAX_JUMP_HOSTS: usize = 8;
+
+/// One hop of a `ProxyJump` list: `[user@]host[:port]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JumpSpec {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+/// Parse a `ProxyJump` value: comma-separated `[user@]host[:port]` or
+/// `ssh://[user@]host[:port]`, IPv6 addresses in brackets.
+pub fn parse_proxy_jump(spec: &str) -> Vec<JumpSpec> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|hop| !hop.is_empty())
+        .map(|hop| {
+            let hop = hop.strip_prefix("ssh://").unwrap_or(hop);
+            let (user, address) = match hop.rsplit_once('@') {
+                Some((user, address)) => (Some(user.to_string()), address),
+                None => (None, hop),
+            };
+            let (host, port) = match address.strip_prefix('[') {
+                Some(bracketed) => {
+                    let (host, rest) = bracketed.split_once(']').unwrap_or((bracketed, ""));
+                    (host, rest.strip_prefix(':'))
+                }
+                None => match address.split_once(':') {
+                    Some((host, port)) => (host, Some(port)),
+                    None => (address, None),
+                },
+            };
+            JumpSpec {
+                user,
+                host: host.to_string(),
+                port: port.and_then(|port| port.parse().ok()),
+            }
+        })
+        .collect()
+}
+
+/// The options Pier uses, resolved for one host alias.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct ResolvedHost {
+    /// The name the user typed (`Host` pattern)
+    pub alias: String,
+    /// Real host name to connect to (`HostName`, else the alias)
+    pub host_name: String,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    /// Keys to try, in order, with `~` and `%` tokens expanded
+    pub identity_files: Vec<String>,
+    /// Jump hosts, as written (`[user@]host[:port]`, comma separated)
+    pub proxy_jump: Option<String>,
+    pub forward_agent: bool,
+    /// Seconds between keepalives (`ServerAliveInterval`)
+    pub server_alive_interval: Option<u32>,
+    /// Unanswered keepalives before giving up (`ServerAliveCountMax`)
+    pub server_alive_count_max: Option<u32>,
+    /// Local variables to pass on, as patterns (`SendEnv`)
+    pub send_env: Vec<String>,
+    /// Variables to set remotely (`SetEnv`)
+    pub set_env: Vec<(String, String)>,
+    /// `Compression yes`
+    pub compression: bool,
+    /// `Ciphers`, as written
+    pub ciphers: Option<String>,
+    /// `KexAlgorithms`, as written
+    pub kex_algorithms: Option<String>,
+    /// `MACs`, as written
+    pub macs: Option<String>,
+    /// `RekeyLimit`
+    pub rekey_limit: Option<RekeyLimits>,
+}
+
+impl ResolvedHost {
+    /// Connection settings for this host. Authenticates with the first
+    /// identity file that exists, else the agent; `default_user` is used
+    /// when no `User` is configured.
+    pub fn to_ssh_config(&self, default_user: &str) -> SshConfig {
+        let key = self.identity_files.iter().find(|path| Path::new(path).exists());
+        SshConfig {
+            host: self.host_name.clone(),
+            port: self.port.unwrap_or(22),
+            username: self.user.clone().unwrap_or_else(|| default_user.to_string()),
+            auth: match key {
+                Some(path) => SshAuth::KeyFile { path: path.clone(), passphrase: None },
+                None => SshAuth::Agent,
+            },
+            jump_hosts: Vec::new(),
+            keepalive: KeepalivePolicy {
+                interval_secs: self.server_alive_interval.unwrap_or(0),
+                count_max: self.server_alive_count_max.unwrap_or(KeepalivePolicy::default().count_max),
+            },
+            reconnect: None,
+            timeouts: Timeouts::default(),
+            set_env: self.set_env.clone(),
+            send_env: self.send_env.clone(),
+            compression: self.compression,
+            algorithms: AlgorithmPreferences {
+                ciphers: self.ciphers.clone(),
+                kex: self.kex_algorithms.clone(),
+                macs: self.macs.clone(),
+            },
+            rekey: self.rekey_limit.unwrap_or_default(),
+            proxy: None,
+        }
+    }
+}
+
+/// Options under one `Host` (or `Match`) line.
+#[derive(Debug)]
+struct Block {
+    /// `None` applies to every host (before the first `Host`, `Host *`
+    /// aside, or `Match all`)
+    patterns: Option<Vec<String>>,
+    options: Vec<(String, Vec<String>)>,
+}
+
+impl Block {
+    fn matches(&self, alias: &str) -> bool {
+        let Some(patterns) = &self.patterns else { return true };
+        let alias = alias.to_ascii_lowercase();
+        let mut matched = false;
+        for pattern in patterns {
+            let pattern = pattern.to_ascii_lowercase();
+            match pattern.strip_prefix('!') {
+                Some(negated) if wildcard_match(negated, &alias) => return false,
+                Some(_) => {}
+                None => matched |= wildcard_match(&pattern, &alias),
+            }
+        }
+        matched
+    }
+}
+
+/// A parsed client configuration.
+#[derive(Debug, Default)]
+pub struct SshConfigFile {
+    blocks: Vec<Block>,
+}
+
+impl SshConfigFile {
+    /// Load `~/.ssh/config`. A missing file gives an empty configuration.
+    pub fn load() -> Self {
+        let Some(ssh_dir) = ssh_dir() else { return Self::default() };
+        let mut config = Self::default();
+        config.include(&ssh_dir.join("config"), &ssh_dir, 0);
+        config
+    }
+
+    /// Parse configuration text. `Include` paths are relative to `ssh_dir`.
+    pub fn parse(text: &str, ssh_dir: &Path) -> Self {
+        let mut config = Self::default();
+        config.parse_into(text, ssh_dir, 0);
+        config
+    }
+
+    fn include(&mut self, path: &Path, ssh_dir: &Path, depth: usize) {
+        if depth > MAX_INCLUDE_DEPTH {
+            log::warn!("ssh config: Include nested too deeply at {}", path.display());
+            return;
+        }
+        match std::fs::read_to_string(path) {
+            Ok(text) => self.parse_into(&text, ssh_dir, depth),
+            Err(e) => log::debug!("ssh config: can't read {}: {}", path.display(), e),
+        }
+    }
+
+    fn parse_into(&mut self, text: &str, ssh_dir: &Path, depth: usize) {
+        for line in text.lines() {
+            let Some((keyword, args)) = parse_line(line) else { continue };
+            match keyword.as_str() {
+                "host" => self.blocks.push(Block {
+                    patterns: Some(args).filter(|patterns| patterns.iter().all(|p| p != "*")),
+                    options: Vec::new(),
+                }),
+                "match" => {
+                    let all = args.len() == 1 && args[0].eq_ignore_ascii_case("all");
+                    // Unsupported criteria: the block never applies
+                    self.blocks.push(Block { patterns: if all { None } else { Some(Vec::new()) }, options: Vec::new() });
+                }
+                "include" => {
+                    for pattern in args {
+                        for path in expand_include(&pattern, ssh_dir) {
+                            self.include(&path, ssh_dir, depth + 1);
+                        }
+                    }
+                }
+                _ => {
+                    if self.blocks.is_empty() {
+                        self.blocks.push(Block { patterns: None, options: Vec::new() });
+                    }
+                    if let Some(block) = self.blocks.last_mut() {
+                        block.options.push((keyword, args));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Resolve the options for `alias`.
+    pub fn resolve(&self, alias: &str) -> ResolvedHost {
+        let mut host_name = None;
+        let mut user = None;
+        let mut port = None;
+        let mut identity_files = Vec::new();
+        let mut proxy_jump = None;
+        let mut forward_agent = None;
+        let mut compression = None;
+        let mut ciphers = None;
+        let mut kex_algorithms = None;
+        let mut macs = None;
+        let mut rekey_limit = None;
+        let mut server_alive_interval = None;
+        let mut server_alive_count_max = None;
+        let mut send_env = Vec::new();
+        let mut set_env: Vec<(String, String)> = Vec::new();
+
+        for block in self.blocks.iter().filter(|block| block.matches(alias)) {
+            for (keyword, args) in &block.options {
+                let Some(value) = args.first() else { continue };
+                match keyword.as_str() {
+                    "hostname" => {
+                        host_name.get_or_insert_with(|| value.clone());
+                    }
+                    "user" => {
+                        user.get_or_insert_with(|| value.clone());
+                    }
+                    "port" if port.is_none() => port = value.parse().ok(),
+                    "identityfile" => identity_files.push(value.clone()),
+                    "proxyjump" => {
+                        proxy_jump.get_or_insert_with(|| value.clone());
+                    }
+                    "forwardagent" => {
+                        forward_agent.get_or_insert_with(|| value.eq_ignore_ascii_case("yes"));
+                    }
+                    "compression" => {
+                        compression.get_or_insert_with(|| value.eq_ignore_ascii_case("yes"));
+                    }
+                    "ciphers" => {
+                        ciphers.get_or_insert_with(|| value.clone());
+                    }
+                    "kexalgorithms" => {
+                        kex_algorithms.get_or_insert_with(|| value.clone());
+                    }
+                    "macs" => {
+                        macs.get_or_insert_with(|| value.clone());
+                    }
+                    "rekeylimit" if rekey_limit.is_none() => {
+                        rekey_limit = parse_rekey_limit(args);
+                    }
+                    "serveraliveinterval" if server_alive_interval.is_none() => {
+                        server_alive_interval = value.parse().ok();
+                    }
+                    "serveralivecountmax" if server_alive_count_max.is_none() => {
+                        server_alive_count_max = value.parse().ok();
+                    }
+                    "sendenv" => send_env.extend(args.iter().cloned()),
+                    // The first value for each variable wins
+                    "setenv" => {
+                        for (name, value) in args.iter().filter_map(|arg| arg.split_once('=')) {
+                            if !set_env.iter().any(|(set, _)| set == name) {
+                                set_env.push((name.to_string(), value.to_string()));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let host_name = expand_tokens(&host_name.unwrap_or_else(|| alias.to_string()), alias, alias, None, None);
+        let identity_files = identity_files
+            .iter()
+            .map(|path| expand_tokens(path, alias, &host_name, user.as_deref(), port))
+            .collect();
+        ResolvedHost {
+            alias: alias.to_string(),
+            host_name,
+            user,
+            port,
+            identity_files,
+            // "none" turns off a jump host set by a broader block
+            proxy_jump: proxy_jump.filter(|jump| !jump.eq_ignore_ascii_case("none")),
+            forward_agent: forward_agent.unwrap_or(false),
+            server_alive_interval,
+            server_alive_count_max,
+            send_env,
+            set_env,
+            compression: compression.unwrap_or(false),
+            ciphers,
+            kex_algorithms,
+            macs,
+            rekey_limit,
+        }
+    }
+
+    /// Connection settings for `alias`, with its `ProxyJump` hosts
+    /// resolved as jump hosts.
+    pub fn ssh_config(&self, alias: &str, default_user: &str) -> SshConfig {
+        let host = self.resolve(alias);
+        let mut config = host.to_ssh_config(default_user);
+        if let Some(spec) = &host.proxy_jump {
+            let mut chain = vec![alias.to_string()];
+            self.collect_jumps(spec, default_user, &mut chain, &mut config.jump_hosts);
+        }
+        config
+    }
+
+    /// Settings for each hop of a `ProxyJump` value. Hops are looked up as
+    /// aliases; a user or port in the value overrides the file. A hop's own
+    /// `ProxyJump` comes before it in the chain.
+    pub fn jump_hosts(&self, spec: &str, default_user: &str) -> Vec<SshConfig> {
+        let mut hops = Vec::new();
+        self.collect_jumps(spec, default_user, &mut Vec::new(), &mut hops);
+        hops
+    }
+
+    /// `chain` holds the hosts being resolved, which are skipped when they
+    /// come up again: a broad `Host *` with `ProxyJump` would otherwise
+    /// make the jump host jump through itself.
+    fn collect_jumps(&self, spec: &str, default_user: &str, chain: &mut Vec<String>, hops: &mut Vec<SshConfig>) {
+        for jump in parse_proxy_jump(spec) {
+            if chain.contains(&jump.host) {
+                continue;
+            }
+            if hops.len() >= MAX_JUMP_HOSTS {
+                log::warn!("ssh config: too many jump hosts for {}", jump.host);
+                return;
+            }
+            let host = self.resolve(&jump.host);
+            if let Some(inner) = &host.proxy_jump {
+                chain.push(jump.host.clone());
+                self.collect_jumps(inner, default_user, chain, hops);
+                chain.pop();
+            }
+            let mut config = host.to_ssh_config(default_user);
+            if let Some(user) = jump.user {
+                config.username = user;
+            }
+            if let Some(port) = jump.port {
+                config.port = port;
+            }
+            hops.push(config);
+        }
+    }
+
+    /// Concrete host aliases (`Host` patterns without wildcards or
+    /// negation), in file order without duplicates.
+    pub fn hosts(&self) -> Vec<String> {
+        let mut hosts: Vec<String> = Vec::new();
+        for block in &self.blocks {
+            let Some(patterns) = &block.patterns else { continue };
+            for pattern in patterns {
+                let concrete = !pattern.contains(['*', '?', '!']);
+                if concrete && !hosts.contains(pattern) {
+                    hosts.push(pattern.clone());
+                }
+            }
+        }
+        hosts
+    }
+}
+
+fn ssh_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".ssh"))
+}
+
+/// Split a line into its lowercase keyword and arguments. Arguments may
+/// be double-quoted; the keyword may be followed by `=`.
+fn parse_line(line: &str) -> Option<(String, Vec<String>)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let end = line.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(line.len());
+    let keyword = line[..end].to_ascii_lowercase();
+    let rest = line[end..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_arg = false;
+    for c in rest.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_arg = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if has_arg {
+                    args.push(std::mem::take(&mut current));
+                    has_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_arg = true;
+            }
+        }
+    }
+    if has_arg {
+        args.push(current);
+    }
+    Some((keyword, args))
+}
+
+/// Files an `Include` argument names: `~` expanded, relative to `ssh_dir`,
+/// with wildcards in the file name matched against the directory, sorted.
+fn expand_include(pattern: &str, ssh_dir: &Path) -> Vec<PathBuf> {
+    let path = match pattern.strip_prefix("~/") {
+        Some(rest) => match ssh_dir.parent() {
+            Some(home) => home.join(rest),
+            None => return Vec::new(),
+        },
+        None => ssh_dir.join(pattern),
+    };
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else { return vec![path] };
+    if !name.contains(['*', '?']) {
+        return vec![path];
+    }
+    let dir = path.parent().unwrap_or(ssh_dir);
+    let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new() };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_str().is_some_and(|entry_name| wildcard_match(name, entry_name)))
+        .map(|entry| entry.path())
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Expand `~` and the `%` tokens ssh(1) allows in these options.
+fn expand_tokens(value: &str, alias: &str, host: &str, user: Option<&str>, port: Option<u16>) -> String {
+    let home = std::env::var("HOME").unwrap_or_default();
+    let value = match value.strip_prefix("~/") {
+        Some(rest) => format!("{}/{}", home, rest),
+        None => value.to_string(),
+    };
+    let local_user = std::env::var("USER").unwrap_or_default();
+
+    let mut expanded = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some('h') => expanded.push_str(host),
+            Some('n') => expanded.push_str(alias),
+            Some('p') => expanded.push_str(&port.unwrap_or(22).to_string()),
+            Some('r') => expanded.push_str(user.unwrap_or(&local_user)),
+            Some('u') => expanded.push_str(&local_user),
+            Some('d') => expanded.push_str(&home),
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            }
+            None => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+/// Parse `RekeyLimit`'s arguments: an amount of data with an optional
+/// K, M or G suffix, or `default`, then optionally a time like `1h30m`, or
+/// `none`/`default`.
+fn parse_rekey_limit(args: &[String]) -> Option<RekeyLimits> {
+    let max_bytes = match args.first()?.as_str() {
+        "default" => 0,
+        amount => {
+            let (digits, scale) = match amount.char_indices().last()? {
+                (at, 'K' | 'k') => (&amount[..at], 1 << 10),
+                (at, 'M' | 'm') => (&amount[..at], 1 << 20),
+                (at, 'G' | 'g') => (&amount[..at], 1 << 30),
+                _ => (amount, 1),
+            };
+            digits.parse::<u64>().ok()?.checked_mul(scale)?
+        }
+    };
+    let interval_secs = match args.get(1).map(String::as_str) {
+        None | Some("none" | "default") => 0,
+        Some(time) => parse_time(time)?,
+    };
+    Some(RekeyLimits { max_bytes, interval_secs })
+}
+
+/// Parse an ssh_config time: seconds, or runs of a number and a unit
+/// (`s`, `m`, `h`, `d`, `w`) such as `1h30m`.
+fn parse_time(time: &str) -> Option<u64> {
+    if let Ok(secs) = time.parse() {
+        return Some(secs);
+    }
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in time.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        total += number.parse::<u64>().ok()? * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        total += number.parse::<u64>().ok()?;
+    }
+    Some(total)
+}
+
+/// Match `text` against a pattern with `*` (any run) and `?` (any one
+/// character).
+pub(super) fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and the text position it's matched up to
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+# Defaults
+User fallback
+
+Host web prod-*  !prod-db
+    HostName %h.example.com
+    Port=2222
+    IdentityFile ~/.ssh/web_key
+    ForwardAgent yes
+    Compression yes
+    KexAlgorithms +diffie-hellman-group1-sha1
+    RekeyLimit 512M 1h30m
+    ServerAliveInterval 15
+    SendEnv LANG LC_*
+    SetEnv "GREETING=hello world" TZ=UTC
+
+Host bastion
+    HostName 10.0.0.1
+    User "jump user"
+
+Host *
+    IdentityFile ~/.ssh/id_ed25519
+    SetEnv TZ=Europe/Paris EDITOR=vi
+    ProxyJump bastion
+    Port 22
+
+Match exec "false"
+    User never
+"#;
+
+    #[test]
+    fn test_resolve() {
+        let config = SshConfigFile::parse(CONFIG, Path::new("/nonexistent"));
+        let home = std::env::var("HOME").unwrap_or_default();
+
+        let web = config.resolve("prod-app");
+        assert_eq!(web.host_name, "prod-app.example.com");
+        // The first value wins: the global User comes before any Host
+        assert_eq!(web.user.as_deref(), Some("fallback"));
+        assert_eq!(web.port, Some(2222));
+        assert_eq!(
+            web.identity_files,
+            vec![format!("{home}/.ssh/web_key"), format!("{home}/.ssh/id_ed25519")]
+        );
+        assert_eq!(web.proxy_jump.as_deref(), Some("bastion"));
+        assert!(web.forward_agent);
+        assert!(web.compression && web.to_ssh_config("me").compression);
+        assert_eq!(web.to_ssh_config("me").algorithms.kex.as_deref(), Some("+diffie-hellman-group1-sha1"));
+        assert_eq!(web.ciphers, None);
+        assert_eq!(web.rekey_limit, Some(RekeyLimits { max_bytes: 512 << 20, interval_secs: 5400 }));
+        assert_eq!(web.to_ssh_config("me").keepalive, KeepalivePolicy { interval_secs: 15, count_max: 3 });
+        assert_eq!(web.send_env, vec!["LANG".to_string(), "LC_*".to_string()]);
+        let set_env: Vec<(&str, &str)> = web.set_env.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        assert_eq!(set_env, vec![("GREETING", "hello world"), ("TZ", "UTC"), ("EDITOR", "vi")]);
+
+        let db = config.resolve("prod-db");
+        assert_eq!(db.host_name, "prod-db");
+        assert_eq!(db.port, Some(22));
+        assert!(!db.forward_agent);
+
+        assert_eq!(config.hosts(), vec!["web", "bastion"]);
+        let ssh_config = db.to_ssh_config("me");
+        assert_eq!((ssh_config.host.as_str(), ssh_config.port, ssh_config.username.as_str()), ("prod-db", 22, "fallback"));
+    }
+
+    #[test]
+    fn test_proxy_jump() {
+        assert_eq!(
+            parse_proxy_jump("alice@gw:2200, ssh://[::1]:22,plain"),
+            vec![
+                JumpSpec { user: Some("alice".into()), host: "gw".into(), port: Some(2200) },
+                JumpSpec { user: None, host: "::1".into(), port: Some(22) },
+                JumpSpec { user: None, host: "plain".into(), port: None },
+            ]
+        );
+
+        let config = SshConfigFile::parse(CONFIG, Path::new("/nonexistent"));
+        let web = config.ssh_config("web", "me");
+        // "bastion" resolves through the file; its own ProxyJump (from
+        // `Host *`) points back at itself and is ignored
+        let hops: Vec<_> = web.jump_hosts.iter().map(|hop| (hop.host.as_str(), hop.username.as_str())).collect();
+        assert_eq!(hops, vec![("10.0.0.1", "fallback")]);
+        assert!(config.ssh_config("bastion", "me").jump_hosts.is_empty());
+
+        let direct = SshConfigFile::parse("Host gw\n  HostName gw.example.com\n  User ops\n", Path::new("/"));
+        let hops = direct.jump_hosts("gw:2022,root@other", "me");
+        let hops: Vec<_> = hops.iter().map(|hop| (hop.host.as_str(), hop.port, hop.username.as_str())).collect();
+        assert_eq!(hops, vec![("gw.example.com", 2022, "ops"), ("other", 22, "root")]);
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line("  # comment"), None);
+        assert_eq!(
+            parse_line("HostName=example.com"),
+            Some(("hostname".to_string(), vec!["example.com".to_string()]))
+        );
+        assert_eq!(
+            parse_line(r#"IdentityFile "~/My Keys/id" second"#),
+            Some(("identityfile".to_string(), vec!["~/My Keys/id".to_string(), "second".to_string()]))
+        );
+    }
+
+    #[test]
+    fn test_parse_rekey_limit() {
+        let args = |line: &str| line.split(' ').map(str::to_string).collect::<Vec<_>>();
+        assert_eq!(parse_rekey_limit(&args("1G")), Some(RekeyLimits { max_bytes: 1 << 30, interval_secs: 0 }));
+        assert_eq!(parse_rekey_limit(&args("default 600")), Some(RekeyLimits { max_bytes: 0, interval_secs: 600 }));
+        assert_eq!(parse_rekey_limit(&args("default 1w2d")).map(|limits| limits.interval_secs), Some(777_600));
+        assert_eq!(parse_rekey_limit(&args("lots")), None);
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", "anything"));
+        assert!(wildcard_match("prod-*", "prod-"));
+        assert!(wildcard_match("*.example.com", "a.b.example.com"));
+        assert!(wildcard_match("web?", "web1"));
+        assert!(!wildcard_match("web?", "web"));
+        assert!(!wildcard_match("*.com", "example.org"));
+        assert!(wildcard_match("a*b*c", "axxbyyc"));
+    }
+}
@@ -0,0 +1,78 @@
+//! Free space on remote filesystems, to warn before uploads that won't fit.
+//!
+//! OpenSSH's SFTP server answers `statvfs@openssh.com`; other servers are
+//! asked with `df` over exec.
+
+use serde::Serialize;
+
+use super::session::SshSession;
+use super::sftp::SftpClient;
+use super::sudo::quote;
+
+/// Space on the filesystem holding a path, in bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct FilesystemSpace {
+    pub total: u64,
+    pub used: u64,
+    /// Free to unprivileged users, what an upload can use
+    pub available: u64,
+}
+
+/// Space on the filesystem holding `path`, from `sftp` if given and the
+/// server supports it, else from `df` on `session`.
+pub async fn filesystem_space(
+    session: Option<&SshSession>,
+    sftp: Option<&SftpClient>,
+    path: &str,
+) -> Result<FilesystemSpace, anyhow::Error> {
+    if let Some(sftp) = sftp {
+        match sftp.statvfs(path).await {
+            Ok(Some(stat)) => {
+                let block = stat.fragment_size;
+                return Ok(FilesystemSpace {
+                    total: stat.blocks * block,
+                    used: stat.blocks.saturating_sub(stat.blocks_free) * block,
+                    available: stat.blocks_avail * block,
+                });
+            }
+            Ok(None) => log::debug!("No statvfs extension, asking df"),
+            Err(e) if session.is_none() => return Err(e),
+            Err(e) => log::debug!("statvfs failed ({}), asking df", e),
+        }
+    }
+    let session = session.ok_or_else(|| anyhow::anyhow!("Server can't report filesystem space over SFTP"))?;
+    let (exit_code, output) = session.exec_command(&format!("df -Pk -- {}", quote(path))).await?;
+    if exit_code != 0 {
+        return Err(anyhow::anyhow!("df failed with exit code {}", exit_code));
+    }
+    parse_df(&output).ok_or_else(|| anyhow::anyhow!("Unexpected df output: {}", output.trim()))
+}
+
+/// The space in POSIX `df -Pk` output, in KiB blocks.
+fn parse_df(output: &str) -> Option<FilesystemSpace> {
+    let line = output.lines().rev().find(|line| !line.trim().is_empty())?;
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    // Names and mount points may hold spaces, so go by the capacity column
+    let capacity = fields.iter().rposition(|field| field.ends_with('%'))?;
+    if capacity < 3 {
+        return None;
+    }
+    let number = |index: usize| fields[index].parse::<u64>().ok().map(|kib| kib * 1024);
+    Some(FilesystemSpace { total: number(capacity - 3)?, used: number(capacity - 2)?, available: number(capacity - 1)? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_df() {
+        let output = "Filesystem     1024-blocks     Used Available Capacity Mounted on\n\
+                      /dev/sda1         41152736 12000000  27042000      31% /mnt/my disk\n";
+        assert_eq!(
+            parse_df(output),
+            Some(FilesystemSpace { total: 41152736 * 1024, used: 12000000 * 1024, available: 27042000 * 1024 })
+        );
+        assert_eq!(parse_df("df: /nope: No such file or directory\n"), None);
+    }
+}
@@ -0,0 +1,160 @@
+//! Remote commands with streamed input and output.
+//!
+//! For piping local data into a command (`cat > file`, `psql < dump.sql`)
+//! without holding it all in memory: input is queued as it comes, the
+//! command reads end of file once input is closed, and output can be read
+//! as it arrives or collected at the end. The channel is pumped like a
+//! remote shell's, so a command that stops reading only stalls its own
+//! channel.
+//!
+//! Output can instead go to a callback chunk by chunk, stdout and stderr
+//! apart, for commands that run long or forever (builds, `tail -f`).
+
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use russh::client::Msg;
+use russh::{Channel, ChannelMsg};
+use tokio::sync::oneshot;
+
+use super::shell::{signal_number, RemoteShell, ShellOutput};
+use crate::terminal::pty::ExitStatus;
+
+/// A command started with `SshSession::exec_channel`.
+pub struct RemoteExec {
+    channel: RemoteShell,
+    output: ShellOutput,
+    /// Output received but not read yet
+    pending: Vec<u8>,
+}
+
+impl RemoteExec {
+    /// Start pumping `channel` on `runtime`.
+    pub fn start(runtime: &tokio::runtime::Handle, channel: Channel<Msg>) -> Self {
+        let (channel, output) = RemoteShell::start(runtime, channel, None);
+        Self { channel, output, pending: Vec::new() }
+    }
+
+    /// Queue input for the command.
+    pub fn write(&self, data: &[u8]) -> Result<(), Error> {
+        self.channel.write(data)
+    }
+
+    /// Close the command's stdin once queued input is sent.
+    pub fn close_stdin(&self) -> Result<(), Error> {
+        self.channel.close_input()
+    }
+
+    /// Read output (stdout and stderr) into `buf`, waiting up to `timeout`
+    /// for some to arrive. Returns the bytes read, 0 at the end of output,
+    /// or a `TimedOut` error.
+    pub async fn read(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        if self.pending.is_empty() {
+            match tokio::time::timeout(timeout, self.output.recv()).await {
+                Ok(Some(chunk)) => self.pending = chunk,
+                Ok(None) => return Ok(0),
+                Err(_) => return Err(Error::new(ErrorKind::TimedOut, "no output yet")),
+            }
+        }
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+
+    /// Wait up to `timeout` for the command to finish, and return its exit
+    /// code with the output not read yet. The code is -1 if the server
+    /// didn't report one, 128 + N if signal N killed the command. On
+    /// timeout the output stays readable.
+    pub async fn finish(&mut self, timeout: Duration) -> Result<(i32, Vec<u8>), Error> {
+        let mut output = std::mem::take(&mut self.pending);
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            match tokio::time::timeout_at(deadline, self.output.recv()).await {
+                Ok(Some(chunk)) => output.extend_from_slice(&chunk),
+                Ok(None) => break,
+                Err(_) => {
+                    self.pending = output;
+                    return Err(Error::new(ErrorKind::TimedOut, "command still running"));
+                }
+            }
+        }
+        let code = match self.channel.exit_status() {
+            Some(ExitStatus::Exited { code }) => code,
+            Some(ExitStatus::Signaled { signal }) => 128 + signal,
+            None => -1,
+        };
+        Ok((code, output))
+    }
+}
+
+/// How long stopping a streaming command waits for the server to close
+/// its channel.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Which output a chunk came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Pass the output of the command on `channel` to `on_output` as it
+/// arrives, until the command ends. Returns its exit code: -1 if the
+/// server didn't report one, 128 + N if signal N killed it.
+pub async fn stream_output(channel: &mut Channel<Msg>, mut on_output: impl FnMut(OutputStream, &[u8])) -> i32 {
+    let mut exit_code = -1;
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Data { data } => on_output(OutputStream::Stdout, &data),
+            ChannelMsg::ExtendedData { data, .. } => on_output(OutputStream::Stderr, &data),
+            ChannelMsg::ExitStatus { exit_status } => exit_code = exit_status as i32,
+            ChannelMsg::ExitSignal { signal_name, .. } => exit_code = 128 + signal_number(&signal_name),
+            ChannelMsg::Close => break,
+            _ => {}
+        }
+    }
+    exit_code
+}
+
+/// A command streaming its output to callbacks from the runtime.
+pub struct StreamingExec {
+    cancel: Option<oneshot::Sender<()>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl StreamingExec {
+    /// Run the command on `channel` on `runtime`, passing its output to
+    /// `on_output` and its exit code to `on_exit`.
+    pub fn start(
+        runtime: &tokio::runtime::Handle,
+        mut channel: Channel<Msg>,
+        on_output: impl FnMut(OutputStream, &[u8]) + Send + 'static,
+        on_exit: impl FnOnce(i32) + Send + 'static,
+    ) -> Self {
+        let (cancel, cancelled) = oneshot::channel::<()>();
+        let task = runtime.spawn(async move {
+            let exit_code = tokio::select! {
+                code = stream_output(&mut channel, on_output) => Some(code),
+                _ = cancelled => None,
+            };
+            match exit_code {
+                Some(code) => on_exit(code),
+                // The command keeps running unless its channel is closed
+                None => {
+                    let _ = tokio::time::timeout(CLOSE_TIMEOUT, channel.close()).await;
+                }
+            }
+        });
+        Self { cancel: Some(cancel), task }
+    }
+
+    /// Stop the command if it's still running. No callback runs once this
+    /// returns; `on_exit` isn't called for a stopped command.
+    pub async fn stop(mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            let _ = cancel.send(());
+        }
+        let _ = (&mut self.task).await;
+    }
+}
@@ -0,0 +1,364 @@
+//! Host key verification and the user's known_hosts file.
+//!
+//! A server whose key isn't recorded, or whose key changed, is only trusted
+//! if the app says so: the registered handler gets the key's type and
+//! fingerprint and answers reject, accept once, or accept and remember.
+//! Without a handler such servers are refused. The file is shared with
+//! OpenSSH, so entries are read and written in its format, hashed host
+//! names included.
+//!
+//! The app can keep its own file instead of `~/.ssh/known_hosts`, and have
+//! new entries written with hashed host names, like OpenSSH's
+//! `HashKnownHosts yes`, so the file doesn't list the hosts connected to.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use russh::keys::{HashAlg, PublicKey};
+
+/// Why the app is asked about a host key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HostKeyStatus {
+    /// No key of this type is recorded for the host
+    New,
+    /// A different key is recorded: the host was reinstalled, or someone
+    /// is intercepting the connection
+    Changed,
+}
+
+/// A host key the app has to decide about.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct HostKeyPrompt {
+    pub host: String,
+    pub port: u16,
+    /// Algorithm, e.g. `ssh-ed25519`
+    pub key_type: String,
+    /// `SHA256:...`, as OpenSSH shows it
+    pub fingerprint: String,
+    pub status: HostKeyStatus,
+}
+
+/// The app's answer to a `HostKeyPrompt`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostKeyDecision {
+    Reject,
+    /// Trust the key for this connection only
+    AcceptOnce,
+    /// Trust the key and record it in known_hosts
+    AcceptAndSave,
+}
+
+/// Decides about unknown or changed host keys. Called on a blocking
+/// thread, so it may wait for the user.
+pub type HostKeyHandler = Arc<dyn Fn(&HostKeyPrompt) -> HostKeyDecision + Send + Sync>;
+
+static HANDLER: Mutex<Option<HostKeyHandler>> = Mutex::new(None);
+
+/// Set the handler for all connections made from now on, or clear it.
+pub fn set_handler(handler: Option<HostKeyHandler>) {
+    *HANDLER.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = handler;
+}
+
+/// The handler connections should ask.
+pub fn handler() -> Option<HostKeyHandler> {
+    HANDLER.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+static PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+static HASH_HOSTS: AtomicBool = AtomicBool::new(false);
+
+/// Use the file at `path` for all connections made from now on, or
+/// `~/.ssh/known_hosts` again with `None`.
+pub fn set_path(path: Option<PathBuf>) {
+    *PATH.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = path;
+}
+
+/// The file host keys are checked against and saved to.
+pub fn path() -> Option<PathBuf> {
+    PATH.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone().or_else(default_path)
+}
+
+/// Set whether keys saved from now on get hashed host names.
+pub fn set_hash_hosts(hash: bool) {
+    HASH_HOSTS.store(hash, Ordering::Relaxed);
+}
+
+/// Whether saved keys get hashed host names.
+pub fn hash_hosts() -> bool {
+    HASH_HOSTS.load(Ordering::Relaxed)
+}
+
+/// One key line of a known_hosts file.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct KnownHost {
+    /// 1-based line number
+    pub line: usize,
+    /// Host patterns as written (`host`, `[host]:port`, comma separated);
+    /// empty for hashed entries
+    pub hosts: Vec<String>,
+    /// The host names are hashed (`HashKnownHosts`), so can't be shown
+    pub hashed: bool,
+    /// `@cert-authority` or `@revoked`, if present
+    pub marker: Option<String>,
+    pub key_type: String,
+    /// `SHA256:...`, or empty if the key can't be parsed
+    pub fingerprint: String,
+}
+
+/// `~/.ssh/known_hosts`.
+pub fn default_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".ssh").join("known_hosts"))
+}
+
+/// Details of `key` to show the user.
+pub fn prompt(host: &str, port: u16, key: &PublicKey, status: HostKeyStatus) -> HostKeyPrompt {
+    HostKeyPrompt {
+        host: host.to_string(),
+        port,
+        key_type: key.algorithm().as_str().to_string(),
+        fingerprint: key.fingerprint(HashAlg::Sha256).to_string(),
+        status,
+    }
+}
+
+/// The entries of a known_hosts file; none if it doesn't exist.
+pub fn list(path: &Path) -> Result<Vec<KnownHost>, std::io::Error> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(text
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| parse_line(line).map(|entry| (index + 1, entry)))
+        .map(|(line, (marker, hosts, key_type, key))| {
+            let hashed = hosts.starts_with("|1|");
+            KnownHost {
+                line,
+                hosts: if hashed { Vec::new() } else { hosts.split(',').map(str::to_string).collect() },
+                hashed,
+                marker: marker.map(str::to_string),
+                key_type: key_type.to_string(),
+                fingerprint: russh::keys::parse_public_key_base64(key)
+                    .map(|key| key.fingerprint(HashAlg::Sha256).to_string())
+                    .unwrap_or_default(),
+            }
+        })
+        .collect())
+}
+
+/// Remove every line recording a key for `host:port`, hashed ones
+/// included, like `ssh-keygen -R`. Returns how many were removed.
+pub fn remove(path: &Path, host: &str, port: u16) -> Result<usize, std::io::Error> {
+    remove_lines(path, host, port, |_| true)
+}
+
+/// Remove the lines for `host:port` whose marker (`None` for plain key
+/// lines) `which` picks. Returns how many were removed.
+fn remove_lines(
+    path: &Path,
+    host: &str,
+    port: u16,
+    which: impl Fn(Option<&str>) -> bool,
+) -> Result<usize, std::io::Error> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let name = host_pattern(host, port);
+    let mut removed = 0;
+    let mut kept = String::with_capacity(text.len());
+    for line in text.lines() {
+        let matches =
+            parse_line(line).is_some_and(|(marker, hosts, _, _)| matches_host(hosts, &name) && which(marker));
+        if matches {
+            removed += 1;
+        } else {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    if removed > 0 {
+        std::fs::write(path, kept)?;
+    }
+    Ok(removed)
+}
+
+/// Record `key` for `host:port`, replacing the plain keys recorded for it;
+/// its `@revoked` and `@cert-authority` lines stay. See `add` for `hash`.
+pub fn update(path: &Path, host: &str, port: u16, key: &PublicKey, hash: bool) -> Result<(), anyhow::Error> {
+    remove_lines(path, host, port, |marker| marker.is_none())?;
+    add(path, host, port, key, hash)
+}
+
+/// Record `key` for `host:port` next to any keys already recorded, with
+/// the host name hashed if `hash` is set.
+pub fn add(path: &Path, host: &str, port: u16, key: &PublicKey, hash: bool) -> Result<(), anyhow::Error> {
+    if !hash {
+        russh::keys::known_hosts::learn_known_hosts_path(host, port, key, path)?;
+        return Ok(());
+    }
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let unterminated = std::fs::read(path).is_ok_and(|text| !text.is_empty() && !text.ends_with(b"\n"));
+    let mut line = if unterminated { "\n".to_string() } else { String::new() };
+    line.push_str(&format!("{} {}\n", hash_host(&host_pattern(host, port))?, key.to_openssh()?));
+    std::fs::OpenOptions::new().create(true).append(true).open(path)?.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// `name` hashed as OpenSSH does: `|1|salt|hash`, the hash being
+/// HMAC-SHA1 of the name keyed with a random salt.
+fn hash_host(name: &str) -> Result<String, anyhow::Error> {
+    use data_encoding::BASE64;
+    use ring::rand::SecureRandom;
+
+    let mut salt = [0u8; 20];
+    ring::rand::SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| anyhow::anyhow!("No random numbers for the host name salt"))?;
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &salt);
+    let hash = ring::hmac::sign(&key, name.as_bytes());
+    Ok(format!("|1|{}|{}", BASE64.encode(&salt), BASE64.encode(hash.as_ref())))
+}
+
+/// How known_hosts names a host: plain on port 22, else `[host]:port`.
+fn host_pattern(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+/// Split a key line into marker, host patterns, key type and base64 key.
+/// `None` for comments, blank and malformed lines.
+fn parse_line(line: &str) -> Option<(Option<&str>, &str, &str, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let mut fields = line.split_whitespace();
+    let mut first = fields.next()?;
+    let marker = first.starts_with('@').then_some(first);
+    if marker.is_some() {
+        first = fields.next()?;
+    }
+    Some((marker, first, fields.next()?, fields.next()?))
+}
+
+/// Whether a comma separated pattern list names `name`, checking hashed
+/// entries (`|1|salt|hash`, HMAC-SHA1 of the name) too.
+fn matches_host(patterns: &str, name: &str) -> bool {
+    use data_encoding::BASE64;
+    patterns.split(',').any(|pattern| match pattern.strip_prefix("|1|") {
+        Some(hashed) => {
+            let Some((salt, hash)) = hashed.split_once('|') else { return false };
+            let (Ok(salt), Ok(hash)) = (BASE64.decode(salt.as_bytes()), BASE64.decode(hash.as_bytes())) else {
+                return false;
+            };
+            let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &salt);
+            ring::hmac::verify(&key, name.as_bytes(), &hash).is_ok()
+        }
+        None => pattern == name,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED25519: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIJdD7y3aLq454yWBdwLWbieU1ebz9/cu7/QEXn9OIeZJ";
+    const OTHER: &str = "AAAAC3NzaC1lZDI1NTE5AAAAILIG2T/B0l0gaqj3puu510tu9N1OkQ4znY3LYuEm5zCF";
+
+    #[test]
+    fn test_list_remove_update() {
+        let path = std::env::temp_dir().join(format!("pier-known-hosts-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            format!(
+                "# comment\n\
+                 [localhost]:13265 ssh-ed25519 {ED25519}\n\
+                 example.org,10.0.0.1 ssh-ed25519 {OTHER}\n\
+                 @cert-authority *.example.org ssh-ed25519 {OTHER}\n\
+                 |1|O33ESRMWPVkMYIwJ1Uw+n877jTo=|nuuC5vEqXlEZ/8BXQR7m619W6Ak= ssh-ed25519 {OTHER}\n"
+            ),
+        )
+        .unwrap();
+
+        let entries = list(&path).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!((entries[0].line, entries[0].hosts.clone()), (2, vec!["[localhost]:13265".to_string()]));
+        assert!(entries[0].fingerprint.starts_with("SHA256:"));
+        assert_eq!(entries[2].marker.as_deref(), Some("@cert-authority"));
+        assert!(entries[3].hashed && entries[3].hosts.is_empty());
+
+        // The hashed line is for example.com
+        assert_eq!(remove(&path, "example.com", 22).unwrap(), 1);
+        assert_eq!(remove(&path, "example.org", 22).unwrap(), 1);
+        assert_eq!(remove(&path, "localhost", 22).unwrap(), 0);
+
+        let key = russh::keys::parse_public_key_base64(OTHER).unwrap();
+        update(&path, "localhost", 13265, &key, false).unwrap();
+        let entries = list(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].hosts, vec!["[localhost]:13265".to_string()]);
+        assert_eq!(entries[1].fingerprint, key.fingerprint(HashAlg::Sha256).to_string());
+        assert!(russh::keys::check_known_hosts_path("localhost", 13265, &key, &path).unwrap());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_update_keeps_markers() {
+        let path = std::env::temp_dir().join(format!("pier-known-hosts-markers-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            format!(
+                "[localhost]:13265 ssh-ed25519 {ED25519}\n\
+                 @revoked [localhost]:13265 ssh-ed25519 {ED25519}\n\
+                 @cert-authority [localhost]:13265 ssh-ed25519 {OTHER}\n"
+            ),
+        )
+        .unwrap();
+
+        let key = russh::keys::parse_public_key_base64(OTHER).unwrap();
+        update(&path, "localhost", 13265, &key, false).unwrap();
+        let entries = list(&path).unwrap();
+        let markers: Vec<Option<&str>> = entries.iter().map(|entry| entry.marker.as_deref()).collect();
+        assert_eq!(markers, vec![Some("@revoked"), Some("@cert-authority"), None]);
+        assert_eq!(entries[2].fingerprint, key.fingerprint(HashAlg::Sha256).to_string());
+
+        // Removing the host takes its marker lines too
+        assert_eq!(remove(&path, "localhost", 13265).unwrap(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_hashed_add() {
+        let path = std::env::temp_dir().join(format!("pier-known-hosts-hashed-{}", std::process::id()));
+        std::fs::write(&path, format!("example.org ssh-ed25519 {OTHER}")).unwrap();
+
+        let key = russh::keys::parse_public_key_base64(ED25519).unwrap();
+        add(&path, "db1.internal", 2222, &key, true).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(!text.contains("db1.internal"));
+        assert_eq!(text.lines().count(), 2);
+
+        let entries = list(&path).unwrap();
+        assert!(entries[1].hashed && entries[1].hosts.is_empty());
+        assert!(russh::keys::check_known_hosts_path("db1.internal", 2222, &key, &path).unwrap());
+        assert!(!russh::keys::check_known_hosts_path("db1.internal", 22, &key, &path).unwrap());
+
+        assert_eq!(remove(&path, "db1.internal", 2222).unwrap(), 1);
+        assert_eq!(list(&path).unwrap().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,7 +1,29 @@
+pub mod algorithms;
+pub mod archive;
+pub mod batch;
+pub mod config_file;
+pub mod disk_space;
+pub mod exec;
+pub mod known_hosts;
+pub mod password_change;
+pub mod pool;
+pub mod proxy;
+pub mod remote_glob;
+pub mod remote_search;
+pub mod remote_watch;
+pub mod security_key;
 pub mod session;
+pub mod shell;
 pub mod sftp;
+pub mod socks;
+pub mod stats;
+pub mod sudo;
+pub mod transcript;
+pub mod transfers;
 pub mod service_detector;
 
+use std::sync::Arc;
+
 /// SSH connection configuration.
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct SshConfig {
@@ -9,8 +31,237 @@ pub struct SshConfig {
     pub port: u16,
     pub username: String,
     pub auth: SshAuth,
+    /// Bastion hosts to connect through, first hop first (ProxyJump)
+    #[serde(default)]
+    pub jump_hosts: Vec<SshConfig>,
+    #[serde(default)]
+    pub keepalive: KeepalivePolicy,
+    /// Reconnect automatically when the connection drops; `None` to stay
+    /// disconnected
+    #[serde(default)]
+    pub reconnect: Option<ReconnectPolicy>,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    /// Variables to set for shells and commands (`SetEnv`)
+    #[serde(default)]
+    pub set_env: Vec<(String, String)>,
+    /// Local variables to pass on, by name or `*`/`?` pattern (`SendEnv`)
+    #[serde(default)]
+    pub send_env: Vec<String>,
+    /// Compress the connection with zlib if the server supports it
+    /// (`Compression`); worth it on slow links, a cost on fast ones
+    #[serde(default)]
+    pub compression: bool,
+    #[serde(default)]
+    pub algorithms: AlgorithmPreferences,
+    #[serde(default)]
+    pub rekey: RekeyLimits,
+    /// Proxy to open the TCP connection through; with jump hosts, the
+    /// first one is reached through it
+    #[serde(default)]
+    pub proxy: Option<proxy::ProxyConfig>,
+}
+
+/// When to renew the session keys, like OpenSSH's `RekeyLimit`. 0 keeps
+/// the default: after 1 GiB each way (also the most allowed) or an hour.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RekeyLimits {
+    /// Bytes sent, or received, under one set of keys
+    pub max_bytes: u64,
+    pub interval_secs: u64,
+}
+
+/// The algorithms a connection negotiated, and how often its keys were
+/// exchanged.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct KeyExchangeInfo {
+    pub kex: String,
+    pub host_key: String,
+    pub cipher: String,
+    pub mac_client_to_server: String,
+    pub mac_server_to_client: String,
+    /// `zlib` or `none`
+    pub compression: String,
+    /// Key exchanges on the connection, the first included
+    pub exchanges: u32,
+    /// When keys were last exchanged, in Unix seconds
+    pub last_exchange: u64,
+}
+
+/// Which ciphers, key exchange algorithms and MACs to offer, as lists in
+/// ssh_config syntax (see `algorithms`); `None` keeps the defaults.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AlgorithmPreferences {
+    /// `Ciphers`
+    pub ciphers: Option<String>,
+    /// `KexAlgorithms`
+    pub kex: Option<String>,
+    /// `MACs`
+    pub macs: Option<String>,
+}
+
+/// Time limits for connecting and for commands, in milliseconds; 0 for
+/// none, e.g. for backups that run for hours.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Timeouts {
+    /// Reaching the server and the SSH handshake, per hop
+    pub connect_ms: u64,
+    /// A command run with `exec_command`, from opening its channel to its
+    /// exit
+    pub exec_ms: u64,
+}
+
+impl Timeouts {
+    pub const DEFAULT: Self = Self { connect_ms: 10_000, exec_ms: 60_000 };
+
+    pub fn connect(&self) -> Option<std::time::Duration> {
+        (self.connect_ms > 0).then(|| std::time::Duration::from_millis(self.connect_ms))
+    }
+
+    pub fn exec(&self) -> Option<std::time::Duration> {
+        (self.exec_ms > 0).then(|| std::time::Duration::from_millis(self.exec_ms))
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Server-alive probing, like OpenSSH's `ServerAliveInterval` and
+/// `ServerAliveCountMax`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct KeepalivePolicy {
+    /// Seconds between probes, 0 to only notice connections the OS
+    /// reports closed
+    pub interval_secs: u32,
+    /// Unanswered probes in a row before the connection counts as dead
+    pub count_max: u32,
 }
 
+impl Default for KeepalivePolicy {
+    fn default() -> Self {
+        Self { interval_secs: 0, count_max: 3 }
+    }
+}
+
+/// How to retry after the connection drops. The delay before each attempt
+/// doubles, from `initial_delay_ms` up to `max_delay_ms`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ReconnectPolicy {
+    /// Attempts before giving up, 0 to keep trying
+    pub max_retries: u32,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self { max_retries: 10, initial_delay_ms: 1000, max_delay_ms: 60_000 }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay before reconnect attempt `attempt` (counting from 1).
+    pub fn delay(&self, attempt: u32) -> std::time::Duration {
+        let factor = 1u64 << attempt.saturating_sub(1).min(32);
+        std::time::Duration::from_millis(self.initial_delay_ms.saturating_mul(factor).min(self.max_delay_ms))
+    }
+
+    /// Whether attempt `attempt` (counting from 1) is allowed.
+    pub fn allows(&self, attempt: u32) -> bool {
+        self.max_retries == 0 || attempt <= self.max_retries
+    }
+}
+
+/// Where a session's connection stands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionState {
+    /// Connecting for the first time, or reconnecting
+    Connecting,
+    Connected,
+    /// Keepalives are going unanswered; the connection may be dead
+    Degraded,
+    #[default]
+    Disconnected,
+}
+
+/// A change of connection state, reported to the state handler.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct ConnectionEvent {
+    pub state: ConnectionState,
+    /// Reconnect attempt being made or that succeeded; with
+    /// `Disconnected`, how many were made before giving up
+    pub attempt: u32,
+    /// Why the connection was lost or the attempt failed
+    pub error: Option<String>,
+}
+
+/// What the servers said while logging in, for the app to show.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct LoginInfo {
+    /// Pre-authentication banners (legal notices, MOTD-style), jump hosts'
+    /// first; some environments require showing them
+    pub banner: Option<String>,
+    /// After a failed login, the methods the server would still accept
+    pub remaining_methods: Vec<String>,
+    /// After a failed login, whether a method was accepted but the server
+    /// wanted another too
+    pub partial_success: bool,
+    /// The server had the password changed while logging in
+    pub password_changed: bool,
+}
+
+/// Notified of connection state changes, on a runtime thread.
+pub type StateHandler = Arc<dyn Fn(&ConnectionEvent) + Send + Sync>;
+
+/// Something that happened to a session, for the app to show as it
+/// happens.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionEvent {
+    /// Connected, or reconnected on attempt `attempt` (0 for the first
+    /// connect, or recovering after unanswered keepalives)
+    Connected { attempt: u32 },
+    /// The connection was lost and is being rebuilt
+    Reconnecting { attempt: u32, reason: String },
+    /// The server is asking the user something; the prompt handler is
+    /// being called
+    AuthPrompt { name: String, instructions: String, prompts: Vec<String> },
+    /// A keepalive went unanswered; the connection may be dead
+    KeepaliveTimeout,
+    /// Given up on, or closed; `reason` is `None` when closed by the app
+    Disconnected { reason: Option<String> },
+    /// A forward's local listener failed; it is bound again as soon as
+    /// it can be
+    ForwardDied { local_port: u16, error: String },
+    /// A forward's listener is back after failing
+    ForwardRestarted { local_port: u16 },
+    /// A connection through a forward couldn't be served, e.g. the server
+    /// couldn't reach its destination; told once until one is served again
+    ForwardFailing { local_port: u16, error: String },
+}
+
+impl SessionEvent {
+    /// The event for a connection state change, if it is one.
+    pub fn from_state(event: &ConnectionEvent) -> Option<Self> {
+        match (event.state, event.attempt) {
+            (ConnectionState::Connecting, 0) => None,
+            (ConnectionState::Connecting, attempt) => {
+                Some(Self::Reconnecting { attempt, reason: event.error.clone().unwrap_or_default() })
+            }
+            (ConnectionState::Connected, attempt) => Some(Self::Connected { attempt }),
+            (ConnectionState::Degraded, _) => Some(Self::KeepaliveTimeout),
+            (ConnectionState::Disconnected, _) => Some(Self::Disconnected { reason: event.error.clone() }),
+        }
+    }
+}
+
+/// Notified of session events, on a runtime thread.
+pub type EventHandler = Arc<dyn Fn(&SessionEvent) + Send + Sync>;
+
 /// SSH authentication method.
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub enum SshAuth {
@@ -20,8 +271,33 @@ pub enum SshAuth {
         passphrase: Option<String>,
     },
     Agent,
+    /// Answer the server's prompts (OTP codes, PAM questions) through the
+    /// session's prompt handler
+    KeyboardInteractive,
+}
+
+/// One round of keyboard-interactive prompts from the server.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct AuthPrompts {
+    /// Title for the round, often empty
+    pub name: String,
+    /// Message to show above the prompts, often empty
+    pub instructions: String,
+    pub prompts: Vec<AuthPrompt>,
 }
 
+/// A question the server asks during keyboard-interactive authentication.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct AuthPrompt {
+    pub prompt: String,
+    /// The answer may be shown as typed (false for passwords and codes)
+    pub echo: bool,
+}
+
+/// Answers a round of prompts, one answer per prompt, or `None` to cancel.
+/// Called on a blocking thread, so it may wait for the user.
+pub type PromptHandler = Arc<dyn Fn(&AuthPrompts) -> Option<Vec<String>> + Send + Sync>;
+
 impl Default for SshConfig {
     fn default() -> Self {
         Self {
@@ -29,6 +305,16 @@ impl Default for SshConfig {
             port: 22,
             username: "root".to_string(),
             auth: SshAuth::Agent,
+            jump_hosts: Vec::new(),
+            keepalive: KeepalivePolicy::default(),
+            reconnect: None,
+            timeouts: Timeouts::default(),
+            set_env: Vec::new(),
+            send_env: Vec::new(),
+            compression: false,
+            algorithms: AlgorithmPreferences::default(),
+            rekey: RekeyLimits::default(),
+            proxy: None,
         }
     }
 }
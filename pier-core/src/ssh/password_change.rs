@@ -0,0 +1,220 @@
+//! Changing an expired password while logging in.
+//!
+//! A server whose account password expired (or an administrator forced a
+//! change) asks for the current password and a new one, typed twice,
+//! through keyboard-interactive prompts, as PAM does. Rather than showing
+//! those prompts one by one, the app's password change handler is asked
+//! once for the old and new passwords, and the prompts are answered from
+//! them. If the server rejects the new password and asks again, the
+//! handler is asked again with the server's explanation.
+//!
+//! The other way to ask, a change request answering `password`
+//! authentication (SSH_MSG_USERAUTH_PASSWD_CHANGEREQ), isn't supported by
+//! the SSH library; with such servers the login doesn't complete.
+
+use std::sync::{Arc, Mutex};
+
+use super::AuthPrompts;
+
+/// What the app is told when a password has to be changed.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct PasswordChangeRequest {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    /// The server's explanation, e.g. "You are required to change your
+    /// password immediately", or why it rejected the last new password
+    pub message: String,
+}
+
+/// The app's answer to a `PasswordChangeRequest`.
+#[derive(Clone, serde::Deserialize)]
+pub struct NewPassword {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+/// Asks for the old and new passwords, or `None` to cancel the login.
+/// Called on a blocking thread, so it may wait for the user.
+pub type PasswordChangeHandler = Arc<dyn Fn(&PasswordChangeRequest) -> Option<NewPassword> + Send + Sync>;
+
+static HANDLER: Mutex<Option<PasswordChangeHandler>> = Mutex::new(None);
+
+/// Set the handler for all connections made from now on, or clear it.
+/// Without one, password change prompts go to the session's prompt
+/// handler like any other.
+pub fn set_handler(handler: Option<PasswordChangeHandler>) {
+    *HANDLER.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = handler;
+}
+
+/// The handler connections should ask.
+pub fn handler() -> Option<PasswordChangeHandler> {
+    HANDLER.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+/// What a prompt of a password change asks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Role {
+    /// The password being replaced
+    Current,
+    /// The new password, or it again to confirm
+    New,
+    /// The password to log in with, before the server says it expired
+    Login,
+}
+
+fn role(prompt: &str) -> Option<Role> {
+    let prompt = prompt.to_lowercase();
+    if !prompt.contains("password") {
+        return None;
+    }
+    if ["new", "retype", "re-enter", "repeat", "again", "confirm"].iter().any(|word| prompt.contains(word)) {
+        Some(Role::New)
+    } else if prompt.contains("current") || prompt.contains("old") {
+        Some(Role::Current)
+    } else {
+        Some(Role::Login)
+    }
+}
+
+/// Answers the prompts of a password change during one login.
+pub(super) struct PasswordChange {
+    request: PasswordChangeRequest,
+    /// The configured password, for the first plain password prompt
+    login_password: Option<String>,
+    answer: Option<NewPassword>,
+    /// New password prompts answered with `answer`
+    new_prompts: usize,
+}
+
+impl PasswordChange {
+    pub fn new(host: &str, port: u16, username: &str, login_password: Option<&str>) -> Self {
+        Self {
+            request: PasswordChangeRequest {
+                host: host.to_string(),
+                port,
+                username: username.to_string(),
+                message: String::new(),
+            },
+            login_password: login_password.map(str::to_string),
+            answer: None,
+            new_prompts: 0,
+        }
+    }
+
+    /// The prompts' roles, if the round is part of a password change:
+    /// only hidden password prompts, at least one for the current or new
+    /// password.
+    fn roles(round: &AuthPrompts) -> Option<Vec<Role>> {
+        let roles = round
+            .prompts
+            .iter()
+            .map(|prompt| if prompt.echo { None } else { role(&prompt.prompt) })
+            .collect::<Option<Vec<_>>>()?;
+        roles.iter().any(|role| *role != Role::Login).then_some(roles)
+    }
+
+    /// What to ask the handler before `round` can be answered, if
+    /// anything: the first time the passwords are needed, and when the
+    /// server asks for a new password again after rejecting one.
+    pub fn ask(&self, round: &AuthPrompts) -> Option<PasswordChangeRequest> {
+        let roles = Self::roles(round)?;
+        let rejected = roles.contains(&Role::New) && self.new_prompts >= 2;
+        if self.answer.is_some() && !rejected {
+            return None;
+        }
+        let message = [round.name.trim(), round.instructions.trim()]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some(PasswordChangeRequest { message, ..self.request.clone() })
+    }
+
+    /// Use `answer` for the prompts from now on.
+    pub fn set_answer(&mut self, answer: NewPassword) {
+        self.answer = Some(answer);
+        self.new_prompts = 0;
+    }
+
+    /// Answers for `round`, if it's part of a password change that has
+    /// been answered, or the login password prompt before one.
+    pub fn answer(&mut self, round: &AuthPrompts) -> Option<Vec<String>> {
+        let Some(roles) = Self::roles(round) else {
+            // A lone "Password:" prompt: the server checks the configured
+            // password before telling it expired
+            let login = round.prompts.len() == 1
+                && !round.prompts[0].echo
+                && role(&round.prompts[0].prompt) == Some(Role::Login);
+            return if login { self.login_password.take().map(|password| vec![password]) } else { None };
+        };
+        let answer = self.answer.as_ref()?;
+        let answers = roles
+            .iter()
+            .map(|role| match role {
+                Role::Current | Role::Login => answer.old_password.clone(),
+                Role::New => answer.new_password.clone(),
+            })
+            .collect();
+        self.new_prompts += roles.iter().filter(|role| **role == Role::New).count();
+        Some(answers)
+    }
+
+    /// Whether a new password was given to the server.
+    pub fn changed(&self) -> bool {
+        self.answer.is_some() && self.new_prompts > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::AuthPrompt;
+
+    fn round(instructions: &str, prompts: &[&str]) -> AuthPrompts {
+        AuthPrompts {
+            name: String::new(),
+            instructions: instructions.to_string(),
+            prompts: prompts.iter().map(|prompt| AuthPrompt { prompt: prompt.to_string(), echo: false }).collect(),
+        }
+    }
+
+    fn new_password(new_password: &str) -> NewPassword {
+        NewPassword { old_password: "old".to_string(), new_password: new_password.to_string() }
+    }
+
+    #[test]
+    fn test_pam_password_change() {
+        let mut change = PasswordChange::new("db1", 22, "deploy", Some("old"));
+
+        // The login password is sent once, without asking
+        let login = round("", &["Password: "]);
+        assert!(change.ask(&login).is_none());
+        assert_eq!(change.answer(&login), Some(vec!["old".to_string()]));
+        assert_eq!(change.answer(&login), None);
+
+        let current = round("You are required to change your password immediately\n", &["Current password: "]);
+        let request = change.ask(&current).unwrap();
+        assert_eq!(request.message, "You are required to change your password immediately");
+        assert_eq!((request.host.as_str(), request.username.as_str()), ("db1", "deploy"));
+        change.set_answer(new_password("weak"));
+        assert_eq!(change.answer(&current), Some(vec!["old".to_string()]));
+
+        let new = round("", &["New password: "]);
+        let retype = round("", &["Retype new password: "]);
+        assert!(change.ask(&new).is_none());
+        assert_eq!(change.answer(&new), Some(vec!["weak".to_string()]));
+        assert_eq!(change.answer(&retype), Some(vec!["weak".to_string()]));
+
+        // Rejected: the server asks again, and so is the handler
+        let again = round("BAD PASSWORD: it is too short", &["New password: "]);
+        assert_eq!(change.ask(&again).unwrap().message, "BAD PASSWORD: it is too short");
+        change.set_answer(new_password("long enough"));
+        assert_eq!(change.answer(&again), Some(vec!["long enough".to_string()]));
+        assert!(change.changed());
+
+        // Other questions aren't touched
+        assert!(change.answer(&round("", &["Verification code: "])).is_none());
+        assert!(PasswordChange::roles(&round("", &["Password: ", "OTP: "])).is_none());
+    }
+}
@@ -0,0 +1,184 @@
+//! Shared connections by destination, like OpenSSH's ControlMaster.
+//!
+//! Opening a tab, an SFTP browser and a tunnel to the same server would
+//! otherwise connect and authenticate three times (with three 2FA prompts).
+//! The pool keeps one connection per (host, port, user) and hands out
+//! sessions that share it; each holds a lease, and once the last one is
+//! gone the connection is kept for an idle period in case it's wanted
+//! again, then closed.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use super::session::SshSession;
+use super::{ConnectionState, PromptHandler, SshConfig};
+
+/// How long an unused connection stays open, unless changed.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+static IDLE_TIMEOUT_MS: AtomicU64 = AtomicU64::new(DEFAULT_IDLE_TIMEOUT.as_millis() as u64);
+
+/// Connections are shared between sessions for the same user on the same
+/// server.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct PoolKey {
+    host: String,
+    port: u16,
+    username: String,
+}
+
+/// One destination's connection.
+struct Slot {
+    key: PoolKey,
+    /// The session owning the connection, once connected
+    master: tokio::sync::Mutex<Option<SshSession>>,
+    /// Leases out
+    leases: AtomicUsize,
+    /// Bumped on every acquire, so an idle timer knows if it's stale
+    generation: AtomicU64,
+    runtime: tokio::runtime::Handle,
+}
+
+/// A session's hold on a pooled connection. Dropping the last one starts
+/// the idle timer.
+pub struct Lease {
+    slot: Arc<Slot>,
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if self.slot.leases.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let slot = self.slot.clone();
+            let generation = slot.generation.load(Ordering::Acquire);
+            self.slot.runtime.spawn(close_when_idle(slot, generation));
+        }
+    }
+}
+
+fn slots() -> &'static Mutex<HashMap<PoolKey, Arc<Slot>>> {
+    static SLOTS: OnceLock<Mutex<HashMap<PoolKey, Arc<Slot>>>> = OnceLock::new();
+    SLOTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Set how long a connection no session uses stays open.
+pub fn set_idle_timeout(timeout: Duration) {
+    IDLE_TIMEOUT_MS.store(timeout.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// A session on the pooled connection to `config`'s destination,
+/// connecting first if there is none or it's down. Settings other than
+/// the destination only matter when connecting. Must run on the runtime
+/// the connection should live on.
+pub async fn acquire(config: SshConfig, prompt_handler: Option<PromptHandler>) -> Result<SshSession, anyhow::Error> {
+    let key = PoolKey { host: config.host.clone(), port: config.port, username: config.username.clone() };
+    let slot = slots()
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .entry(key.clone())
+        .or_insert_with(|| {
+            Arc::new(Slot {
+                key,
+                master: tokio::sync::Mutex::new(None),
+                leases: AtomicUsize::new(0),
+                generation: AtomicU64::new(0),
+                runtime: tokio::runtime::Handle::current(),
+            })
+        })
+        .clone();
+
+    // Concurrent acquires for the same destination wait for one connect
+    let mut master = slot.master.lock().await;
+    let alive = master.as_ref().is_some_and(|session| {
+        matches!(session.state(), ConnectionState::Connected | ConnectionState::Degraded | ConnectionState::Connecting)
+    });
+    if !alive {
+        if let Some(mut stale) = master.take() {
+            let _ = stale.disconnect().await;
+        }
+        let mut session = SshSession::new(config);
+        session.set_prompt_handler(prompt_handler);
+        if let Err(e) = session.connect().await {
+            if slot.leases.load(Ordering::Acquire) == 0 {
+                forget(&slot);
+            }
+            return Err(e);
+        }
+        log::info!("SSH pool: new connection to {}@{}:{}", slot.key.username, slot.key.host, slot.key.port);
+        *master = Some(session);
+    }
+
+    slot.generation.fetch_add(1, Ordering::AcqRel);
+    slot.leases.fetch_add(1, Ordering::AcqRel);
+    let lease = Lease { slot: slot.clone() };
+    Ok(master.as_ref().map(|session| session.share(lease)).expect("connected above"))
+}
+
+/// A destination with a pooled connection.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct PooledConnection {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    /// Sessions using it; 0 while it idles
+    pub sessions: usize,
+}
+
+/// The pooled connections.
+pub fn connections() -> Vec<PooledConnection> {
+    slots()
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .values()
+        .map(|slot| PooledConnection {
+            host: slot.key.host.clone(),
+            port: slot.key.port,
+            username: slot.key.username.clone(),
+            sessions: slot.leases.load(Ordering::Acquire),
+        })
+        .collect()
+}
+
+/// Close `slot`'s connection after the idle timeout, unless it was
+/// acquired again meanwhile.
+async fn close_when_idle(slot: Arc<Slot>, generation: u64) {
+    tokio::time::sleep(Duration::from_millis(IDLE_TIMEOUT_MS.load(Ordering::Relaxed))).await;
+    let mut master = slot.master.lock().await;
+    if slot.leases.load(Ordering::Acquire) > 0 || slot.generation.load(Ordering::Acquire) != generation {
+        return;
+    }
+    forget(&slot);
+    if let Some(mut session) = master.take() {
+        log::info!("SSH pool: closing idle connection to {}:{}", slot.key.host, slot.key.port);
+        if let Err(e) = session.disconnect().await {
+            log::debug!("SSH pool disconnect failed: {}", e);
+        }
+    }
+}
+
+/// Take `slot` out of the pool, so the next acquire starts afresh.
+fn forget(slot: &Arc<Slot>) {
+    let mut slots = slots().lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if slots.get(&slot.key).is_some_and(|current| Arc::ptr_eq(current, slot)) {
+        slots.remove(&slot.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_failed_connect_is_not_pooled() {
+        let config = SshConfig {
+            host: "127.0.0.1".to_string(),
+            // Nothing listens on port 1
+            port: 1,
+            username: "nobody".to_string(),
+            ..SshConfig::default()
+        };
+        assert!(acquire(config, None).await.is_err());
+        assert!(!connections().iter().any(|pooled| pooled.host == "127.0.0.1" && pooled.port == 1));
+    }
+}
@@ -0,0 +1,279 @@
+//! Reaching SSH servers through a SOCKS5 or HTTP proxy.
+//!
+//! Behind proxies that only let web traffic out, the TCP connection is
+//! asked of the proxy: a SOCKS5 CONNECT (RFC 1928), with username/password
+//! authentication (RFC 1929) if credentials are set, or an HTTP `CONNECT`
+//! with Basic authentication. The host name is passed to the proxy
+//! unresolved, since hosts behind it are often only known to its DNS.
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::socks::{ATYP_DOMAIN, ATYP_IPV4, ATYP_IPV6, CMD_CONNECT, NO_ACCEPTABLE_METHOD, NO_AUTH, VERSION};
+
+/// Username/password authentication method, and its subnegotiation
+/// version.
+const USERNAME_PASSWORD: u8 = 2;
+const USERNAME_PASSWORD_VERSION: u8 = 1;
+
+/// Longest HTTP response head read from a proxy.
+const MAX_HTTP_RESPONSE: usize = 16 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyKind {
+    Socks5,
+    /// HTTP `CONNECT`
+    Http,
+}
+
+/// A proxy to open connections through.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ProxyConfig {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    /// Credentials, if the proxy asks for them
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+/// A TCP connection to `host`:`port` through `proxy`.
+pub async fn connect(proxy: &ProxyConfig, host: &str, port: u16) -> std::io::Result<TcpStream> {
+    let mut stream = TcpStream::connect((proxy.host.as_str(), proxy.port))
+        .await
+        .map_err(|e| failure(format!("Proxy {}:{} unreachable: {}", proxy.host, proxy.port, e)))?;
+    match proxy.kind {
+        ProxyKind::Socks5 => socks5_connect(&mut stream, proxy, host, port).await?,
+        ProxyKind::Http => http_connect(&mut stream, proxy, host, port).await?,
+    }
+    Ok(stream)
+}
+
+fn failure(message: String) -> std::io::Error {
+    std::io::Error::other(message)
+}
+
+/// Have a SOCKS5 proxy connect `stream` to `host`:`port`.
+async fn socks5_connect<S>(stream: &mut S, proxy: &ProxyConfig, host: &str, port: u16) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let credentials = proxy.username.as_deref().map(|username| (username, proxy.password.as_deref().unwrap_or("")));
+    if credentials.is_some() {
+        stream.write_all(&[VERSION, 2, NO_AUTH, USERNAME_PASSWORD]).await?;
+    } else {
+        stream.write_all(&[VERSION, 1, NO_AUTH]).await?;
+    }
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice[0] != VERSION {
+        return Err(failure(format!("Not a SOCKS5 proxy (version {})", choice[0])));
+    }
+    match (choice[1], credentials) {
+        (NO_AUTH, _) => {}
+        (USERNAME_PASSWORD, Some((username, password))) => {
+            if username.len() > 255 || password.len() > 255 {
+                return Err(failure("SOCKS proxy username or password too long".to_string()));
+            }
+            let mut request = vec![USERNAME_PASSWORD_VERSION, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            let sent = stream.write_all(&request).await;
+            request.fill(0);
+            sent?;
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await?;
+            if status[1] != 0 {
+                return Err(failure("SOCKS proxy rejected the username or password".to_string()));
+            }
+        }
+        (NO_ACCEPTABLE_METHOD, None) => {
+            return Err(failure("SOCKS proxy requires authentication".to_string()));
+        }
+        (method, _) => {
+            return Err(failure(format!("SOCKS proxy chose unsupported method {}", method)));
+        }
+    }
+
+    if host.len() > 255 {
+        return Err(failure(format!("Host name too long for SOCKS: {}", host)));
+    }
+    let mut request = vec![VERSION, CMD_CONNECT, 0, ATYP_DOMAIN, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != super::socks::REPLY_SUCCEEDED {
+        return Err(failure(format!("SOCKS proxy couldn't connect to {}:{}: {}", host, port, reply_text(reply[1]))));
+    }
+    // The bound address isn't needed, but has to be read past
+    let address_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => stream.read_u8().await? as usize,
+        other => return Err(failure(format!("SOCKS proxy sent unknown address type {}", other))),
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+/// What a SOCKS5 reply code means (RFC 1928, section 6).
+fn reply_text(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+/// Have an HTTP proxy connect `stream` to `host`:`port` with `CONNECT`.
+async fn http_connect<S>(stream: &mut S, proxy: &ProxyConfig, host: &str, port: u16) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // IPv6 addresses go in brackets
+    let authority =
+        if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) };
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some(username) = &proxy.username {
+        let credentials = format!("{}:{}", username, proxy.password.as_deref().unwrap_or(""));
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            data_encoding::BASE64.encode(credentials.as_bytes())
+        ));
+    }
+    request.push_str("\r\n");
+    let sent = stream.write_all(request.as_bytes()).await;
+    let mut request = request.into_bytes();
+    request.fill(0);
+    sent?;
+
+    // Read byte by byte, so nothing of the SSH stream after the head is
+    // consumed
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HTTP_RESPONSE {
+            return Err(failure("HTTP proxy response too long".to_string()));
+        }
+        head.push(stream.read_u8().await?);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let status_line = head.lines().next().unwrap_or("");
+    let mut parts = status_line.splitn(3, ' ');
+    let (version, status) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    if !version.starts_with("HTTP/") {
+        return Err(failure(format!("Not an HTTP proxy response: {}", status_line)));
+    }
+    match status {
+        "200" => Ok(()),
+        "407" => Err(failure("HTTP proxy requires authentication".to_string())),
+        _ => Err(failure(format!("HTTP proxy couldn't connect to {}: {}", authority, status_line))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxy(kind: ProxyKind, username: Option<&str>) -> ProxyConfig {
+        ProxyConfig {
+            kind,
+            host: "proxy".to_string(),
+            port: 1080,
+            username: username.map(str::to_string),
+            password: username.map(|_| "secret".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_socks5_with_auth() {
+        let (mut client, mut server) = tokio::io::duplex(256);
+        let server = tokio::spawn(async move {
+            let mut greeting = [0u8; 4];
+            server.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 2, NO_AUTH, USERNAME_PASSWORD]);
+            server.write_all(&[5, USERNAME_PASSWORD]).await.unwrap();
+            let mut auth = [0u8; 13];
+            server.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth, b"\x01\x04user\x06secret");
+            server.write_all(&[1, 0]).await.unwrap();
+
+            let mut request = [0u8; 16];
+            server.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request, b"\x05\x01\x00\x03\x09db1.local\x00\x16");
+            server.write_all(&[5, 0, 0, ATYP_IPV4, 10, 0, 0, 1, 0, 22]).await.unwrap();
+            server.write_all(b"SSH-2.0-OpenSSH\r\n").await.unwrap();
+        });
+
+        socks5_connect(&mut client, &proxy(ProxyKind::Socks5, Some("user")), "db1.local", 22).await.unwrap();
+        server.await.unwrap();
+        let mut banner = [0u8; 4];
+        client.read_exact(&mut banner).await.unwrap();
+        assert_eq!(&banner, b"SSH-");
+    }
+
+    #[tokio::test]
+    async fn test_socks5_refused() {
+        let (mut client, mut server) = tokio::io::duplex(256);
+        tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            server.write_all(&[5, NO_AUTH]).await.unwrap();
+            let mut request = [0u8; 16];
+            server.read_exact(&mut request).await.unwrap();
+            server.write_all(&[5, 5, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).await.unwrap();
+        });
+        let error = socks5_connect(&mut client, &proxy(ProxyKind::Socks5, None), "db1.local", 22).await.unwrap_err();
+        assert_eq!(error.to_string(), "SOCKS proxy couldn't connect to db1.local:22: connection refused");
+    }
+
+    #[tokio::test]
+    async fn test_http_connect() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let server = tokio::spawn(async move {
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(server.read_u8().await.unwrap());
+            }
+            server.write_all(b"HTTP/1.1 200 Connection established\r\n\r\nSSH-2.0").await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        http_connect(&mut client, &proxy(ProxyKind::Http, Some("user")), "2001:db8::1", 22).await.unwrap();
+        assert_eq!(
+            server.await.unwrap(),
+            "CONNECT [2001:db8::1]:22 HTTP/1.1\r\nHost: [2001:db8::1]:22\r\n\
+             Proxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\n\r\n"
+        );
+        let mut banner = [0u8; 7];
+        client.read_exact(&mut banner).await.unwrap();
+        assert_eq!(&banner, b"SSH-2.0");
+    }
+
+    #[tokio::test]
+    async fn test_http_auth_required() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(server.read_u8().await.unwrap());
+            }
+            server.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n").await.unwrap();
+        });
+        let error = http_connect(&mut client, &proxy(ProxyKind::Http, None), "db1", 22).await.unwrap_err();
+        assert_eq!(error.to_string(), "HTTP proxy requires authentication");
+    }
+}
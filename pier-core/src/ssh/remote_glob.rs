@@ -0,0 +1,351 @@
+//! Wildcards in remote paths, expanded over SFTP the way a shell would, so
+//! `logs/*.log` or `etc/**/*.conf` can be listed, downloaded or removed in
+//! one go.
+//!
+//! `*` and `?` match within a name, `[a-z]`/`[!x]` match a character from
+//! a set, and a `**` component matches any depth of directories. Like the
+//! shell, wildcards don't match names starting with `.` unless the pattern
+//! does, and symlinked directories aren't descended.
+
+use std::path::{Component, Path};
+
+use super::sftp::{RemoteFileEntry, SftpClient};
+
+/// Deepest level a `**` descends, as for remote search.
+const MAX_DEPTH: usize = 10;
+
+/// Most matches a download or remove acts on.
+const MAX_MATCHES: usize = 10_000;
+
+/// One element of a name pattern.
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    /// `*`
+    Any,
+    /// `?`
+    One,
+    /// `[...]`, as inclusive ranges
+    Class { negated: bool, ranges: Vec<(char, char)> },
+    Char(char),
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Any | Token::One => true,
+            Token::Class { negated, ranges } => ranges.iter().any(|&(low, high)| low <= c && c <= high) != *negated,
+            Token::Char(expected) => *expected == c,
+        }
+    }
+}
+
+/// The tokens of a name pattern. A `\` makes the next character literal,
+/// and a `[` without a closing `]` is literal too.
+fn tokens(pattern: &str) -> Vec<Token> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let token = match chars[i] {
+            '*' => Token::Any,
+            '?' => Token::One,
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                Token::Char(chars[i])
+            }
+            '[' => match class(&chars[i + 1..]) {
+                Some((class, len)) => {
+                    i += len;
+                    class
+                }
+                None => Token::Char('['),
+            },
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+        i += 1;
+    }
+    tokens
+}
+
+/// The class at the start of `chars`, just after its `[`, and how many
+/// characters it takes up with its `]`. A `]` first is a member.
+fn class(chars: &[char]) -> Option<(Token, usize)> {
+    let negated = matches!(chars.first(), Some('!' | '^'));
+    let start = usize::from(negated);
+    let mut ranges = Vec::new();
+    let mut i = start;
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && i > start {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        match (chars.get(i + 1), chars.get(i + 2)) {
+            (Some('-'), Some(&high)) if high != ']' => {
+                ranges.push((c, high));
+                i += 3;
+            }
+            _ => {
+                ranges.push((c, c));
+                i += 1;
+            }
+        }
+    }
+}
+
+/// The name a pattern stands for if it has no wildcards.
+fn literal(pattern: &str) -> Option<String> {
+    tokens(pattern)
+        .into_iter()
+        .map(|token| match token {
+            Token::Char(c) => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether `name` matches a one-component `pattern`.
+fn name_matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let tokens = tokens(pattern);
+    let text: Vec<char> = name.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and the text position it's matched up to
+    let mut backtrack = None;
+    while t < text.len() {
+        match tokens.get(p) {
+            Some(Token::Any) => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(token) if token.matches(text[t]) => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    tokens[p..].iter().all(|token| *token == Token::Any)
+}
+
+/// Whether a remote path has anything to expand.
+pub fn has_wildcards(pattern: &str) -> bool {
+    pattern.split('/').any(|component| literal(component).is_none())
+}
+
+/// `name` in `dir`, where an empty `dir` is the SFTP working directory.
+fn join(dir: &str, name: &str) -> String {
+    match dir {
+        "" => name.to_string(),
+        dir if dir.ends_with('/') => format!("{dir}{name}"),
+        dir => format!("{dir}/{name}"),
+    }
+}
+
+async fn list(sftp: &SftpClient, dir: &str) -> Option<Vec<RemoteFileEntry>> {
+    match sftp.list_dir(if dir.is_empty() { "." } else { dir }).await {
+        Ok(entries) => Some(entries),
+        Err(e) => {
+            log::debug!("Expanding in {} failed: {}", dir, e);
+            None
+        }
+    }
+}
+
+/// The remote files and directories matching `pattern`, up to
+/// `max_results`, sorted by path. Unreadable directories are skipped, and
+/// a pattern matching nothing gives nothing, not an error.
+pub async fn expand(
+    sftp: &SftpClient,
+    pattern: &str,
+    max_results: usize,
+) -> Result<Vec<RemoteFileEntry>, anyhow::Error> {
+    let components: Vec<&str> = pattern.split('/').filter(|component| !component.is_empty()).collect();
+    if components.is_empty() {
+        return Ok(sftp.entry(pattern).await.into_iter().collect());
+    }
+
+    let mut dirs = vec![if pattern.starts_with('/') { "/".to_string() } else { String::new() }];
+    let mut matches = Vec::new();
+    for (i, &component) in components.iter().enumerate() {
+        let last = i + 1 == components.len();
+        let component = if component == "**" {
+            dirs = with_subdirectories(sftp, dirs).await;
+            if !last {
+                continue;
+            }
+            // Trailing, everything at any depth
+            "*"
+        } else {
+            component
+        };
+        match literal(component) {
+            Some(name) if last => {
+                for dir in &dirs {
+                    if let Ok(entry) = sftp.entry(&join(dir, &name)).await {
+                        matches.push(entry);
+                    }
+                }
+            }
+            Some(name) => dirs = dirs.iter().map(|dir| join(dir, &name)).collect(),
+            None => {
+                let mut next = Vec::new();
+                for dir in &dirs {
+                    let Some(entries) = list(sftp, dir).await else {
+                        continue;
+                    };
+                    for mut entry in entries.into_iter().filter(|entry| name_matches(component, &entry.name)) {
+                        entry.path = join(dir, &entry.name);
+                        if last {
+                            matches.push(entry);
+                        } else if entry.is_dir {
+                            next.push(entry.path);
+                        }
+                    }
+                }
+                dirs = next;
+            }
+        }
+        if dirs.is_empty() {
+            break;
+        }
+    }
+
+    matches.sort_by(|a, b| a.path.cmp(&b.path));
+    matches.dedup_by(|a, b| a.path == b.path);
+    matches.truncate(max_results);
+    Ok(matches)
+}
+
+/// `dirs` and every directory below them, for `**`. Hidden directories
+/// aren't descended.
+async fn with_subdirectories(sftp: &SftpClient, dirs: Vec<String>) -> Vec<String> {
+    let mut all = dirs.clone();
+    let mut level = dirs;
+    for _ in 0..MAX_DEPTH {
+        let mut next = Vec::new();
+        for dir in &level {
+            let Some(entries) = list(sftp, dir).await else {
+                continue;
+            };
+            next.extend(
+                entries
+                    .into_iter()
+                    .filter(|entry| entry.is_dir && !entry.name.starts_with('.'))
+                    .map(|entry| join(dir, &entry.name)),
+            );
+        }
+        if next.is_empty() {
+            break;
+        }
+        all.extend(next.iter().cloned());
+        level = next;
+    }
+    all.sort();
+    all.dedup();
+    all
+}
+
+/// The directory a pattern's matches are under before any wildcard, which
+/// downloads keep the layout below.
+fn literal_base(pattern: &str) -> String {
+    let components: Vec<&str> = pattern.split('/').collect();
+    let parents = &components[..components.len() - 1];
+    let base: Vec<String> = parents.iter().map_while(|component| literal(component)).collect();
+    match base.join("/") {
+        // Just the root
+        base if base.is_empty() && pattern.starts_with('/') => "/".to_string(),
+        base => base,
+    }
+}
+
+/// Download the files matching `pattern` into `local_dir`, keeping their
+/// layout below the pattern's first wildcard. Directories matched aren't
+/// copied. Returns how many files were downloaded.
+pub async fn download_matches(sftp: &SftpClient, pattern: &str, local_dir: &Path) -> Result<usize, anyhow::Error> {
+    let base = literal_base(pattern);
+    let mut count = 0;
+    for entry in expand(sftp, pattern, MAX_MATCHES).await? {
+        if entry.is_dir {
+            continue;
+        }
+        let relative = Path::new(entry.path.strip_prefix(base.as_str()).unwrap_or(&entry.path).trim_start_matches('/'));
+        if !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(anyhow::anyhow!("{} would land outside {}", entry.path, local_dir.display()));
+        }
+        let local_path = local_dir.join(relative);
+        if let Some(parent) = local_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        sftp.download(&entry.path, &local_path).await?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Remove the files matching `pattern`, like `rm` without `-r`: directories
+/// matched are left. Carries on past files that can't be removed, then
+/// fails if there were any. Returns how many files were removed.
+pub async fn remove_matches(sftp: &SftpClient, pattern: &str) -> Result<usize, anyhow::Error> {
+    let mut removed = 0;
+    let mut failed = Vec::new();
+    for entry in expand(sftp, pattern, MAX_MATCHES).await? {
+        if entry.is_dir {
+            continue;
+        }
+        match sftp.remove_file(&entry.path).await {
+            Ok(()) => removed += 1,
+            Err(e) => failed.push(format!("{}: {}", entry.path, e)),
+        }
+    }
+    match failed.first() {
+        None => Ok(removed),
+        Some(first) => Err(anyhow::anyhow!("Removed {} files, {} failed ({})", removed, failed.len(), first)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_matches() {
+        assert!(name_matches("*.log", "app.log"));
+        assert!(!name_matches("*.log", "app.log.1"));
+        assert!(!name_matches("*", ".bashrc"));
+        assert!(name_matches(".*rc", ".bashrc"));
+        assert!(name_matches("app-?.[0-9]", "app-a.7"));
+        assert!(!name_matches("app-[!a-c].log", "app-b.log"));
+        assert!(name_matches("[]x]", "]"));
+        assert!(name_matches(r"a\*", "a*"));
+        assert!(!name_matches(r"a\*", "ab"));
+        assert!(name_matches("[oops", "[oops"));
+    }
+
+    #[test]
+    fn test_has_wildcards() {
+        assert!(has_wildcards("/var/log/*.log"));
+        assert!(has_wildcards("etc/**/x.conf"));
+        assert!(!has_wildcards("/var/log/syslog"));
+        assert!(!has_wildcards(r"/srv/a\*b"));
+        assert_eq!(literal(r"a\*b"), Some("a*b".to_string()));
+    }
+
+    #[test]
+    fn test_literal_base() {
+        assert_eq!(literal_base("/var/log/*.log"), "/var/log");
+        assert_eq!(literal_base("etc/**/*.conf"), "etc");
+        assert_eq!(literal_base("*.txt"), "");
+        assert_eq!(literal_base("/*.txt"), "/");
+        assert_eq!(literal_base(r"a/b\*/c*/d"), "a/b*");
+    }
+}
@@ -0,0 +1,149 @@
+//! File search on a remote host, for Cmd-P on SSH sessions.
+//!
+//! Matches the way local search does: names containing the pattern,
+//! ignoring case, up to 10 levels below the root. GNU `find` does the
+//! walk on the server when it's there; otherwise (BusyBox, BSD) the tree
+//! is walked over SFTP, a directory listing per round trip.
+
+use super::session::SshSession;
+use super::sftp::{RemoteFileEntry, SftpClient};
+use super::sudo::quote;
+
+/// Deepest level searched below the root, as for local search.
+const MAX_DEPTH: usize = 10;
+
+/// Find up to `max_results` files and directories under `root` whose names
+/// contain `pattern`, ignoring case.
+pub async fn search_remote(
+    session: &SshSession,
+    root: &str,
+    pattern: &str,
+    max_results: usize,
+) -> Result<Vec<RemoteFileEntry>, anyhow::Error> {
+    if max_results == 0 {
+        return Ok(Vec::new());
+    }
+    match session.exec_command(&find_command(root, pattern, max_results)).await {
+        Ok((0, output)) => return Ok(parse_find_output(&output, max_results)),
+        Ok((code, _)) => log::debug!("find unavailable (exit {}), searching over SFTP", code),
+        Err(e) => log::debug!("find failed ({}), searching over SFTP", e),
+    }
+    let sftp = session.open_sftp().await?;
+    walk(&sftp, root, pattern, max_results).await
+}
+
+/// A `find` run printing type, size, mtime, mode and path of each match,
+/// NUL-terminated. It fails up front if `find` lacks `-printf`.
+fn find_command(root: &str, pattern: &str, max_results: usize) -> String {
+    // `./` keeps a relative root starting with `-` from reading as an option
+    let root = if root.starts_with('/') { quote(root) } else { quote(&format!("./{}", root)) };
+    format!(
+        "find {root} -maxdepth 0 -printf '' >/dev/null 2>&1 && \
+         find {root} -mindepth 1 -maxdepth {MAX_DEPTH} -iname {name} \
+         -printf '%y\\t%s\\t%T@\\t%m\\t%p\\0' 2>/dev/null | head -z -n {max_results}",
+        name = quote(&format!("*{}*", escape_glob(pattern))),
+    )
+}
+
+/// `pattern` matching itself literally in a `find -name` glob.
+fn escape_glob(pattern: &str) -> String {
+    let mut escaped = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The entries printed by `find_command`.
+fn parse_find_output(output: &str, max_results: usize) -> Vec<RemoteFileEntry> {
+    output
+        .split('\0')
+        .filter_map(|record| {
+            let mut fields = record.trim_start_matches('\n').splitn(5, '\t');
+            let (kind, size, mtime, mode, path) =
+                (fields.next()?, fields.next()?, fields.next()?, fields.next()?, fields.next()?);
+            let file_type = match kind {
+                "d" => 0o040000,
+                "l" => 0o120000,
+                "f" => 0o100000,
+                _ => 0,
+            };
+            let mode = u32::from_str_radix(mode, 8).ok()?;
+            Some(RemoteFileEntry {
+                name: path.rsplit('/').next().unwrap_or(path).to_string(),
+                path: path.to_string(),
+                is_dir: kind == "d",
+                size: size.parse().unwrap_or(0),
+                modified: mtime.parse::<f64>().ok().map(|mtime| mtime as u64),
+                permissions: Some(file_type | mode),
+            })
+        })
+        .take(max_results)
+        .collect()
+}
+
+/// Search by listing directories over SFTP, breadth first. Unreadable
+/// directories are skipped, and symlinks aren't followed.
+async fn walk(
+    sftp: &SftpClient,
+    root: &str,
+    pattern: &str,
+    max_results: usize,
+) -> Result<Vec<RemoteFileEntry>, anyhow::Error> {
+    let pattern = pattern.to_lowercase();
+    let mut results = Vec::new();
+    let mut level = vec![root.to_string()];
+    for depth in 1..=MAX_DEPTH {
+        let mut next = Vec::new();
+        for dir in level {
+            let entries = match sftp.list_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if depth == 1 => return Err(e),
+                Err(_) => continue,
+            };
+            for entry in entries {
+                if entry.is_dir {
+                    next.push(entry.path.clone());
+                }
+                if entry.name.to_lowercase().contains(&pattern) {
+                    results.push(entry);
+                    if results.len() >= max_results {
+                        return Ok(results);
+                    }
+                }
+            }
+        }
+        level = next;
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_command() {
+        assert_eq!(escape_glob("a*b[1]?"), r"a\*b\[1\]\?");
+        let command = find_command("/srv/it's", "log", 50);
+        assert!(command.starts_with(r"find '/srv/it'\''s' -maxdepth 0 -printf"));
+        assert!(command.contains("-iname '*log*'"));
+        assert!(command.ends_with("head -z -n 50"));
+        assert!(find_command("-logs", "x", 5).starts_with("find './-logs' -maxdepth 0"));
+    }
+
+    #[test]
+    fn test_parse_find_output() {
+        let output = "d\t4096\t1700000000.5\t755\t/srv/logs\0f\t12\t1700000001.0\t644\t/srv/logs/app\tlog\0";
+        let entries = parse_find_output(output, 10);
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].name.as_str(), entries[0].is_dir), ("logs", true));
+        assert_eq!(entries[0].permissions, Some(0o40755));
+        assert_eq!(entries[1].name, "app\tlog");
+        assert_eq!((entries[1].size, entries[1].modified), (12, Some(1700000001)));
+        assert_eq!(parse_find_output(output, 1).len(), 1);
+    }
+}
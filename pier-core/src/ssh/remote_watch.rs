@@ -0,0 +1,188 @@
+//! Noticing changes to remote files and directories, by polling them over
+//! SFTP, so open views and previews refresh by themselves.
+//!
+//! A file counts as changed when its size or modification time does; a
+//! directory when an entry is added, removed or changed in size or time.
+//! Changes within one poll interval are reported once.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use russh_sftp::client::SftpSession;
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use super::sftp::SftpClient;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+}
+
+/// A change to a watched path.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RemoteChange {
+    pub path: String,
+    pub kind: ChangeKind,
+}
+
+/// What a path looked like at a poll.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Snapshot {
+    Missing,
+    File { size: u64, mtime: u32 },
+    /// A digest of the listing
+    Dir(u64),
+}
+
+impl Snapshot {
+    /// How `self` became `now`, if it changed.
+    fn change(self, now: Snapshot) -> Option<ChangeKind> {
+        match (self, now) {
+            (before, now) if before == now => None,
+            (Snapshot::Missing, _) => Some(ChangeKind::Created),
+            (_, Snapshot::Missing) => Some(ChangeKind::Deleted),
+            _ => Some(ChangeKind::Modified),
+        }
+    }
+}
+
+/// Remote paths polled for changes, see `RemoteWatch::start`.
+pub struct RemoteWatch {
+    /// Watched paths, with how they looked at the last poll
+    paths: Arc<Mutex<HashMap<String, Option<Snapshot>>>>,
+    cancel: Option<oneshot::Sender<()>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl RemoteWatch {
+    /// Poll the watched paths over `sftp` every `interval` on `runtime`,
+    /// passing each change to `on_change`. Paths are added with `watch`.
+    pub fn start(
+        sftp: &SftpClient,
+        runtime: &tokio::runtime::Handle,
+        interval: Duration,
+        mut on_change: impl FnMut(&RemoteChange) + Send + 'static,
+    ) -> Result<Self, anyhow::Error> {
+        let sftp = sftp.shared_session()?;
+        let paths: Arc<Mutex<HashMap<String, Option<Snapshot>>>> = Arc::default();
+        let polled = paths.clone();
+        let (cancel, mut cancelled) = oneshot::channel::<()>();
+        let task = runtime.spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = poll(&sftp, &polled, &mut on_change) => {}
+                    _ = &mut cancelled => return,
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {}
+                    _ = &mut cancelled => return,
+                }
+            }
+        });
+        Ok(Self { paths, cancel: Some(cancel), task })
+    }
+
+    /// Start watching `path`; changes count from the next poll.
+    pub fn watch(&self, path: &str) {
+        self.lock().entry(path.to_string()).or_insert(None);
+    }
+
+    /// Stop watching `path`.
+    pub fn unwatch(&self, path: &str) -> bool {
+        self.lock().remove(path).is_some()
+    }
+
+    /// Stop polling. No callback runs once this returns.
+    pub async fn stop(mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            let _ = cancel.send(());
+        }
+        let _ = (&mut self.task).await;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Option<Snapshot>>> {
+        self.paths.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Look at each watched path once, reporting what changed since last time.
+async fn poll(
+    sftp: &SftpSession,
+    paths: &Mutex<HashMap<String, Option<Snapshot>>>,
+    on_change: &mut (impl FnMut(&RemoteChange) + Send),
+) {
+    let watched: Vec<String> = paths.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).keys().cloned().collect();
+    for path in watched {
+        let Some(now) = snapshot(sftp, &path).await else {
+            continue;
+        };
+        let before = {
+            let mut paths = paths.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            match paths.get_mut(&path) {
+                Some(last) => last.replace(now),
+                // Unwatched meanwhile
+                None => continue,
+            }
+        };
+        if let Some(kind) = before.and_then(|before| before.change(now)) {
+            on_change(&RemoteChange { path, kind });
+        }
+    }
+}
+
+/// How `path` looks now, or `None` if the server couldn't say.
+async fn snapshot(sftp: &SftpSession, path: &str) -> Option<Snapshot> {
+    let metadata = match sftp.metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(russh_sftp::client::error::Error::Status(status))
+            if status.status_code == russh_sftp::protocol::StatusCode::NoSuchFile =>
+        {
+            return Some(Snapshot::Missing);
+        }
+        Err(e) => {
+            log::debug!("Watching {} failed: {}", path, e);
+            return None;
+        }
+    };
+    if !metadata.file_type().is_dir() {
+        return Some(Snapshot::File { size: metadata.size.unwrap_or(0), mtime: metadata.mtime.unwrap_or(0) });
+    }
+    let mut entries: Vec<(String, u64, u32)> = match sftp.read_dir(path).await {
+        Ok(dir) => dir
+            .map(|entry| {
+                let metadata = entry.metadata();
+                (entry.file_name(), metadata.size.unwrap_or(0), metadata.mtime.unwrap_or(0))
+            })
+            .collect(),
+        Err(e) => {
+            log::debug!("Watching {} failed: {}", path, e);
+            return None;
+        }
+    };
+    entries.sort();
+    let mut hasher = DefaultHasher::new();
+    entries.hash(&mut hasher);
+    Some(Snapshot::Dir(hasher.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_change() {
+        let file = Snapshot::File { size: 10, mtime: 100 };
+        assert_eq!(file.change(file), None);
+        assert_eq!(file.change(Snapshot::File { size: 10, mtime: 101 }), Some(ChangeKind::Modified));
+        assert_eq!(file.change(Snapshot::Missing), Some(ChangeKind::Deleted));
+        assert_eq!(Snapshot::Missing.change(Snapshot::Dir(7)), Some(ChangeKind::Created));
+        assert_eq!(Snapshot::Dir(7).change(Snapshot::Dir(8)), Some(ChangeKind::Modified));
+    }
+}
@@ -0,0 +1,222 @@
+//! Security key (FIDO2) identities, `sk-ssh-ed25519@openssh.com` and
+//! `sk-ecdsa-sha2-nistp256@openssh.com`, as made by `ssh-keygen -t ed25519-sk`.
+//!
+//! The private key file of such an identity only holds a handle to the key
+//! kept on the authenticator, so every signature takes the authenticator,
+//! and usually a touch. The app's security key handler does that part: it
+//! is given the key handle and the challenge, tells the user to touch the
+//! key (and asks for the PIN if needed), and returns the authenticator's
+//! assertion, which is encoded here into the SSH signature as OpenSSH's
+//! PROTOCOL.u2f describes.
+
+use std::sync::{Arc, Mutex};
+
+use russh::keys::{Algorithm, PrivateKey};
+use russh::CryptoVec;
+
+/// Key flag: the authenticator must see a touch to sign.
+pub const FLAG_USER_PRESENCE: u8 = 0x01;
+/// Key flag: the authenticator must verify the user, e.g. by PIN.
+pub const FLAG_USER_VERIFICATION: u8 = 0x04;
+
+/// What the app is asked to sign with a security key.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct SecurityKeyRequest {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    /// `sk-ssh-ed25519@openssh.com` or `sk-ecdsa-sha2-nistp256@openssh.com`
+    pub algorithm: String,
+    /// FIDO relying party id the key was made for, usually `ssh:`
+    pub application: String,
+    /// Credential id on the authenticator, base64
+    pub key_handle: String,
+    /// The key's flags (`FLAG_USER_PRESENCE`, `FLAG_USER_VERIFICATION`)
+    pub flags: u8,
+    /// Client data hash to have signed: the SHA-256 of the data, base64
+    pub challenge: String,
+}
+
+/// The authenticator's assertion for a `SecurityKeyRequest`.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct SecurityKeySignature {
+    /// Base64: 64 bytes for Ed25519, DER for ECDSA, as authenticators
+    /// return them
+    pub signature: String,
+    /// Authenticator data flags
+    pub flags: u8,
+    /// Signature counter
+    pub counter: u32,
+}
+
+/// Signs with the authenticator, or `None` to cancel. Called on a blocking
+/// thread, so it may wait for the touch.
+pub type SecurityKeyHandler = Arc<dyn Fn(&SecurityKeyRequest) -> Option<SecurityKeySignature> + Send + Sync>;
+
+static HANDLER: Mutex<Option<SecurityKeyHandler>> = Mutex::new(None);
+
+/// Set the handler for all connections made from now on, or clear it.
+/// Without one, security key identities can't log in.
+pub fn set_handler(handler: Option<SecurityKeyHandler>) {
+    *HANDLER.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = handler;
+}
+
+/// The handler connections should ask.
+pub fn handler() -> Option<SecurityKeyHandler> {
+    HANDLER.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+/// Whether `key` lives on a security key.
+pub fn is_security_key(key: &PrivateKey) -> bool {
+    matches!(key.algorithm(), Algorithm::SkEd25519 | Algorithm::SkEcdsaSha2NistP256)
+}
+
+/// Signs public key authentication with a security key, through the
+/// handler.
+pub(super) struct SecurityKeySigner {
+    /// The request, all but the challenge
+    request: SecurityKeyRequest,
+    handler: SecurityKeyHandler,
+}
+
+impl SecurityKeySigner {
+    /// A signer for `key`, if it is a security key.
+    pub fn new(
+        key: &PrivateKey,
+        host: &str,
+        port: u16,
+        username: &str,
+        handler: SecurityKeyHandler,
+    ) -> Option<Self> {
+        let data = key.key_data();
+        let (application, key_handle, flags) = match (data.sk_ed25519(), data.sk_ecdsa_p256()) {
+            (Some(sk), _) => (sk.public().application(), sk.key_handle(), sk.flags()),
+            (None, Some(sk)) => (sk.public().application(), sk.key_handle(), sk.flags()),
+            (None, None) => return None,
+        };
+        Some(Self {
+            request: SecurityKeyRequest {
+                host: host.to_string(),
+                port,
+                username: username.to_string(),
+                algorithm: key.algorithm().to_string(),
+                application: application.to_string(),
+                key_handle: data_encoding::BASE64.encode(key_handle),
+                flags,
+                challenge: String::new(),
+            },
+            handler,
+        })
+    }
+}
+
+impl russh::Signer for SecurityKeySigner {
+    type Error = anyhow::Error;
+
+    fn auth_publickey_sign(
+        &mut self,
+        _key: &russh::keys::PublicKey,
+        _hash_alg: Option<russh::keys::HashAlg>,
+        mut to_sign: CryptoVec,
+    ) -> impl std::future::Future<Output = Result<CryptoVec, Self::Error>> + Send {
+        let challenge = ring::digest::digest(&ring::digest::SHA256, &to_sign);
+        let request =
+            SecurityKeyRequest { challenge: data_encoding::BASE64.encode(challenge.as_ref()), ..self.request.clone() };
+        let handler = self.handler.clone();
+        async move {
+            let asked = request.clone();
+            let assertion = tokio::task::spawn_blocking(move || handler(&asked))
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Security key signing cancelled"))?;
+            let signature = data_encoding::BASE64
+                .decode(assertion.signature.as_bytes())
+                .map_err(|_| anyhow::anyhow!("Invalid security key signature"))?;
+            let blob = encode_signature(&request.algorithm, &signature, assertion.flags, assertion.counter)?;
+            to_sign.extend(&(blob.len() as u32).to_be_bytes());
+            to_sign.extend(&blob);
+            Ok(to_sign)
+        }
+    }
+}
+
+/// The SSH signature blob for an authenticator's assertion.
+fn encode_signature(algorithm: &str, signature: &[u8], flags: u8, counter: u32) -> Result<Vec<u8>, anyhow::Error> {
+    let signature = if algorithm.starts_with("sk-ecdsa-") {
+        // Each DER INTEGER is already in mpint form
+        let (r, s) = der_signature(signature).ok_or_else(|| anyhow::anyhow!("Invalid ECDSA signature"))?;
+        let mut encoded = Vec::new();
+        put_string(&mut encoded, r);
+        put_string(&mut encoded, s);
+        encoded
+    } else {
+        if signature.len() != 64 {
+            return Err(anyhow::anyhow!("Invalid Ed25519 signature length {}", signature.len()));
+        }
+        signature.to_vec()
+    };
+    let mut blob = Vec::new();
+    put_string(&mut blob, algorithm.as_bytes());
+    put_string(&mut blob, &signature);
+    blob.push(flags);
+    blob.extend_from_slice(&counter.to_be_bytes());
+    Ok(blob)
+}
+
+fn put_string(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(data);
+}
+
+/// The integers of a DER `SEQUENCE { INTEGER r, INTEGER s }`. P-256
+/// signatures are short enough for single-byte lengths.
+fn der_signature(der: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&len, rest) = rest.split_first()?;
+    if tag != 0x30 || len as usize != rest.len() {
+        return None;
+    }
+    let (r, used) = der_integer(rest)?;
+    let (s, used_s) = der_integer(&rest[used..])?;
+    (used + used_s == rest.len()).then_some((r, s))
+}
+
+/// The DER `INTEGER` at the start of `der`, and the bytes it takes.
+fn der_integer(der: &[u8]) -> Option<(&[u8], usize)> {
+    let (&tag, rest) = der.split_first()?;
+    let (&len, rest) = rest.split_first()?;
+    let len = len as usize;
+    (tag == 0x02 && len < 0x80 && rest.len() >= len).then(|| (&rest[..len], 2 + len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_ed25519_signature() {
+        let blob = encode_signature("sk-ssh-ed25519@openssh.com", &[7; 64], FLAG_USER_PRESENCE, 42).unwrap();
+        let mut expected = Vec::new();
+        put_string(&mut expected, b"sk-ssh-ed25519@openssh.com");
+        put_string(&mut expected, &[7; 64]);
+        expected.extend_from_slice(&[1, 0, 0, 0, 42]);
+        assert_eq!(blob, expected);
+        assert!(encode_signature("sk-ssh-ed25519@openssh.com", &[7; 63], 1, 0).is_err());
+    }
+
+    #[test]
+    fn test_encode_ecdsa_signature() {
+        // r with its sign byte, s short
+        let der = [0x30, 0x0a, 0x02, 0x03, 0x00, 0x80, 0x01, 0x02, 0x03, 0x01, 0x02, 0x03];
+        assert_eq!(der_signature(&der), Some((&[0x00, 0x80, 0x01][..], &[0x01, 0x02, 0x03][..])));
+        let blob = encode_signature("sk-ecdsa-sha2-nistp256@openssh.com", &der, 5, 1).unwrap();
+        let mut signature = Vec::new();
+        put_string(&mut signature, &[0x00, 0x80, 0x01]);
+        put_string(&mut signature, &[0x01, 0x02, 0x03]);
+        let mut expected = Vec::new();
+        put_string(&mut expected, b"sk-ecdsa-sha2-nistp256@openssh.com");
+        put_string(&mut expected, &signature);
+        expected.extend_from_slice(&[5, 0, 0, 0, 1]);
+        assert_eq!(blob, expected);
+        assert!(der_signature(&der[..11]).is_none());
+    }
+}
@@ -1,4 +1,18 @@
-use super::{SshConfig, SshAuth};
+use super::known_hosts::{self, HostKeyDecision, HostKeyHandler, HostKeyStatus};
+use super::config_file;
+use super::exec::{self, OutputStream};
+use super::password_change::{self, PasswordChange};
+use super::pool::Lease;
+use super::proxy::ProxyConfig;
+use super::security_key::{self, SecurityKeySigner};
+use super::sftp::SftpClient;
+use super::sudo::{self, SudoOutput, SudoRun};
+use super::stats::{ConnectionStats, Counted, LatencyHistory, StatsSnapshot, TunnelCounters};
+use super::transcript::{Transcript, TranscriptOptions};
+use super::{
+    socks, AuthPrompt, AuthPrompts, ConnectionEvent, ConnectionState, EventHandler, KeepalivePolicy,
+    KeyExchangeInfo, LoginInfo, PromptHandler, ReconnectPolicy, SessionEvent, SshAuth, SshConfig, StateHandler,
+};
 use russh::*;
 use russh::keys::*;
 use std::sync::Arc;
@@ -7,12 +21,258 @@ use tokio::sync::Mutex;
 use tokio::sync::watch;
 use tokio::net::TcpListener;
 
+/// Most keyboard-interactive rounds answered before giving up on a server
+/// that keeps asking.
+const MAX_PROMPT_ROUNDS: usize = 16;
+
+/// How often a connection without keepalives is checked for having closed.
+const CLOSED_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Longest wait for a keepalive reply.
+const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// First wait before binding a failed forward listener again, doubled
+/// after each failed try up to the maximum.
+const FORWARD_RESTART_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+const FORWARD_RESTART_MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Wait before accepting again when out of file descriptors.
+const FORWARD_ACCEPT_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Source of session ids, which key their state handlers.
+static NEXT_SESSION_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+
 /// SSH session manager.
 pub struct SshSession {
+    id: u64,
     config: SshConfig,
     handle: Option<Arc<Mutex<client::Handle<SshHandler>>>>,
     /// Active port forwards: local_port → cancel sender (send true to stop)
     forwards: HashMap<u16, watch::Sender<bool>>,
+    /// Active SOCKS5 (dynamic) forwards: local_port → cancel sender
+    dynamic_forwards: HashMap<u16, watch::Sender<bool>>,
+    /// Answers keyboard-interactive prompts, if the app can ask the user
+    prompt_handler: Option<PromptHandler>,
+    /// Sessions on the jump hosts, in connection order; kept open while
+    /// the connection through them is in use
+    jumps: Arc<Mutex<Vec<client::Handle<SshHandler>>>>,
+    /// Keepalive and reconnect settings, shared with the supervisor
+    supervision: Arc<std::sync::Mutex<Supervision>>,
+    /// Watches the connection and reconnects, while connected
+    supervisor: Option<tokio::task::JoinHandle<()>>,
+    /// Wakes the supervisor to check the connection right away
+    wake: Arc<tokio::sync::Notify>,
+    /// For a session sharing a pooled connection, its hold on it
+    lease: Option<Lease>,
+    /// What the server said during the last connect
+    login: LoginInfo,
+    /// The connection's algorithms and traffic, kept up by its handler
+    transport: Arc<TransportInfo>,
+    /// Where this session logs what it runs, if anywhere
+    transcript: Option<Arc<Transcript>>,
+}
+
+/// What the destination's transport reports, shared with its handler and
+/// kept across reconnects.
+#[derive(Debug, Default)]
+struct TransportInfo {
+    key_exchange: std::sync::Mutex<Option<KeyExchangeInfo>>,
+    stats: ConnectionStats,
+}
+
+#[derive(Clone, Default)]
+struct Supervision {
+    keepalive: KeepalivePolicy,
+    reconnect: Option<ReconnectPolicy>,
+    /// By session id; sessions sharing a connection each have one
+    state_handlers: HashMap<u64, StateHandler>,
+    /// By session id, like `state_handlers`
+    event_handlers: HashMap<u64, EventHandler>,
+    state: ConnectionState,
+    /// How often to ping for the round-trip history when keepalives are
+    /// off
+    latency_interval: Option<std::time::Duration>,
+}
+
+/// Record a state change and tell the state and event handlers.
+fn set_state(supervision: &std::sync::Mutex<Supervision>, state: ConnectionState, attempt: u32, error: Option<String>) {
+    let handlers: Vec<StateHandler> = {
+        let mut supervision = supervision.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        supervision.state = state;
+        supervision.state_handlers.values().cloned().collect()
+    };
+    let event = ConnectionEvent { state, attempt, error };
+    for handler in handlers {
+        handler(&event);
+    }
+    if let Some(event) = SessionEvent::from_state(&event) {
+        emit_event(supervision, None, &event);
+    }
+}
+
+/// Tell session `session`'s event handler about `event`, or those of all
+/// sessions on the connection with `None`.
+fn emit_event(supervision: &std::sync::Mutex<Supervision>, session: Option<u64>, event: &SessionEvent) {
+    let handlers: Vec<EventHandler> = {
+        let supervision = supervision.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match session {
+            Some(id) => supervision.event_handlers.get(&id).cloned().into_iter().collect(),
+            None => supervision.event_handlers.values().cloned().collect(),
+        }
+    };
+    for handler in handlers {
+        handler(event);
+    }
+}
+
+/// Whether a listener's accept error means it can't go on, rather than
+/// one connection failing before it was accepted or the system running
+/// short for a moment.
+fn listener_failed(error: &std::io::Error) -> bool {
+    !matches!(
+        error.kind(),
+        std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::Interrupted
+    ) && !out_of_resources(error)
+}
+
+/// Whether an accept error means the process or system is out of file
+/// descriptors or buffers, which passes once connections close.
+fn out_of_resources(error: &std::io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS))
+}
+
+/// A forward's listener, and where to report on it.
+struct ForwardListener {
+    /// `None` while a failed listener is being bound again
+    listener: Option<TcpListener>,
+    local_port: u16,
+    counters: Arc<TunnelCounters>,
+    supervision: Arc<std::sync::Mutex<Supervision>>,
+    /// The session the forward belongs to
+    session: u64,
+}
+
+impl ForwardListener {
+    /// Serve each connection with `serve` until `cancel` fires. A failing
+    /// listener is bound again, waiting longer after each failed try.
+    async fn run<F, Fut>(mut self, mut cancel: watch::Receiver<bool>, serve: F)
+    where
+        F: Fn(tokio::net::TcpStream, watch::Receiver<bool>, Arc<TunnelCounters>) -> Fut,
+        Fut: std::future::Future<Output = Result<(), anyhow::Error>> + Send + 'static,
+    {
+        let local_port = self.local_port;
+        loop {
+            let Some(listener) = &self.listener else { return };
+            let failure = loop {
+                tokio::select! {
+                    _ = cancelled(&mut cancel) => {
+                        log::info!("Forward on {} cancelled", local_port);
+                        return;
+                    }
+                    result = listener.accept() => match result {
+                        Ok((tcp_stream, peer)) => {
+                            log::debug!("Forwarded connection from {} on port {}", peer, local_port);
+                            let connection = self.counters.connection();
+                            let serving = serve(tcp_stream, cancel.clone(), self.counters.clone());
+                            let (counters, supervision, session) =
+                                (self.counters.clone(), self.supervision.clone(), self.session);
+                            tokio::spawn(async move {
+                                let error = serving.await.err().map(|e| e.to_string());
+                                drop(connection);
+                                if let Some(e) = &error {
+                                    log::debug!("Forwarded connection on {} failed: {}", local_port, e);
+                                }
+                                if counters.served(error.clone()) {
+                                    let event = SessionEvent::ForwardFailing {
+                                        local_port,
+                                        error: error.unwrap_or_default(),
+                                    };
+                                    emit_event(&supervision, Some(session), &event);
+                                }
+                            });
+                        }
+                        Err(e) if out_of_resources(&e) => {
+                            log::warn!("Forward on port {} can't accept for now: {}", local_port, e);
+                            tokio::select! {
+                                _ = cancelled(&mut cancel) => return,
+                                _ = tokio::time::sleep(FORWARD_ACCEPT_RETRY_DELAY) => {}
+                            }
+                        }
+                        Err(e) if listener_failed(&e) => break e,
+                        Err(e) => log::debug!("Forward accept error on port {}: {}", local_port, e),
+                    }
+                }
+            };
+
+            log::error!("Forward listener on {} failed: {}", local_port, failure);
+            self.counters.listener_failed(failure.to_string());
+            let event = SessionEvent::ForwardDied { local_port, error: failure.to_string() };
+            emit_event(&self.supervision, Some(self.session), &event);
+            if !self.restart(&mut cancel).await {
+                return;
+            }
+        }
+    }
+
+    /// Bind the failed listener's port again, waiting longer after each
+    /// failed try. Returns false if cancelled first.
+    async fn restart(&mut self, cancel: &mut watch::Receiver<bool>) -> bool {
+        // The failed listener still holds the port
+        self.listener = None;
+        let mut delay = FORWARD_RESTART_DELAY;
+        let listener = loop {
+            tokio::select! {
+                _ = cancelled(cancel) => return false,
+                _ = tokio::time::sleep(delay) => {}
+            }
+            match TcpListener::bind(("127.0.0.1", self.local_port)).await {
+                Ok(listener) => break listener,
+                Err(e) => {
+                    log::debug!("Rebinding forward on {} failed: {}", self.local_port, e);
+                    delay = (delay * 2).min(FORWARD_RESTART_MAX_DELAY);
+                }
+            }
+        };
+        self.listener = Some(listener);
+        log::info!("Forward listener on {} restarted", self.local_port);
+        self.counters.listener_restarted();
+        let event = SessionEvent::ForwardRestarted { local_port: self.local_port };
+        emit_event(&self.supervision, Some(self.session), &event);
+        true
+    }
+}
+
+/// Resolves once `cancel` is set, or its sender is gone.
+async fn cancelled(cancel: &mut watch::Receiver<bool>) {
+    while !*cancel.borrow_and_update() {
+        if cancel.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// `handler`, announcing each round of prompts to the event handlers
+/// before answering it.
+fn announcing_prompts(
+    supervision: &Arc<std::sync::Mutex<Supervision>>,
+    handler: Option<&PromptHandler>,
+) -> Option<PromptHandler> {
+    let handler = handler?.clone();
+    let supervision = supervision.clone();
+    Some(Arc::new(move |round: &AuthPrompts| {
+        let event = SessionEvent::AuthPrompt {
+            name: round.name.clone(),
+            instructions: round.instructions.clone(),
+            prompts: round.prompts.iter().map(|prompt| prompt.prompt.clone()).collect(),
+        };
+        emit_event(&supervision, None, &event);
+        handler(round)
+    }))
+}
+
+/// The known_hosts file as error messages name it.
+fn known_hosts_name(path: Option<&std::path::Path>) -> String {
+    path.map_or_else(|| "~/.ssh/known_hosts".to_string(), |path| path.display().to_string())
 }
 
 /// Minimal SSH client handler with host key verification.
@@ -21,39 +281,148 @@ struct SshHandler {
     host: String,
     /// Port for known_hosts lookup.
     port: u16,
+    /// Asks the app about unknown and changed keys
+    host_key_handler: Option<HostKeyHandler>,
+    /// Collects the banner
+    login: Arc<std::sync::Mutex<LoginInfo>>,
+    /// Where to note negotiated algorithms and channels; only for the
+    /// destination, not jump hosts
+    transport: Option<Arc<TransportInfo>>,
+    /// Key exchanges so far
+    exchanges: u32,
 }
 
 impl client::Handler for SshHandler {
     type Error = anyhow::Error;
 
+    async fn kex_done(
+        &mut self,
+        _shared_secret: Option<&[u8]>,
+        names: &russh::Names,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        self.exchanges += 1;
+        let Some(transport) = &self.transport else { return Ok(()) };
+        let compression = |compression: &compression::Compression| match compression {
+            compression::Compression::None => "none",
+            compression::Compression::Zlib => "zlib",
+        };
+        let info = KeyExchangeInfo {
+            kex: names.kex.as_ref().to_string(),
+            host_key: names.key.as_str().to_string(),
+            cipher: names.cipher.as_ref().to_string(),
+            mac_client_to_server: names.client_mac.as_ref().to_string(),
+            mac_server_to_client: names.server_mac.as_ref().to_string(),
+            compression: compression(&names.client_compression).to_string(),
+            exchanges: self.exchanges,
+            last_exchange: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        if self.exchanges > 1 {
+            log::info!("SSH keys renewed for {}:{} (exchange {})", self.host, self.port, self.exchanges);
+        }
+        *transport.key_exchange.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(info);
+        Ok(())
+    }
+
+    async fn channel_open_confirmation(
+        &mut self,
+        _id: ChannelId,
+        _max_packet_size: u32,
+        _window_size: u32,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        if let Some(transport) = &self.transport {
+            transport.stats.channel_opened();
+        }
+        Ok(())
+    }
+
+    async fn channel_close(&mut self, _channel: ChannelId, _session: &mut client::Session) -> Result<(), Self::Error> {
+        if let Some(transport) = &self.transport {
+            transport.stats.channel_closed();
+        }
+        Ok(())
+    }
+
+    async fn auth_banner(&mut self, banner: &str, _session: &mut client::Session) -> Result<(), Self::Error> {
+        let mut login = self.login.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match &mut login.banner {
+            Some(banners) => {
+                banners.push('\n');
+                banners.push_str(banner);
+            }
+            None => login.banner = Some(banner.to_string()),
+        }
+        Ok(())
+    }
+
     async fn check_server_key(
         &mut self,
         server_public_key: &ssh_key::PublicKey,
     ) -> Result<bool, Self::Error> {
-        use russh::keys::known_hosts::{check_known_hosts, learn_known_hosts};
+        use russh::keys::known_hosts::check_known_hosts_path;
 
-        match check_known_hosts(&self.host, self.port, server_public_key) {
+        let path = known_hosts::path();
+        let checked = match &path {
+            Some(path) => check_known_hosts_path(&self.host, self.port, server_public_key, path),
+            None => Ok(false),
+        };
+        let status = match checked {
             Ok(true) => {
                 log::info!("Host key verified for {}:{}", self.host, self.port);
-                Ok(true)
+                return Ok(true);
             }
-            Ok(false) => {
-                // Key mismatch — possible MITM attack.
-                log::error!(
-                    "HOST KEY MISMATCH for {}:{} — possible MITM attack! Connection rejected.",
-                    self.host, self.port
+            Ok(false) => HostKeyStatus::New,
+            Err(russh::keys::Error::KeyChanged { line }) => {
+                log::warn!(
+                    "Host key for {}:{} differs from known_hosts line {} — possible MITM attack",
+                    self.host, self.port, line
                 );
-                Err(anyhow::anyhow!(
+                HostKeyStatus::Changed
+            }
+            Err(e) => {
+                log::warn!("Can't read known_hosts: {}", e);
+                HostKeyStatus::New
+            }
+        };
+
+        let prompt = known_hosts::prompt(&self.host, self.port, server_public_key, status);
+        let Some(handler) = self.host_key_handler.clone() else {
+            return Err(match status {
+                HostKeyStatus::New => anyhow::anyhow!(
+                    "Unknown host key for {}:{} ({} {}). Add it to {} to connect.",
+                    self.host, self.port, prompt.key_type, prompt.fingerprint, known_hosts_name(path.as_deref())
+                ),
+                HostKeyStatus::Changed => anyhow::anyhow!(
                     "Host key mismatch for {}:{}. The server's key has changed, which could indicate a man-in-the-middle attack. \
-                     If you trust this change, remove the old key from ~/.ssh/known_hosts and reconnect.",
-                    self.host, self.port
-                ))
-            }
-            Err(_) => {
-                // Host not found in known_hosts — Trust On First Use (TOFU).
-                log::info!("New host key for {}:{} — adding to known_hosts (TOFU)", self.host, self.port);
-                if let Err(e) = learn_known_hosts(&self.host, self.port, server_public_key) {
-                    log::warn!("Failed to save host key: {}", e);
+                     If you trust this change, remove the old key from {} and reconnect.",
+                    self.host, self.port, known_hosts_name(path.as_deref())
+                ),
+            });
+        };
+
+        // The app waits for the user, so keep it off the runtime's workers
+        let asked = prompt.clone();
+        let decision = tokio::task::spawn_blocking(move || handler(&asked)).await?;
+        match decision {
+            HostKeyDecision::Reject => {
+                Err(anyhow::anyhow!("Host key for {}:{} rejected", self.host, self.port))
+            }
+            HostKeyDecision::AcceptOnce => Ok(true),
+            HostKeyDecision::AcceptAndSave => {
+                let hash = known_hosts::hash_hosts();
+                let saved = path.ok_or_else(|| anyhow::anyhow!("No home directory")).and_then(|path| match status {
+                    HostKeyStatus::New => known_hosts::add(&path, &self.host, self.port, server_public_key, hash),
+                    HostKeyStatus::Changed => {
+                        known_hosts::update(&path, &self.host, self.port, server_public_key, hash)
+                    }
+                });
+                match saved {
+                    Ok(()) => log::info!("Saved host key for {}:{} ({})", self.host, self.port, prompt.fingerprint),
+                    Err(e) => log::warn!("Failed to save host key: {}", e),
                 }
                 Ok(true)
             }
@@ -64,67 +433,468 @@ impl client::Handler for SshHandler {
 impl SshSession {
     pub fn new(config: SshConfig) -> Self {
         Self {
-            config,
+            id: NEXT_SESSION_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
             handle: None,
             forwards: HashMap::new(),
+            dynamic_forwards: HashMap::new(),
+            prompt_handler: None,
+            jumps: Arc::new(Mutex::new(Vec::new())),
+            supervision: Arc::new(std::sync::Mutex::new(Supervision {
+                keepalive: config.keepalive,
+                reconnect: config.reconnect,
+                state_handlers: HashMap::new(),
+                event_handlers: HashMap::new(),
+                state: ConnectionState::Disconnected,
+                latency_interval: None,
+            })),
+            supervisor: None,
+            wake: Arc::new(tokio::sync::Notify::new()),
+            lease: None,
+            login: LoginInfo::default(),
+            transport: Arc::new(TransportInfo::default()),
+            transcript: None,
+            config,
+        }
+    }
+
+    /// Another session on this one's connection, holding `lease` on it.
+    /// It has its own forwards and state handler; keepalive and reconnect
+    /// settings belong to the connection and are shared.
+    pub(super) fn share(&self, lease: Lease) -> Self {
+        Self {
+            id: NEXT_SESSION_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+            config: self.config.clone(),
+            handle: self.handle.clone(),
+            forwards: HashMap::new(),
+            dynamic_forwards: HashMap::new(),
+            prompt_handler: self.prompt_handler.clone(),
+            jumps: self.jumps.clone(),
+            supervision: self.supervision.clone(),
+            supervisor: None,
+            wake: self.wake.clone(),
+            lease: Some(lease),
+            login: self.login.clone(),
+            transport: self.transport.clone(),
+            transcript: None,
+        }
+    }
+
+    /// The algorithms the connection negotiated, and its key exchanges;
+    /// `None` until connected.
+    pub fn key_exchange_info(&self) -> Option<KeyExchangeInfo> {
+        self.transport.key_exchange.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    /// Traffic on the connection and its port forwards, channel counts and
+    /// the last measured round trip.
+    pub fn stats(&self) -> StatsSnapshot {
+        self.transport.stats.snapshot()
+    }
+
+    /// Renew the session keys now rather than at the rekey limits, e.g.
+    /// before a tunnel carries sensitive data.
+    pub async fn rekey(&self) -> Result<(), anyhow::Error> {
+        let handle = self.live_handle()?;
+        handle.lock().await.rekey_soon().await?;
+        Ok(())
+    }
+
+    /// What the server said during the last connect: its banner, and why
+    /// logging in failed.
+    pub fn login_info(&self) -> &LoginInfo {
+        &self.login
+    }
+
+    /// Log this session's connects, commands and shells opened from now
+    /// on as `options` says, or stop logging with `None`. A session
+    /// sharing a pooled connection has its own transcript.
+    pub fn set_transcript(&mut self, options: Option<TranscriptOptions>) -> std::io::Result<()> {
+        self.transcript = match options {
+            Some(options) => {
+                let label = format!("{}@{}:{}", self.config.username, self.config.host, self.config.port);
+                Some(Arc::new(Transcript::open(options, label)?))
+            }
+            None => None,
+        };
+        self.note(|| format!("transcript started, {}", if self.is_connected() { "connected" } else { "not connected" }));
+        Ok(())
+    }
+
+    /// The transcript shells opened on this session should log to.
+    pub fn transcript(&self) -> Option<Arc<Transcript>> {
+        self.transcript.clone()
+    }
+
+    fn note(&self, event: impl FnOnce() -> String) {
+        if let Some(transcript) = &self.transcript {
+            transcript.record(&event());
         }
     }
 
-    /// Establish an SSH connection.
+    /// Set how keyboard-interactive prompts are answered. Needed for
+    /// `SshAuth::KeyboardInteractive`, and lets a server ask for a second
+    /// factor after a password or key was accepted.
+    pub fn set_prompt_handler(&mut self, handler: Option<PromptHandler>) {
+        self.prompt_handler = handler;
+    }
+
+    /// Set the keepalive probing; takes effect right away.
+    pub fn set_keepalive(&mut self, keepalive: KeepalivePolicy) {
+        self.config.keepalive = keepalive;
+        self.supervision().keepalive = keepalive;
+        self.wake.notify_one();
+    }
+
+    /// Ping every `interval` to measure round trips while keepalives are
+    /// off (keepalive pings are measured anyway), or stop with `None`.
+    /// Unanswered ones are recorded but don't drop the connection.
+    pub fn set_latency_interval(&mut self, interval: Option<std::time::Duration>) {
+        self.supervision().latency_interval = interval;
+        self.wake.notify_one();
+    }
+
+    /// The connection's recent round trips.
+    pub fn latency(&self) -> LatencyHistory {
+        self.transport.stats.latency()
+    }
+
+    /// Set whether and how to reconnect when the connection drops.
+    pub fn set_reconnect_policy(&mut self, policy: Option<ReconnectPolicy>) {
+        self.config.reconnect = policy;
+        self.supervision().reconnect = policy;
+    }
+
+    /// Set the handler told when the connection drops, reconnects, or is
+    /// given up on.
+    pub fn set_state_handler(&mut self, handler: Option<StateHandler>) {
+        let id = self.id;
+        let mut supervision = self.supervision();
+        match handler {
+            Some(handler) => supervision.state_handlers.insert(id, handler),
+            None => supervision.state_handlers.remove(&id),
+        };
+    }
+
+    /// Set the handler told of this session's events: connection changes
+    /// as for the state handler, prompts, and forwards failing.
+    pub fn set_event_handler(&mut self, handler: Option<EventHandler>) {
+        let id = self.id;
+        let mut supervision = self.supervision();
+        match handler {
+            Some(handler) => supervision.event_handlers.insert(id, handler),
+            None => supervision.event_handlers.remove(&id),
+        };
+    }
+
+    /// Check the connection now instead of at the next keepalive, and skip
+    /// the wait before a pending reconnect attempt. For network changes
+    /// and waking from sleep, when an idle connection is likely dead.
+    pub fn check_connection(&self) {
+        self.wake.notify_one();
+    }
+
+    /// Where the connection stands, as of the last keepalive.
+    pub fn state(&self) -> ConnectionState {
+        self.supervision().state
+    }
+
+    fn supervision(&self) -> std::sync::MutexGuard<'_, Supervision> {
+        self.supervision.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Establish an SSH connection, through the configured jump hosts in
+    /// order. Each hop is reached over a direct-tcpip channel on the one
+    /// before, and authenticates with its own settings.
+    ///
+    /// The connection is then watched: keepalives detect a dead one, which
+    /// is reconnected as the reconnect policy allows.
     pub async fn connect(&mut self) -> Result<(), anyhow::Error> {
-        let ssh_config = client::Config::default();
+        set_state(&self.supervision, ConnectionState::Connecting, 0, None);
+        let login = Arc::new(std::sync::Mutex::new(LoginInfo::default()));
+        let prompt_handler = announcing_prompts(&self.supervision, self.prompt_handler.as_ref());
+        let established = Self::establish(&self.config, prompt_handler.as_ref(), &login, &self.transport).await;
+        self.login = login.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        let (session, jumps) = match established {
+            Ok(connection) => connection,
+            Err(e) => {
+                set_state(&self.supervision, ConnectionState::Disconnected, 0, Some(e.to_string()));
+                self.note(|| format!("connect failed: {}", e));
+                return Err(e);
+            }
+        };
+        let handle = Arc::new(Mutex::new(session));
+        *self.jumps.lock().await = jumps;
+        self.handle = Some(handle.clone());
+        log::info!("SSH connected to {}:{}", self.config.host, self.config.port);
+        set_state(&self.supervision, ConnectionState::Connected, 0, None);
+        self.note(|| "connected".to_string());
+
+        if let Some(supervisor) = self.supervisor.take() {
+            supervisor.abort();
+        }
+        let link = Link {
+            config: self.config.clone(),
+            prompt_handler: self.prompt_handler.clone(),
+            handle,
+            jumps: self.jumps.clone(),
+            supervision: self.supervision.clone(),
+            wake: self.wake.clone(),
+            transport: self.transport.clone(),
+        };
+        self.supervisor = Some(tokio::spawn(link.supervise()));
+        Ok(())
+    }
+
+    /// Connect and log in to `config`'s host and its jump hosts. Returns
+    /// the session and the jump host sessions it runs through.
+    async fn establish(
+        config: &SshConfig,
+        prompt_handler: Option<&PromptHandler>,
+        login: &Arc<std::sync::Mutex<LoginInfo>>,
+        transport: &Arc<TransportInfo>,
+    ) -> Result<(client::Handle<SshHandler>, Vec<client::Handle<SshHandler>>), anyhow::Error> {
+        let mut jumps: Vec<client::Handle<SshHandler>> = Vec::new();
+        for jump in &config.jump_hosts {
+            let proxy = config.proxy.as_ref().filter(|_| jumps.is_empty());
+            let mut session = Self::open_transport(jumps.last(), proxy, jump, login, None).await?;
+            Self::authenticate(&mut session, jump, prompt_handler, login).await?;
+            log::info!("SSH jump host {}:{} connected", jump.host, jump.port);
+            jumps.push(session);
+        }
+
+        let proxy = config.proxy.as_ref().filter(|_| jumps.is_empty());
+        let mut session = Self::open_transport(jumps.last(), proxy, config, login, Some(transport)).await?;
+        Self::authenticate(&mut session, config, prompt_handler, login).await?;
+        Ok((session, jumps))
+    }
+
+    /// Start the SSH handshake with `config`'s host, over TCP (through
+    /// `proxy` if given) or, with a jump host, over a channel it opens.
+    async fn open_transport(
+        via: Option<&client::Handle<SshHandler>>,
+        proxy: Option<&ProxyConfig>,
+        config: &SshConfig,
+        login: &Arc<std::sync::Mutex<LoginInfo>>,
+        transport: Option<&Arc<TransportInfo>>,
+    ) -> Result<client::Handle<SshHandler>, anyhow::Error> {
+        let ssh_config = Arc::new(Self::client_config(config)?);
+        // Only the destination's traffic is counted
+        let counters = transport.map(|transport| transport.stats.transport.clone()).unwrap_or_default();
+        if let Some(transport) = transport {
+            transport.stats.transport_started();
+        }
         let handler = SshHandler {
-            host: self.config.host.clone(),
-            port: self.config.port,
+            host: config.host.clone(),
+            port: config.port,
+            host_key_handler: known_hosts::handler(),
+            login: login.clone(),
+            transport: transport.cloned(),
+            exchanges: 0,
         };
 
-        // 10-second timeout for TCP connect to avoid blocking indefinitely
-        // when the target host is unreachable (e.g. network change).
-        let mut session = match tokio::time::timeout(
-            std::time::Duration::from_secs(10),
-            client::connect(
-                Arc::new(ssh_config),
-                (self.config.host.as_str(), self.config.port),
-                handler,
-            ),
-        ).await {
-            Ok(result) => result?,
-            Err(_) => return Err(anyhow::anyhow!("SSH connect timed out after 10s")),
+        // Time-limited by default, to avoid blocking indefinitely when the
+        // target host is unreachable (e.g. network change).
+        let connecting = async {
+            match via {
+                Some(jump) => {
+                    let channel = jump
+                        .channel_open_direct_tcpip(config.host.as_str(), config.port as u32, "127.0.0.1", 0)
+                        .await?;
+                    client::connect_stream(ssh_config, Counted::new(channel.into_stream(), counters), handler).await
+                }
+                None => {
+                    let socket = match proxy {
+                        Some(proxy) => super::proxy::connect(proxy, &config.host, config.port).await?,
+                        None => tokio::net::TcpStream::connect((config.host.as_str(), config.port)).await?,
+                    };
+                    client::connect_stream(ssh_config, Counted::new(socket, counters), handler).await
+                }
+            }
+        };
+        let Some(limit) = config.timeouts.connect() else {
+            return connecting.await;
         };
+        match tokio::time::timeout(limit, connecting).await {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(anyhow::anyhow!("SSH connect to {} timed out after {:?}", config.host, limit)),
+        }
+    }
 
-        // Authenticate
-        let result = match &self.config.auth {
+    /// Transport settings for `config`. With compression on, zlib is
+    /// preferred, falling back to none for servers without it. Rekey
+    /// limits above what the library allows are capped.
+    fn client_config(config: &SshConfig) -> Result<client::Config, anyhow::Error> {
+        let algorithms: &'static [compression::Name] = if config.compression {
+            &[compression::ZLIB_LEGACY, compression::ZLIB, compression::NONE]
+        } else {
+            &[compression::NONE]
+        };
+        let preferred = super::algorithms::preferred(&config.algorithms)?;
+        let defaults = Limits::default();
+        let max_bytes = match config.rekey.max_bytes {
+            0 => defaults.rekey_write_limit,
+            max_bytes => max_bytes.min(defaults.rekey_write_limit as u64) as usize,
+        };
+        let interval = match config.rekey.interval_secs {
+            0 => defaults.rekey_time_limit,
+            secs => std::time::Duration::from_secs(secs),
+        };
+        Ok(client::Config {
+            preferred: Preferred { compression: algorithms.into(), ..preferred },
+            limits: Limits::new(max_bytes, max_bytes, interval),
+            ..client::Config::default()
+        })
+    }
+
+    /// Log in to `session` as `config` says. A failure is noted in `login`.
+    async fn authenticate(
+        session: &mut client::Handle<SshHandler>,
+        config: &SshConfig,
+        prompt_handler: Option<&PromptHandler>,
+        login: &Arc<std::sync::Mutex<LoginInfo>>,
+    ) -> Result<(), anyhow::Error> {
+        let mut result = match &config.auth {
             SshAuth::Password(password) => {
                 session
-                    .authenticate_password(&self.config.username, password)
+                    .authenticate_password(&config.username, password)
                     .await?
             }
             SshAuth::KeyFile { path, passphrase } => {
                 let key_pair = load_secret_key(path, passphrase.as_deref())?;
-                let pk = PrivateKeyWithHashAlg::new(
-                    Arc::new(key_pair),
-                    None, // Use default hash algorithm
-                );
-                session
-                    .authenticate_publickey(&self.config.username, pk)
-                    .await?
+                if security_key::is_security_key(&key_pair) {
+                    // The file only names the key; the authenticator signs
+                    let handler = security_key::handler()
+                        .ok_or_else(|| anyhow::anyhow!("{} is a security key, which needs a handler", path))?;
+                    let mut signer =
+                        SecurityKeySigner::new(&key_pair, &config.host, config.port, &config.username, handler)
+                            .ok_or_else(|| anyhow::anyhow!("Unsupported security key {}", path))?;
+                    session
+                        .authenticate_publickey_with(&config.username, key_pair.public_key().clone(), None, &mut signer)
+                        .await?
+                } else {
+                    let pk = PrivateKeyWithHashAlg::new(
+                        Arc::new(key_pair),
+                        None, // Use default hash algorithm
+                    );
+                    session
+                        .authenticate_publickey(&config.username, pk)
+                        .await?
+                }
             }
             SshAuth::Agent => {
                 // TODO: implement SSH agent forwarding
                 return Err(anyhow::anyhow!("SSH Agent auth not yet implemented"));
             }
+            SshAuth::KeyboardInteractive => {
+                Self::authenticate_keyboard_interactive(session, config, prompt_handler, login).await?
+            }
         };
 
+        // Servers requiring a second factor accept the first method only
+        // partially and continue with keyboard-interactive; a refused
+        // password may have expired, which PAM servers let change there
+        if let client::AuthResult::Failure { remaining_methods, partial_success } = &result {
+            let retry = if *partial_success {
+                prompt_handler.is_some()
+            } else {
+                matches!(config.auth, SshAuth::Password(_)) && password_change::handler().is_some()
+            };
+            if retry && remaining_methods.contains(&MethodKind::KeyboardInteractive) {
+                result = Self::authenticate_keyboard_interactive(session, config, prompt_handler, login).await?;
+            }
+        }
+
         match result {
-            client::AuthResult::Success => {},
-            client::AuthResult::Failure { .. } => {
-                return Err(anyhow::anyhow!("SSH authentication failed"));
+            client::AuthResult::Success => Ok(()),
+            client::AuthResult::Failure { remaining_methods, partial_success } => {
+                let mut login = login.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                login.remaining_methods = remaining_methods.iter().map(String::from).collect();
+                login.partial_success = partial_success;
+                Err(anyhow::anyhow!("SSH authentication failed for {}@{}", config.username, config.host))
             }
         }
+    }
 
-        self.handle = Some(Arc::new(Mutex::new(session)));
-        log::info!("SSH connected to {}:{}", self.config.host, self.config.port);
-        Ok(())
+    /// Run keyboard-interactive authentication, passing each round of
+    /// prompts to the prompt handler. A password change is answered
+    /// through the password change handler, if one is set.
+    async fn authenticate_keyboard_interactive(
+        session: &mut client::Handle<SshHandler>,
+        config: &SshConfig,
+        prompt_handler: Option<&PromptHandler>,
+        login: &Arc<std::sync::Mutex<LoginInfo>>,
+    ) -> Result<client::AuthResult, anyhow::Error> {
+        let change_handler = password_change::handler();
+        if prompt_handler.is_none() && change_handler.is_none() {
+            return Err(anyhow::anyhow!("Keyboard-interactive authentication needs a prompt handler"));
+        }
+        let password = match &config.auth {
+            SshAuth::Password(password) => Some(password.as_str()),
+            _ => None,
+        };
+        let mut change = PasswordChange::new(&config.host, config.port, &config.username, password);
+
+        let mut response = session
+            .authenticate_keyboard_interactive_start(&config.username, None)
+            .await?;
+        for _ in 0..MAX_PROMPT_ROUNDS {
+            let (name, instructions, prompts) = match response {
+                client::KeyboardInteractiveAuthResponse::Success => {
+                    if change.changed() {
+                        log::info!("Password changed for {}@{}", config.username, config.host);
+                        login.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).password_changed = true;
+                    }
+                    return Ok(client::AuthResult::Success);
+                }
+                client::KeyboardInteractiveAuthResponse::Failure { remaining_methods, partial_success } => {
+                    return Ok(client::AuthResult::Failure { remaining_methods, partial_success });
+                }
+                client::KeyboardInteractiveAuthResponse::InfoRequest { name, instructions, prompts } => {
+                    (name, instructions, prompts)
+                }
+            };
+
+            let answers = if prompts.is_empty() && name.is_empty() && instructions.is_empty() {
+                // Nothing to show; some servers send an empty round
+                Vec::new()
+            } else {
+                let request = AuthPrompts {
+                    name,
+                    instructions,
+                    prompts: prompts
+                        .into_iter()
+                        .map(|prompt| AuthPrompt { prompt: prompt.prompt, echo: prompt.echo })
+                        .collect(),
+                };
+                if let Some(change_handler) = change_handler.clone() {
+                    if let Some(asked) = change.ask(&request) {
+                        let answer = tokio::task::spawn_blocking(move || change_handler(&asked))
+                            .await?
+                            .ok_or_else(|| anyhow::anyhow!("SSH password change cancelled"))?;
+                        change.set_answer(answer);
+                    }
+                }
+                match change_handler.as_ref().and_then(|_| change.answer(&request)) {
+                    Some(answers) => answers,
+                    None => {
+                        let handler = prompt_handler.cloned().ok_or_else(|| {
+                            anyhow::anyhow!("Keyboard-interactive authentication needs a prompt handler")
+                        })?;
+                        let expected = request.prompts.len();
+                        // The app waits for the user, so keep it off the runtime's workers
+                        let answers = tokio::task::spawn_blocking(move || handler(&request))
+                            .await?
+                            .ok_or_else(|| anyhow::anyhow!("SSH authentication cancelled"))?;
+                        if answers.len() != expected {
+                            return Err(anyhow::anyhow!("Expected {} answers, got {}", expected, answers.len()));
+                        }
+                        answers
+                    }
+                }
+            };
+            response = session.authenticate_keyboard_interactive_respond(answers).await?;
+        }
+        Err(anyhow::anyhow!("Server sent too many authentication prompts"))
     }
 
     /// Open an interactive shell channel.
@@ -133,24 +903,37 @@ impl SshSession {
         cols: u32,
         rows: u32,
     ) -> Result<russh::Channel<client::Msg>, anyhow::Error> {
-        let handle = self
-            .handle
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+        let handle = self.live_handle()?;
 
         let handle = handle.lock().await;
         let channel = handle.channel_open_session().await?;
+        self.send_environment(&channel).await?;
 
         channel
             .request_pty(false, "xterm-256color", cols, rows, 0, 0, &[])
             .await?;
         channel.request_shell(false).await?;
+        self.note(|| format!("channel {} shell {}x{}", channel.id(), cols, rows));
 
         Ok(channel)
     }
 
     /// Disconnect the SSH session.
+    ///
+    /// A session sharing a pooled connection only lets go of it; the pool
+    /// closes the connection once it has been unused for a while.
     pub async fn disconnect(&mut self) -> Result<(), anyhow::Error> {
+        self.stop_all_forwards();
+        self.set_state_handler(None);
+        self.set_event_handler(None);
+        self.note(|| "disconnected".to_string());
+        if self.lease.take().is_some() {
+            self.handle = None;
+            return Ok(());
+        }
+        if let Some(supervisor) = self.supervisor.take() {
+            supervisor.abort();
+        }
         if let Some(handle) = self.handle.take() {
             // 5-second timeout: if the server is unreachable, the disconnect
             // handshake will hang. We'd rather drop the handle than block.
@@ -168,11 +951,59 @@ impl SshSession {
                 Err(_) => log::warn!("SSH disconnect timed out, dropping handle"),
             }
         }
+        // Innermost first: each jump carries the connections after it
+        let mut jumps = self.jumps.lock().await;
+        while let Some(jump) = jumps.pop() {
+            let result = tokio::time::timeout(
+                std::time::Duration::from_secs(5),
+                jump.disconnect(Disconnect::ByApplication, "User disconnect", "en"),
+            ).await;
+            if !matches!(result, Ok(Ok(()))) {
+                log::debug!("SSH jump host disconnect failed, dropping handle");
+            }
+        }
+        set_state(&self.supervision, ConnectionState::Disconnected, 0, None);
         Ok(())
     }
 
+    /// Whether the connection is up: connected or degraded, and not closed
+    /// underneath. Doesn't touch the network; `probe` does a round trip.
     pub fn is_connected(&self) -> bool {
-        self.handle.is_some()
+        self.live_handle()
+            .map(|handle| handle.try_lock().map(|h| !h.is_closed()).unwrap_or(true))
+            .unwrap_or(false)
+    }
+
+    /// Check that the server still answers, with a keepalive round trip of
+    /// at most `timeout`. A failed probe has the supervisor check the
+    /// connection right away.
+    pub async fn probe(&self, timeout: std::time::Duration) -> bool {
+        let Ok(handle) = self.live_handle() else { return false };
+        let alive = tokio::time::timeout(timeout, async {
+            let handle = handle.lock().await;
+            let sent = std::time::Instant::now();
+            let answered = !handle.is_closed() && handle.send_ping().await.is_ok();
+            if answered {
+                self.transport.stats.record_rtt(sent.elapsed());
+            }
+            answered
+        })
+        .await
+        .unwrap_or(false);
+        if !alive {
+            self.transport.stats.record_lost_ping();
+            self.wake.notify_one();
+        }
+        alive
+    }
+
+    /// The session handle, unless the connection is known to be down.
+    fn live_handle(&self) -> Result<&Arc<Mutex<client::Handle<SshHandler>>>, anyhow::Error> {
+        match (&self.handle, self.state()) {
+            (Some(handle), ConnectionState::Connected | ConnectionState::Degraded) => Ok(handle),
+            (Some(_), ConnectionState::Connecting) => Err(anyhow::anyhow!("Reconnecting")),
+            _ => Err(anyhow::anyhow!("Not connected")),
+        }
     }
 
     /// Start local port forwarding: 127.0.0.1:local_port → remote_host:remote_port
@@ -185,7 +1016,7 @@ impl SshSession {
         remote_host: &str,
         remote_port: u16,
     ) -> Result<(), anyhow::Error> {
-        if self.forwards.contains_key(&local_port) {
+        if self.forwards.contains_key(&local_port) || self.dynamic_forwards.contains_key(&local_port) {
             return Err(anyhow::anyhow!("Port {} already forwarded", local_port));
         }
 
@@ -195,7 +1026,7 @@ impl SshSession {
             .ok_or_else(|| anyhow::anyhow!("Not connected"))?
             .clone();
 
-        let listener = TcpListener::bind(format!("127.0.0.1:{}", local_port)).await?;
+        let listener = self.forward_listener(local_port).await?;
         let (cancel_tx, cancel_rx) = watch::channel(false);
         let rhost = remote_host.to_string();
 
@@ -204,54 +1035,41 @@ impl SshSession {
             local_port, remote_host, remote_port
         );
 
-        tokio::spawn(async move {
-            let mut rx = cancel_rx;
-            loop {
-                tokio::select! {
-                    _ = async { loop {
-                        if rx.changed().await.is_err() || *rx.borrow() { break; }
-                    }} => {
-                        log::info!("Port forward on {} cancelled", local_port);
-                        break;
-                    }
-                    result = listener.accept() => {
-                        match result {
-                            Ok((mut tcp_stream, peer)) => {
-                                log::debug!("Tunnel connection from {} on port {}", peer, local_port);
-                                let h = handle.clone();
-                                let host = rhost.clone();
-                                let conn_rx = rx.clone();
-                                tokio::spawn(async move {
-                                    if let Err(e) = Self::handle_forward_connection(
-                                        &h, &mut tcp_stream, &host, remote_port, conn_rx,
-                                    ).await {
-                                        log::debug!("Tunnel connection ended: {}", e);
-                                    }
-                                });
-                            }
-                            Err(e) => {
-                                log::error!("Tunnel accept error on port {}: {}", local_port, e);
-                            }
-                        }
-                    }
-                }
+        tokio::spawn(listener.run(cancel_rx, move |mut tcp_stream, cancel_rx, counters| {
+            let (h, host) = (handle.clone(), rhost.clone());
+            async move {
+                Self::handle_forward_connection(&h, &mut tcp_stream, &host, remote_port, cancel_rx, &counters).await
             }
-        });
+        }));
 
         self.forwards.insert(local_port, cancel_tx);
         Ok(())
     }
 
+    /// Bind a forward's listener on 127.0.0.1:`local_port`, and start
+    /// counting its traffic.
+    async fn forward_listener(&self, local_port: u16) -> Result<ForwardListener, anyhow::Error> {
+        let listener = TcpListener::bind(format!("127.0.0.1:{}", local_port)).await?;
+        Ok(ForwardListener {
+            listener: Some(listener),
+            local_port,
+            counters: self.transport.stats.add_tunnel(local_port),
+            supervision: self.supervision.clone(),
+            session: self.id,
+        })
+    }
+
     /// Handle a single forwarded connection.
     async fn handle_forward_connection(
         handle: &Arc<Mutex<client::Handle<SshHandler>>>,
         tcp_stream: &mut tokio::net::TcpStream,
         remote_host: &str,
         remote_port: u16,
-        mut cancel_rx: watch::Receiver<bool>,
+        cancel_rx: watch::Receiver<bool>,
+        counters: &TunnelCounters,
     ) -> Result<(), anyhow::Error> {
         let h = handle.lock().await;
-        let mut channel = h
+        let channel = h
             .channel_open_direct_tcpip(
                 remote_host,
                 remote_port as u32,
@@ -261,6 +1079,17 @@ impl SshSession {
             .await?;
         drop(h); // Release the lock
 
+        Self::relay(channel, tcp_stream, cancel_rx, counters).await
+    }
+
+    /// Copy data both ways between a local connection and an SSH channel
+    /// until either side closes or the forward is stopped.
+    async fn relay(
+        mut channel: Channel<client::Msg>,
+        tcp_stream: &mut tokio::net::TcpStream,
+        mut cancel_rx: watch::Receiver<bool>,
+        counters: &TunnelCounters,
+    ) -> Result<(), anyhow::Error> {
         use tokio::io::{AsyncReadExt, AsyncWriteExt};
         let (mut tcp_read, mut tcp_write) = tcp_stream.split();
         let mut buf = vec![0u8; 8192];
@@ -278,6 +1107,7 @@ impl SshSession {
                             if channel.data(&buf[..n]).await.is_err() {
                                 break;
                             }
+                            counters.bytes.add_sent(n);
                         }
                     }
                 }
@@ -288,6 +1118,7 @@ impl SshSession {
                             if tcp_write.write_all(data).await.is_err() {
                                 break;
                             }
+                            counters.bytes.add_received(data.len());
                         }
                         Some(russh::ChannelMsg::Eof) | None => break,
                         _ => {}
@@ -299,10 +1130,90 @@ impl SshSession {
         Ok(())
     }
 
+    /// Start dynamic forwarding (`ssh -D`): a SOCKS5 proxy on
+    /// 127.0.0.1:local_port whose connections are opened from the server.
+    ///
+    /// Each client names its destination in the SOCKS handshake; the name
+    /// is resolved by the server, so internal hostnames work too.
+    pub async fn start_dynamic_forward(&mut self, local_port: u16) -> Result<(), anyhow::Error> {
+        if self.forwards.contains_key(&local_port) || self.dynamic_forwards.contains_key(&local_port) {
+            return Err(anyhow::anyhow!("Port {} already forwarded", local_port));
+        }
+
+        let handle = self
+            .handle
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))?
+            .clone();
+
+        let listener = self.forward_listener(local_port).await?;
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+
+        log::info!("SOCKS proxy on 127.0.0.1:{}", local_port);
+
+        tokio::spawn(listener.run(cancel_rx, move |mut tcp_stream, cancel_rx, counters| {
+            let h = handle.clone();
+            async move { Self::handle_socks_connection(&h, &mut tcp_stream, cancel_rx, &counters).await }
+        }));
+
+        self.dynamic_forwards.insert(local_port, cancel_tx);
+        Ok(())
+    }
+
+    /// Handle a single SOCKS client: read where it wants to go, open a
+    /// direct-tcpip channel there, and relay.
+    async fn handle_socks_connection(
+        handle: &Arc<Mutex<client::Handle<SshHandler>>>,
+        tcp_stream: &mut tokio::net::TcpStream,
+        cancel_rx: watch::Receiver<bool>,
+        counters: &TunnelCounters,
+    ) -> Result<(), anyhow::Error> {
+        let (host, port) = socks::accept(tcp_stream).await?;
+        let h = handle.lock().await;
+        let opened = h.channel_open_direct_tcpip(host.as_str(), port as u32, "127.0.0.1", 0).await;
+        drop(h);
+
+        let channel = match opened {
+            Ok(channel) => channel,
+            Err(e) => {
+                let code = match e {
+                    russh::Error::ChannelOpenFailure(ChannelOpenFailure::AdministrativelyProhibited) => {
+                        socks::REPLY_NOT_ALLOWED
+                    }
+                    russh::Error::ChannelOpenFailure(_) => socks::REPLY_CONNECTION_REFUSED,
+                    _ => socks::REPLY_GENERAL_FAILURE,
+                };
+                socks::reply(tcp_stream, code).await?;
+                return Err(anyhow::anyhow!("Can't reach {}:{}: {}", host, port, e));
+            }
+        };
+        socks::reply(tcp_stream, socks::REPLY_SUCCEEDED).await?;
+        log::debug!("SOCKS tunnel to {}:{}", host, port);
+        Self::relay(channel, tcp_stream, cancel_rx, counters).await
+    }
+
+    /// Stop a dynamic forward.
+    pub fn stop_dynamic_forward(&mut self, local_port: u16) -> Result<(), anyhow::Error> {
+        if let Some(tx) = self.dynamic_forwards.remove(&local_port) {
+            let _ = tx.send(true);
+            self.transport.stats.remove_tunnel(local_port);
+            log::info!("Stopped dynamic forward on {}", local_port);
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("No dynamic forward on port {}", local_port))
+        }
+    }
+
+    /// List local ports with a SOCKS proxy.
+    pub fn active_dynamic_forwards(&self) -> Vec<u16> {
+        self.dynamic_forwards.keys().copied().collect()
+    }
+
     /// Stop a port forward.
     pub fn stop_port_forward(&mut self, local_port: u16) -> Result<(), anyhow::Error> {
         if let Some(tx) = self.forwards.remove(&local_port) {
             let _ = tx.send(true);
+            self.transport.stats.remove_tunnel(local_port);
             log::info!("Stopped port forward on {}", local_port);
             Ok(())
         } else {
@@ -310,10 +1221,11 @@ impl SshSession {
         }
     }
 
-    /// Stop all port forwards.
+    /// Stop all port forwards, dynamic ones included.
     pub fn stop_all_forwards(&mut self) {
-        for (port, tx) in self.forwards.drain() {
+        for (port, tx) in self.forwards.drain().chain(self.dynamic_forwards.drain()) {
             let _ = tx.send(true);
+            self.transport.stats.remove_tunnel(port);
             log::info!("Stopped port forward on {}", port);
         }
     }
@@ -323,36 +1235,196 @@ impl SshSession {
         self.forwards.keys().copied().collect()
     }
 
-    /// Execute a single command over SSH and return (exit_code, stdout).
+    /// Execute a single command over SSH and return (exit_code, stdout),
+    /// within the configured exec timeout.
     pub async fn exec_command(&self, command: &str) -> Result<(i32, String), anyhow::Error> {
-        let handle = self
-            .handle
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+        self.exec_command_with_timeout(command, self.config.timeouts.exec()).await
+    }
 
-        let handle = handle.lock().await;
-        let mut channel = handle.channel_open_session().await?;
+    /// Execute a single command over SSH and return (exit_code, stdout),
+    /// failing if it hasn't exited within `timeout` (`None` to wait as long
+    /// as it takes).
+    pub async fn exec_command_with_timeout(
+        &self,
+        command: &str,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<(i32, String), anyhow::Error> {
+        let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+        let channel = Self::until(deadline, command, self.exec_channel(command)).await??;
+        let id = channel.id();
+        let output = Self::collect_output(channel, command, deadline).await;
+        self.note_exit(id, output.as_ref().map(|(exit_code, _)| *exit_code));
+        output
+    }
+
+    /// Execute a command with `input` as its stdin, and return (exit_code,
+    /// stdout), within the configured exec timeout.
+    pub async fn exec_with_input(&self, command: &str, input: &[u8]) -> Result<(i32, String), anyhow::Error> {
+        use tokio::io::AsyncWriteExt;
+
+        let deadline = self.config.timeouts.exec().map(|timeout| tokio::time::Instant::now() + timeout);
+        let channel = Self::until(deadline, command, self.exec_channel(command)).await??;
+        // Feed input while reading output, so a command that writes as it
+        // reads doesn't stall with both windows full
+        let mut writer = channel.make_writer();
+        let feed = async move {
+            writer.write_all(input).await?;
+            writer.shutdown().await
+        };
+        let id = channel.id();
+        let (fed, output) = tokio::join!(feed, Self::collect_output(channel, command, deadline));
+        if let Err(e) = fed {
+            log::debug!("SSH exec input not fully sent: {}", e);
+        }
+        self.note_exit(id, output.as_ref().map(|(exit_code, _)| *exit_code));
+        output
+    }
+
+    /// Execute a command as root through sudo, within the configured exec
+    /// timeout, answering sudo's password prompt with `password` if it
+    /// asks. Whether sudo let the command run is reported apart from the
+    /// command's own exit code.
+    pub async fn exec_sudo(&self, command: &str, password: &str) -> Result<SudoOutput, anyhow::Error> {
+        let deadline = self.config.timeouts.exec().map(|timeout| tokio::time::Instant::now() + timeout);
+        let run = SudoRun::new()?;
+        let mut channel = Self::until(deadline, command, self.exec_channel(&run.command(command))).await??;
+        let output = match Self::until(deadline, command, sudo::collect(&mut channel, &run, password)).await {
+            Ok(output) => output,
+            Err(e) => {
+                let _ = channel.close().await;
+                Err(e)
+            }
+        };
+        self.note_exit(channel.id(), output.as_ref().map(|output| output.exit_code));
+        output
+    }
+
+    /// Execute a command and pass its output to `on_output` as it arrives,
+    /// for long-running commands whose output should show live. There is
+    /// no time limit. Returns the exit code, -1 if the server didn't report
+    /// one.
+    pub async fn exec_command_streaming(
+        &self,
+        command: &str,
+        on_output: impl FnMut(OutputStream, &[u8]),
+    ) -> Result<i32, anyhow::Error> {
+        let mut channel = self.exec_channel(command).await?;
+        let exit_code = exec::stream_output(&mut channel, on_output).await;
+        self.note_exit(channel.id(), Ok(exit_code));
+        Ok(exit_code)
+    }
+
+    /// Start `command` on a new channel; its input and output go through
+    /// the channel.
+    pub async fn exec_channel(&self, command: &str) -> Result<russh::Channel<client::Msg>, anyhow::Error> {
+        let handle = self.live_handle()?;
+        let channel = handle.lock().await.channel_open_session().await?;
+        self.send_environment(&channel).await?;
         channel.exec(true, command).await?;
+        self.note(|| format!("channel {} exec {}", channel.id(), command));
+        Ok(channel)
+    }
+
+    /// Start an SFTP session on a channel of its own, able to open a
+    /// second one for extended requests when first needed.
+    pub async fn open_sftp(&self) -> Result<SftpClient, anyhow::Error> {
+        let channel = self.sftp_channel().await?;
+        let mut sftp = SftpClient::new();
+        sftp.init(channel).await?;
+        let (handle, transcript) = (self.live_handle()?.clone(), self.transcript.clone());
+        sftp.set_extension_channel(move || {
+            let (handle, transcript) = (handle.clone(), transcript.clone());
+            async move {
+                let channel = handle.lock().await.channel_open_session().await?;
+                if let Some(transcript) = &transcript {
+                    transcript.record(&format!("channel {} sftp", channel.id()));
+                }
+                Ok::<_, anyhow::Error>(channel)
+            }
+        });
+        Ok(sftp)
+    }
+
+    /// Open a channel for an SFTP session, before the subsystem request.
+    pub async fn sftp_channel(&self) -> Result<russh::Channel<client::Msg>, anyhow::Error> {
+        let handle = self.live_handle()?;
+        let channel = handle.lock().await.channel_open_session().await?;
+        self.note(|| format!("channel {} sftp", channel.id()));
+        Ok(channel)
+    }
+
+    fn note_exit(&self, channel: ChannelId, exit_code: Result<i32, &anyhow::Error>) {
+        self.note(|| match exit_code {
+            Ok(exit_code) => format!("channel {} exit {}", channel, exit_code),
+            Err(e) => format!("channel {} failed: {}", channel, e),
+        });
+    }
 
+    /// Set the variables for shells and commands started from now on, and
+    /// the local variables to pass on to them (names or `*`/`?` patterns).
+    pub fn set_environment(&mut self, set_env: Vec<(String, String)>, send_env: Vec<String>) {
+        self.config.set_env = set_env;
+        self.config.send_env = send_env;
+    }
+
+    /// The variables to set on a new channel: local ones `send_env`
+    /// matches, then `set_env`, which wins for a name in both.
+    fn environment(&self) -> Vec<(String, String)> {
+        let mut vars: Vec<(String, String)> = std::env::vars()
+            .filter(|(name, _)| {
+                self.config.send_env.iter().any(|pattern| config_file::wildcard_match(pattern, name))
+                    && !self.config.set_env.iter().any(|(set, _)| set == name)
+            })
+            .collect();
+        vars.sort();
+        vars.extend(self.config.set_env.iter().cloned());
+        vars
+    }
+
+    /// Ask the server to set the configured variables on `channel`.
+    /// Servers only accept the names their `AcceptEnv` allows; like
+    /// OpenSSH, refusals are ignored.
+    async fn send_environment(&self, channel: &russh::Channel<client::Msg>) -> Result<(), anyhow::Error> {
+        for (name, value) in self.environment() {
+            channel.set_env(false, name, value).await?;
+        }
+        Ok(())
+    }
+
+    /// Run `work` for `command`, failing if `deadline` passes first.
+    async fn until<T>(
+        deadline: Option<tokio::time::Instant>,
+        command: &str,
+        work: impl std::future::Future<Output = T>,
+    ) -> Result<T, anyhow::Error> {
+        let Some(deadline) = deadline else {
+            return Ok(work.await);
+        };
+        tokio::time::timeout_at(deadline, work).await.map_err(|_| {
+            log::warn!("SSH exec timeout for command: {}", command);
+            anyhow::anyhow!("command timed out")
+        })
+    }
+
+    /// Read a command's output until it exits, or until `deadline`. The
+    /// channel is closed on timeout, which ends the command on most
+    /// servers.
+    async fn collect_output(
+        mut channel: russh::Channel<client::Msg>,
+        command: &str,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<(i32, String), anyhow::Error> {
         let mut stdout = Vec::new();
         let mut exit_code: i32 = -1;
         let mut got_eof = false;
 
-        // Overall command timeout: 60 seconds max for the entire command.
-        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(60);
-
         loop {
-            // Check overall deadline
-            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
-            if remaining.is_zero() {
-                log::warn!("SSH exec overall timeout (60s) for command: {}", command);
-                break;
-            }
-
-            // Per-message timeout: min of 10s and remaining overall time
-            let msg_timeout = remaining.min(std::time::Duration::from_secs(10));
-            match tokio::time::timeout(msg_timeout, channel.wait()).await {
-                Ok(Some(msg)) => {
+            let Ok(msg) = Self::until(deadline, command, channel.wait()).await else {
+                let _ = channel.close().await;
+                return Err(anyhow::anyhow!("command timed out"));
+            };
+            match msg {
+                Some(msg) => {
                     match msg {
                         russh::ChannelMsg::Data { ref data } => {
                             stdout.extend_from_slice(data);
@@ -378,12 +1450,7 @@ impl SshSession {
                         _ => {}
                     }
                 }
-                Ok(None) => break, // Channel closed
-                Err(_) => {
-                    // Timeout — command took too long
-                    log::warn!("SSH exec timeout for command: {}", command);
-                    break;
-                }
+                None => break, // Channel closed
             }
         }
 
@@ -391,3 +1458,273 @@ impl SshSession {
         Ok((exit_code, output))
     }
 }
+
+impl Drop for SshSession {
+    fn drop(&mut self) {
+        if let Some(supervisor) = self.supervisor.take() {
+            supervisor.abort();
+        }
+        self.set_state_handler(None);
+        self.set_event_handler(None);
+    }
+}
+
+/// What the supervisor needs to watch a connection and rebuild it.
+///
+/// A reconnect swaps the new session into the shared handle, so port
+/// forward listeners stay bound and their new connections use it;
+/// connections open when the old one dropped are lost.
+struct Link {
+    config: SshConfig,
+    prompt_handler: Option<PromptHandler>,
+    handle: Arc<Mutex<client::Handle<SshHandler>>>,
+    jumps: Arc<Mutex<Vec<client::Handle<SshHandler>>>>,
+    supervision: Arc<std::sync::Mutex<Supervision>>,
+    wake: Arc<tokio::sync::Notify>,
+    transport: Arc<TransportInfo>,
+}
+
+impl Link {
+    async fn supervise(self) {
+        loop {
+            let error = self.watch().await;
+            log::warn!("SSH connection to {} lost: {}", self.config.host, error);
+            if !self.reconnect(error).await {
+                return;
+            }
+        }
+    }
+
+    fn settings(&self) -> Supervision {
+        self.supervision.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    fn emit(&self, state: ConnectionState, attempt: u32, error: Option<String>) {
+        set_state(&self.supervision, state, attempt, error);
+    }
+
+    /// Wait for the connection to die. Returns why.
+    async fn watch(&self) -> String {
+        let mut missed = 0;
+        let mut last_measured = std::time::Instant::now();
+        loop {
+            let settings = self.settings();
+            let (keepalive, latency_interval) = (settings.keepalive, settings.latency_interval);
+            let interval = match keepalive.interval_secs {
+                0 => latency_interval.map_or(CLOSED_CHECK_INTERVAL, |latency| latency.min(CLOSED_CHECK_INTERVAL)),
+                secs => std::time::Duration::from_secs(secs as u64),
+            };
+            let woken = tokio::select! {
+                _ = tokio::time::sleep(interval) => false,
+                _ = self.wake.notified() => true,
+            };
+
+            // Someone using the connection will notice if it's dead
+            let Ok(handle) = self.handle.try_lock() else { continue };
+            if handle.is_closed() {
+                return "connection closed".to_string();
+            }
+            // Without keepalives, only latency pings are sent when due
+            let measuring = keepalive.interval_secs == 0
+                && !woken
+                && latency_interval.is_some_and(|latency| last_measured.elapsed() >= latency);
+            if keepalive.interval_secs == 0 && !woken && !measuring {
+                continue;
+            }
+            let sent = std::time::Instant::now();
+            last_measured = sent;
+            let probe = tokio::time::timeout(interval.min(PROBE_TIMEOUT), handle.send_ping()).await;
+            drop(handle);
+            if matches!(probe, Ok(Ok(()))) {
+                self.transport.stats.record_rtt(sent.elapsed());
+                if missed > 0 {
+                    self.emit(ConnectionState::Connected, 0, None);
+                }
+                missed = 0;
+                continue;
+            }
+            self.transport.stats.record_lost_ping();
+            if measuring {
+                continue;
+            }
+            missed += 1;
+            log::debug!("SSH keepalive to {} unanswered ({} in a row)", self.config.host, missed);
+            // After a network change one lost probe is enough
+            if woken || missed >= keepalive.count_max.max(1) {
+                return format!("no reply to {} keepalive(s)", missed);
+            }
+            if missed == 1 {
+                self.emit(ConnectionState::Degraded, 0, Some("keepalive unanswered".to_string()));
+            }
+        }
+    }
+
+    /// Connect again as the reconnect policy allows, and swap the new
+    /// session in. Returns whether it succeeded.
+    async fn reconnect(&self, mut error: String) -> bool {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let Some(policy) = self.settings().reconnect.filter(|policy| policy.allows(attempt)) else {
+                self.emit(ConnectionState::Disconnected, attempt - 1, Some(error));
+                return false;
+            };
+            self.emit(ConnectionState::Connecting, attempt, Some(error.clone()));
+            tokio::select! {
+                _ = tokio::time::sleep(policy.delay(attempt)) => {}
+                _ = self.wake.notified() => {}
+            }
+
+            let login = Arc::new(std::sync::Mutex::new(LoginInfo::default()));
+            let prompt_handler = announcing_prompts(&self.supervision, self.prompt_handler.as_ref());
+            let established =
+                SshSession::establish(&self.config, prompt_handler.as_ref(), &login, &self.transport).await;
+            match established {
+                Ok((session, jumps)) => {
+                    let old = std::mem::replace(&mut *self.handle.lock().await, session);
+                    let old_jumps = std::mem::replace(&mut *self.jumps.lock().await, jumps);
+                    drop((old, old_jumps));
+                    log::info!("SSH reconnected to {}:{} (attempt {})", self.config.host, self.config.port, attempt);
+                    self.emit(ConnectionState::Connected, attempt, None);
+                    return true;
+                }
+                Err(e) => {
+                    log::warn!("SSH reconnect attempt {} to {} failed: {}", attempt, self.config.host, e);
+                    error = e.to_string();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::Timeouts;
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let policy = ReconnectPolicy { max_retries: 5, initial_delay_ms: 500, max_delay_ms: 5000 };
+        let delays: Vec<u64> = (1..=6).map(|attempt| policy.delay(attempt).as_millis() as u64).collect();
+        assert_eq!(delays, vec![500, 1000, 2000, 4000, 5000, 5000]);
+        assert_eq!(policy.delay(1000).as_millis(), 5000);
+        assert!(policy.allows(5) && !policy.allows(6));
+        assert!(ReconnectPolicy { max_retries: 0, ..policy }.allows(u32::MAX));
+    }
+
+    #[test]
+    fn test_unconnected_session_is_down() {
+        let session = SshSession::new(SshConfig::default());
+        assert_eq!(session.state(), ConnectionState::Disconnected);
+        assert!(!session.is_connected());
+        assert_eq!(session.live_handle().err().map(|e| e.to_string()).as_deref(), Some("Not connected"));
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        // Accepts connections but never answers the handshake
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = SshConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            timeouts: Timeouts { connect_ms: 200, ..Timeouts::default() },
+            ..SshConfig::default()
+        };
+        assert_eq!(Timeouts { connect_ms: 0, exec_ms: 0 }.exec(), None);
+
+        let mut session = SshSession::new(config);
+        let error = session.connect().await.unwrap_err();
+        assert!(error.to_string().contains("timed out"), "{}", error);
+        assert_eq!(session.state(), ConnectionState::Disconnected);
+        // Nothing was said before the timeout
+        assert_eq!(session.login_info(), &LoginInfo::default());
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn test_connect_failure_event() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let mut session = SshSession::new(SshConfig { host: "127.0.0.1".to_string(), port, ..SshConfig::default() });
+        session.set_event_handler(Some(Arc::new(move |event: &SessionEvent| seen.lock().unwrap().push(event.clone()))));
+        assert!(session.connect().await.is_err());
+        let events = events.lock().unwrap().clone();
+        assert!(matches!(events.as_slice(), [SessionEvent::Disconnected { reason: Some(_) }]), "{:?}", events);
+
+        let reconnecting = ConnectionEvent {
+            state: ConnectionState::Connecting,
+            attempt: 2,
+            error: Some("connection closed".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_string(&SessionEvent::from_state(&reconnecting)).unwrap(),
+            r#"{"type":"reconnecting","attempt":2,"reason":"connection closed"}"#
+        );
+        assert_eq!(SessionEvent::from_state(&ConnectionEvent { attempt: 0, ..reconnecting }), None);
+    }
+
+    #[test]
+    fn test_forward_accept_errors() {
+        let error = |code| std::io::Error::from_raw_os_error(code);
+        assert!(out_of_resources(&error(libc::EMFILE)) && !listener_failed(&error(libc::EMFILE)));
+        assert!(out_of_resources(&error(libc::ENFILE)) && !listener_failed(&error(libc::ENOBUFS)));
+        assert!(!listener_failed(&std::io::ErrorKind::ConnectionAborted.into()));
+        assert!(!out_of_resources(&error(libc::EBADF)) && listener_failed(&error(libc::EBADF)));
+    }
+
+    #[tokio::test]
+    async fn test_forward_listener_restart() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_port = listener.local_addr().unwrap().port();
+        let stats = ConnectionStats::default();
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let supervision = Arc::new(std::sync::Mutex::new(Supervision::default()));
+        let handler: EventHandler = Arc::new(move |event: &SessionEvent| seen.lock().unwrap().push(event.clone()));
+        supervision.lock().unwrap().event_handlers.insert(1, handler);
+        let mut forward = ForwardListener {
+            listener: Some(listener),
+            local_port,
+            counters: stats.add_tunnel(local_port),
+            supervision,
+            session: 1,
+        };
+
+        // The port is bound again while the old listener is still held
+        forward.counters.listener_failed("accept failed".to_string());
+        let (cancel_tx, mut cancel) = watch::channel(false);
+        assert!(forward.restart(&mut cancel).await);
+        let rebound = forward.listener.as_ref().unwrap().local_addr().unwrap().port();
+        assert_eq!(rebound, local_port);
+        let tunnel = stats.snapshot().tunnels[0].clone();
+        assert_eq!((tunnel.listening, tunnel.restarts), (true, 1));
+        assert_eq!(events.lock().unwrap().as_slice(), [SessionEvent::ForwardRestarted { local_port }]);
+
+        // Cancelled while waiting, the port is let go
+        cancel_tx.send(true).unwrap();
+        assert!(!forward.restart(&mut cancel).await);
+        assert!(forward.listener.is_none());
+        assert_eq!(stats.snapshot().tunnels[0].restarts, 1);
+    }
+
+    #[test]
+    fn test_environment() {
+        let mut session = SshSession::new(SshConfig::default());
+        assert!(session.environment().is_empty());
+
+        let path = std::env::var("PATH").unwrap();
+        session.set_environment(vec![("LANG".to_string(), "C.UTF-8".to_string())], vec!["PAT?".to_string()]);
+        assert_eq!(
+            session.environment(),
+            vec![("PATH".to_string(), path), ("LANG".to_string(), "C.UTF-8".to_string())]
+        );
+        // A set value replaces a sent one
+        session.set_environment(vec![("PATH".to_string(), "/bin".to_string())], vec!["PATH".to_string()]);
+        assert_eq!(session.environment(), vec![("PATH".to_string(), "/bin".to_string())]);
+    }
+}
@@ -1,6 +1,86 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::SeekFrom;
 use std::path::Path;
-use russh_sftp::client::SftpSession;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use russh_sftp::client::{RawSftpSession, SftpSession};
+use russh_sftp::extensions::Statvfs;
+use russh_sftp::protocol::{FileAttributes, OpenFlags, Packet, StatusCode};
 use serde::{Serialize, Deserialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{oneshot, OnceCell};
+
+/// Bytes moved per read/write during a transfer.
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Least time between two progress reports, the last one aside.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Smallest chunk moved under a rate limit; limited transfers otherwise
+/// move a tenth of a second's worth at a time, to keep the rate even.
+const MIN_LIMITED_CHUNK: usize = 4 * 1024;
+
+/// Extension renaming over an existing file in one step.
+const POSIX_RENAME: &str = "posix-rename@openssh.com";
+
+/// Extension having the server copy between two open files itself.
+const COPY_DATA: &str = "copy-data";
+
+/// Limit on all SFTP transfers together.
+static GLOBAL_RATE_LIMIT: RateLimiter = RateLimiter::new(0);
+
+/// Limit all SFTP transfers together to `bytes_per_sec`, 0 for no limit,
+/// leaving room on the link for interactive sessions. Applies to running
+/// transfers too.
+pub fn set_global_rate_limit(bytes_per_sec: u64) {
+    GLOBAL_RATE_LIMIT.set(bytes_per_sec);
+}
+
+/// Paces the transfers sharing it to a rate, by reserving each chunk's
+/// share of time in turn.
+pub struct RateLimiter {
+    /// 0 for no limit
+    bytes_per_sec: AtomicU64,
+    /// When the bytes let through so far have had their time
+    next_free: std::sync::Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub const fn new(bytes_per_sec: u64) -> Self {
+        Self { bytes_per_sec: AtomicU64::new(bytes_per_sec), next_free: std::sync::Mutex::new(None) }
+    }
+
+    /// Change the rate, 0 for no limit.
+    pub fn set(&self, bytes_per_sec: u64) {
+        self.bytes_per_sec.store(bytes_per_sec, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.bytes_per_sec.load(Ordering::Relaxed)
+    }
+
+    /// Account for `bytes` just moved, waiting as long as they take at the
+    /// rate.
+    async fn pass(&self, bytes: usize) {
+        let rate = self.get();
+        if rate == 0 {
+            return;
+        }
+        let wait = {
+            let mut next_free = self.next_free.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let now = Instant::now();
+            // Time left unused while idle isn't saved up for a burst
+            let from = next_free.map_or(now, |next_free| next_free.max(now));
+            let until = from + Duration::from_secs_f64(bytes as f64 / rate as f64);
+            *next_free = Some(until);
+            until - now
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
 
 /// Represents a remote file entry.
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -13,14 +93,135 @@ pub struct RemoteFileEntry {
     pub permissions: Option<u32>,
 }
 
+/// How far a transfer has got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct TransferProgress {
+    pub done: u64,
+    /// Size of the file when the transfer started
+    pub total: u64,
+    /// Average rate so far
+    pub bytes_per_sec: u64,
+    /// Time left at that rate, unknown until some bytes have moved
+    pub eta_secs: Option<u64>,
+}
+
+/// A remote file read for preview or editing.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FileContent {
+    pub size: u64,
+    /// `utf-8`, `utf-16le` or `utf-16be`; `None` for binary files
+    pub encoding: Option<&'static str>,
+    /// Whether the text starts with a byte order mark, left out of `content`
+    pub bom: bool,
+    /// Not text in one of the encodings above
+    pub binary: bool,
+    /// The text, or for binary files the bytes in base64
+    pub content: String,
+}
+
+impl FileContent {
+    /// Tell text from binary data, and decode it.
+    pub fn decode(data: &[u8]) -> Self {
+        let size = data.len() as u64;
+        let text = if let Some(rest) = data.strip_prefix(b"\xEF\xBB\xBF") {
+            std::str::from_utf8(rest).ok().map(|text| ("utf-8", true, text.to_string()))
+        } else if let Some(rest) = data.strip_prefix(b"\xFF\xFE") {
+            decode_utf16(rest, u16::from_le_bytes).map(|text| ("utf-16le", true, text))
+        } else if let Some(rest) = data.strip_prefix(b"\xFE\xFF") {
+            decode_utf16(rest, u16::from_be_bytes).map(|text| ("utf-16be", true, text))
+        } else {
+            // NULs don't occur in text files
+            std::str::from_utf8(data)
+                .ok()
+                .filter(|text| !text.contains('\0'))
+                .map(|text| ("utf-8", false, text.to_string()))
+        };
+        match text {
+            Some((encoding, bom, content)) => Self { size, encoding: Some(encoding), bom, binary: false, content },
+            None => Self {
+                size,
+                encoding: None,
+                bom: false,
+                binary: true,
+                content: data_encoding::BASE64.encode(data),
+            },
+        }
+    }
+}
+
+fn decode_utf16(data: &[u8], unit: fn([u8; 2]) -> u16) -> Option<String> {
+    if !data.len().is_multiple_of(2) {
+        return None;
+    }
+    let units = data.chunks_exact(2).map(|pair| unit([pair[0], pair[1]]));
+    char::decode_utf16(units).collect::<Result<String, _>>().ok()
+}
+
+/// A new channel on the connection, not yet asked for a subsystem.
+type ChannelFuture = Pin<Box<dyn Future<Output = Result<russh::Channel<russh::client::Msg>, anyhow::Error>> + Send>>;
+
+/// A session for the extended requests `SftpSession` has no call for.
+struct Extended {
+    session: RawSftpSession,
+    /// Extensions the server offers, with their versions
+    extensions: HashMap<String, String>,
+}
+
+impl Extended {
+    async fn start<S>(stream: S) -> Result<Self, anyhow::Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let session = RawSftpSession::new(stream);
+        let extensions = session.init().await?.extensions;
+        Ok(Self { session, extensions })
+    }
+}
+
 /// SFTP operations wrapper.
 pub struct SftpClient {
-    session: Option<SftpSession>,
+    /// Shared with the tails following files
+    session: Option<Arc<SftpSession>>,
+    /// Opens the channel for `extended`
+    extension_channel: Option<Arc<dyn Fn() -> ChannelFuture + Send + Sync>>,
+    /// The server again, on a channel of its own, started the first time
+    /// an extension is wanted; `None` inside if that failed
+    extended: OnceCell<Option<Extended>>,
+    /// Whether transfers give the copy the original's mode bits and times
+    preserve_attributes: bool,
+    /// Limit on each transfer in bytes per second, 0 for none
+    transfer_rate_limit: u64,
 }
 
 impl SftpClient {
     pub fn new() -> Self {
-        Self { session: None }
+        Self {
+            session: None,
+            extension_channel: None,
+            extended: OnceCell::new(),
+            preserve_attributes: false,
+            transfer_rate_limit: 0,
+        }
+    }
+
+    /// Have uploads and downloads carry over mode bits and modification
+    /// times, as `scp -p` does. Off by default.
+    pub fn set_preserve_attributes(&mut self, preserve: bool) {
+        self.preserve_attributes = preserve;
+    }
+
+    pub fn preserves_attributes(&self) -> bool {
+        self.preserve_attributes
+    }
+
+    /// Limit each upload, download and copy to `bytes_per_sec`, 0 for no
+    /// limit. The global limit applies as well.
+    pub fn set_transfer_rate_limit(&mut self, bytes_per_sec: u64) {
+        self.transfer_rate_limit = bytes_per_sec;
+    }
+
+    pub fn transfer_rate_limit(&self) -> u64 {
+        self.transfer_rate_limit
     }
 
     /// Initialize SFTP session from an existing SSH channel.
@@ -30,10 +231,50 @@ impl SftpClient {
     ) -> Result<(), anyhow::Error> {
         channel.request_subsystem(false, "sftp").await?;
         let sftp = SftpSession::new(channel.into_stream()).await?;
-        self.session = Some(sftp);
+        self.session = Some(Arc::new(sftp));
         Ok(())
     }
 
+    /// Have `open` give the channel for a second session, for extended
+    /// requests like `posix-rename@openssh.com` and `copy-data`. It's
+    /// opened the first time a rename over a file or a copy needs one;
+    /// without it, those fall back to plain requests.
+    pub fn set_extension_channel<F, Fut>(&mut self, open: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<russh::Channel<russh::client::Msg>, anyhow::Error>> + Send + 'static,
+    {
+        self.extension_channel = Some(Arc::new(move || -> ChannelFuture { Box::pin(open()) }));
+    }
+
+    /// The session for extended requests, if the server offers `name`.
+    async fn extension(&self, name: &str) -> Option<&RawSftpSession> {
+        let extended = self
+            .extended
+            .get_or_init(|| async {
+                let open = self.extension_channel.as_ref()?;
+                let started = async {
+                    let channel = open().await?;
+                    channel.request_subsystem(false, "sftp").await?;
+                    Extended::start(channel.into_stream()).await
+                };
+                match started.await {
+                    Ok(extended) => Some(extended),
+                    Err(e) => {
+                        log::debug!("No SFTP session for extended requests: {}", e);
+                        None
+                    }
+                }
+            })
+            .await
+            .as_ref()?;
+        extended
+            .extensions
+            .get(name)
+            .is_some_and(|version| version == "1")
+            .then_some(&extended.session)
+    }
+
     /// List directory contents on the remote server.
     pub async fn list_dir(&self, path: &str) -> Result<Vec<RemoteFileEntry>, anyhow::Error> {
         let sftp = self
@@ -49,19 +290,7 @@ impl SftpClient {
             if name == "." || name == ".." {
                 continue;
             }
-            let file_path = format!("{}/{}", path.trim_end_matches('/'), name);
-            let is_dir = entry.file_type().is_dir();
-            let size = entry.metadata().size.unwrap_or(0);
-            let modified = entry.metadata().mtime.map(|v| v as u64);
-
-            entries.push(RemoteFileEntry {
-                name,
-                path: file_path,
-                is_dir,
-                size,
-                modified,
-                permissions: entry.metadata().permissions,
-            });
+            entries.push(remote_entry(path, name, &entry.metadata()));
         }
 
         // Sort: directories first, then files, alphabetically
@@ -79,14 +308,25 @@ impl SftpClient {
         &self,
         remote_path: &str,
         local_path: &Path,
+    ) -> Result<(), anyhow::Error> {
+        self.download_with_progress(remote_path, local_path, |_| {}).await
+    }
+
+    /// Download a file from remote to local path in chunks, never holding
+    /// more than one in memory, reporting progress along the way.
+    pub async fn download_with_progress(
+        &self,
+        remote_path: &str,
+        local_path: &Path,
+        on_progress: impl FnMut(TransferProgress),
     ) -> Result<(), anyhow::Error> {
         let sftp = self
             .session
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
 
-        let data = sftp.read(remote_path).await?;
-        tokio::fs::write(local_path, data).await?;
+        let limit = RateLimiter::new(self.transfer_rate_limit);
+        download_from(sftp, remote_path, local_path, 0, self.preserve_attributes, &limit, on_progress).await?;
 
         log::info!(
             "Downloaded {} -> {}",
@@ -101,14 +341,25 @@ impl SftpClient {
         &self,
         local_path: &Path,
         remote_path: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.upload_with_progress(local_path, remote_path, |_| {}).await
+    }
+
+    /// Upload a local file to remote path in chunks, never holding more
+    /// than one in memory, reporting progress along the way.
+    pub async fn upload_with_progress(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        on_progress: impl FnMut(TransferProgress),
     ) -> Result<(), anyhow::Error> {
         let sftp = self
             .session
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
 
-        let data = tokio::fs::read(local_path).await?;
-        sftp.write(remote_path, &data).await?;
+        let limit = RateLimiter::new(self.transfer_rate_limit);
+        upload_from(sftp, local_path, remote_path, 0, self.preserve_attributes, &limit, on_progress).await?;
 
         log::info!(
             "Uploaded {} -> {}",
@@ -118,6 +369,11 @@ impl SftpClient {
         Ok(())
     }
 
+    /// The session, for work that outlives a borrow of the client.
+    pub(super) fn shared_session(&self) -> Result<Arc<SftpSession>, anyhow::Error> {
+        self.session.clone().ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))
+    }
+
     /// Remove a file on the remote server.
     pub async fn remove_file(&self, path: &str) -> Result<(), anyhow::Error> {
         let sftp = self
@@ -138,6 +394,359 @@ impl SftpClient {
         Ok(())
     }
 
+    /// Rename or move a remote file or directory. SFTP's rename fails when
+    /// `new_path` exists; with `overwrite`, a file there is replaced, in
+    /// one step if the server has `posix-rename@openssh.com`.
+    pub async fn rename(&self, old_path: &str, new_path: &str, overwrite: bool) -> Result<(), anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+        match sftp.rename(old_path, new_path).await {
+            Ok(()) => Ok(()),
+            Err(e) if overwrite => {
+                let target = match sftp.symlink_metadata(new_path).await {
+                    Ok(target) => target,
+                    // Nothing in the way, so the rename failed for another reason
+                    Err(_) => return Err(e.into()),
+                };
+                if target.file_type().is_dir() {
+                    return Err(anyhow::anyhow!("{} is a directory", new_path));
+                }
+                self.replace(old_path, new_path).await
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Move `from` over the file `to`: in one step with
+    /// `posix-rename@openssh.com`, otherwise by removing `to` first, which
+    /// leaves nothing there should the connection drop in between.
+    async fn replace(&self, from: &str, to: &str) -> Result<(), anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+        if let Some(extended) = self.extension(POSIX_RENAME).await {
+            let request = PosixRename { oldpath: from.to_string(), newpath: to.to_string() };
+            let reply = extended.extended(POSIX_RENAME, russh_sftp::ser::to_bytes(&request)?.to_vec()).await?;
+            return Ok(extended_status(reply)?);
+        }
+        sftp.remove_file(to).await?;
+        sftp.rename(from, to).await?;
+        Ok(())
+    }
+
+    /// Copy a remote file to another remote path, replacing it if it
+    /// exists. With the `copy-data` extension the server copies it itself,
+    /// reporting progress once at the end; otherwise the data passes
+    /// through this end in chunks, nothing goes to local disk. The copy
+    /// gets the source's permissions. Like `cp`, it refuses to copy a file
+    /// onto itself, symlinks followed.
+    pub async fn copy(
+        &self,
+        from_path: &str,
+        to_path: &str,
+        mut on_progress: impl FnMut(TransferProgress),
+    ) -> Result<(), anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+
+        let metadata = sftp.metadata(from_path).await?;
+        if metadata.file_type().is_dir() {
+            return Err(anyhow::anyhow!("{} is a directory", from_path));
+        }
+        // The target is truncated before anything is read, which would
+        // empty a source it leads to
+        let from_real = sftp.canonicalize(from_path).await?;
+        if sftp.canonicalize(to_path).await.is_ok_and(|to_real| to_real == from_real) {
+            return Err(anyhow::anyhow!("{} and {} are the same file", from_path, to_path));
+        }
+        let total = metadata.size.unwrap_or(0);
+        let started = Instant::now();
+        let copied_on_server = match self.extension(COPY_DATA).await {
+            Some(extended) => copy_on_server(extended, from_path, to_path).await?,
+            None => false,
+        };
+        if copied_on_server {
+            on_progress(progress(total, total, started.elapsed()));
+        } else {
+            let mut from = sftp.open(from_path).await?;
+            let mut to = sftp.create(to_path).await?;
+            let limit = RateLimiter::new(self.transfer_rate_limit);
+            copy_with_progress(&mut from, &mut to, total, &limit, on_progress).await?;
+            to.shutdown().await?;
+        }
+        if let Some(permissions) = metadata.permissions {
+            let mut attributes = FileAttributes::empty();
+            attributes.permissions = Some(permissions & 0o7777);
+            sftp.set_metadata(to_path, attributes).await?;
+        }
+
+        log::info!("Copied {} -> {}", from_path, to_path);
+        Ok(())
+    }
+
+    /// Set the permission bits of a remote entry, like `chmod`.
+    pub async fn chmod(&self, path: &str, mode: u32) -> Result<(), anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+        let mut attributes = FileAttributes::empty();
+        attributes.permissions = Some(mode & 0o7777);
+        sftp.set_metadata(path, attributes).await?;
+        Ok(())
+    }
+
+    /// Change the owner and/or group of a remote entry, like `chown`;
+    /// `None` keeps the current one.
+    pub async fn chown(&self, path: &str, uid: Option<u32>, gid: Option<u32>) -> Result<(), anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+        if uid.is_none() && gid.is_none() {
+            return Ok(());
+        }
+        // The protocol sets both ids at once
+        let mut attributes = FileAttributes::empty();
+        (attributes.uid, attributes.gid) = (uid, gid);
+        if uid.is_none() || gid.is_none() {
+            let current = sftp.metadata(path).await?;
+            attributes.uid = uid.or(current.uid);
+            attributes.gid = gid.or(current.gid);
+        }
+        sftp.set_metadata(path, attributes).await?;
+        Ok(())
+    }
+
+    /// Set the access and/or modification time of a remote entry, in Unix
+    /// seconds, like `touch -d`; `None` keeps the current one.
+    pub async fn set_times(&self, path: &str, atime: Option<u32>, mtime: Option<u32>) -> Result<(), anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+        if atime.is_none() && mtime.is_none() {
+            return Ok(());
+        }
+        // The protocol sets both times at once
+        let mut attributes = FileAttributes::empty();
+        (attributes.atime, attributes.mtime) = (atime, mtime);
+        if atime.is_none() || mtime.is_none() {
+            let current = sftp.metadata(path).await?;
+            attributes.atime = atime.or(current.atime);
+            attributes.mtime = mtime.or(current.mtime);
+        }
+        sftp.set_metadata(path, attributes).await?;
+        Ok(())
+    }
+
+    /// End the SFTP session and close its channel.
+    pub async fn close(&mut self) -> Result<(), anyhow::Error> {
+        if let Some(Some(extended)) = self.extended.take() {
+            let _ = extended.session.close_session();
+        }
+        if let Some(sftp) = self.session.take() {
+            sftp.close().await?;
+        }
+        Ok(())
+    }
+
+    /// Read up to `len` bytes of a remote file from `offset`, fewer at the
+    /// end of the file, e.g. to preview part of a large one.
+    pub async fn read_range(&self, path: &str, offset: u64, len: usize) -> Result<Vec<u8>, anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+
+        let mut file = sftp.open(path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut data = Vec::with_capacity(len.min(CHUNK_SIZE));
+        (&mut file).take(len as u64).read_to_end(&mut data).await?;
+        Ok(data)
+    }
+
+    /// Read a whole remote file into memory, failing if it's over
+    /// `max_size` bytes rather than reading it.
+    pub async fn read_to_bytes(&self, path: &str, max_size: u64) -> Result<Vec<u8>, anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+
+        let mut file = sftp.open(path).await?;
+        let size = file.metadata().await?.size.unwrap_or(0);
+        if size > max_size {
+            return Err(anyhow::anyhow!("{} is {} bytes, over the {} byte limit", path, size, max_size));
+        }
+        let mut data = Vec::with_capacity(size as usize);
+        // It may have grown since
+        (&mut file).take(max_size.saturating_add(1)).read_to_end(&mut data).await?;
+        if data.len() as u64 > max_size {
+            return Err(anyhow::anyhow!("{} is over the {} byte limit", path, max_size));
+        }
+        Ok(data)
+    }
+
+    /// Replace a remote file with `data`, or create it. The data goes to a
+    /// temporary file next to it first, which then takes its place with the
+    /// old file's permissions (and owner, where allowed), so a dropped
+    /// connection never leaves a truncated file behind. The swap is one
+    /// step with `posix-rename@openssh.com`; without it the old file is
+    /// removed just before, so for a moment there's none. If that last
+    /// step fails, the error names the temporary file holding the data.
+    pub async fn write_bytes(&self, path: &str, data: &[u8]) -> Result<(), anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+
+        // Write through symlinks rather than replacing them
+        let (path, existing) = match sftp.canonicalize(path).await {
+            Ok(real) => match sftp.metadata(&real).await {
+                Ok(metadata) => (real, Some(metadata)),
+                Err(_) => (path.to_string(), None),
+            },
+            Err(_) => (path.to_string(), None),
+        };
+        if existing.as_ref().is_some_and(|metadata| metadata.file_type().is_dir()) {
+            return Err(anyhow::anyhow!("{} is a directory", path));
+        }
+
+        let temp = temp_path(&path);
+        let written = async {
+            let mut file = sftp.create(&temp).await?;
+            file.write_all(data).await?;
+            file.flush().await?;
+            file.shutdown().await?;
+            if let Some(existing) = &existing {
+                let mut attributes = FileAttributes::empty();
+                attributes.permissions = existing.permissions.map(|mode| mode & 0o7777);
+                sftp.set_metadata(&temp, attributes).await?;
+                let mut owner = FileAttributes::empty();
+                (owner.uid, owner.gid) = (existing.uid, existing.gid);
+                if owner.uid.is_some() && owner.gid.is_some() {
+                    // Only root may give files away; others keep their own
+                    let _ = sftp.set_metadata(&temp, owner).await;
+                }
+            }
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+        if let Err(e) = written {
+            let _ = sftp.remove_file(&temp).await;
+            return Err(e);
+        }
+
+        let swapped = match existing {
+            // Straight to the swap, atomic where the server allows
+            Some(_) => self.replace(&temp, &path).await,
+            None => self.rename(&temp, &path, true).await,
+        };
+        swapped.map_err(|e| anyhow::anyhow!("Saved to {} but couldn't replace {}: {}", temp, path, e))?;
+        log::info!("Wrote {} bytes to {}", data.len(), path);
+        Ok(())
+    }
+
+    /// Follow a remote file as it grows, like `tail -F`, passing appended
+    /// bytes to `on_data` from `runtime`. Starts at `offset`, or at the
+    /// current end if `None`. The file is checked every `interval`; if it
+    /// shrinks (truncated, or rotated to a new file), it's read again from
+    /// the start, and while it's missing the tail waits for it.
+    pub fn tail_follow(
+        &self,
+        runtime: &tokio::runtime::Handle,
+        path: &str,
+        offset: Option<u64>,
+        interval: Duration,
+        mut on_data: impl FnMut(&[u8]) + Send + 'static,
+    ) -> Result<RemoteTail, anyhow::Error> {
+        let sftp = self
+            .session
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+
+        let path = path.to_string();
+        let (cancel, cancelled) = oneshot::channel::<()>();
+        let task = runtime.spawn(async move {
+            tokio::select! {
+                _ = follow(&sftp, &path, offset, interval, &mut on_data) => {}
+                _ = cancelled => {}
+            }
+        });
+        Ok(RemoteTail { cancel: Some(cancel), task })
+    }
+
+    /// Space on the filesystem holding a remote path, or `None` if the
+    /// server lacks the `statvfs@openssh.com` extension.
+    pub async fn statvfs(&self, path: &str) -> Result<Option<Statvfs>, anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+        Ok(sftp.fs_info(path).await?)
+    }
+
+    /// The entry for a single remote path, following symlinks.
+    pub async fn entry(&self, path: &str) -> Result<RemoteFileEntry, anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+        let metadata = sftp.metadata(path).await?;
+        let name = path.trim_end_matches('/').rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or(path);
+        Ok(RemoteFileEntry {
+            name: name.to_string(),
+            path: path.to_string(),
+            is_dir: metadata.file_type().is_dir(),
+            size: metadata.size.unwrap_or(0),
+            modified: metadata.mtime.map(|v| v as u64),
+            permissions: metadata.permissions,
+        })
+    }
+
+    /// Whether anything exists at a remote path.
+    pub async fn exists(&self, path: &str) -> Result<bool, anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+        Ok(sftp.try_exists(path).await?)
+    }
+
+    /// Create a remote directory and any missing parents, like `mkdir -p`.
+    /// Succeeds if the directory is already there.
+    pub async fn create_dir_all(&self, path: &str) -> Result<(), anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+
+        // Walk up to the deepest directory that exists, then create down
+        let mut missing = Vec::new();
+        for dir in dir_ancestors(path) {
+            match sftp.metadata(dir).await {
+                Ok(metadata) if metadata.file_type().is_dir() => break,
+                Ok(_) => return Err(anyhow::anyhow!("{} is not a directory", dir)),
+                Err(_) => missing.push(dir),
+            }
+        }
+        for dir in missing.into_iter().rev() {
+            if let Err(e) = sftp.create_dir(dir).await {
+                // Someone else may have made it meanwhile
+                if !sftp.metadata(dir).await.is_ok_and(|metadata| metadata.file_type().is_dir()) {
+                    return Err(e.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Get the current working directory of the SFTP session.
     pub async fn pwd(&self) -> Result<String, anyhow::Error> {
         let sftp = self
@@ -147,4 +756,816 @@ impl SftpClient {
         let path = sftp.canonicalize(".").await?;
         Ok(path)
     }
+
+    /// The absolute path `path` stands for, with `.`, `..` and symlinks
+    /// resolved (e.g. a `current` deploy link to the release it points
+    /// at). Relative paths are from the working directory. OpenSSH fails
+    /// for paths that don't exist.
+    pub async fn canonicalize(&self, path: &str) -> Result<String, anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+        Ok(sftp.canonicalize(path).await?)
+    }
+
+    /// Make `link_path` a hard link to the file `target`. Returns `false`
+    /// if the server lacks the `hardlink@openssh.com` extension.
+    pub async fn hard_link(&self, target: &str, link_path: &str) -> Result<bool, anyhow::Error> {
+        let sftp = self
+            .session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SFTP session not initialized"))?;
+        Ok(sftp.hardlink(target, link_path).await?)
+    }
+}
+
+/// Download `remote_path` to `local_path`, carrying on after the first
+/// `offset` bytes if some are already there. Progress counts those too.
+/// With `preserve`, the local file gets the remote one's mode and times.
+pub(super) async fn download_from(
+    sftp: &SftpSession,
+    remote_path: &str,
+    local_path: &Path,
+    offset: u64,
+    preserve: bool,
+    limit: &RateLimiter,
+    mut on_progress: impl FnMut(TransferProgress),
+) -> Result<(), anyhow::Error> {
+    let mut remote = sftp.open(remote_path).await?;
+    let metadata = remote.metadata().await?;
+    let total = metadata.size.unwrap_or(0);
+    let mut local = if offset == 0 {
+        tokio::fs::File::create(local_path).await?
+    } else {
+        // Anything past the offset may be a partly written chunk
+        let local = tokio::fs::OpenOptions::new().write(true).open(local_path).await?;
+        local.set_len(offset).await?;
+        remote.seek(SeekFrom::Start(offset)).await?;
+        local
+    };
+    local.seek(SeekFrom::Start(offset)).await?;
+    copy_with_progress(&mut remote, &mut local, total.saturating_sub(offset), limit, |progress| {
+        on_progress(TransferProgress { done: offset + progress.done, total: offset + progress.total, ..progress })
+    })
+    .await?;
+    local.sync_all().await?;
+    if preserve {
+        set_local_attributes(local.into_std().await, &metadata)?;
+    }
+    Ok(())
+}
+
+/// Give a downloaded file the remote file's mode bits and times.
+fn set_local_attributes(local: std::fs::File, remote: &FileAttributes) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if let Some(mode) = remote.permissions {
+        use std::os::unix::fs::PermissionsExt;
+        local.set_permissions(std::fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    let at = |secs: u32| std::time::UNIX_EPOCH + Duration::from_secs(secs as u64);
+    let mut times = std::fs::FileTimes::new();
+    if let Some(mtime) = remote.mtime {
+        times = times.set_modified(at(mtime));
+    }
+    if let Some(atime) = remote.atime {
+        times = times.set_accessed(at(atime));
+    }
+    local.set_times(times)
+}
+
+/// The mode bits and times of a local file, to give its upload.
+fn local_attributes(local: &std::fs::Metadata) -> FileAttributes {
+    let secs = |time: std::io::Result<std::time::SystemTime>| {
+        time.ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .and_then(|since| u32::try_from(since.as_secs()).ok())
+    };
+    let mut attributes = FileAttributes::empty();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        attributes.permissions = Some(local.permissions().mode() & 0o7777);
+    }
+    // Both times go together; without an access time, use the modification
+    attributes.mtime = secs(local.modified());
+    attributes.atime = secs(local.accessed()).or(attributes.mtime);
+    if attributes.mtime.is_none() {
+        attributes.atime = None;
+    }
+    attributes
+}
+
+/// Upload `local_path` to `remote_path`, carrying on after the first
+/// `offset` bytes if some are already there. Progress counts those too.
+/// With `preserve`, the remote file gets the local one's mode and times.
+pub(super) async fn upload_from(
+    sftp: &SftpSession,
+    local_path: &Path,
+    remote_path: &str,
+    offset: u64,
+    preserve: bool,
+    limit: &RateLimiter,
+    mut on_progress: impl FnMut(TransferProgress),
+) -> Result<(), anyhow::Error> {
+    let mut local = tokio::fs::File::open(local_path).await?;
+    let metadata = local.metadata().await?;
+    let total = metadata.len();
+    let mut remote = if offset == 0 {
+        sftp.create(remote_path).await?
+    } else {
+        let mut remote = sftp.open_with_flags(remote_path, OpenFlags::WRITE).await?;
+        remote.seek(SeekFrom::Start(offset)).await?;
+        local.seek(SeekFrom::Start(offset)).await?;
+        remote
+    };
+    copy_with_progress(&mut local, &mut remote, total.saturating_sub(offset), limit, |progress| {
+        on_progress(TransferProgress { done: offset + progress.done, total: offset + progress.total, ..progress })
+    })
+    .await?;
+    // Closes the handle, so a failed close isn't lost
+    remote.shutdown().await?;
+    if preserve {
+        sftp.set_metadata(remote_path, local_attributes(&metadata)).await?;
+    }
+    Ok(())
+}
+
+/// The entry for `name` in the remote directory `dir`.
+fn remote_entry(dir: &str, name: String, attrs: &FileAttributes) -> RemoteFileEntry {
+    RemoteFileEntry {
+        path: format!("{}/{}", dir.trim_end_matches('/'), name),
+        is_dir: attrs.file_type().is_dir(),
+        size: attrs.size.unwrap_or(0),
+        modified: attrs.mtime.map(|v| v as u64),
+        permissions: attrs.permissions,
+        name,
+    }
+}
+
+/// A remote directory being listed in batches, see `DirListing::start`.
+pub struct DirListing {
+    cancel: Option<oneshot::Sender<()>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl DirListing {
+    /// List `path` on `runtime` over `channel`, a fresh one from
+    /// `SshSession::sftp_channel`, so huge directories show as they're
+    /// read. Entries go to `on_batch` as the server sends them, unsorted
+    /// and typically a hundred or so at a time; then the number listed, or
+    /// what went wrong, goes to `on_done`.
+    pub fn start(
+        runtime: &tokio::runtime::Handle,
+        channel: russh::Channel<russh::client::Msg>,
+        path: String,
+        mut on_batch: impl FnMut(Vec<RemoteFileEntry>) + Send + 'static,
+        on_done: impl FnOnce(Result<u64, anyhow::Error>) + Send + 'static,
+    ) -> Self {
+        let (cancel, cancelled) = oneshot::channel::<()>();
+        let task = runtime.spawn(async move {
+            tokio::select! {
+                result = list_in_batches(channel, &path, &mut on_batch) => on_done(result),
+                // Dropping the session closes its channel
+                _ = cancelled => {}
+            }
+        });
+        Self { cancel: Some(cancel), task }
+    }
+
+    /// Stop listing if it's still going. No callback runs once this
+    /// returns; `on_done` isn't called for a stopped listing.
+    pub async fn stop(mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            let _ = cancel.send(());
+        }
+        let _ = (&mut self.task).await;
+    }
+}
+
+/// Read `path` a server reply at a time. `SftpSession::read_dir` only
+/// returns once it has the whole directory, so this speaks the protocol
+/// directly.
+async fn list_in_batches(
+    channel: russh::Channel<russh::client::Msg>,
+    path: &str,
+    on_batch: &mut (impl FnMut(Vec<RemoteFileEntry>) + Send),
+) -> Result<u64, anyhow::Error> {
+    channel.request_subsystem(false, "sftp").await?;
+    let sftp = RawSftpSession::new(channel.into_stream());
+    sftp.init().await?;
+    let handle = sftp.opendir(path).await?.handle;
+    let mut count = 0;
+    let result = loop {
+        match sftp.readdir(handle.as_str()).await {
+            Ok(name) => {
+                let batch: Vec<RemoteFileEntry> = name
+                    .files
+                    .into_iter()
+                    .filter(|file| file.filename != "." && file.filename != "..")
+                    .map(|file| remote_entry(path, file.filename, &file.attrs))
+                    .collect();
+                if !batch.is_empty() {
+                    count += batch.len() as u64;
+                    on_batch(batch);
+                }
+            }
+            Err(russh_sftp::client::error::Error::Status(status)) if status.status_code == StatusCode::Eof => {
+                break Ok(count);
+            }
+            Err(e) => break Err(e.into()),
+        }
+    };
+    let _ = sftp.close(handle).await;
+    result
+}
+
+/// A remote file being followed, see `SftpClient::tail_follow`.
+pub struct RemoteTail {
+    cancel: Option<oneshot::Sender<()>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl RemoteTail {
+    /// Stop following. No callback runs once this returns.
+    pub async fn stop(mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            let _ = cancel.send(());
+        }
+        let _ = (&mut self.task).await;
+    }
+}
+
+/// Poll `path` for growth forever, passing what's appended to `on_data`.
+async fn follow(
+    sftp: &SftpSession,
+    path: &str,
+    offset: Option<u64>,
+    interval: Duration,
+    on_data: &mut (impl FnMut(&[u8]) + Send),
+) {
+    let mut position = offset;
+    loop {
+        match sftp.metadata(path).await {
+            Ok(metadata) => {
+                let size = metadata.size.unwrap_or(0);
+                let mut from = *position.get_or_insert(size);
+                if size < from {
+                    from = 0;
+                }
+                if size > from {
+                    if let Err(e) = read_appended(sftp, path, &mut from, on_data).await {
+                        log::debug!("Reading {} for tail failed: {}", path, e);
+                    }
+                }
+                position = Some(from);
+            }
+            Err(e) => log::debug!("Tail waiting for {}: {}", path, e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Pass `path` from `position` to its end to `on_data`, advancing
+/// `position` past what was passed.
+async fn read_appended(
+    sftp: &SftpSession,
+    path: &str,
+    position: &mut u64,
+    on_data: &mut (impl FnMut(&[u8]) + Send),
+) -> Result<(), anyhow::Error> {
+    let mut file = sftp.open(path).await?;
+    file.seek(SeekFrom::Start(*position)).await?;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        on_data(&buf[..n]);
+        *position += n as u64;
+    }
+}
+
+/// `posix-rename@openssh.com` request.
+#[derive(Serialize)]
+struct PosixRename {
+    oldpath: String,
+    newpath: String,
+}
+
+/// `copy-data` request; a length of 0 copies to the end of the file.
+#[derive(Serialize)]
+struct CopyData {
+    read_from_handle: String,
+    read_from_offset: u64,
+    read_data_length: u64,
+    write_to_handle: String,
+    write_to_offset: u64,
+}
+
+/// The outcome of an extended request answered with a status.
+fn extended_status(reply: Packet) -> Result<(), russh_sftp::client::error::Error> {
+    match reply {
+        Packet::Status(status) if status.status_code == StatusCode::Ok => Ok(()),
+        Packet::Status(status) => Err(status.into()),
+        _ => Err(russh_sftp::client::error::Error::UnexpectedPacket),
+    }
+}
+
+/// Have the server copy `from_path` over `to_path` with `copy-data`.
+/// Returns `false` if it turns out not to support that after all.
+async fn copy_on_server(sftp: &RawSftpSession, from_path: &str, to_path: &str) -> Result<bool, anyhow::Error> {
+    let from = sftp.open(from_path, OpenFlags::READ, FileAttributes::empty()).await?.handle;
+    let flags = OpenFlags::CREATE | OpenFlags::TRUNCATE | OpenFlags::WRITE;
+    let to = match sftp.open(to_path, flags, FileAttributes::empty()).await {
+        Ok(to) => to.handle,
+        Err(e) => {
+            let _ = sftp.close(from).await;
+            return Err(e.into());
+        }
+    };
+    let copied = async {
+        let request = CopyData {
+            read_from_handle: from.clone(),
+            read_from_offset: 0,
+            read_data_length: 0,
+            write_to_handle: to.clone(),
+            write_to_offset: 0,
+        };
+        let reply = sftp.extended(COPY_DATA, russh_sftp::ser::to_bytes(&request)?.to_vec()).await?;
+        match extended_status(reply) {
+            Ok(()) => Ok(true),
+            Err(russh_sftp::client::error::Error::Status(status)) if status.status_code == StatusCode::OpUnsupported => {
+                Ok(false)
+            }
+            Err(e) => Err(anyhow::Error::from(e)),
+        }
+    }
+    .await;
+    let _ = sftp.close(from).await;
+    let closed = sftp.close(to).await;
+    let copied = copied?;
+    closed?;
+    Ok(copied)
+}
+
+/// A hidden name next to `path` for writing it before the swap.
+fn temp_path(path: &str) -> String {
+    let (dir, name) = match path.rfind('/') {
+        Some(slash) => path.split_at(slash + 1),
+        None => ("", path),
+    };
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|since| since.subsec_nanos())
+        .unwrap_or(0);
+    format!("{}.{}.pier-{:08x}.tmp", dir, name, nanos)
+}
+
+/// `path` and its parent directories, deepest first, without the root.
+fn dir_ancestors(path: &str) -> Vec<&str> {
+    let mut dirs = Vec::new();
+    let mut path = path.trim_end_matches('/');
+    while !path.is_empty() && path != "." {
+        dirs.push(path);
+        path = match path.rfind('/') {
+            Some(slash) => path[..slash].trim_end_matches('/'),
+            None => "",
+        };
+    }
+    dirs
+}
+
+/// Copy `reader` to `writer` a chunk at a time, no faster than `limit` and
+/// the global limit allow, calling `on_progress` at most every
+/// `PROGRESS_INTERVAL` and once at the end.
+/// Returns the number of bytes copied.
+async fn copy_with_progress<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    total: u64,
+    limit: &RateLimiter,
+    mut on_progress: impl FnMut(TransferProgress),
+) -> std::io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let started = Instant::now();
+    let mut last_report = started;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut done = 0u64;
+    loop {
+        let n = reader.read(&mut buf[..chunk_len(&[limit, &GLOBAL_RATE_LIMIT])]).await?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buf[..n]).await?;
+        done += n as u64;
+        limit.pass(n).await;
+        GLOBAL_RATE_LIMIT.pass(n).await;
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            on_progress(progress(done, total, started.elapsed()));
+        }
+    }
+    writer.flush().await?;
+    on_progress(progress(done, total.max(done), started.elapsed()));
+    Ok(done)
+}
+
+/// How much to move at a time under `limits`.
+fn chunk_len(limits: &[&RateLimiter]) -> usize {
+    limits
+        .iter()
+        .map(|limit| limit.get())
+        .filter(|&rate| rate > 0)
+        .min()
+        .map_or(CHUNK_SIZE, |rate| ((rate / 10) as usize).clamp(MIN_LIMITED_CHUNK, CHUNK_SIZE))
+}
+
+fn progress(done: u64, total: u64, elapsed: Duration) -> TransferProgress {
+    let secs = elapsed.as_secs_f64();
+    let bytes_per_sec = if secs > 0.0 { (done as f64 / secs) as u64 } else { 0 };
+    let eta_secs = (bytes_per_sec > 0).then(|| total.saturating_sub(done).div_ceil(bytes_per_sec));
+    TransferProgress { done, total, bytes_per_sec, eta_secs }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh_sftp::protocol::{Attrs, Data, File, Handle, Name, Status, Version};
+
+    type Files = Arc<std::sync::Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// An in-memory SFTP server of plain files, noting the requests made.
+    #[derive(Default)]
+    struct FakeServer {
+        files: Files,
+        extensions: HashMap<String, String>,
+        requests: Arc<std::sync::Mutex<Vec<String>>>,
+        handles: HashMap<String, String>,
+    }
+
+    impl FakeServer {
+        fn note(&self, request: &str) {
+            self.requests.lock().unwrap().push(request.to_string());
+        }
+
+        fn attrs(&self, id: u32, path: &str) -> Result<Attrs, StatusCode> {
+            let files = self.files.lock().unwrap();
+            let data = files.get(path).ok_or(StatusCode::NoSuchFile)?;
+            let attrs = FileAttributes { size: Some(data.len() as u64), permissions: Some(0o100640), ..Default::default() };
+            Ok(Attrs { id, attrs })
+        }
+
+        fn path(&self, handle: &str) -> Result<String, StatusCode> {
+            self.handles.get(handle).cloned().ok_or(StatusCode::Failure)
+        }
+    }
+
+    fn ok(id: u32) -> Status {
+        Status { id, status_code: StatusCode::Ok, error_message: "Ok".to_string(), language_tag: "en-US".to_string() }
+    }
+
+    /// Take an SSH string or a u64 off the front of an extended request.
+    fn take_string(data: &mut &[u8]) -> String {
+        let len = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+        let string = String::from_utf8(data[4..4 + len].to_vec()).unwrap();
+        *data = &data[4 + len..];
+        string
+    }
+
+    fn take_u64(data: &mut &[u8]) -> u64 {
+        let n = u64::from_be_bytes(data[..8].try_into().unwrap());
+        *data = &data[8..];
+        n
+    }
+
+    impl russh_sftp::server::Handler for FakeServer {
+        type Error = StatusCode;
+
+        fn unimplemented(&self) -> Self::Error {
+            StatusCode::OpUnsupported
+        }
+
+        async fn init(&mut self, _version: u32, _extensions: HashMap<String, String>) -> Result<Version, Self::Error> {
+            Ok(Version { version: 3, extensions: self.extensions.clone() })
+        }
+
+        async fn open(
+            &mut self,
+            id: u32,
+            filename: String,
+            pflags: OpenFlags,
+            _attrs: FileAttributes,
+        ) -> Result<Handle, Self::Error> {
+            self.note("open");
+            let mut files = self.files.lock().unwrap();
+            if pflags.contains(OpenFlags::TRUNCATE) {
+                files.insert(filename.clone(), Vec::new());
+            } else if pflags.contains(OpenFlags::CREATE) {
+                files.entry(filename.clone()).or_default();
+            } else if !files.contains_key(&filename) {
+                return Err(StatusCode::NoSuchFile);
+            }
+            let handle = (self.handles.len() + 1).to_string();
+            self.handles.insert(handle.clone(), filename);
+            Ok(Handle { id, handle })
+        }
+
+        async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
+            self.handles.remove(&handle);
+            Ok(ok(id))
+        }
+
+        async fn read(&mut self, id: u32, handle: String, offset: u64, len: u32) -> Result<Data, Self::Error> {
+            self.note("read");
+            let path = self.path(&handle)?;
+            let files = self.files.lock().unwrap();
+            let data = files.get(&path).ok_or(StatusCode::NoSuchFile)?;
+            let start = offset as usize;
+            if start >= data.len() {
+                return Err(StatusCode::Eof);
+            }
+            let end = (start + len as usize).min(data.len());
+            Ok(Data { id, data: data[start..end].to_vec() })
+        }
+
+        async fn write(&mut self, id: u32, handle: String, offset: u64, data: Vec<u8>) -> Result<Status, Self::Error> {
+            self.note("write");
+            let path = self.path(&handle)?;
+            let mut files = self.files.lock().unwrap();
+            let file = files.get_mut(&path).ok_or(StatusCode::NoSuchFile)?;
+            let end = offset as usize + data.len();
+            if file.len() < end {
+                file.resize(end, 0);
+            }
+            file[offset as usize..end].copy_from_slice(&data);
+            Ok(ok(id))
+        }
+
+        async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+            self.attrs(id, &path)
+        }
+
+        async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+            self.attrs(id, &path)
+        }
+
+        async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs, Self::Error> {
+            let path = self.path(&handle)?;
+            self.attrs(id, &path)
+        }
+
+        async fn setstat(&mut self, id: u32, _path: String, _attrs: FileAttributes) -> Result<Status, Self::Error> {
+            Ok(ok(id))
+        }
+
+        async fn realpath(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+            Ok(Name { id, files: vec![File::dummy(path)] })
+        }
+
+        async fn remove(&mut self, id: u32, filename: String) -> Result<Status, Self::Error> {
+            self.note("remove");
+            self.files.lock().unwrap().remove(&filename).ok_or(StatusCode::NoSuchFile)?;
+            Ok(ok(id))
+        }
+
+        async fn rename(&mut self, id: u32, oldpath: String, newpath: String) -> Result<Status, Self::Error> {
+            self.note("rename");
+            let mut files = self.files.lock().unwrap();
+            if files.contains_key(&newpath) {
+                return Err(StatusCode::Failure);
+            }
+            let data = files.remove(&oldpath).ok_or(StatusCode::NoSuchFile)?;
+            files.insert(newpath, data);
+            Ok(ok(id))
+        }
+
+        async fn extended(&mut self, id: u32, request: String, data: Vec<u8>) -> Result<Packet, Self::Error> {
+            self.note(&request);
+            let mut data = data.as_slice();
+            let mut files = self.files.lock().unwrap();
+            match request.as_str() {
+                POSIX_RENAME => {
+                    let (oldpath, newpath) = (take_string(&mut data), take_string(&mut data));
+                    let moved = files.remove(&oldpath).ok_or(StatusCode::NoSuchFile)?;
+                    files.insert(newpath, moved);
+                }
+                COPY_DATA => {
+                    let from = self.path(&take_string(&mut data))?;
+                    let (offset, len) = (take_u64(&mut data), take_u64(&mut data));
+                    let to = self.path(&take_string(&mut data))?;
+                    assert_eq!((offset, len, take_u64(&mut data)), (0, 0, 0));
+                    let copied = files.get(&from).cloned().ok_or(StatusCode::NoSuchFile)?;
+                    files.insert(to, copied);
+                }
+                _ => return Err(StatusCode::OpUnsupported),
+            }
+            Ok(ok(id).into())
+        }
+    }
+
+    /// A client on fake servers sharing `files`, its extended-request one
+    /// offering `extensions`. Also returns the requests made.
+    async fn fake_client(files: &Files, extensions: &[&str]) -> (SftpClient, Arc<std::sync::Mutex<Vec<String>>>) {
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let server = |extensions: &[&str]| FakeServer {
+            files: files.clone(),
+            extensions: extensions.iter().map(|name| (name.to_string(), "1".to_string())).collect(),
+            requests: requests.clone(),
+            ..FakeServer::default()
+        };
+        let mut client = SftpClient::new();
+        let (stream, served) = tokio::io::duplex(64 * 1024);
+        russh_sftp::server::run(served, server(&[])).await;
+        client.session = Some(Arc::new(SftpSession::new(stream).await.unwrap()));
+        let (stream, served) = tokio::io::duplex(64 * 1024);
+        russh_sftp::server::run(served, server(extensions)).await;
+        client.extended = OnceCell::from(Some(Extended::start(stream).await.unwrap()));
+        (client, requests)
+    }
+
+    fn fake_files(files: &[(&str, &[u8])]) -> Files {
+        let files = files.iter().map(|(path, data)| (path.to_string(), data.to_vec())).collect();
+        Arc::new(std::sync::Mutex::new(files))
+    }
+
+    #[tokio::test]
+    async fn test_rename_over_file() {
+        for (extensions, expected) in [
+            (&[POSIX_RENAME][..], vec!["rename", POSIX_RENAME]),
+            (&[][..], vec!["rename", "remove", "rename"]),
+        ] {
+            let files = fake_files(&[("/a", b"new"), ("/b", b"old")]);
+            let (client, requests) = fake_client(&files, extensions).await;
+            assert!(client.rename("/a", "/b", false).await.is_err());
+            requests.lock().unwrap().clear();
+            client.rename("/a", "/b", true).await.unwrap();
+            assert_eq!(*requests.lock().unwrap(), expected);
+            assert_eq!(*files.lock().unwrap(), HashMap::from([("/b".to_string(), b"new".to_vec())]));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_extension_channel_opened_once() {
+        let files = fake_files(&[("/a", b"new"), ("/b", b"old")]);
+        let (stream, served) = tokio::io::duplex(64 * 1024);
+        russh_sftp::server::run(served, FakeServer { files: files.clone(), ..FakeServer::default() }).await;
+        let mut client = SftpClient::new();
+        client.session = Some(Arc::new(SftpSession::new(stream).await.unwrap()));
+        let opened = Arc::new(AtomicU64::new(0));
+        let counter = opened.clone();
+        client.set_extension_channel(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+            async { Err(anyhow::anyhow!("channel refused")) }
+        });
+
+        // Nothing is opened until an extension is wanted, and a failure
+        // isn't retried: renames fall back to plain requests
+        assert!(client.exists("/a").await.unwrap());
+        assert_eq!(opened.load(Ordering::Relaxed), 0);
+        client.rename("/a", "/b", true).await.unwrap();
+        client.rename("/b", "/c", true).await.unwrap();
+        assert_eq!(opened.load(Ordering::Relaxed), 1);
+        assert_eq!(files.lock().unwrap()["/c"], b"new");
+    }
+
+    #[tokio::test]
+    async fn test_write_bytes_swap() {
+        for (extensions, removes) in [(&[POSIX_RENAME][..], false), (&[][..], true)] {
+            let files = fake_files(&[("/etc/hosts", b"old")]);
+            let (client, requests) = fake_client(&files, extensions).await;
+            client.write_bytes("/etc/hosts", b"127.0.0.1 localhost\n").await.unwrap();
+            let hosts = files.lock().unwrap().clone();
+            assert_eq!(hosts, HashMap::from([("/etc/hosts".to_string(), b"127.0.0.1 localhost\n".to_vec())]));
+            let requests = requests.lock().unwrap();
+            assert_eq!(requests.contains(&"remove".to_string()), removes, "{:?}", requests);
+            assert_eq!(requests.contains(&POSIX_RENAME.to_string()), !removes);
+        }
+
+        // A new file takes a plain rename
+        let files = fake_files(&[]);
+        let (client, requests) = fake_client(&files, &[POSIX_RENAME]).await;
+        client.write_bytes("/notes.txt", b"hi").await.unwrap();
+        assert_eq!(files.lock().unwrap()["/notes.txt"], b"hi");
+        assert_eq!(requests.lock().unwrap().last().map(String::as_str), Some("rename"));
+    }
+
+    #[tokio::test]
+    async fn test_copy_on_server() {
+        let data: Vec<u8> = (0..CHUNK_SIZE + 10).map(|i| i as u8).collect();
+        for (extensions, on_server) in [(&[COPY_DATA][..], true), (&[][..], false)] {
+            let files = fake_files(&[("/src", &data), ("/dst", b"stale")]);
+            let (client, requests) = fake_client(&files, extensions).await;
+            let mut reports = Vec::new();
+            client.copy("/src", "/dst", |p| reports.push(p)).await.unwrap();
+            assert_eq!(files.lock().unwrap()["/dst"], data);
+            let requests = requests.lock().unwrap().clone();
+            assert_eq!(requests.contains(&COPY_DATA.to_string()), on_server);
+            assert_eq!(requests.contains(&"read".to_string()), !on_server);
+            let last = reports.last().unwrap();
+            assert_eq!((last.done, last.total), (data.len() as u64, data.len() as u64));
+
+            let error = client.copy("/src", "/src", |_| {}).await.unwrap_err();
+            assert_eq!(error.to_string(), "/src and /src are the same file");
+            assert_eq!(files.lock().unwrap()["/src"], data);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_copy_with_progress() {
+        let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        let mut copied = Vec::new();
+        let mut reports = Vec::new();
+        let limit = RateLimiter::new(0);
+        let n = copy_with_progress(&mut data.as_slice(), &mut copied, data.len() as u64, &limit, |p| reports.push(p))
+            .await
+            .unwrap();
+        assert_eq!(n, data.len() as u64);
+        assert_eq!(copied, data);
+        let last = reports.last().unwrap();
+        assert_eq!((last.done, last.total), (n, n));
+    }
+
+    #[test]
+    fn test_decode_content() {
+        let text = FileContent::decode("port = 22\n".as_bytes());
+        assert_eq!((text.encoding, text.bom, text.binary), (Some("utf-8"), false, false));
+        assert_eq!(text.content, "port = 22\n");
+
+        let bom = FileContent::decode(b"\xEF\xBB\xBFkey");
+        assert_eq!((bom.encoding, bom.bom, bom.content.as_str()), (Some("utf-8"), true, "key"));
+
+        let utf16 = FileContent::decode(b"\xFF\xFEh\0i\0");
+        assert_eq!((utf16.encoding, utf16.content.as_str(), utf16.size), (Some("utf-16le"), "hi", 6));
+
+        let binary = FileContent::decode(b"\x7fELF\0\x01");
+        assert_eq!((binary.encoding, binary.binary), (None, true));
+        assert_eq!(binary.content, "f0VMRgAB");
+        assert!(FileContent::decode(b"caf\xe9").binary);
+    }
+
+    #[test]
+    fn test_temp_path() {
+        let temp = temp_path("/etc/nginx/nginx.conf");
+        assert!(temp.starts_with("/etc/nginx/.nginx.conf.pier-") && temp.ends_with(".tmp"), "{}", temp);
+        assert!(temp_path("notes.txt").starts_with(".notes.txt.pier-"));
+    }
+
+    #[test]
+    fn test_local_attributes_round_trip() {
+        let path = std::env::temp_dir().join(format!("pier-attributes-{}", std::process::id()));
+        std::fs::write(&path, b"#!/bin/sh\n").unwrap();
+        let mut remote = FileAttributes::empty();
+        (remote.permissions, remote.mtime, remote.atime) = (Some(0o100750), Some(1_600_000_000), Some(1_600_000_100));
+        set_local_attributes(std::fs::File::options().write(true).open(&path).unwrap(), &remote).unwrap();
+
+        let local = local_attributes(&std::fs::metadata(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        #[cfg(unix)]
+        assert_eq!(local.permissions, Some(0o750));
+        assert_eq!((local.mtime, local.atime), (Some(1_600_000_000), Some(1_600_000_100)));
+    }
+
+    #[test]
+    fn test_dir_ancestors() {
+        assert_eq!(dir_ancestors("/srv/app//logs/"), vec!["/srv/app//logs", "/srv/app", "/srv"]);
+        assert_eq!(dir_ancestors("backups/2024"), vec!["backups/2024", "backups"]);
+        assert!(dir_ancestors("/").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let limit = RateLimiter::new(1_000_000);
+        assert_eq!(chunk_len(&[&limit, &RateLimiter::new(0)]), 100_000);
+        assert_eq!(chunk_len(&[&RateLimiter::new(1_000)]), MIN_LIMITED_CHUNK);
+        assert_eq!(chunk_len(&[]), CHUNK_SIZE);
+
+        let data = vec![0u8; 300_000];
+        let mut copied = Vec::new();
+        let started = Instant::now();
+        copy_with_progress(&mut data.as_slice(), &mut copied, 300_000, &limit, |_| {}).await.unwrap();
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(290) && elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn test_progress_rate() {
+        let halfway = progress(1000, 4000, Duration::from_secs(2));
+        assert_eq!((halfway.bytes_per_sec, halfway.eta_secs), (500, Some(6)));
+        let started = progress(0, 4000, Duration::ZERO);
+        assert_eq!((started.bytes_per_sec, started.eta_secs), (0, None));
+    }
+
+    #[test]
+    fn test_remote_entry() {
+        let attrs = FileAttributes { size: Some(4096), mtime: Some(1700000000), permissions: Some(0o40755), ..Default::default() };
+        let entry = remote_entry("/", "etc".to_string(), &attrs);
+        assert_eq!((entry.name.as_str(), entry.path.as_str(), entry.is_dir), ("etc", "/etc", true));
+        assert_eq!(remote_entry("/srv/", "etc".to_string(), &attrs).path, "/srv/etc");
+        assert_eq!(entry.modified, Some(1700000000));
+    }
 }
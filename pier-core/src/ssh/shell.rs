@@ -0,0 +1,241 @@
+//! Interactive remote shells for terminal sessions.
+//!
+//! A shell channel is split in two: one task forwards the channel's output
+//! to the session's reader thread, the other writes input, window changes
+//! and signals as the session queues them. Output goes through a bounded
+//! queue, so a session that stops reading (paused output) leaves data in the
+//! SSH window and the server stops sending, like a full PTY buffer.
+//!
+//! With a transcript, the channel's end is logged, and its input and
+//! output too if the transcript wants raw shell I/O.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use russh::client::Msg;
+use russh::{Channel, ChannelId, ChannelMsg, Sig};
+use tokio::sync::mpsc;
+
+use super::transcript::Transcript;
+use crate::terminal::pty::ExitStatus;
+
+/// Output chunks buffered between the channel and the reader thread.
+const OUTPUT_QUEUE_LEN: usize = 64;
+
+/// How long closing waits for the server before giving up on the channel.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Output of a remote shell; ends when the channel closes.
+pub type ShellOutput = mpsc::Receiver<Vec<u8>>;
+
+enum ShellRequest {
+    Data(Vec<u8>),
+    Resize(u16, u16),
+    Signal(Sig),
+    Eof,
+    Close,
+}
+
+/// The input side of a shell channel, and how the remote command ended.
+pub struct RemoteShell {
+    requests: mpsc::UnboundedSender<ShellRequest>,
+    reader: tokio::task::JoinHandle<()>,
+    exit_status: Arc<Mutex<Option<ExitStatus>>>,
+}
+
+impl RemoteShell {
+    /// Start pumping `channel` on `runtime`, logging to `transcript` if
+    /// given. Its output arrives on the returned queue.
+    pub fn start(
+        runtime: &tokio::runtime::Handle,
+        channel: Channel<Msg>,
+        transcript: Option<Arc<Transcript>>,
+    ) -> (Self, ShellOutput) {
+        let id = channel.id();
+        let (mut read_half, write_half) = channel.split();
+        let (output_tx, output_rx) = mpsc::channel(OUTPUT_QUEUE_LEN);
+        let (requests, mut request_rx) = mpsc::unbounded_channel();
+        let exit_status = Arc::new(Mutex::new(None));
+
+        let slot = exit_status.clone();
+        let output_transcript = transcript.clone();
+        let end = transcript.clone().map(|transcript| ChannelEnd { transcript, id, exit_status: exit_status.clone() });
+        let reader = runtime.spawn(async move {
+            // Logs the end when the channel closes or the shell is closed
+            let _end = end;
+            while let Some(msg) = read_half.wait().await {
+                let status = match msg {
+                    ChannelMsg::Data { data } | ChannelMsg::ExtendedData { data, .. } => {
+                        if let Some(transcript) = &output_transcript {
+                            transcript.shell_output(id, &data);
+                        }
+                        if output_tx.send(data.to_vec()).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    ChannelMsg::ExitStatus { exit_status } => ExitStatus::Exited { code: exit_status as i32 },
+                    ChannelMsg::ExitSignal { signal_name, .. } => {
+                        ExitStatus::Signaled { signal: signal_number(&signal_name) }
+                    }
+                    ChannelMsg::Close => break,
+                    _ => continue,
+                };
+                *slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(status);
+            }
+        });
+
+        runtime.spawn(async move {
+            while let Some(request) = request_rx.recv().await {
+                let result = match request {
+                    ShellRequest::Data(data) => {
+                        if let Some(transcript) = &transcript {
+                            transcript.shell_input(id, &data);
+                        }
+                        write_half.data(&data[..]).await
+                    }
+                    ShellRequest::Resize(cols, rows) => write_half.window_change(cols as u32, rows as u32, 0, 0).await,
+                    ShellRequest::Signal(signal) => write_half.signal(signal).await,
+                    ShellRequest::Eof => write_half.eof().await,
+                    ShellRequest::Close => break,
+                };
+                if let Err(e) = result {
+                    log::debug!("Remote shell input failed: {}", e);
+                    break;
+                }
+            }
+            let _ = tokio::time::timeout(CLOSE_TIMEOUT, write_half.close()).await;
+        });
+
+        (Self { requests, reader, exit_status }, output_rx)
+    }
+
+    fn send(&self, request: ShellRequest) -> Result<(), std::io::Error> {
+        self.requests
+            .send(request)
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "remote shell closed"))
+    }
+
+    /// Queue input for the remote shell.
+    pub fn write(&self, data: &[u8]) -> Result<(), std::io::Error> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.send(ShellRequest::Data(data.to_vec()))
+    }
+
+    /// End the input, after what was written so far; the remote side reads
+    /// end of file.
+    pub fn close_input(&self) -> Result<(), std::io::Error> {
+        self.send(ShellRequest::Eof)
+    }
+
+    /// Tell the server the terminal size changed.
+    pub fn resize(&self, cols: u16, rows: u16) -> Result<(), std::io::Error> {
+        self.send(ShellRequest::Resize(cols, rows))
+    }
+
+    /// Deliver a signal to the remote command. Servers may ignore it
+    /// (OpenSSH only honors signals since 7.9).
+    pub fn signal(&self, signal: i32) -> Result<(), std::io::Error> {
+        let signal = remote_signal(signal).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::Unsupported, "signal can't be sent over SSH")
+        })?;
+        self.send(ShellRequest::Signal(signal))
+    }
+
+    /// How the remote command ended, once the server reported it.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        *self.exit_status.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Close the channel. The output queue ends right away, without waiting
+    /// for the server.
+    pub fn close(&self) {
+        let _ = self.requests.send(ShellRequest::Close);
+        self.reader.abort();
+    }
+}
+
+impl Drop for RemoteShell {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Logs how a channel ended when dropped.
+struct ChannelEnd {
+    transcript: Arc<Transcript>,
+    id: ChannelId,
+    exit_status: Arc<Mutex<Option<ExitStatus>>>,
+}
+
+impl Drop for ChannelEnd {
+    fn drop(&mut self) {
+        let event = match *self.exit_status.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+            Some(ExitStatus::Exited { code }) => format!("channel {} exit {}", self.id, code),
+            Some(ExitStatus::Signaled { signal }) => format!("channel {} killed by signal {}", self.id, signal),
+            None => format!("channel {} closed", self.id),
+        };
+        self.transcript.record(&event);
+    }
+}
+
+/// The SSH name for a local signal number (RFC 4254 section 6.10).
+fn remote_signal(signal: i32) -> Option<Sig> {
+    Some(match signal {
+        libc::SIGABRT => Sig::ABRT,
+        libc::SIGALRM => Sig::ALRM,
+        libc::SIGFPE => Sig::FPE,
+        libc::SIGHUP => Sig::HUP,
+        libc::SIGILL => Sig::ILL,
+        libc::SIGINT => Sig::INT,
+        libc::SIGKILL => Sig::KILL,
+        libc::SIGPIPE => Sig::PIPE,
+        libc::SIGQUIT => Sig::QUIT,
+        libc::SIGSEGV => Sig::SEGV,
+        libc::SIGTERM => Sig::TERM,
+        libc::SIGUSR1 => Sig::USR1,
+        _ => return None,
+    })
+}
+
+/// Local number for a signal the server reported, 0 if unknown.
+pub(super) fn signal_number(signal: &Sig) -> i32 {
+    match signal {
+        Sig::ABRT => libc::SIGABRT,
+        Sig::ALRM => libc::SIGALRM,
+        Sig::FPE => libc::SIGFPE,
+        Sig::HUP => libc::SIGHUP,
+        Sig::ILL => libc::SIGILL,
+        Sig::INT => libc::SIGINT,
+        Sig::KILL => libc::SIGKILL,
+        Sig::PIPE => libc::SIGPIPE,
+        Sig::QUIT => libc::SIGQUIT,
+        Sig::SEGV => libc::SIGSEGV,
+        Sig::TERM => libc::SIGTERM,
+        Sig::USR1 => libc::SIGUSR1,
+        Sig::Custom(name) => match name.as_str() {
+            "USR2" => libc::SIGUSR2,
+            "TSTP" => libc::SIGTSTP,
+            "CONT" => libc::SIGCONT,
+            "WINCH" => libc::SIGWINCH,
+            _ => 0,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal_names() {
+        for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGKILL, libc::SIGUSR1] {
+            assert_eq!(signal_number(&remote_signal(signal).unwrap()), signal);
+        }
+        assert!(remote_signal(libc::SIGTSTP).is_none());
+        assert_eq!(signal_number(&Sig::Custom("WINCH".into())), libc::SIGWINCH);
+        assert_eq!(signal_number(&Sig::Custom("XYZ".into())), 0);
+    }
+}
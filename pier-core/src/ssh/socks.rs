@@ -0,0 +1,129 @@
+//! Minimal SOCKS5 server side (RFC 1928) for dynamic port forwarding.
+//!
+//! Only what `ssh -D` offers: no authentication and the CONNECT command.
+//! The requested address is passed to the server unresolved, so names are
+//! looked up on the remote side like with OpenSSH.
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub(super) const VERSION: u8 = 5;
+pub(super) const NO_AUTH: u8 = 0;
+pub(super) const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+pub(super) const CMD_CONNECT: u8 = 1;
+pub(super) const ATYP_IPV4: u8 = 1;
+pub(super) const ATYP_DOMAIN: u8 = 3;
+pub(super) const ATYP_IPV6: u8 = 4;
+
+/// Reply codes sent back to the client.
+pub const REPLY_SUCCEEDED: u8 = 0;
+pub const REPLY_GENERAL_FAILURE: u8 = 1;
+pub const REPLY_NOT_ALLOWED: u8 = 2;
+pub const REPLY_CONNECTION_REFUSED: u8 = 5;
+pub const REPLY_COMMAND_NOT_SUPPORTED: u8 = 7;
+pub const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 8;
+
+/// Negotiate with a SOCKS5 client and read its CONNECT request. Returns
+/// the host (name or address) and port to connect to; the caller answers
+/// with `reply`. Unsupported requests are refused here.
+pub async fn accept<S>(stream: &mut S) -> Result<(String, u16), anyhow::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await?;
+    if header[0] != VERSION {
+        return Err(anyhow::anyhow!("Not a SOCKS5 client (version {})", header[0]));
+    }
+    let mut methods = vec![0u8; header[1] as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&NO_AUTH) {
+        stream.write_all(&[VERSION, NO_ACCEPTABLE_METHOD]).await?;
+        return Err(anyhow::anyhow!("SOCKS client requires authentication"));
+    }
+    stream.write_all(&[VERSION, NO_AUTH]).await?;
+
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request).await?;
+    let [_, command, _, address_type] = request;
+    let host = match address_type {
+        ATYP_IPV4 => {
+            let mut octets = [0u8; 4];
+            stream.read_exact(&mut octets).await?;
+            std::net::Ipv4Addr::from(octets).to_string()
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            stream.read_exact(&mut octets).await?;
+            std::net::Ipv6Addr::from(octets).to_string()
+        }
+        ATYP_DOMAIN => {
+            let len = stream.read_u8().await? as usize;
+            let mut name = vec![0u8; len];
+            stream.read_exact(&mut name).await?;
+            String::from_utf8(name).map_err(|_| anyhow::anyhow!("Invalid SOCKS host name"))?
+        }
+        _ => {
+            reply(stream, REPLY_ADDRESS_NOT_SUPPORTED).await?;
+            return Err(anyhow::anyhow!("Unsupported SOCKS address type {}", address_type));
+        }
+    };
+    let port = stream.read_u16().await?;
+
+    if command != CMD_CONNECT {
+        reply(stream, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(anyhow::anyhow!("Unsupported SOCKS command {}", command));
+    }
+    Ok((host, port))
+}
+
+/// Answer a CONNECT request. The bound address isn't meaningful for a
+/// tunnel, so it's reported as 0.0.0.0:0 like OpenSSH does.
+pub async fn reply<S>(stream: &mut S, code: u8) -> Result<(), anyhow::Error>
+where
+    S: AsyncWrite + Unpin,
+{
+    stream.write_all(&[VERSION, code, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connect_request() {
+        let (mut client, mut server) = tokio::io::duplex(256);
+        let server = tokio::spawn(async move {
+            let target = accept(&mut server).await;
+            reply(&mut server, REPLY_SUCCEEDED).await.unwrap();
+            target
+        });
+
+        client.write_all(&[5, 2, 2, 0]).await.unwrap();
+        let mut choice = [0u8; 2];
+        client.read_exact(&mut choice).await.unwrap();
+        assert_eq!(choice, [5, 0]);
+
+        let mut request = vec![5, 1, 0, 3, 11];
+        request.extend_from_slice(b"example.com");
+        request.extend_from_slice(&443u16.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+        let mut answer = [0u8; 10];
+        client.read_exact(&mut answer).await.unwrap();
+        assert_eq!(answer[..2], [5, REPLY_SUCCEEDED]);
+
+        assert_eq!(server.await.unwrap().unwrap(), ("example.com".to_string(), 443));
+    }
+
+    #[tokio::test]
+    async fn test_refuses_auth_only_client() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let server = tokio::spawn(async move { accept(&mut server).await });
+        // Username/password only
+        client.write_all(&[5, 1, 2]).await.unwrap();
+        let mut choice = [0u8; 2];
+        client.read_exact(&mut choice).await.unwrap();
+        assert_eq!(choice, [5, NO_ACCEPTABLE_METHOD]);
+        assert!(server.await.unwrap().is_err());
+    }
+}
@@ -0,0 +1,405 @@
+//! Traffic statistics for a connection and its port forwards.
+//!
+//! The connection's bytes are counted on its socket, so they are what the
+//! link carries: encrypted, compressed, with SSH framing. A forward counts
+//! the payload it relays, per local port. Round-trip time comes from the
+//! keepalive, latency and probe pings; the last `RTT_HISTORY` of them are
+//! kept, unanswered ones included, for the app to chart.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Pings kept in the round-trip history.
+pub const RTT_HISTORY: usize = 120;
+
+/// Bytes each way.
+#[derive(Debug, Default)]
+pub struct Counters {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl Counters {
+    pub fn add_sent(&self, bytes: usize) {
+        self.sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_received(&self, bytes: usize) {
+        self.received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// A forward's relayed bytes and connections, and its health.
+#[derive(Debug, Default)]
+pub struct TunnelCounters {
+    pub bytes: Counters,
+    /// Connections relayed, finished ones included
+    connections: AtomicU64,
+    /// Connections being relayed
+    active: AtomicU64,
+    /// When the last connection came, in Unix milliseconds, 0 for never
+    last_connection_ms: AtomicU64,
+    /// Connections that couldn't be served, and listener failures
+    errors: AtomicU64,
+    last_error: Mutex<Option<String>>,
+    /// The last connection failed
+    failing: AtomicBool,
+    /// The listener failed and is being restarted
+    listener_down: AtomicBool,
+    /// Times the listener was restarted
+    restarts: AtomicU64,
+}
+
+impl TunnelCounters {
+    /// Count a connection for as long as the returned guard lives.
+    pub fn connection(self: &Arc<Self>) -> TunnelConnection {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.active.fetch_add(1, Ordering::Relaxed);
+        self.last_connection_ms.store(unix_ms(), Ordering::Relaxed);
+        TunnelConnection(self.clone())
+    }
+
+    /// Note how serving a connection went. Returns whether it is the first
+    /// failure since one was served.
+    pub fn served(&self, error: Option<String>) -> bool {
+        match error {
+            Some(error) => {
+                self.note_error(error);
+                !self.failing.swap(true, Ordering::Relaxed)
+            }
+            None => {
+                self.failing.store(false, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    /// The listener failed with `error`.
+    pub fn listener_failed(&self, error: String) {
+        self.listener_down.store(true, Ordering::Relaxed);
+        self.note_error(error);
+    }
+
+    /// The listener is back after failing.
+    pub fn listener_restarted(&self) {
+        self.listener_down.store(false, Ordering::Relaxed);
+        self.restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn note_error(&self, error: String) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(error);
+    }
+}
+
+/// A connection being relayed; see `TunnelCounters::connection`.
+pub struct TunnelConnection(Arc<TunnelCounters>);
+
+impl Drop for TunnelConnection {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// One connection's statistics, kept across reconnects.
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    pub transport: Arc<Counters>,
+    /// Channels opened, over all reconnects
+    channels_opened: AtomicU64,
+    /// Channels open on the current transport
+    channels_open: AtomicU64,
+    /// Last measured round trip in microseconds, 0 if none yet
+    rtt_us: AtomicU64,
+    /// The last pings, oldest first
+    rtt_history: Mutex<VecDeque<RttSample>>,
+    tunnels: Mutex<BTreeMap<u16, Arc<TunnelCounters>>>,
+}
+
+impl ConnectionStats {
+    pub fn channel_opened(&self) {
+        self.channels_opened.fetch_add(1, Ordering::Relaxed);
+        self.channels_open.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn channel_closed(&self) {
+        let _ = self.channels_open.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |open| open.checked_sub(1));
+    }
+
+    /// A new transport starts without channels.
+    pub fn transport_started(&self) {
+        self.channels_open.store(0, Ordering::Relaxed);
+    }
+
+    pub fn record_rtt(&self, rtt: Duration) {
+        self.rtt_us.store((rtt.as_micros() as u64).max(1), Ordering::Relaxed);
+        self.add_rtt_sample(Some(rtt.as_micros() as f64 / 1000.0));
+    }
+
+    /// Note a ping that went unanswered.
+    pub fn record_lost_ping(&self) {
+        self.add_rtt_sample(None);
+    }
+
+    fn add_rtt_sample(&self, rtt_ms: Option<f64>) {
+        let at_ms = unix_ms();
+        let mut history = self.rtt_history.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if history.len() == RTT_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(RttSample { at_ms, rtt_ms });
+    }
+
+    /// The round-trip history and its summary.
+    pub fn latency(&self) -> LatencyHistory {
+        let samples: Vec<RttSample> =
+            self.rtt_history.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter().copied().collect();
+        let answered: Vec<f64> = samples.iter().filter_map(|sample| sample.rtt_ms).collect();
+        let count = answered.len() as f64;
+        LatencyHistory {
+            min_ms: answered.iter().copied().reduce(f64::min),
+            max_ms: answered.iter().copied().reduce(f64::max),
+            avg_ms: (!answered.is_empty()).then(|| answered.iter().sum::<f64>() / count),
+            loss: if samples.is_empty() { 0.0 } else { 1.0 - count / samples.len() as f64 },
+            samples,
+        }
+    }
+
+    /// Start counting the forward on `local_port`.
+    pub fn add_tunnel(&self, local_port: u16) -> Arc<TunnelCounters> {
+        let counters = Arc::new(TunnelCounters::default());
+        self.tunnels().insert(local_port, counters.clone());
+        counters
+    }
+
+    pub fn remove_tunnel(&self, local_port: u16) {
+        self.tunnels().remove(&local_port);
+    }
+
+    fn tunnels(&self) -> std::sync::MutexGuard<'_, BTreeMap<u16, Arc<TunnelCounters>>> {
+        self.tunnels.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let rtt_us = self.rtt_us.load(Ordering::Relaxed);
+        StatsSnapshot {
+            bytes_sent: self.transport.sent.load(Ordering::Relaxed),
+            bytes_received: self.transport.received.load(Ordering::Relaxed),
+            channels_open: self.channels_open.load(Ordering::Relaxed),
+            channels_opened: self.channels_opened.load(Ordering::Relaxed),
+            rtt_ms: (rtt_us > 0).then(|| rtt_us as f64 / 1000.0),
+            tunnels: self
+                .tunnels()
+                .iter()
+                .map(|(&local_port, counters)| TunnelStats {
+                    local_port,
+                    bytes_sent: counters.bytes.sent.load(Ordering::Relaxed),
+                    bytes_received: counters.bytes.received.load(Ordering::Relaxed),
+                    connections: counters.connections.load(Ordering::Relaxed),
+                    active_connections: counters.active.load(Ordering::Relaxed),
+                    listening: !counters.listener_down.load(Ordering::Relaxed),
+                    last_connection_ms: Some(counters.last_connection_ms.load(Ordering::Relaxed))
+                        .filter(|ms| *ms > 0),
+                    errors: counters.errors.load(Ordering::Relaxed),
+                    last_error: counters.last_error.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone(),
+                    restarts: counters.restarts.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A connection's statistics at one moment.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct StatsSnapshot {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub channels_open: u64,
+    pub channels_opened: u64,
+    /// Last keepalive or probe round trip, if one was measured
+    pub rtt_ms: Option<f64>,
+    /// Active port forwards, dynamic ones included
+    pub tunnels: Vec<TunnelStats>,
+}
+
+/// One ping of the round-trip history.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+pub struct RttSample {
+    /// When it was sent, in Unix milliseconds
+    pub at_ms: u64,
+    /// `None` if it went unanswered
+    pub rtt_ms: Option<f64>,
+}
+
+/// The recent round trips of a connection.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct LatencyHistory {
+    /// Oldest first
+    pub samples: Vec<RttSample>,
+    pub min_ms: Option<f64>,
+    pub avg_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+    /// Share of the pings that went unanswered, from 0 to 1
+    pub loss: f64,
+}
+
+/// A port forward's traffic and health; sent is toward the server.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct TunnelStats {
+    pub local_port: u16,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub connections: u64,
+    pub active_connections: u64,
+    /// The local listener is up; false while it is being restarted
+    pub listening: bool,
+    /// When the last connection came, in Unix milliseconds
+    pub last_connection_ms: Option<u64>,
+    /// Connections that couldn't be served, and listener failures
+    pub errors: u64,
+    pub last_error: Option<String>,
+    /// Times the listener was restarted after failing
+    pub restarts: u64,
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// A stream counting the bytes through it.
+pub struct Counted<S> {
+    inner: S,
+    counters: Arc<Counters>,
+}
+
+impl<S> Counted<S> {
+    pub fn new(inner: S, counters: Arc<Counters>) -> Self {
+        Self { inner, counters }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            self.counters.add_received(buf.filled().len() - before);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.counters.add_sent(written);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_counted_stream() {
+        let (client, mut server) = tokio::io::duplex(64);
+        let stats = ConnectionStats::default();
+        let mut client = Counted::new(client, stats.transport.clone());
+        client.write_all(b"hello").await.unwrap();
+        server.write_all(b"hi").await.unwrap();
+        let mut reply = [0u8; 2];
+        client.read_exact(&mut reply).await.unwrap();
+
+        let tunnel = stats.add_tunnel(8080);
+        let connection = tunnel.connection();
+        tunnel.bytes.add_sent(3);
+        stats.channel_opened();
+        stats.channel_opened();
+        stats.channel_closed();
+
+        let snapshot = stats.snapshot();
+        assert_eq!((snapshot.bytes_sent, snapshot.bytes_received), (5, 2));
+        assert_eq!((snapshot.channels_open, snapshot.channels_opened), (1, 2));
+        assert_eq!(snapshot.rtt_ms, None);
+        let tunnel_stats = &snapshot.tunnels[0];
+        assert_eq!(
+            (tunnel_stats.local_port, tunnel_stats.bytes_sent, tunnel_stats.connections, tunnel_stats.active_connections),
+            (8080, 3, 1, 1)
+        );
+        assert!(tunnel_stats.last_connection_ms.is_some() && tunnel_stats.listening);
+        drop(connection);
+        assert_eq!(stats.snapshot().tunnels[0].active_connections, 0);
+        stats.remove_tunnel(8080);
+        assert!(stats.snapshot().tunnels.is_empty());
+    }
+
+    #[test]
+    fn test_tunnel_health() {
+        let stats = ConnectionStats::default();
+        let tunnel = stats.add_tunnel(2222);
+        let health = |stats: &ConnectionStats| {
+            let tunnel = stats.snapshot().tunnels[0].clone();
+            (tunnel.listening, tunnel.errors, tunnel.last_error, tunnel.restarts)
+        };
+        assert_eq!(health(&stats), (true, 0, None, 0));
+        assert_eq!(stats.snapshot().tunnels[0].last_connection_ms, None);
+
+        // Only the first failure after a served connection is news
+        assert!(!tunnel.served(None));
+        assert!(tunnel.served(Some("refused".to_string())));
+        assert!(!tunnel.served(Some("refused".to_string())));
+        assert!(!tunnel.served(None));
+        assert!(!tunnel.served(None));
+        assert!(tunnel.served(Some("refused again".to_string())));
+        assert_eq!(health(&stats), (true, 3, Some("refused again".to_string()), 0));
+
+        tunnel.listener_failed("bad file descriptor".to_string());
+        assert_eq!(health(&stats), (false, 4, Some("bad file descriptor".to_string()), 0));
+        tunnel.listener_restarted();
+        assert_eq!(health(&stats), (true, 4, Some("bad file descriptor".to_string()), 1));
+        tunnel.listener_failed("bad file descriptor".to_string());
+        tunnel.listener_restarted();
+        assert_eq!(health(&stats).3, 2);
+    }
+
+    #[test]
+    fn test_latency_history() {
+        let stats = ConnectionStats::default();
+        assert_eq!(stats.latency().avg_ms, None);
+        stats.record_rtt(Duration::from_millis(20));
+        stats.record_lost_ping();
+        stats.record_rtt(Duration::from_millis(40));
+        stats.record_lost_ping();
+        let latency = stats.latency();
+        let rtts: Vec<Option<f64>> = latency.samples.iter().map(|sample| sample.rtt_ms).collect();
+        assert_eq!(rtts, vec![Some(20.0), None, Some(40.0), None]);
+        assert_eq!((latency.min_ms, latency.avg_ms, latency.max_ms), (Some(20.0), Some(30.0), Some(40.0)));
+        assert_eq!(latency.loss, 0.5);
+        assert_eq!(stats.snapshot().rtt_ms, Some(40.0));
+
+        for _ in 0..RTT_HISTORY {
+            stats.record_rtt(Duration::from_millis(5));
+        }
+        let latency = stats.latency();
+        assert_eq!(latency.samples.len(), RTT_HISTORY);
+        assert_eq!((latency.max_ms, latency.loss), (Some(5.0), 0.0));
+    }
+}
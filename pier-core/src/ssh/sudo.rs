@@ -0,0 +1,219 @@
+//! Commands run with sudo, answering its password prompt.
+//!
+//! The command runs as `sudo -S -p <prompt> -- sh -c <command>`: `-S` has
+//! sudo read the password from stdin instead of a terminal, and the prompt
+//! is a random marker, so it is recognized exactly and removed from the
+//! output. The password is sent once; sudo asking again means it was
+//! wrong, and the command is stopped rather than left waiting. A second
+//! marker, printed by the shell sudo starts, tells that elevation worked,
+//! whatever the command's own exit code.
+
+use russh::client::Msg;
+use russh::{Channel, ChannelMsg};
+
+/// How a sudo command went.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct SudoOutput {
+    /// -1 if the server didn't report one
+    pub exit_code: i32,
+    /// Stdout and stderr, without the prompt and marker
+    pub output: String,
+    /// sudo let the command run
+    pub elevated: bool,
+    /// Why elevation failed, e.g. a wrong password or not being in the
+    /// sudoers file
+    pub error: Option<String>,
+}
+
+/// The markers of one sudo command.
+pub(super) struct SudoRun {
+    prompt: String,
+    granted: String,
+}
+
+impl SudoRun {
+    pub fn new() -> Result<Self, anyhow::Error> {
+        use ring::rand::SecureRandom;
+
+        let mut nonce = [0u8; 8];
+        ring::rand::SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow::anyhow!("RNG failed"))?;
+        let nonce = data_encoding::HEXLOWER.encode(&nonce);
+        Ok(Self { prompt: format!("[pier-sudo-{}]", nonce), granted: format!("pier-sudo-granted-{}", nonce) })
+    }
+
+    /// `command` wrapped to run through sudo.
+    pub fn command(&self, command: &str) -> String {
+        let script = format!("echo {} >&2; {}", self.granted, command);
+        format!("sudo -S -p {} -- sh -c {}", quote(&self.prompt), quote(&script))
+    }
+
+    /// Output without the markers.
+    fn strip(&self, output: &[u8]) -> String {
+        String::from_utf8_lossy(output)
+            .replace(&format!("{}\n", self.granted), "")
+            .replace(&self.prompt, "")
+            .trim()
+            .to_string()
+    }
+}
+
+/// `text` as one single-quoted shell word.
+pub(super) fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// Finds the markers in output as it grows, including ones split across
+/// chunks.
+struct Scanner<'a> {
+    run: &'a SudoRun,
+    /// Where the next search for the prompt starts
+    prompt_from: usize,
+    /// Where the next search for the granted marker starts
+    granted_from: usize,
+}
+
+impl Scanner<'_> {
+    /// New prompts in `output` since the last call, and whether the
+    /// granted marker showed up.
+    fn scan(&mut self, output: &[u8]) -> (usize, bool) {
+        let prompts = occurrences(output, self.run.prompt.as_bytes(), &mut self.prompt_from);
+        let granted = occurrences(output, self.run.granted.as_bytes(), &mut self.granted_from) > 0;
+        (prompts, granted)
+    }
+}
+
+/// Count `needle` in `haystack` from `*from`, then move `from` past the
+/// last match, keeping the tail a match split across chunks may start in.
+fn occurrences(haystack: &[u8], needle: &[u8], from: &mut usize) -> usize {
+    let start = (*from).min(haystack.len());
+    let mut count = 0;
+    let mut next = haystack.len().saturating_sub(needle.len() - 1).max(start);
+    for (offset, window) in haystack[start..].windows(needle.len()).enumerate() {
+        if window == needle {
+            count += 1;
+            next = next.max(start + offset + needle.len());
+        }
+    }
+    *from = next;
+    count
+}
+
+/// Read the output of a command started with `run.command`, answering
+/// sudo's prompt with `password`, until the command exits.
+pub(super) async fn collect(
+    channel: &mut Channel<Msg>,
+    run: &SudoRun,
+    password: &str,
+) -> Result<SudoOutput, anyhow::Error> {
+    let mut output = Vec::new();
+    let mut scanner = Scanner { run, prompt_from: 0, granted_from: 0 };
+    let mut prompts = 0;
+    let mut elevated = false;
+    let mut wrong_password = false;
+    let mut input_closed = false;
+    let mut exit_code: i32 = -1;
+    let mut got_eof = false;
+
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Data { data } | ChannelMsg::ExtendedData { data, .. } => {
+                output.extend_from_slice(&data);
+                let (new_prompts, granted) = scanner.scan(&output);
+                elevated |= granted;
+                if new_prompts > 0 && prompts == 0 && !input_closed {
+                    let mut line = format!("{}\n", password).into_bytes();
+                    let sent = channel.data(&line[..]).await;
+                    line.fill(0);
+                    sent?;
+                }
+                prompts += new_prompts;
+                if prompts > 1 {
+                    // Asked again: the password was wrong
+                    wrong_password = true;
+                    let _ = channel.close().await;
+                    break;
+                }
+                if (prompts == 1 || elevated) && !input_closed {
+                    // Nothing else to read for sudo, nor for the command
+                    channel.eof().await?;
+                    input_closed = true;
+                }
+            }
+            ChannelMsg::ExitStatus { exit_status } => {
+                exit_code = exit_status as i32;
+                if got_eof {
+                    break;
+                }
+            }
+            ChannelMsg::Eof => {
+                got_eof = true;
+                if exit_code != -1 {
+                    break;
+                }
+            }
+            ChannelMsg::Close => break,
+            _ => {}
+        }
+    }
+
+    let output = run.strip(&output);
+    let error = if wrong_password {
+        Some("Incorrect sudo password".to_string())
+    } else if !elevated {
+        Some(
+            output
+                .lines()
+                .find(|line| line.starts_with("sudo:"))
+                .unwrap_or("sudo failed")
+                .to_string(),
+        )
+    } else {
+        None
+    };
+    Ok(SudoOutput { exit_code, output, elevated, error })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        let run = SudoRun { prompt: "[p]".to_string(), granted: "ok".to_string() };
+        assert_eq!(
+            run.command("systemctl restart 'my app'"),
+            r#"sudo -S -p '[p]' -- sh -c 'echo ok >&2; systemctl restart '\''my app'\'''"#
+        );
+    }
+
+    #[test]
+    fn test_scan_split_markers() {
+        let run = SudoRun { prompt: "[pier-sudo-1]".to_string(), granted: "pier-sudo-granted-1".to_string() };
+        let mut scanner = Scanner { run: &run, prompt_from: 0, granted_from: 0 };
+        let mut output = b"[pier-sud".to_vec();
+        assert_eq!(scanner.scan(&output), (0, false));
+        output.extend_from_slice(b"o-1]pier-sudo-gra");
+        assert_eq!(scanner.scan(&output), (1, false));
+        output.extend_from_slice(b"nted-1\nactive\n");
+        assert_eq!(scanner.scan(&output), (0, true));
+        assert_eq!(run.strip(&output), "active");
+    }
+
+    #[test]
+    fn test_scan_prompt_then_granted() {
+        // sudo -S prints the prompt and waits, so it ends the output
+        let run = SudoRun {
+            prompt: "[pier-sudo-0123456789abcdef]".to_string(),
+            granted: "pier-sudo-granted-0123456789abcdef".to_string(),
+        };
+        let mut scanner = Scanner { run: &run, prompt_from: 0, granted_from: 0 };
+        let mut output = b"[pier-sudo-0123456789abcdef]".to_vec();
+        assert_eq!(scanner.scan(&output), (1, false));
+        output.extend_from_slice(b"\npier-sudo-granted-0123456789abcdef\n");
+        assert_eq!(scanner.scan(&output), (0, true));
+        output.extend_from_slice(b"done\n");
+        assert_eq!(scanner.scan(&output), (0, false));
+    }
+}
@@ -0,0 +1,187 @@
+//! Session transcripts: a log of what ran on a server.
+//!
+//! One line per event, `2026-01-31T12:00:00.000Z user@host:22 <event>`:
+//! connects and disconnects, commands with their exit codes, shells opening
+//! and closing. Raw shell input and output are only written when asked
+//! for, since they can hold passwords typed at prompts. When the file
+//! grows past its limit it moves to `<path>.1` (the older ones to `.2`,
+//! `.3`, ...) and a new one starts.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where and how much to log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TranscriptOptions {
+    pub path: PathBuf,
+    /// Size at which the file is rotated, 0 for never
+    pub max_bytes: u64,
+    /// Rotated files kept besides the current one
+    pub keep: u32,
+    /// Also log what is typed into shells and what they print
+    pub shell_io: bool,
+}
+
+/// An open transcript, shared by a session and its shells.
+#[derive(Debug)]
+pub struct Transcript {
+    options: TranscriptOptions,
+    /// Who the events are about, e.g. `root@db1:22`
+    label: String,
+    file: Mutex<TranscriptFile>,
+}
+
+#[derive(Debug)]
+struct TranscriptFile {
+    file: File,
+    /// Bytes in the current file
+    len: u64,
+}
+
+impl Transcript {
+    /// Open `options.path` for appending, creating it if needed.
+    pub fn open(options: TranscriptOptions, label: impl Into<String>) -> std::io::Result<Self> {
+        if let Some(parent) = options.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = append(&options.path)?;
+        let len = file.metadata()?.len();
+        Ok(Self { options, label: label.into(), file: Mutex::new(TranscriptFile { file, len }) })
+    }
+
+    pub fn options(&self) -> &TranscriptOptions {
+        &self.options
+    }
+
+    /// Log `event`. Failures are only reported to the app log: a full disk
+    /// shouldn't break the session.
+    pub fn record(&self, event: &str) {
+        let line = format!("{} {} {}\n", timestamp(SystemTime::now()), self.label, event);
+        let mut current = self.file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if self.options.max_bytes > 0 && current.len > 0 && current.len + line.len() as u64 > self.options.max_bytes {
+            match self.rotate() {
+                Ok(file) => *current = TranscriptFile { file, len: 0 },
+                Err(e) => log::warn!("Transcript rotation failed for {}: {}", self.options.path.display(), e),
+            }
+        }
+        match current.file.write_all(line.as_bytes()) {
+            Ok(()) => current.len += line.len() as u64,
+            Err(e) => log::warn!("Transcript write failed for {}: {}", self.options.path.display(), e),
+        }
+    }
+
+    /// Log shell input, if raw shell I/O is wanted.
+    pub fn shell_input(&self, channel: impl std::fmt::Display, data: &[u8]) {
+        if self.options.shell_io {
+            self.record(&format!("channel {} input \"{}\"", channel, escape(data)));
+        }
+    }
+
+    /// Log shell output, if raw shell I/O is wanted.
+    pub fn shell_output(&self, channel: impl std::fmt::Display, data: &[u8]) {
+        if self.options.shell_io {
+            self.record(&format!("channel {} output \"{}\"", channel, escape(data)));
+        }
+    }
+
+    /// Shift the rotated files up by one, dropping the oldest, and start a
+    /// new file.
+    fn rotate(&self) -> std::io::Result<File> {
+        let path = &self.options.path;
+        if self.options.keep == 0 {
+            return File::create(path);
+        }
+        let _ = std::fs::remove_file(rotated(path, self.options.keep));
+        for n in (1..self.options.keep).rev() {
+            let from = rotated(path, n);
+            if from.exists() {
+                std::fs::rename(&from, rotated(path, n + 1))?;
+            }
+        }
+        std::fs::rename(path, rotated(path, 1))?;
+        append(path)
+    }
+}
+
+fn append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// `path` with `.n` appended.
+fn rotated(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Bytes as printable text, control characters and quotes escaped.
+fn escape(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).escape_debug().to_string()
+}
+
+/// `time` as UTC in RFC 3339 form, to the millisecond.
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+        assert_eq!(timestamp(time), "2024-02-29T12:34:56.789Z");
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("pier-transcript-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("session.log");
+        let options = TranscriptOptions { path: path.clone(), max_bytes: 50, keep: 2, shell_io: false };
+        let transcript = Transcript::open(options, "root@db1:22").unwrap();
+
+        transcript.shell_input(0, b"secret\r");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        for n in 0..4 {
+            transcript.record(&format!("exec {}", n));
+        }
+        let current = std::fs::read_to_string(&path).unwrap();
+        assert!(current.ends_with(" root@db1:22 exec 3\n"), "{}", current);
+        assert!(std::fs::read_to_string(rotated(&path, 1)).unwrap().contains("exec 2"));
+        assert!(std::fs::read_to_string(rotated(&path, 2)).unwrap().contains("exec 1"));
+        assert!(!rotated(&path, 3).exists());
+        assert_eq!(escape(b"ls \"a\"\r\n"), "ls \\\"a\\\"\\r\\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,513 @@
+//! Queued SFTP uploads and downloads, for the Transfers panel.
+//!
+//! Transfers start in queue order, at most `max_concurrent` at a time.
+//! They share the SFTP session the manager was made with, unless more
+//! channels are opened for it: each transfer then takes the channel with
+//! the fewest running, which pays off for many small files on a slow
+//! link, where one channel spends most of its time waiting. A paused or
+//! failed transfer keeps the bytes it has moved and carries on from there
+//! when resumed; a cancelled one leaves its partial file behind.
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use russh_sftp::client::SftpSession;
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use super::session::SshSession;
+use super::sftp::{self, RateLimiter, SftpClient, TransferProgress};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferState {
+    Queued,
+    Running,
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// A transfer, as shown in the queue.
+#[derive(Clone, Debug, Serialize)]
+pub struct TransferInfo {
+    pub id: u64,
+    pub direction: TransferDirection,
+    pub local_path: String,
+    pub remote_path: String,
+    pub state: TransferState,
+    pub done: u64,
+    /// File size, 0 until the transfer has started
+    pub total: u64,
+    pub bytes_per_sec: u64,
+    pub eta_secs: Option<u64>,
+    /// Bytes per second this transfer is held to, 0 for no limit
+    pub rate_limit: u64,
+    /// Why the transfer failed
+    pub error: Option<String>,
+}
+
+struct Entry {
+    info: TransferInfo,
+    /// Stops the transfer while it runs
+    stop: Option<oneshot::Sender<()>>,
+    limit: Arc<RateLimiter>,
+}
+
+/// The transfers and their states, in queue order.
+struct Queue {
+    entries: Vec<Entry>,
+    next_id: u64,
+    max_concurrent: usize,
+    /// Rate limit new transfers start with
+    rate_limit: u64,
+}
+
+impl Queue {
+    fn new(max_concurrent: usize) -> Self {
+        Self { entries: Vec::new(), next_id: 1, max_concurrent: max_concurrent.max(1), rate_limit: 0 }
+    }
+
+    fn add(&mut self, direction: TransferDirection, local_path: &str, remote_path: &str) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push(Entry {
+            info: TransferInfo {
+                id,
+                direction,
+                local_path: local_path.to_string(),
+                remote_path: remote_path.to_string(),
+                state: TransferState::Queued,
+                done: 0,
+                total: 0,
+                bytes_per_sec: 0,
+                eta_secs: None,
+                rate_limit: self.rate_limit,
+                error: None,
+            },
+            stop: None,
+            limit: Arc::new(RateLimiter::new(self.rate_limit)),
+        });
+        id
+    }
+
+    fn entry(&mut self, id: u64) -> Result<&mut Entry, anyhow::Error> {
+        self.entries
+            .iter_mut()
+            .find(|entry| entry.info.id == id)
+            .ok_or_else(|| anyhow::anyhow!("No transfer {}", id))
+    }
+
+    /// Mark the transfers that fit under the limit as running, and return
+    /// them with their rate limits and what stops each.
+    fn start_next(&mut self) -> Vec<(TransferInfo, Arc<RateLimiter>, oneshot::Receiver<()>)> {
+        let running = self.entries.iter().filter(|entry| entry.info.state == TransferState::Running).count();
+        let free = self.max_concurrent.saturating_sub(running);
+        self.entries
+            .iter_mut()
+            .filter(|entry| entry.info.state == TransferState::Queued)
+            .take(free)
+            .map(|entry| {
+                let (stop, stopped) = oneshot::channel();
+                entry.info.state = TransferState::Running;
+                entry.stop = Some(stop);
+                (entry.info.clone(), entry.limit.clone(), stopped)
+            })
+            .collect()
+    }
+
+    fn pause(&mut self, id: u64) -> Result<(), anyhow::Error> {
+        let entry = self.entry(id)?;
+        match entry.info.state {
+            TransferState::Queued | TransferState::Running => {
+                entry.info.state = TransferState::Paused;
+                entry.info.bytes_per_sec = 0;
+                entry.info.eta_secs = None;
+                if let Some(stop) = entry.stop.take() {
+                    let _ = stop.send(());
+                }
+                Ok(())
+            }
+            state => Err(anyhow::anyhow!("Can't pause a transfer that is {:?}", state)),
+        }
+    }
+
+    /// Queue a paused or failed transfer again.
+    fn resume(&mut self, id: u64) -> Result<(), anyhow::Error> {
+        let entry = self.entry(id)?;
+        match entry.info.state {
+            TransferState::Paused | TransferState::Failed => {
+                entry.info.state = TransferState::Queued;
+                entry.info.error = None;
+                Ok(())
+            }
+            state => Err(anyhow::anyhow!("Can't resume a transfer that is {:?}", state)),
+        }
+    }
+
+    fn cancel(&mut self, id: u64) -> Result<(), anyhow::Error> {
+        let entry = self.entry(id)?;
+        match entry.info.state {
+            TransferState::Completed | TransferState::Cancelled => Ok(()),
+            _ => {
+                entry.info.state = TransferState::Cancelled;
+                entry.info.bytes_per_sec = 0;
+                entry.info.eta_secs = None;
+                if let Some(stop) = entry.stop.take() {
+                    let _ = stop.send(());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Move a transfer to `index` in the queue, or the end if past it.
+    fn move_to(&mut self, id: u64, index: usize) -> Result<(), anyhow::Error> {
+        let from = self
+            .entries
+            .iter()
+            .position(|entry| entry.info.id == id)
+            .ok_or_else(|| anyhow::anyhow!("No transfer {}", id))?;
+        let entry = self.entries.remove(from);
+        self.entries.insert(index.min(self.entries.len()), entry);
+        Ok(())
+    }
+
+    /// Hold a transfer to `bytes_per_sec`, 0 for no limit, from now on.
+    fn set_rate_limit(&mut self, id: u64, bytes_per_sec: u64) -> Result<(), anyhow::Error> {
+        let entry = self.entry(id)?;
+        entry.info.rate_limit = bytes_per_sec;
+        entry.limit.set(bytes_per_sec);
+        Ok(())
+    }
+
+    fn cancel_all(&mut self) {
+        let ids: Vec<u64> = self.entries.iter().map(|entry| entry.info.id).collect();
+        for id in ids {
+            let _ = self.cancel(id);
+        }
+    }
+
+    /// Forget completed and cancelled transfers.
+    fn clear_finished(&mut self) {
+        self.entries.retain(|entry| {
+            !matches!(entry.info.state, TransferState::Completed | TransferState::Cancelled)
+        });
+    }
+
+    fn progress(&mut self, id: u64, progress: TransferProgress) {
+        if let Ok(entry) = self.entry(id) {
+            if entry.info.state == TransferState::Running {
+                entry.info.done = progress.done;
+                entry.info.total = progress.total;
+                entry.info.bytes_per_sec = progress.bytes_per_sec;
+                entry.info.eta_secs = progress.eta_secs;
+            }
+        }
+    }
+
+    /// Record how a running transfer ended. Paused and cancelled ones have
+    /// been dealt with already.
+    fn finished(&mut self, id: u64, result: Result<(), String>) {
+        if let Ok(entry) = self.entry(id) {
+            if entry.info.state != TransferState::Running {
+                return;
+            }
+            entry.stop = None;
+            entry.info.eta_secs = None;
+            match result {
+                Ok(()) => {
+                    entry.info.state = TransferState::Completed;
+                    entry.info.done = entry.info.total;
+                }
+                Err(e) => {
+                    entry.info.state = TransferState::Failed;
+                    entry.info.bytes_per_sec = 0;
+                    entry.info.error = Some(e);
+                }
+            }
+        }
+    }
+}
+
+/// Channels transfers are spread across, with how many each is running.
+struct Channels<T> {
+    channels: Mutex<Vec<(Arc<T>, usize)>>,
+}
+
+impl<T> Channels<T> {
+    fn new(first: Arc<T>) -> Self {
+        Self { channels: Mutex::new(vec![(first, 0)]) }
+    }
+
+    fn add(&self, channel: Arc<T>) {
+        self.lock().push((channel, 0));
+    }
+
+    fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// The least busy channel, counted busier until the lease is dropped.
+    fn lease(&self) -> ChannelLease<'_, T> {
+        let mut channels = self.lock();
+        let index = (0..channels.len()).min_by_key(|&index| channels[index].1).unwrap_or(0);
+        channels[index].1 += 1;
+        ChannelLease { channels: self, index, channel: channels[index].0.clone() }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<(Arc<T>, usize)>> {
+        self.channels.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+struct ChannelLease<'a, T> {
+    channels: &'a Channels<T>,
+    index: usize,
+    channel: Arc<T>,
+}
+
+impl<T> Drop for ChannelLease<'_, T> {
+    fn drop(&mut self) {
+        self.channels.lock()[self.index].1 -= 1;
+    }
+}
+
+struct Shared {
+    sftp: Channels<SftpSession>,
+    preserve_attributes: bool,
+    queue: Mutex<Queue>,
+    runtime: tokio::runtime::Handle,
+}
+
+impl Shared {
+    fn queue(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Runs queued transfers over an SFTP session. Dropping it cancels the
+/// transfers still running.
+pub struct TransferManager {
+    shared: Arc<Shared>,
+}
+
+impl TransferManager {
+    /// A manager running up to `max_concurrent` transfers on `runtime`.
+    /// Transfers keep mode bits and times if `sftp` was set to, and start
+    /// with its transfer rate limit.
+    pub fn new(
+        sftp: &SftpClient,
+        runtime: &tokio::runtime::Handle,
+        max_concurrent: usize,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            shared: Arc::new(Shared {
+                sftp: Channels::new(sftp.shared_session()?),
+                preserve_attributes: sftp.preserves_attributes(),
+                queue: Mutex::new(Queue { rate_limit: sftp.transfer_rate_limit(), ..Queue::new(max_concurrent) }),
+                runtime: runtime.clone(),
+            }),
+        })
+    }
+
+    /// Open `count` more SFTP channels on `session`, the connection the
+    /// manager's SFTP session runs on, to spread transfers across.
+    /// Returns how many channels there are now.
+    pub async fn open_channels(&self, session: &SshSession, count: usize) -> Result<usize, anyhow::Error> {
+        for _ in 0..count {
+            let mut sftp = SftpClient::new();
+            sftp.init(session.sftp_channel().await?).await?;
+            self.shared.sftp.add(sftp.shared_session()?);
+        }
+        Ok(self.shared.sftp.len())
+    }
+
+    /// Queue a download, and return its id.
+    pub fn download(&self, remote_path: &str, local_path: &str) -> u64 {
+        let id = self.shared.queue().add(TransferDirection::Download, local_path, remote_path);
+        schedule(&self.shared);
+        id
+    }
+
+    /// Queue an upload, and return its id.
+    pub fn upload(&self, local_path: &str, remote_path: &str) -> u64 {
+        let id = self.shared.queue().add(TransferDirection::Upload, local_path, remote_path);
+        schedule(&self.shared);
+        id
+    }
+
+    /// Pause a queued or running transfer.
+    pub fn pause(&self, id: u64) -> Result<(), anyhow::Error> {
+        self.shared.queue().pause(id)?;
+        schedule(&self.shared);
+        Ok(())
+    }
+
+    /// Queue a paused or failed transfer again, to carry on where it
+    /// stopped.
+    pub fn resume(&self, id: u64) -> Result<(), anyhow::Error> {
+        self.shared.queue().resume(id)?;
+        schedule(&self.shared);
+        Ok(())
+    }
+
+    pub fn cancel(&self, id: u64) -> Result<(), anyhow::Error> {
+        self.shared.queue().cancel(id)?;
+        schedule(&self.shared);
+        Ok(())
+    }
+
+    /// Move a transfer to `index` in the queue; queued transfers start in
+    /// queue order.
+    pub fn move_to(&self, id: u64, index: usize) -> Result<(), anyhow::Error> {
+        self.shared.queue().move_to(id, index)
+    }
+
+    /// Change how many transfers run at once. Running transfers over the
+    /// new limit finish.
+    pub fn set_max_concurrent(&self, max_concurrent: usize) {
+        self.shared.queue().max_concurrent = max_concurrent.max(1);
+        schedule(&self.shared);
+    }
+
+    /// Hold a transfer to `bytes_per_sec`, 0 for no limit, taking effect
+    /// at once if it's running.
+    pub fn set_rate_limit(&self, id: u64, bytes_per_sec: u64) -> Result<(), anyhow::Error> {
+        self.shared.queue().set_rate_limit(id, bytes_per_sec)
+    }
+
+    /// All transfers, in queue order.
+    pub fn list(&self) -> Vec<TransferInfo> {
+        self.shared.queue().entries.iter().map(|entry| entry.info.clone()).collect()
+    }
+
+    /// Forget completed and cancelled transfers.
+    pub fn clear_finished(&self) {
+        self.shared.queue().clear_finished();
+    }
+}
+
+impl Drop for TransferManager {
+    fn drop(&mut self) {
+        self.shared.queue().cancel_all();
+    }
+}
+
+/// Start the queued transfers there's room for.
+fn schedule(shared: &Arc<Shared>) {
+    let started = shared.queue().start_next();
+    for (info, limit, stopped) in started {
+        let task_shared = shared.clone();
+        shared.runtime.spawn(async move {
+            let shared = task_shared;
+            let result = tokio::select! {
+                result = run(&shared, &info, &limit) => result,
+                // Paused or cancelled
+                _ = stopped => return,
+            };
+            if let Err(e) = &result {
+                log::warn!("Transfer {} failed: {}", info.id, e);
+            }
+            shared.queue().finished(info.id, result.map_err(|e| e.to_string()));
+            schedule(&shared);
+        });
+    }
+}
+
+async fn run(shared: &Shared, info: &TransferInfo, limit: &RateLimiter) -> Result<(), anyhow::Error> {
+    let on_progress = |progress| shared.queue().progress(info.id, progress);
+    let (local, remote) = (Path::new(&info.local_path), info.remote_path.as_str());
+    let preserve = shared.preserve_attributes;
+    let channel = shared.sftp.lease();
+    match info.direction {
+        TransferDirection::Download => {
+            sftp::download_from(&channel.channel, remote, local, info.done, preserve, limit, on_progress).await
+        }
+        TransferDirection::Upload => {
+            sftp::upload_from(&channel.channel, local, remote, info.done, preserve, limit, on_progress).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn states(queue: &Queue) -> Vec<(u64, TransferState)> {
+        queue.entries.iter().map(|entry| (entry.info.id, entry.info.state)).collect()
+    }
+
+    #[test]
+    fn test_queue_concurrency_and_order() {
+        let mut queue = Queue::new(2);
+        for name in ["a", "b", "c"] {
+            queue.add(TransferDirection::Download, name, name);
+        }
+        queue.move_to(3, 0).unwrap();
+        let started: Vec<u64> = queue.start_next().into_iter().map(|(info, _, _)| info.id).collect();
+        assert_eq!(started, vec![3, 1]);
+        assert!(queue.start_next().is_empty());
+
+        queue.finished(3, Ok(()));
+        let started: Vec<u64> = queue.start_next().into_iter().map(|(info, _, _)| info.id).collect();
+        assert_eq!(started, vec![2]);
+        queue.finished(2, Err("No such file".to_string()));
+        assert_eq!(
+            states(&queue),
+            vec![(3, TransferState::Completed), (1, TransferState::Running), (2, TransferState::Failed)]
+        );
+        queue.clear_finished();
+        assert_eq!(states(&queue), vec![(1, TransferState::Running), (2, TransferState::Failed)]);
+    }
+
+    #[test]
+    fn test_channels_least_busy() {
+        let channels = Channels::new(Arc::new("first"));
+        channels.add(Arc::new("second"));
+        let a = channels.lease();
+        let b = channels.lease();
+        assert_eq!((*a.channel, *b.channel), ("first", "second"));
+        drop(a);
+        let c = channels.lease();
+        assert_eq!(*c.channel, "first");
+        let d = channels.lease();
+        assert_eq!(channels.lock().iter().map(|(_, running)| *running).collect::<Vec<_>>(), vec![2, 1]);
+        drop((b, c, d));
+        assert_eq!(channels.lock().iter().map(|(_, running)| *running).collect::<Vec<_>>(), vec![0, 0]);
+    }
+
+    #[test]
+    fn test_pause_resume_cancel() {
+        let mut queue = Queue::new(1);
+        queue.add(TransferDirection::Upload, "a", "a");
+        let (_, limit, mut stopped) = queue.start_next().pop().unwrap();
+        queue.set_rate_limit(1, 50_000).unwrap();
+        assert_eq!((limit.get(), queue.entries[0].info.rate_limit), (50_000, 50_000));
+        queue.progress(1, TransferProgress { done: 10, total: 40, bytes_per_sec: 5, eta_secs: Some(6) });
+
+        queue.pause(1).unwrap();
+        assert!(stopped.try_recv().is_ok());
+        // The stopped run reporting in changes nothing
+        queue.finished(1, Err("Stopped".to_string()));
+        assert_eq!(states(&queue), vec![(1, TransferState::Paused)]);
+        assert_eq!(queue.entries[0].info.done, 10);
+        assert!(queue.pause(1).is_err());
+
+        queue.resume(1).unwrap();
+        let (info, _, _) = queue.start_next().pop().unwrap();
+        assert_eq!(info.done, 10);
+        queue.cancel(1).unwrap();
+        assert_eq!(states(&queue), vec![(1, TransferState::Cancelled)]);
+        assert!(queue.resume(1).is_err());
+        assert!(queue.cancel(7).is_err());
+    }
+}
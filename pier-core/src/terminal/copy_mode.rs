@@ -0,0 +1,557 @@
+//! Keyboard-driven copy mode over the screen and scrollback.
+//!
+//! A vi-style cursor moves over absolute rows (see
+//! [`VtEmulator::screen_base_row`]) independently of the terminal cursor.
+//! `v`, `V` and `Ctrl-V` start a character, line or block selection, `y`
+//! copies it, and `/`, `?`, `n`, `N` search. Keys are interpreted here so
+//! every front-end gets the same bindings.
+
+use regex::Regex;
+
+use crate::terminal::emulator::VtEmulator;
+use crate::terminal::keys::{Key, MOD_CTRL};
+use crate::terminal::search::{self as terminal_search, SearchOptions};
+use crate::terminal::selection::{is_word_cell, SelectionMode, SelectionRange};
+
+/// Largest count prefix accepted (`999j`).
+const MAX_COUNT: usize = 9999;
+
+/// Kind of selection being made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VisualMode {
+    /// Running text, `v`
+    Char,
+    /// Whole lines, `V`
+    Line,
+    /// Rectangle, `Ctrl-V`
+    Block,
+}
+
+/// What the app should do after a key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CopyModeAction {
+    /// Stay in copy mode and redraw from `state`
+    Continue,
+    /// Leave copy mode without copying
+    Exit,
+    /// Put the text on the clipboard and leave copy mode
+    Copy(String),
+}
+
+/// Search being typed after `/` or `?`.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct SearchPrompt {
+    pub query: String,
+    pub forward: bool,
+}
+
+/// Copy-mode cursor, viewport and selection, serialized for rendering.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct CopyModeState {
+    pub cursor_row: u64,
+    pub cursor_col: usize,
+    /// Absolute row to show at the top of the view
+    pub viewport_top: u64,
+    pub visual: Option<VisualMode>,
+    pub selection: Option<SelectionRange>,
+    pub search_prompt: Option<SearchPrompt>,
+}
+
+/// Character class used by word motions.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Blank,
+    Word,
+    Punct,
+}
+
+/// Copy-mode state of one session.
+pub struct CopyMode {
+    row: u64,
+    col: usize,
+    viewport_top: u64,
+    visual: Option<VisualMode>,
+    anchor: (u64, usize),
+    /// Last search pattern and direction, for `n` and `N`
+    search: Option<(Regex, bool)>,
+    prompt: Option<SearchPrompt>,
+    /// Digits typed before a motion
+    count: usize,
+    /// `g` was pressed, waiting for the second `g`
+    pending_g: bool,
+}
+
+impl CopyMode {
+    /// Enter copy mode at the terminal cursor, viewing the live screen.
+    pub fn new(emu: &VtEmulator) -> Self {
+        let base = emu.screen_base_row();
+        Self {
+            row: base + emu.cursor_y as u64,
+            col: emu.cursor_x,
+            viewport_top: base,
+            visual: None,
+            anchor: (0, 0),
+            search: None,
+            prompt: None,
+            count: 0,
+            pending_g: false,
+        }
+    }
+
+    pub fn state(&self, emu: &VtEmulator) -> CopyModeState {
+        CopyModeState {
+            cursor_row: self.row,
+            cursor_col: self.col,
+            viewport_top: self.viewport_top,
+            visual: self.visual,
+            selection: self.selection(emu),
+            search_prompt: self.prompt.clone(),
+        }
+    }
+
+    /// The selected range, expanded to whole lines in line mode.
+    pub fn selection(&self, emu: &VtEmulator) -> Option<SelectionRange> {
+        let visual = self.visual?;
+        let (start, end) = match visual {
+            VisualMode::Char => (self.anchor.min(self.cursor()), self.anchor.max(self.cursor())),
+            VisualMode::Line => {
+                let (first, last) = (self.anchor.0.min(self.row), self.anchor.0.max(self.row));
+                ((first, 0), (last, line_len(emu, last).saturating_sub(1)))
+            }
+            VisualMode::Block => (
+                (self.anchor.0.min(self.row), self.anchor.1.min(self.col)),
+                (self.anchor.0.max(self.row), self.anchor.1.max(self.col)),
+            ),
+        };
+        Some(SelectionRange { start_row: start.0, start_col: start.1, end_row: end.0, end_col: end.1 })
+    }
+
+    /// Text of the current selection, empty without one.
+    pub fn selected_text(&self, emu: &VtEmulator) -> String {
+        let Some(range) = self.selection(emu) else { return String::new() };
+        let mode = if self.visual == Some(VisualMode::Block) { SelectionMode::Block } else { SelectionMode::Stream };
+        emu.selection_text((range.start_row, range.start_col), (range.end_row, range.end_col), mode)
+    }
+
+    /// Handle a key press. `modifiers` takes the `keys::MOD_*` bits.
+    pub fn handle_key(&mut self, emu: &VtEmulator, key: Key, modifiers: u8) -> CopyModeAction {
+        self.clamp(emu);
+        if self.prompt.is_some() {
+            self.prompt_key(emu, key);
+            return CopyModeAction::Continue;
+        }
+
+        // Control letters arrive either as the letter with Ctrl or as C0 codes
+        let ctrl = |letter: char| match key {
+            Key::Char(ch) if modifiers & MOD_CTRL != 0 => ch.eq_ignore_ascii_case(&letter),
+            Key::Char(ch) => ch as u32 == (letter as u32 & 0x1f),
+            _ => false,
+        };
+        let pending_g = std::mem::take(&mut self.pending_g);
+        let count = std::mem::take(&mut self.count);
+        let repeat = count.max(1);
+        let half_page = (emu.rows / 2).max(1) * repeat;
+
+        if ctrl('u') {
+            self.scroll_by(emu, -(half_page as i64));
+        } else if ctrl('d') {
+            self.scroll_by(emu, half_page as i64);
+        } else if ctrl('b') || key == Key::PageUp {
+            self.scroll_by(emu, -((emu.rows * repeat) as i64));
+        } else if ctrl('f') || key == Key::PageDown {
+            self.scroll_by(emu, (emu.rows * repeat) as i64);
+        } else if ctrl('v') {
+            self.toggle_visual(VisualMode::Block);
+        } else if modifiers & MOD_CTRL != 0 {
+            // Other Ctrl chords are unbound
+        } else {
+            match key {
+                Key::Char(ch @ '1'..='9') => self.count = (count * 10 + ch as usize - '0' as usize).min(MAX_COUNT),
+                Key::Char('0') if count > 0 => self.count = (count * 10).min(MAX_COUNT),
+                Key::Char('h') | Key::Left | Key::Backspace => self.move_left(emu, repeat),
+                Key::Char('l') | Key::Right => self.move_right(emu, repeat),
+                Key::Char('k') | Key::Up => self.move_rows(emu, -(repeat as i64)),
+                Key::Char('j') | Key::Down => self.move_rows(emu, repeat as i64),
+                Key::Char('w') => (0..repeat).for_each(|_| self.word_forward(emu)),
+                Key::Char('b') => (0..repeat).for_each(|_| self.word_backward(emu)),
+                Key::Char('e') => (0..repeat).for_each(|_| self.word_end(emu)),
+                Key::Char('0') | Key::Home => self.col = 0,
+                Key::Char('^') => self.col = first_non_blank(emu, self.row),
+                Key::Char('$') | Key::End => self.col = last_non_blank(emu, self.row),
+                Key::Char('g') if pending_g => self.go_to_row(emu, first_row(emu)),
+                Key::Char('g') => self.pending_g = true,
+                Key::Char('G') => self.go_to_row(emu, last_row(emu)),
+                Key::Char('H') => self.go_to_row(emu, self.viewport_top),
+                Key::Char('M') => self.go_to_row(emu, self.viewport_top + (emu.rows.max(1) as u64 - 1) / 2),
+                Key::Char('L') => self.go_to_row(emu, self.viewport_top + emu.rows.max(1) as u64 - 1),
+                Key::Char('v') => self.toggle_visual(VisualMode::Char),
+                Key::Char('V') => self.toggle_visual(VisualMode::Line),
+                Key::Char('/') => self.prompt = Some(SearchPrompt { query: String::new(), forward: true }),
+                Key::Char('?') => self.prompt = Some(SearchPrompt { query: String::new(), forward: false }),
+                Key::Char('n') => (0..repeat).for_each(|_| self.repeat_search(emu, false)),
+                Key::Char('N') => (0..repeat).for_each(|_| self.repeat_search(emu, true)),
+                Key::Char('y') | Key::Enter if self.visual.is_some() => {
+                    return CopyModeAction::Copy(self.selected_text(emu));
+                }
+                Key::Escape if self.visual.is_some() => self.visual = None,
+                Key::Char('q') | Key::Escape => return CopyModeAction::Exit,
+                _ => {}
+            }
+        }
+        self.snap_to_glyph(emu);
+        self.follow_cursor(emu);
+        CopyModeAction::Continue
+    }
+
+    /// Search for `pattern` and jump to the nearest match in the given
+    /// direction, wrapping around. Returns whether anything matched. The
+    /// pattern is kept for `n` and `N`.
+    pub fn search(&mut self, emu: &VtEmulator, pattern: Regex, forward: bool) -> bool {
+        self.clamp(emu);
+        self.search = Some((pattern, forward));
+        let found = self.jump_to_match(emu, forward);
+        self.follow_cursor(emu);
+        found
+    }
+
+    fn prompt_key(&mut self, emu: &VtEmulator, key: Key) {
+        let Some(prompt) = self.prompt.as_mut() else { return };
+        match key {
+            Key::Char(ch) if !ch.is_control() => prompt.query.push(ch),
+            Key::Backspace => {
+                prompt.query.pop();
+            }
+            Key::Escape => self.prompt = None,
+            Key::Enter => {
+                let Some(prompt) = self.prompt.take() else { return };
+                if prompt.query.is_empty() {
+                    // An empty search repeats the last one, as in vi
+                    self.repeat_search(emu, !prompt.forward);
+                    self.follow_cursor(emu);
+                    return;
+                }
+                // Smart case: lowercase queries ignore case
+                let options = SearchOptions {
+                    regex: false,
+                    case_insensitive: !prompt.query.chars().any(char::is_uppercase),
+                };
+                if let Ok(pattern) = terminal_search::build_pattern(&prompt.query, options) {
+                    self.search(emu, pattern, prompt.forward);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// `n` (or `N` when `reverse`): repeat the last search.
+    fn repeat_search(&mut self, emu: &VtEmulator, reverse: bool) {
+        if let Some((_, forward)) = self.search {
+            self.jump_to_match(emu, forward != reverse);
+        }
+    }
+
+    fn jump_to_match(&mut self, emu: &VtEmulator, forward: bool) -> bool {
+        let Some((pattern, _)) = &self.search else { return false };
+        let matches = emu.search(pattern);
+        let cursor = self.cursor();
+        let start = |m: &SelectionRange| (m.start_row, m.start_col);
+        let target = if forward {
+            matches.iter().find(|m| start(m) > cursor).or(matches.first())
+        } else {
+            matches.iter().rev().find(|m| start(m) < cursor).or(matches.last())
+        };
+        match target {
+            Some(m) => {
+                (self.row, self.col) = start(m);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn toggle_visual(&mut self, mode: VisualMode) {
+        if self.visual == Some(mode) {
+            self.visual = None;
+        } else {
+            if self.visual.is_none() {
+                self.anchor = self.cursor();
+            }
+            self.visual = Some(mode);
+        }
+    }
+
+    fn cursor(&self) -> (u64, usize) {
+        (self.row, self.col)
+    }
+
+    fn move_left(&mut self, emu: &VtEmulator, n: usize) {
+        for _ in 0..n {
+            self.col = self.col.saturating_sub(1);
+            self.snap_to_glyph(emu);
+        }
+    }
+
+    fn move_right(&mut self, emu: &VtEmulator, n: usize) {
+        let last = line_len(emu, self.row).saturating_sub(1);
+        for _ in 0..n {
+            let line = emu.line_at(self.row);
+            let step = match line.as_deref().and_then(|line| line.get(self.col)) {
+                Some(cell) if cell.wide => 2,
+                _ => 1,
+            };
+            self.col = (self.col + step).min(last);
+        }
+    }
+
+    fn move_rows(&mut self, emu: &VtEmulator, delta: i64) {
+        let row = self.row.saturating_add_signed(delta);
+        self.row = row.clamp(first_row(emu), last_row(emu));
+        self.col = self.col.min(line_len(emu, self.row).saturating_sub(1));
+    }
+
+    fn go_to_row(&mut self, emu: &VtEmulator, row: u64) {
+        self.row = row.clamp(first_row(emu), last_row(emu));
+        self.col = first_non_blank(emu, self.row);
+    }
+
+    /// Ctrl-U/D/B/F: move the cursor and the viewport together.
+    fn scroll_by(&mut self, emu: &VtEmulator, delta: i64) {
+        let max_top = emu.screen_base_row();
+        self.viewport_top = self.viewport_top.saturating_add_signed(delta).clamp(first_row(emu), max_top);
+        self.move_rows(emu, delta);
+    }
+
+    /// `w`: start of the next word, crossing lines.
+    fn word_forward(&mut self, emu: &VtEmulator) {
+        let mut pos = self.cursor();
+        let class = class_at(emu, pos);
+        while let Some(next) = step_forward(emu, pos) {
+            let new_line = next.0 != pos.0;
+            pos = next;
+            if new_line || class_at(emu, pos) != class {
+                break;
+            }
+        }
+        while class_at(emu, pos) == Class::Blank {
+            match step_forward(emu, pos) {
+                Some(next) => pos = next,
+                None => break,
+            }
+        }
+        (self.row, self.col) = pos;
+    }
+
+    /// `b`: start of the current or previous word.
+    fn word_backward(&mut self, emu: &VtEmulator) {
+        let Some(mut pos) = step_backward(emu, self.cursor()) else { return };
+        while class_at(emu, pos) == Class::Blank {
+            match step_backward(emu, pos) {
+                Some(prev) => pos = prev,
+                None => break,
+            }
+        }
+        let class = class_at(emu, pos);
+        while let Some(prev) = step_backward(emu, pos) {
+            if prev.0 != pos.0 || class_at(emu, prev) != class {
+                break;
+            }
+            pos = prev;
+        }
+        (self.row, self.col) = pos;
+    }
+
+    /// `e`: end of the current or next word.
+    fn word_end(&mut self, emu: &VtEmulator) {
+        let Some(mut pos) = step_forward(emu, self.cursor()) else { return };
+        while class_at(emu, pos) == Class::Blank {
+            match step_forward(emu, pos) {
+                Some(next) => pos = next,
+                None => break,
+            }
+        }
+        let class = class_at(emu, pos);
+        while let Some(next) = step_forward(emu, pos) {
+            if next.0 != pos.0 || class_at(emu, next) != class {
+                break;
+            }
+            pos = next;
+        }
+        (self.row, self.col) = pos;
+    }
+
+    /// Keep the cursor on the leading half of a wide glyph.
+    fn snap_to_glyph(&mut self, emu: &VtEmulator) {
+        let on_spacer = emu
+            .line_at(self.row)
+            .is_some_and(|line| line.get(self.col).is_some_and(|cell| cell.wide_spacer));
+        if on_spacer && self.col > 0 {
+            self.col -= 1;
+        }
+    }
+
+    /// Pull the cursor back into the available rows after output scrolled
+    /// or scrollback was trimmed.
+    fn clamp(&mut self, emu: &VtEmulator) {
+        self.row = self.row.clamp(first_row(emu), last_row(emu));
+        self.col = self.col.min(line_len(emu, self.row).saturating_sub(1));
+        self.anchor.0 = self.anchor.0.clamp(first_row(emu), last_row(emu));
+        self.viewport_top = self.viewport_top.clamp(first_row(emu), emu.screen_base_row());
+    }
+
+    /// Scroll the viewport just enough to show the cursor.
+    fn follow_cursor(&mut self, emu: &VtEmulator) {
+        let rows = emu.rows.max(1) as u64;
+        if self.row < self.viewport_top {
+            self.viewport_top = self.row;
+        } else if self.row >= self.viewport_top + rows {
+            self.viewport_top = self.row + 1 - rows;
+        }
+    }
+}
+
+fn first_row(emu: &VtEmulator) -> u64 {
+    emu.screen_base_row() - emu.scrollback_len() as u64
+}
+
+fn last_row(emu: &VtEmulator) -> u64 {
+    emu.screen_base_row() + emu.rows.max(1) as u64 - 1
+}
+
+fn line_len(emu: &VtEmulator, row: u64) -> usize {
+    emu.line_at(row).map_or(0, |line| line.len())
+}
+
+fn class_at(emu: &VtEmulator, (row, col): (u64, usize)) -> Class {
+    let Some(line) = emu.line_at(row) else { return Class::Blank };
+    let Some(cell) = line.get(col) else { return Class::Blank };
+    let cell = if cell.wide_spacer && col > 0 { &line[col - 1] } else { cell };
+    if cell.ch.is_whitespace() {
+        Class::Blank
+    } else if is_word_cell(&line, col) {
+        Class::Word
+    } else {
+        Class::Punct
+    }
+}
+
+fn step_forward(emu: &VtEmulator, (row, col): (u64, usize)) -> Option<(u64, usize)> {
+    if col + 1 < line_len(emu, row) {
+        Some((row, col + 1))
+    } else if row < last_row(emu) {
+        Some((row + 1, 0))
+    } else {
+        None
+    }
+}
+
+fn step_backward(emu: &VtEmulator, (row, col): (u64, usize)) -> Option<(u64, usize)> {
+    if col > 0 {
+        Some((row, col - 1))
+    } else if row > first_row(emu) {
+        Some((row - 1, line_len(emu, row - 1).saturating_sub(1)))
+    } else {
+        None
+    }
+}
+
+fn first_non_blank(emu: &VtEmulator, row: u64) -> usize {
+    emu.line_at(row)
+        .and_then(|line| line.iter().position(|cell| !cell.ch.is_whitespace()))
+        .unwrap_or(0)
+}
+
+fn last_non_blank(emu: &VtEmulator, row: u64) -> usize {
+    emu.line_at(row)
+        .and_then(|line| line.iter().rposition(|cell| !cell.ch.is_whitespace() && !cell.wide_spacer))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(mode: &mut CopyMode, emu: &VtEmulator, keys: &str) -> CopyModeAction {
+        let mut action = CopyModeAction::Continue;
+        for ch in keys.chars() {
+            action = mode.handle_key(emu, Key::Char(ch), 0);
+        }
+        action
+    }
+
+    #[test]
+    fn test_motions() {
+        let mut emu = VtEmulator::new(20, 3);
+        emu.process(b"foo bar,baz\r\n  qux\r\nlast");
+        let mut mode = CopyMode::new(&emu);
+        assert_eq!((mode.row, mode.col), (2, 4));
+
+        press(&mut mode, &emu, "gg");
+        assert_eq!((mode.row, mode.col), (0, 0));
+        press(&mut mode, &emu, "w");
+        assert_eq!(mode.col, 4);
+        press(&mut mode, &emu, "w");
+        assert_eq!(mode.col, 7);
+        press(&mut mode, &emu, "e");
+        assert_eq!(mode.col, 10);
+        press(&mut mode, &emu, "w");
+        assert_eq!((mode.row, mode.col), (1, 2));
+        press(&mut mode, &emu, "b");
+        assert_eq!((mode.row, mode.col), (0, 8));
+        press(&mut mode, &emu, "$");
+        assert_eq!(mode.col, 10);
+        press(&mut mode, &emu, "j^");
+        assert_eq!((mode.row, mode.col), (1, 2));
+        press(&mut mode, &emu, "2k");
+        assert_eq!(mode.row, 0);
+        press(&mut mode, &emu, "G");
+        assert_eq!(mode.row, 2);
+        assert_eq!(mode.handle_key(&emu, Key::Char('q'), 0), CopyModeAction::Exit);
+    }
+
+    #[test]
+    fn test_visual_selection_and_yank() {
+        let mut emu = VtEmulator::new(10, 3);
+        emu.process(b"one two\r\nthree\r\nfour");
+        let mut mode = CopyMode::new(&emu);
+
+        press(&mut mode, &emu, "ggwv");
+        press(&mut mode, &emu, "j");
+        let range = mode.selection(&emu).unwrap();
+        assert_eq!((range.start_row, range.start_col, range.end_row, range.end_col), (0, 4, 1, 4));
+        assert_eq!(press(&mut mode, &emu, "y"), CopyModeAction::Copy("two\nthree".to_string()));
+
+        let mut mode = CopyMode::new(&emu);
+        assert_eq!(press(&mut mode, &emu, "kVjy"), CopyModeAction::Copy("three\nfour".to_string()));
+
+        let mut mode = CopyMode::new(&emu);
+        mode.handle_key(&emu, Key::Char('v'), MOD_CTRL);
+        assert_eq!(mode.visual, Some(VisualMode::Block));
+        mode.handle_key(&emu, Key::Escape, 0);
+        assert_eq!(mode.visual, None);
+        assert_eq!(mode.handle_key(&emu, Key::Escape, 0), CopyModeAction::Exit);
+    }
+
+    #[test]
+    fn test_search_and_scrollback() {
+        let mut emu = VtEmulator::new(10, 2);
+        emu.process(b"match 1\r\nother\r\nMatch 2\r\nend");
+        let mut mode = CopyMode::new(&emu);
+        assert_eq!(mode.viewport_top, 2);
+
+        press(&mut mode, &emu, "?match");
+        assert!(mode.prompt.is_some());
+        mode.handle_key(&emu, Key::Enter, 0);
+        assert_eq!((mode.row, mode.col), (2, 0));
+        press(&mut mode, &emu, "n");
+        assert_eq!(mode.row, 0);
+        assert_eq!(mode.viewport_top, 0);
+        press(&mut mode, &emu, "N");
+        assert_eq!(mode.row, 2);
+
+        mode.handle_key(&emu, Key::Char('u'), MOD_CTRL);
+        assert_eq!((mode.row, mode.viewport_top), (1, 0));
+        mode.handle_key(&emu, Key::Char('\u{4}'), 0);
+        assert_eq!((mode.row, mode.viewport_top), (2, 1));
+    }
+}
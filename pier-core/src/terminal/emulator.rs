@@ -1,5 +1,23 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::terminal::images::{self, ImageSize, InlineImage};
+use crate::terminal::keys::KeyboardState;
+use crate::terminal::modes::{Mode, ModeTable};
+use crate::terminal::palette::{self, Palette};
+use crate::terminal::scrollback::Scrollback;
+use crate::terminal::width::{char_width, joins_cluster};
 use vte::{Parser, Perform};
 
+/// Maximum number of undelivered clipboard events kept per emulator.
+const MAX_PENDING_CLIPBOARD_EVENTS: usize = 64;
+
+/// Maximum number of undelivered bell and notification events.
+const MAX_PENDING_NOTIFICATIONS: usize = 64;
+
+/// Maximum number of shell-integration command records kept per emulator.
+const MAX_COMMAND_HISTORY: usize = 1000;
+
 /// VT100/ANSI escape sequence parser wrapping `vte` crate.
 /// Tracks cursor position, text attributes, and screen content.
 pub struct VtEmulator {
@@ -10,26 +28,418 @@ pub struct VtEmulator {
     pub rows: usize,
     /// Screen buffer: rows x cols of characters
     pub cells: Vec<Vec<Cell>>,
+    /// Current graphic rendition applied to newly printed characters (SGR state)
+    pen: Cell,
+    /// Primary screen stashed while the alternate screen is active
+    saved_primary: Option<SavedScreen>,
+    /// Scroll region top margin (inclusive, 0-based), set by DECSTBM
+    scroll_top: usize,
+    /// Scroll region bottom margin (inclusive, 0-based), set by DECSTBM
+    scroll_bottom: usize,
+    /// Lines scrolled off the top of the primary screen, oldest first
+    scrollback: Scrollback,
+    /// Total lines ever scrolled off the primary screen; the absolute row of screen row 0
+    lines_scrolled: u64,
+    /// Window title set via OSC 0/2
+    title: String,
+    /// Icon name set via OSC 0/1
+    icon_name: String,
+    /// Which OSC 52 clipboard operations programs may perform
+    clipboard_policy: ClipboardPolicy,
+    /// Reply to ENQ (0x05); empty sends nothing
+    answerback: String,
+    /// Colors in effect, including changes made by the program
+    palette: Palette,
+    /// Colors chosen by the app, restored by OSC 104/110/111/112
+    theme: Palette,
+    /// OSC 52 requests waiting to be picked up by the app
+    clipboard_events: VecDeque<ClipboardEvent>,
+    /// Completed commands reported through OSC 133 shell integration
+    commands: VecDeque<CommandRecord>,
+    /// Command currently between its prompt (133;A) and completion (133;D)
+    pending_command: Option<PendingCommand>,
+    /// Per screen row: changed since the last `take_dirty_rows`
+    dirty: Vec<bool>,
+    /// Per screen row: DEC line size; lines entering scrollback lose it
+    line_renditions: Vec<LineRendition>,
+    /// Number of `take_updates` calls that reported a change
+    generation: u64,
+    /// Cursor and `lines_scrolled` as of the last `take_updates`, to tell
+    /// whether anything besides row contents changed
+    last_update: Option<(UpdateCursor, u64)>,
+    /// Cursor shape and blinking set via DECSCUSR (`CSI Ps SP q`)
+    cursor_style: CursorStyle,
+    /// Per column: a tab stop is set (HTS / TBC)
+    tab_stops: Vec<bool>,
+    /// Replies to terminal queries (DSR, DA) waiting to be written to the PTY
+    responses: Vec<u8>,
+    /// ANSI and DEC private mode states (SM/RM, DECSET/DECRST)
+    modes: ModeTable,
+    /// xterm modifyOtherKeys level set via `CSI > 4 ; n m`
+    modify_other_keys: u8,
+    /// Kitty keyboard protocol flag stack; the top entry is in effect
+    kitty_keyboard: Vec<u32>,
+    /// Bells and desktop notification requests not yet taken by the app
+    notifications: VecDeque<Notification>,
+    /// Inline images (OSC 1337) waiting to be picked up by the renderer
+    images: VecDeque<InlineImage>,
+    /// Id for the next inline image
+    next_image_id: u64,
+    /// A character was written to the last column; the next printable
+    /// character wraps first. Cleared by any cursor movement.
+    wrap_pending: bool,
+}
+
+/// Keyboard input modes set by the running program.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct InputModes {
+    /// DECCKM (`CSI ? 1 h`): arrow keys send `ESC O A` instead of `ESC [ A`
+    pub application_cursor: bool,
+    /// DECKPAM (`ESC =`): keypad sends `ESC O p`.. instead of digits
+    pub application_keypad: bool,
+}
+
+/// Maximum number of undelivered inline images; the oldest are dropped.
+const MAX_PENDING_IMAGES: usize = 16;
+
+/// Maximum depth of the kitty keyboard flag stack; the oldest entry is
+/// dropped on overflow.
+const MAX_KITTY_KEYBOARD_STACK: usize = 16;
+
+/// Default tab stop interval for new columns.
+const TAB_WIDTH: usize = 8;
+
+fn default_tab_stops(cols: usize) -> Vec<bool> {
+    (0..cols).map(|x| x > 0 && x % TAB_WIDTH == 0).collect()
+}
+
+/// Cursor shape requested by the running program.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CursorShape {
+    #[default]
+    Block,
+    Underline,
+    Bar,
+}
+
+/// Cursor shape plus whether it blinks, as set by DECSCUSR.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    pub blinking: bool,
+}
+
+impl Default for CursorStyle {
+    fn default() -> Self {
+        // DECSCUSR 0/1: blinking block
+        Self {
+            shape: CursorShape::Block,
+            blinking: true,
+        }
+    }
+}
+
+impl CursorStyle {
+    /// Decode a DECSCUSR parameter; `None` for unknown values.
+    fn from_decscusr(param: u16) -> Option<Self> {
+        let shape = match param {
+            0..=2 => CursorShape::Block,
+            3 | 4 => CursorShape::Underline,
+            5 | 6 => CursorShape::Bar,
+            _ => return None,
+        };
+        Some(Self {
+            shape,
+            // Odd values (and the 0 default) blink, even values are steady
+            blinking: param == 0 || param % 2 == 1,
+        })
+    }
+}
+
+/// A shell command delimited by OSC 133 semantic prompt marks.
+///
+/// Rows are absolute: row 0 is the first line ever printed, so they stay
+/// valid as output scrolls. See [`VtEmulator::screen_base_row`].
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct CommandRecord {
+    /// Command line as typed at the prompt
+    pub command: String,
+    /// Exit code reported by 133;D, if the shell sent one
+    pub exit_code: Option<i32>,
+    /// Row where the prompt started (133;A)
+    pub prompt_row: u64,
+    /// First row of command output (133;C)
+    pub output_start_row: Option<u64>,
+    /// Row after the last output row (133;D), exclusive
+    pub output_end_row: Option<u64>,
+}
+
+/// Shell-integration state for the command currently being entered or run.
+struct PendingCommand {
+    record: CommandRecord,
+    /// Absolute position (row, col) where user input began (133;B)
+    input_start: Option<(u64, usize)>,
+}
+
+/// Controls which OSC 52 clipboard operations are honored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClipboardPolicy {
+    /// Allow programs to set the clipboard (tmux/nvim yank).
+    pub allow_write: bool,
+    /// Allow programs to request the clipboard contents. Off by default since
+    /// any remote program could otherwise read whatever the user copied.
+    pub allow_read: bool,
+}
+
+impl Default for ClipboardPolicy {
+    fn default() -> Self {
+        Self {
+            allow_write: true,
+            allow_read: false,
+        }
+    }
+}
+
+/// A clipboard operation requested by the running program via OSC 52.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ClipboardEvent {
+    /// Set the clipboard `selection` ("c", "p", ...) to the decoded `data`.
+    Write { selection: String, data: String },
+    /// The program asked for the clipboard contents; answer with `osc52_response`.
+    Read { selection: String },
+}
+
+/// Something the program wants the user's attention for.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum NotificationKind {
+    /// BEL (0x07)
+    Bell,
+    /// Desktop notification via OSC 9 (iTerm2, body only) or
+    /// OSC 777 `notify` (rxvt, title and body)
+    Notify { title: String, body: String },
+}
+
+/// A bell or notification with the time it was received.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct Notification {
+    #[serde(flatten)]
+    pub kind: NotificationKind,
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+}
+
+/// DEC line size attribute (`ESC # 3/4/5/6`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineRendition {
+    #[default]
+    Single,
+    /// DECDWL: each cell drawn twice as wide; only the left half of the
+    /// columns is usable
+    DoubleWidth,
+    /// DECDHL top half: double width and height, upper half of the glyphs
+    DoubleHeightTop,
+    /// DECDHL bottom half
+    DoubleHeightBottom,
+}
+
+impl LineRendition {
+    fn is_single(&self) -> bool {
+        *self == LineRendition::Single
+    }
+}
+
+/// Primary screen contents and cursor, kept aside during alternate-screen mode.
+struct SavedScreen {
+    cells: Vec<Vec<Cell>>,
+    renditions: Vec<LineRendition>,
+    /// Cursor (x, y) to restore on exit; only saved for mode 1049
+    cursor: Option<(usize, usize)>,
 }
 
 /// A single cell in the terminal grid.
-#[derive(Clone, Debug)]
+///
+/// Serializes compactly: default colors and unset attributes are omitted,
+/// so a plain cell is just `{"ch":"a"}`.
+#[derive(Clone, Debug, serde::Serialize)]
 pub struct Cell {
     pub ch: char,
+    #[serde(skip_serializing_if = "Color::is_default")]
     pub fg: Color,
+    #[serde(skip_serializing_if = "Color::is_default")]
     pub bg: Color,
+    #[serde(skip_serializing_if = "is_false")]
     pub bold: bool,
-    pub underline: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub faint: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub italic: bool,
+    #[serde(skip_serializing_if = "UnderlineStyle::is_none")]
+    pub underline: UnderlineStyle,
+    /// Underline color (SGR 58); `Default` uses the foreground color
+    #[serde(skip_serializing_if = "Color::is_default")]
+    pub underline_color: Color,
+    #[serde(skip_serializing_if = "is_false")]
+    pub blink: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub reverse: bool,
+    /// Concealed text (SGR 8): occupies space but isn't drawn
+    #[serde(skip_serializing_if = "is_false")]
+    pub invisible: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub strikethrough: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub overline: bool,
+    /// Leading half of a double-width glyph; the next cell is its spacer
+    #[serde(skip_serializing_if = "is_false")]
+    pub wide: bool,
+    /// Trailing half of a double-width glyph; renders nothing on its own
+    #[serde(skip_serializing_if = "is_false")]
+    pub wide_spacer: bool,
+    /// Combining marks and joined characters that follow `ch` in the grapheme
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub combining: Option<Box<str>>,
+}
+
+impl Cell {
+    /// Append this cell's text (base character plus combining marks) to `out`.
+    /// Spacer cells contribute nothing.
+    pub fn push_text(&self, out: &mut String) {
+        if self.wide_spacer {
+            return;
+        }
+        out.push(self.ch);
+        if let Some(marks) = &self.combining {
+            out.push_str(marks);
+        }
+    }
+}
+
+/// Blank out halves of wide glyphs left without their partner after cells
+/// were shifted or erased.
+fn repair_wide_cells(line: &mut [Cell]) {
+    for x in 0..line.len() {
+        let orphaned = if line[x].wide {
+            !line.get(x + 1).is_some_and(|next| next.wide_spacer)
+        } else {
+            line[x].wide_spacer && (x == 0 || !line[x - 1].wide)
+        };
+        if orphaned {
+            line[x] = Cell { ch: ' ', wide: false, wide_spacer: false, combining: None, ..line[x].clone() };
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// Full renderable state of the screen, serialized by `pier_terminal_snapshot`.
+#[derive(Debug, serde::Serialize)]
+pub struct ScreenSnapshot<'a> {
+    pub cols: usize,
+    pub rows: usize,
+    pub cursor_x: usize,
+    pub cursor_y: usize,
+    pub cursor_visible: bool,
+    pub cursor_style: CursorStyle,
+    /// DECSCNM: swap default foreground and background for the whole screen
+    pub reverse_video: bool,
+    pub title: &'a str,
+    pub alternate_screen: bool,
+    /// Visible rows, top to bottom
+    pub lines: &'a [Vec<Cell>],
+    /// DEC line size of each visible row
+    pub line_renditions: &'a [LineRendition],
+}
+
+/// Cursor state reported with each incremental update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct UpdateCursor {
+    pub x: usize,
+    pub y: usize,
+    pub visible: bool,
+    pub style: CursorStyle,
+}
+
+/// One changed screen row.
+#[derive(Debug, serde::Serialize)]
+pub struct RowUpdate<'a> {
+    pub row: usize,
+    pub cells: &'a [Cell],
+    #[serde(skip_serializing_if = "LineRendition::is_single")]
+    pub rendition: LineRendition,
+}
+
+/// Changes since the previous `take_updates`, serialized by
+/// `pier_terminal_take_updates`.
+#[derive(Debug, serde::Serialize)]
+pub struct ScreenUpdate<'a> {
+    /// Incremented whenever an update carries changes; unchanged when
+    /// nothing happened, so the renderer can skip the frame
+    pub generation: u64,
+    pub cols: usize,
+    pub rows: usize,
+    pub cursor: UpdateCursor,
+    pub reverse_video: bool,
+    pub alternate_screen: bool,
+    /// Lines that scrolled into history since the previous update; the
+    /// renderer can shift cached rows by this much before applying `lines`
+    pub scrolled: u64,
+    /// Rows whose content or attributes changed, top to bottom
+    pub lines: Vec<RowUpdate<'a>>,
+}
+
+/// Underline drawn under a cell (SGR 4, `4:n` and 21).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnderlineStyle {
+    #[default]
+    None,
+    Single,
+    Double,
+    /// Wavy underline, used by editors for diagnostics
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+impl UnderlineStyle {
+    fn is_none(&self) -> bool {
+        *self == UnderlineStyle::None
+    }
+
+    /// Decode the subparameter of `SGR 4:n`.
+    fn from_subparam(n: u16) -> Option<Self> {
+        match n {
+            0 => Some(Self::None),
+            1 => Some(Self::Single),
+            2 => Some(Self::Double),
+            3 => Some(Self::Curly),
+            4 => Some(Self::Dotted),
+            5 => Some(Self::Dashed),
+            _ => None,
+        }
+    }
 }
 
 /// Terminal color representation.
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq, serde::Serialize)]
 pub enum Color {
     Default,
     Indexed(u8),
     Rgb(u8, u8, u8),
 }
 
+impl Color {
+    fn is_default(&self) -> bool {
+        matches!(self, Color::Default)
+    }
+}
+
 impl Default for Cell {
     fn default() -> Self {
         Self {
@@ -37,125 +447,1110 @@ impl Default for Cell {
             fg: Color::Default,
             bg: Color::Default,
             bold: false,
-            underline: false,
+            faint: false,
+            italic: false,
+            underline: UnderlineStyle::None,
+            underline_color: Color::Default,
+            blink: false,
+            reverse: false,
+            invisible: false,
+            strikethrough: false,
+            overline: false,
+            wide: false,
+            wide_spacer: false,
+            combining: None,
+        }
+    }
+}
+
+impl VtEmulator {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        let cells = vec![vec![Cell::default(); cols]; rows];
+        Self {
+            parser: Parser::new(),
+            cursor_x: 0,
+            cursor_y: 0,
+            cols,
+            rows,
+            cells,
+            pen: Cell::default(),
+            saved_primary: None,
+            scroll_top: 0,
+            scroll_bottom: rows.saturating_sub(1),
+            scrollback: Scrollback::default(),
+            lines_scrolled: 0,
+            title: String::new(),
+            icon_name: String::new(),
+            clipboard_policy: ClipboardPolicy::default(),
+            answerback: String::new(),
+            palette: Palette::default(),
+            theme: Palette::default(),
+            clipboard_events: VecDeque::new(),
+            commands: VecDeque::new(),
+            pending_command: None,
+            dirty: vec![true; rows],
+            line_renditions: vec![LineRendition::Single; rows],
+            generation: 0,
+            last_update: None,
+            cursor_style: CursorStyle::default(),
+            tab_stops: default_tab_stops(cols),
+            responses: Vec::new(),
+            modes: ModeTable::default(),
+            modify_other_keys: 0,
+            kitty_keyboard: Vec::new(),
+            notifications: VecDeque::new(),
+            images: VecDeque::new(),
+            next_image_id: 1,
+            wrap_pending: false,
+        }
+    }
+
+    /// Feed raw bytes from PTY into the VT parser.
+    ///
+    /// Input may be split anywhere, including inside an escape sequence or a
+    /// multi-byte UTF-8 character; the parser carries the partial input over
+    /// to the next call.
+    pub fn process(&mut self, bytes: &[u8]) {
+        // The parser is moved out for the duration of the call so the
+        // performer can borrow the rest of the emulator mutably.
+        let mut parser = std::mem::take(&mut self.parser);
+        parser.advance(&mut EmulatorPerformer { emu: self }, bytes);
+        self.parser = parser;
+    }
+
+    /// Resize the emulator grid. Sizes below one cell are clamped to one.
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        let (cols, rows) = (cols.max(1), rows.max(1));
+        self.cols = cols;
+        self.rows = rows;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        self.cells.resize(rows, vec![Cell::default(); cols]);
+        for row in self.cells.iter_mut() {
+            row.resize(cols, Cell::default());
+        }
+        self.dirty = vec![true; rows];
+        self.line_renditions.resize(rows, LineRendition::Single);
+        self.wrap_pending = false;
+        // Keep custom stops; new columns get the default interval
+        let old_cols = self.tab_stops.len();
+        self.tab_stops.resize(cols, false);
+        for x in old_cols..cols {
+            self.tab_stops[x] = x % TAB_WIDTH == 0;
+        }
+        if self.cursor_x >= cols {
+            self.cursor_x = cols - 1;
+        }
+        if self.cursor_y >= rows {
+            self.cursor_y = rows - 1;
+        }
+        if let Some(saved) = self.saved_primary.as_mut() {
+            saved.cells.resize(rows, vec![Cell::default(); cols]);
+            saved.renditions.resize(rows, LineRendition::Single);
+            for row in saved.cells.iter_mut() {
+                row.resize(cols, Cell::default());
+            }
+            if let Some((x, y)) = saved.cursor.as_mut() {
+                *x = (*x).min(cols - 1);
+                *y = (*y).min(rows - 1);
+            }
+        }
+    }
+
+    /// Set the maximum number of scrollback lines, discarding the oldest excess.
+    pub fn set_scrollback_limit(&mut self, limit: usize) {
+        let max_bytes = self.scrollback.max_bytes();
+        self.scrollback.set_limits(limit, max_bytes);
+    }
+
+    /// Set the scrollback memory budget in bytes, discarding the oldest
+    /// lines until it fits.
+    pub fn set_scrollback_memory_limit(&mut self, max_bytes: usize) {
+        let max_lines = self.scrollback.max_lines();
+        self.scrollback.set_limits(max_lines, max_bytes);
+    }
+
+    /// Approximate memory held by scrollback, in bytes.
+    pub fn scrollback_memory_usage(&self) -> usize {
+        self.scrollback.memory_usage()
+    }
+
+    /// Number of lines currently held in scrollback.
+    pub fn scrollback_len(&self) -> usize {
+        self.scrollback.len()
+    }
+
+    /// Get a scrollback line by index (0 = oldest).
+    pub fn scrollback_line(&self, index: usize) -> Option<Vec<Cell>> {
+        self.scrollback.get(index)
+    }
+
+    /// Get up to `count` scrollback lines starting at `start` (0 = oldest).
+    pub fn scrollback_lines(&self, start: usize, count: usize) -> Vec<Vec<Cell>> {
+        let end = start.saturating_add(count).min(self.scrollback.len());
+        (start..end).filter_map(|index| self.scrollback.get(index)).collect()
+    }
+
+    /// Absolute row number of the first screen row.
+    ///
+    /// Absolute rows count every line since the session started; scrollback
+    /// index `i` is absolute row `screen_base_row() - scrollback_len() + i`.
+    pub fn screen_base_row(&self) -> u64 {
+        self.lines_scrolled
+    }
+
+    /// Get a line by absolute row, from scrollback or the visible screen.
+    /// Scrollback lines are expanded from their compact form.
+    pub fn line_at(&self, row: u64) -> Option<Cow<'_, [Cell]>> {
+        let oldest = self.lines_scrolled - self.scrollback.len() as u64;
+        if row < oldest {
+            None
+        } else if row < self.lines_scrolled {
+            self.scrollback_line((row - oldest) as usize).map(Cow::Owned)
+        } else {
+            self.cells
+                .get((row - self.lines_scrolled) as usize)
+                .map(|line| Cow::Borrowed(line.as_slice()))
+        }
+    }
+
+    /// Commands recorded through OSC 133 shell integration, oldest first.
+    pub fn command_history(&self) -> Vec<CommandRecord> {
+        self.commands.iter().cloned().collect()
+    }
+
+    /// Bytes the terminal must send back to the program in answer to queries
+    /// (cursor position, device attributes). Drains the queue; the session
+    /// writes these to the PTY after each `process`.
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.responses)
+    }
+
+    /// Keyboard modes to use when encoding arrow and keypad keys.
+    pub fn input_modes(&self) -> InputModes {
+        InputModes {
+            application_cursor: self.modes.get(Mode::APP_CURSOR),
+            application_keypad: self.modes.get(Mode::APP_KEYPAD),
+        }
+    }
+
+    /// Everything the key encoder needs: input modes, modifyOtherKeys level
+    /// and kitty keyboard flags.
+    pub fn keyboard_state(&self) -> KeyboardState {
+        KeyboardState {
+            input_modes: self.input_modes(),
+            modify_other_keys: self.modify_other_keys,
+            kitty_flags: self.kitty_keyboard.last().copied().unwrap_or(0),
+        }
+    }
+
+    /// Current state of `mode`, or `None` if the emulator doesn't know it.
+    pub fn mode(&self, mode: Mode) -> Option<bool> {
+        match mode {
+            Mode::Dec(47 | 1047 | 1049) => Some(self.is_alternate_screen()),
+            _ => self.modes.query(mode),
+        }
+    }
+
+    /// Whether the program wants the cursor drawn (DECTCEM).
+    pub fn cursor_visible(&self) -> bool {
+        self.modes.get(Mode::CURSOR_VISIBLE)
+    }
+
+    /// Cursor shape and blinking requested via DECSCUSR.
+    pub fn cursor_style(&self) -> CursorStyle {
+        self.cursor_style
+    }
+
+    /// Capture the visible screen, cursor and title for rendering.
+    pub fn snapshot(&self) -> ScreenSnapshot<'_> {
+        ScreenSnapshot {
+            cols: self.cols,
+            rows: self.rows,
+            cursor_x: self.cursor_x,
+            cursor_y: self.cursor_y,
+            cursor_visible: self.cursor_visible(),
+            cursor_style: self.cursor_style,
+            reverse_video: self.modes.get(Mode::REVERSE_VIDEO),
+            title: &self.title,
+            alternate_screen: self.is_alternate_screen(),
+            lines: &self.cells,
+            line_renditions: &self.line_renditions,
+        }
+    }
+
+    /// Rows, cursor and scrolling changed since the previous call. Shares
+    /// dirty flags with `take_dirty_rows`, so use one or the other.
+    pub fn take_updates(&mut self) -> ScreenUpdate<'_> {
+        let rows = self.take_dirty_rows();
+        let cursor = UpdateCursor {
+            x: self.cursor_x,
+            y: self.cursor_y,
+            visible: self.cursor_visible(),
+            style: self.cursor_style,
+        };
+        let previous = self.last_update.replace((cursor, self.lines_scrolled));
+        let scrolled = previous.map_or(0, |(_, scrolled)| self.lines_scrolled - scrolled);
+        if !rows.is_empty() || previous.map(|(cursor, _)| cursor) != Some(cursor) || scrolled > 0 {
+            self.generation += 1;
+        }
+        ScreenUpdate {
+            generation: self.generation,
+            cols: self.cols,
+            rows: self.rows,
+            cursor,
+            reverse_video: self.modes.get(Mode::REVERSE_VIDEO),
+            alternate_screen: self.is_alternate_screen(),
+            scrolled,
+            lines: rows
+                .into_iter()
+                .map(|row| RowUpdate {
+                    row,
+                    cells: &self.cells[row],
+                    rendition: self.line_renditions[row],
+                })
+                .collect(),
+        }
+    }
+
+    /// Screen rows changed since the previous call, in ascending order.
+    /// Clears the dirty flags.
+    pub fn take_dirty_rows(&mut self) -> Vec<usize> {
+        let rows = self
+            .dirty
+            .iter()
+            .enumerate()
+            .filter_map(|(row, &dirty)| dirty.then_some(row))
+            .collect();
+        self.dirty.iter_mut().for_each(|d| *d = false);
+        rows
+    }
+
+    /// Drop all scrollback history.
+    pub fn clear_scrollback(&mut self) {
+        self.scrollback.clear();
+    }
+
+    /// Window title most recently set by the running program (empty if never set).
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// Icon name most recently set by the running program (empty if never set).
+    pub fn icon_name(&self) -> &str {
+        &self.icon_name
+    }
+
+    /// Configure which OSC 52 clipboard operations are honored.
+    pub fn set_clipboard_policy(&mut self, policy: ClipboardPolicy) {
+        self.clipboard_policy = policy;
+    }
+
+    /// Colors currently in effect.
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Set the app's color theme, replacing any colors the program changed.
+    pub fn set_theme(&mut self, theme: Palette) {
+        self.palette = theme.clone();
+        self.theme = theme;
+        self.mark_rows_dirty(0, self.rows - 1);
+    }
+
+    /// Set the answerback message sent in reply to ENQ.
+    pub fn set_answerback(&mut self, answerback: String) {
+        self.answerback = answerback;
+    }
+
+    /// Drain pending OSC 52 clipboard events, oldest first.
+    pub fn take_clipboard_events(&mut self) -> Vec<ClipboardEvent> {
+        self.clipboard_events.drain(..).collect()
+    }
+
+    /// Drain bells and notifications received since the last call, oldest first.
+    pub fn take_notifications(&mut self) -> Vec<Notification> {
+        self.notifications.drain(..).collect()
+    }
+
+    /// Drain inline images received since the last call, oldest first.
+    pub fn take_images(&mut self) -> Vec<InlineImage> {
+        self.images.drain(..).collect()
+    }
+
+    /// Whether the alternate screen buffer (used by vim, less, etc.) is active.
+    pub fn is_alternate_screen(&self) -> bool {
+        self.saved_primary.is_some()
+    }
+
+    /// Get the text content of a specific line.
+    pub fn get_line_text(&self, row: usize) -> String {
+        if row < self.cells.len() {
+            let mut text = String::with_capacity(self.cols);
+            for cell in &self.cells[row] {
+                cell.push_text(&mut text);
+            }
+            text
+        } else {
+            String::new()
+        }
+    }
+}
+
+/// Internal performer that implements vte::Perform.
+struct EmulatorPerformer<'a> {
+    emu: &'a mut VtEmulator,
+}
+
+impl VtEmulator {
+    /// Column of the last printed grapheme on the cursor row, if any.
+    fn previous_cell(&self) -> Option<usize> {
+        let row = self.cells.get(self.cursor_y)?;
+        // With a pending wrap the cursor still sits on the last written cell
+        let x = if self.wrap_pending {
+            self.cursor_x.min(row.len().checked_sub(1)?)
+        } else {
+            self.cursor_x.min(row.len()).checked_sub(1)?
+        };
+        if row[x].wide_spacer && x > 0 {
+            Some(x - 1)
+        } else {
+            Some(x)
+        }
+    }
+
+    /// Whether `ch` continues the previous grapheme without widening it,
+    /// so it belongs in that cell (emoji ZWJ sequences, skin tones).
+    fn joins_previous(&self, ch: char) -> bool {
+        self.previous_cell().is_some_and(|x| {
+            let cell = &self.cells[self.cursor_y][x];
+            match cell.combining.as_deref() {
+                None => joins_cluster(cell.ch.encode_utf8(&mut [0; 4]), ch),
+                Some(marks) => joins_cluster(&format!("{}{}", cell.ch, marks), ch),
+            }
+        })
+    }
+
+    /// Attach a zero-width character to the previous grapheme. Marks with no
+    /// base cell to attach to are dropped.
+    fn attach_to_previous(&mut self, ch: char) {
+        let Some(x) = self.previous_cell() else { return };
+        let cell = &mut self.cells[self.cursor_y][x];
+        let mut marks = cell.combining.take().map(String::from).unwrap_or_default();
+        marks.push(ch);
+        cell.combining = Some(marks.into_boxed_str());
+        self.dirty[self.cursor_y] = true;
+    }
+
+    /// Write `cell` at column `x` of the cursor row (plus its spacer when
+    /// wide), blanking the other half of any wide glyph it partially overwrites.
+    fn put_cell(&mut self, x: usize, cell: Cell) {
+        let blank = self.blank();
+        let end = if cell.wide { x + 1 } else { x };
+        let row = &mut self.cells[self.cursor_y];
+        if row[x].wide_spacer && x > 0 {
+            row[x - 1] = blank.clone();
+        }
+        if row[end].wide && end + 1 < row.len() {
+            row[end + 1] = blank;
+        }
+        if cell.wide {
+            row[end] = Cell { wide_spacer: true, wide: false, ch: ' ', ..cell.clone() };
+        }
+        row[x] = cell;
+    }
+
+    /// CPR reply (`CSI [prefix] row ; col R`), 1-based and relative to the
+    /// origin. A pending wrap reports the last column.
+    fn cursor_position_report(&self, prefix: &str) -> String {
+        let col = self.cursor_x.min(self.cols.saturating_sub(1)) + 1;
+        let origin = if self.modes.get(Mode::ORIGIN) { self.scroll_top } else { 0 };
+        format!("\x1b[{prefix}{};{col}R", self.cursor_y.saturating_sub(origin) + 1)
+    }
+
+    /// Move the cursor forward `n` tab stops, stopping at the last column.
+    fn tab_forward(&mut self, n: usize) {
+        for _ in 0..n {
+            let last = self.cols.saturating_sub(1);
+            self.cursor_x = (self.cursor_x + 1..last)
+                .find(|&x| self.tab_stops[x])
+                .unwrap_or(last);
+        }
+    }
+
+    /// Move the cursor back `n` tab stops, stopping at column 0.
+    fn tab_backward(&mut self, n: usize) {
+        for _ in 0..n {
+            self.cursor_x = (1..self.cursor_x.min(self.cols))
+                .rev()
+                .find(|&x| self.tab_stops[x])
+                .unwrap_or(0);
+        }
+    }
+
+    /// An empty cell carrying the current background color (xterm BCE).
+    fn blank(&self) -> Cell {
+        Cell {
+            bg: self.pen.bg,
+            ..Cell::default()
+        }
+    }
+
+    fn mark_dirty(&mut self, row: usize) {
+        if let Some(flag) = self.dirty.get_mut(row) {
+            *flag = true;
+        }
+    }
+
+    fn mark_rows_dirty(&mut self, top: usize, bottom: usize) {
+        for row in top..=bottom {
+            self.mark_dirty(row);
+        }
+    }
+
+    /// Blank cells `from..to` of screen row `row` with the current background.
+    fn erase(&mut self, row: usize, from: usize, to: usize) {
+        let blank = self.blank();
+        let to = to.min(self.cols);
+        if let Some(line) = self.cells.get_mut(row) {
+            for cell in line[from.min(to)..to].iter_mut() {
+                *cell = blank.clone();
+            }
+            self.mark_dirty(row);
+        }
+    }
+
+    /// Insert `n` blank cells at the cursor, shifting the rest of the line
+    /// right; cells pushed past the right margin are lost (ICH).
+    fn insert_chars(&mut self, n: usize) {
+        let blank = self.blank();
+        let x = self.cursor_x.min(self.cols.saturating_sub(1));
+        if let Some(line) = self.cells.get_mut(self.cursor_y) {
+            let n = n.min(line.len() - x);
+            line[x..].rotate_right(n);
+            line[x..x + n].fill(blank);
+            repair_wide_cells(line);
+            self.mark_dirty(self.cursor_y);
+        }
+    }
+
+    /// Delete `n` cells at the cursor, shifting the rest of the line left and
+    /// filling the right margin with blanks (DCH).
+    fn delete_chars(&mut self, n: usize) {
+        let blank = self.blank();
+        let x = self.cursor_x.min(self.cols.saturating_sub(1));
+        if let Some(line) = self.cells.get_mut(self.cursor_y) {
+            let n = n.min(line.len() - x);
+            line[x..].rotate_left(n);
+            let len = line.len();
+            line[len - n..].fill(blank);
+            repair_wide_cells(line);
+            self.mark_dirty(self.cursor_y);
+        }
+    }
+
+    /// Usable columns on the cursor's line: half the screen on double-width
+    /// and double-height lines.
+    fn line_width(&self) -> usize {
+        match self.line_renditions.get(self.cursor_y) {
+            Some(LineRendition::Single) | None => self.cols,
+            Some(_) => (self.cols / 2).max(1),
+        }
+    }
+
+    /// Set the cursor line's size (DECSWL/DECDWL/DECDHL). Columns beyond the
+    /// new width are lost, as on a VT100.
+    fn set_line_rendition(&mut self, rendition: LineRendition) {
+        let y = self.cursor_y;
+        if self.line_renditions.get(y) == Some(&rendition) {
+            return;
+        }
+        self.line_renditions[y] = rendition;
+        let width = self.line_width();
+        if width < self.cols {
+            self.erase(y, width, self.cols);
+        }
+        self.cursor_x = self.cursor_x.min(width - 1);
+        self.wrap_pending = false;
+        self.mark_dirty(y);
+    }
+
+    /// Scroll the lines in `top..=bottom` up by `n`, filling the bottom with blanks.
+    fn scroll_region_up(&mut self, top: usize, bottom: usize, n: usize) {
+        let n = n.min(bottom + 1 - top);
+        let blank = self.blank();
+        let removed: Vec<Vec<Cell>> = self.cells.drain(top..top + n).collect();
+        self.line_renditions.drain(top..top + n);
+        // Only full-screen scrolls of the primary screen feed scrollback;
+        // partial regions and the alternate screen (vim, less) must not pollute it.
+        if top == 0 && self.saved_primary.is_none() {
+            self.lines_scrolled += n as u64;
+            for line in &removed {
+                self.scrollback.push(line);
+            }
+        }
+        for _ in 0..n {
+            self.cells.insert(bottom + 1 - n, vec![blank.clone(); self.cols]);
+            self.line_renditions.insert(bottom + 1 - n, LineRendition::Single);
+        }
+        self.mark_rows_dirty(top, bottom);
+    }
+
+    /// Scroll the lines in `top..=bottom` down by `n`, filling the top with blanks.
+    fn scroll_region_down(&mut self, top: usize, bottom: usize, n: usize) {
+        let n = n.min(bottom + 1 - top);
+        let blank = self.blank();
+        self.cells.drain(bottom + 1 - n..=bottom);
+        self.line_renditions.drain(bottom + 1 - n..=bottom);
+        for _ in 0..n {
+            self.cells.insert(top, vec![blank.clone(); self.cols]);
+            self.line_renditions.insert(top, LineRendition::Single);
+        }
+        self.mark_rows_dirty(top, bottom);
+    }
+
+    fn scroll_up(&mut self) {
+        self.scroll_region_up(self.scroll_top, self.scroll_bottom, 1);
+    }
+
+    /// Move the cursor down one line, scrolling if it sits on the bottom margin.
+    fn linefeed(&mut self) {
+        if self.cursor_y == self.scroll_bottom {
+            self.scroll_up();
+        } else if self.cursor_y + 1 < self.rows {
+            self.cursor_y += 1;
+        }
+    }
+
+    /// Move the cursor up one line, scrolling down if it sits on the top margin (RI).
+    fn reverse_index(&mut self) {
+        if self.cursor_y == self.scroll_top {
+            self.scroll_region_down(self.scroll_top, self.scroll_bottom, 1);
+        } else if self.cursor_y > 0 {
+            self.cursor_y -= 1;
+        }
+    }
+
+    fn newline(&mut self) {
+        self.cursor_x = 0;
+        self.linefeed();
+    }
+
+    /// Switch to the alternate screen, stashing the primary grid.
+    /// With `save_cursor` (mode 1049) the primary cursor is restored on exit.
+    fn enter_alternate_screen(&mut self, save_cursor: bool) {
+        if self.saved_primary.is_some() {
+            return;
+        }
+        let blank = vec![vec![Cell::default(); self.cols]; self.rows];
+        let cells = std::mem::replace(&mut self.cells, blank);
+        let renditions = std::mem::replace(&mut self.line_renditions, vec![LineRendition::Single; self.rows]);
+        let cursor = save_cursor.then_some((self.cursor_x, self.cursor_y));
+        self.saved_primary = Some(SavedScreen { cells, renditions, cursor });
+        self.mark_rows_dirty(0, self.rows - 1);
+    }
+
+    /// Leave the alternate screen, discarding its content and restoring the primary grid.
+    fn leave_alternate_screen(&mut self) {
+        if let Some(saved) = self.saved_primary.take() {
+            self.cells = saved.cells;
+            self.line_renditions = saved.renditions;
+            if let Some((x, y)) = saved.cursor {
+                self.cursor_x = x;
+                self.cursor_y = y;
+                self.wrap_pending = false;
+            }
+            self.mark_rows_dirty(0, self.rows - 1);
+        }
+    }
+
+    /// Queue a bell or notification, dropping the oldest once the queue is full.
+    fn push_notification(&mut self, kind: NotificationKind) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        if self.notifications.len() >= MAX_PENDING_NOTIFICATIONS {
+            self.notifications.pop_front();
+        }
+        self.notifications.push_back(Notification { kind, timestamp_ms });
+    }
+
+    /// Handle `OSC 9 ; body` and `OSC 777 ; notify ; title ; body`.
+    fn handle_notification_osc(&mut self, params: &[&[u8]]) {
+        let join = |parts: &[&[u8]]| {
+            parts
+                .iter()
+                .map(|p| String::from_utf8_lossy(p))
+                .collect::<Vec<_>>()
+                .join(";")
+        };
+        let kind = match params[0] {
+            // ConEmu reuses OSC 9 with numeric subcommands (progress etc.)
+            b"9" if params[1].iter().all(u8::is_ascii_digit) && params.len() > 2 => return,
+            b"9" => NotificationKind::Notify { title: String::new(), body: join(&params[1..]) },
+            _ if params.len() >= 3 && params[1] == b"notify" => NotificationKind::Notify {
+                title: String::from_utf8_lossy(params[2]).into_owned(),
+                body: join(&params[3..]),
+            },
+            _ => return,
+        };
+        self.push_notification(kind);
+    }
+
+    /// Handle `OSC 4 ; index ; spec [; index ; spec ...]`, where a `?` spec
+    /// queries the color.
+    fn handle_palette_osc(&mut self, params: &[&[u8]], terminator: &str) {
+        for pair in params[1..].chunks_exact(2) {
+            let Some(index) = std::str::from_utf8(pair[0]).ok().and_then(|i| i.parse::<u8>().ok()) else {
+                continue;
+            };
+            let spec = String::from_utf8_lossy(pair[1]);
+            if spec == "?" {
+                let color = palette::format_color_spec(self.palette.colors[index as usize]);
+                let reply = format!("\x1b]4;{index};{color}{terminator}");
+                self.responses.extend_from_slice(reply.as_bytes());
+            } else if let Some(color) = palette::parse_color_spec(&spec) {
+                self.palette.colors[index as usize] = color;
+                self.mark_rows_dirty(0, self.rows - 1);
+            }
+        }
+    }
+
+    /// Handle OSC 10/11/12 (foreground, background, cursor color). Further
+    /// specs apply to the following commands, so `OSC 10 ; fg ; bg` sets both.
+    fn handle_dynamic_color_osc(&mut self, first: u8, specs: &[&[u8]], terminator: &str) {
+        for (command, spec) in (first..=12).zip(specs) {
+            let spec = String::from_utf8_lossy(spec);
+            if spec == "?" {
+                let color = match command {
+                    10 => self.palette.foreground,
+                    11 => self.palette.background,
+                    _ => self.palette.cursor.unwrap_or(self.palette.foreground),
+                };
+                let reply = format!("\x1b]{command};{}{terminator}", palette::format_color_spec(color));
+                self.responses.extend_from_slice(reply.as_bytes());
+                continue;
+            }
+            let Some(color) = palette::parse_color_spec(&spec) else { continue };
+            match command {
+                10 => self.palette.foreground = color,
+                11 => self.palette.background = color,
+                _ => self.palette.cursor = Some(color),
+            }
+            self.mark_rows_dirty(0, self.rows - 1);
+        }
+    }
+
+    /// Handle OSC 104 (reset palette entries, all if none are listed) and
+    /// OSC 110/111/112 (reset foreground, background, cursor color).
+    fn handle_color_reset_osc(&mut self, params: &[&[u8]]) {
+        match params[0] {
+            b"104" => {
+                let indices: Vec<u8> = params[1..]
+                    .iter()
+                    .filter_map(|p| std::str::from_utf8(p).ok()?.parse().ok())
+                    .collect();
+                if params.len() == 1 || params[1].is_empty() {
+                    self.palette.colors = self.theme.colors;
+                }
+                for index in indices {
+                    self.palette.colors[index as usize] = self.theme.colors[index as usize];
+                }
+            }
+            b"110" => self.palette.foreground = self.theme.foreground,
+            b"111" => self.palette.background = self.theme.background,
+            _ => self.palette.cursor = self.theme.cursor,
+        }
+        self.mark_rows_dirty(0, self.rows - 1);
+    }
+
+    /// Handle an iTerm2 `OSC 1337 ; File=...` inline image. Images sized in
+    /// cells move the cursor past them like text would; other sizes depend
+    /// on pixel dimensions only the renderer knows.
+    fn handle_image_osc(&mut self, params: &[&[u8]]) {
+        let text = params[1..].join(&b';');
+        let Some(mut image) = images::parse_file_payload(&text) else { return };
+        image.id = self.next_image_id;
+        self.next_image_id += 1;
+        image.row = self.cursor_row();
+        image.col = self.cursor_x;
+
+        if image.inline {
+            if let ImageSize::Cells(height) = image.height {
+                for _ in 1..height {
+                    self.linefeed();
+                }
+            }
+            if let ImageSize::Cells(width) = image.width {
+                self.cursor_x = (image.col + width as usize).min(self.cols - 1);
+            }
+            self.wrap_pending = false;
+        }
+
+        if self.images.len() >= MAX_PENDING_IMAGES {
+            self.images.pop_front();
+        }
+        self.images.push_back(image);
+    }
+
+    /// Handle an OSC 52 clipboard request: `52 ; selection ; base64 | ?`.
+    fn handle_clipboard_osc(&mut self, selection: &[u8], payload: &[u8]) {
+        let selection = if selection.is_empty() {
+            "c".to_string()
+        } else {
+            String::from_utf8_lossy(selection).into_owned()
+        };
+
+        let event = if payload == b"?" {
+            if !self.clipboard_policy.allow_read {
+                log::debug!("OSC 52 clipboard read denied by policy");
+                return;
+            }
+            ClipboardEvent::Read { selection }
+        } else {
+            if !self.clipboard_policy.allow_write {
+                log::debug!("OSC 52 clipboard write denied by policy");
+                return;
+            }
+            match data_encoding::BASE64.decode(payload) {
+                Ok(bytes) => ClipboardEvent::Write {
+                    selection,
+                    data: String::from_utf8_lossy(&bytes).into_owned(),
+                },
+                Err(e) => {
+                    log::debug!("Ignoring malformed OSC 52 payload: {}", e);
+                    return;
+                }
+            }
+        };
+
+        if self.clipboard_events.len() >= MAX_PENDING_CLIPBOARD_EVENTS {
+            self.clipboard_events.pop_front();
+        }
+        self.clipboard_events.push_back(event);
+    }
+
+    /// Absolute row of the cursor.
+    fn cursor_row(&self) -> u64 {
+        self.lines_scrolled + self.cursor_y as u64
+    }
+
+    /// Collect the text between an absolute (row, col) position and the cursor.
+    fn text_since(&self, (row, col): (u64, usize)) -> String {
+        let end_row = self.cursor_row();
+        let mut text = String::new();
+        for r in row..=end_row {
+            let Some(line) = self.line_at(r) else { continue };
+            let from = if r == row { col.min(line.len()) } else { 0 };
+            let end_col = self.cursor_x + usize::from(self.wrap_pending);
+            let to = if r == end_row { end_col.min(line.len()).max(from) } else { line.len() };
+            for cell in &line[from..to] {
+                cell.push_text(&mut text);
+            }
+        }
+        text.trim().to_string()
+    }
+
+    /// Record a finished command, trimming history to its cap.
+    fn finish_command(&mut self, record: CommandRecord) {
+        if self.commands.len() >= MAX_COMMAND_HISTORY {
+            self.commands.pop_front();
+        }
+        self.commands.push_back(record);
+    }
+
+    /// Handle an OSC 133 (FinalTerm) semantic prompt mark.
+    fn handle_prompt_mark(&mut self, params: &[&[u8]]) {
+        let Some(&mark) = params.get(1) else { return };
+        let row = self.cursor_row();
+        match mark {
+            // Prompt start: a new command begins
+            b"A" => {
+                if let Some(previous) = self.pending_command.take() {
+                    if previous.record.output_start_row.is_some() {
+                        self.finish_command(previous.record);
+                    }
+                }
+                self.pending_command = Some(PendingCommand {
+                    record: CommandRecord {
+                        command: String::new(),
+                        exit_code: None,
+                        prompt_row: row,
+                        output_start_row: None,
+                        output_end_row: None,
+                    },
+                    input_start: None,
+                });
+            }
+            // Prompt end: user input starts at the cursor
+            b"B" => {
+                let pos = (row, self.cursor_x);
+                if let Some(pending) = self.pending_command.as_mut() {
+                    pending.input_start = Some(pos);
+                }
+            }
+            // Command executed: output starts
+            b"C" => {
+                let Some(input_start) = self.pending_command.as_ref().and_then(|p| p.input_start) else {
+                    return;
+                };
+                let command = self.text_since(input_start);
+                if let Some(pending) = self.pending_command.as_mut() {
+                    pending.record.command = command;
+                    pending.record.output_start_row = Some(row);
+                }
+            }
+            // Command finished, optionally with its exit code
+            b"D" => {
+                if let Some(mut pending) = self.pending_command.take() {
+                    if pending.record.output_start_row.is_none() {
+                        // Empty command line (just Enter): nothing ran.
+                        return;
+                    }
+                    pending.record.exit_code = params
+                        .get(2)
+                        .and_then(|code| std::str::from_utf8(code).ok())
+                        .and_then(|code| code.parse().ok());
+                    pending.record.output_end_row = Some(row);
+                    self.finish_command(pending.record);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle mode set/reset (`CSI [?] Pm h` / `CSI [?] Pm l`): record the
+    /// new state, then apply any side effects the mode has.
+    fn set_mode(&mut self, mode: Mode, enabled: bool) {
+        if let Mode::Dec(code @ (47 | 1047 | 1049)) = mode {
+            // Alternate screen state lives in `saved_primary`
+            if enabled {
+                self.enter_alternate_screen(code == 1049);
+            } else {
+                self.leave_alternate_screen();
+            }
+            return;
+        }
+        self.modes.set(mode, enabled);
+        match mode {
+            Mode::AUTOWRAP if !enabled => self.wrap_pending = false,
+            Mode::CURSOR_VISIBLE => self.mark_dirty(self.cursor_y),
+            Mode::REVERSE_VIDEO => self.mark_rows_dirty(0, self.rows - 1),
+            // DECOM homes the cursor to the new origin
+            Mode::ORIGIN => self.move_to(0, 0),
+            _ => {}
         }
     }
-}
 
-impl VtEmulator {
-    pub fn new(cols: usize, rows: usize) -> Self {
-        let cells = vec![vec![Cell::default(); cols]; rows];
-        Self {
-            parser: Parser::new(),
-            cursor_x: 0,
-            cursor_y: 0,
-            cols,
-            rows,
-            cells,
+    /// DECRQM reply (`CSI [?] Ps ; Pm $ y`): 1 set, 2 reset, 0 unrecognized.
+    fn mode_report(&self, mode: Mode) -> String {
+        let state = match self.mode(mode) {
+            Some(true) => 1,
+            Some(false) => 2,
+            None => 0,
+        };
+        match mode {
+            Mode::Ansi(code) => format!("\x1b[{code};{state}$y"),
+            Mode::Dec(code) => format!("\x1b[?{code};{state}$y"),
         }
     }
 
-    /// Feed raw bytes from PTY into the VT parser.
-    pub fn process(&mut self, bytes: &[u8]) {
-        let mut performer = EmulatorPerformer {
-            cursor_x: &mut self.cursor_x,
-            cursor_y: &mut self.cursor_y,
-            cols: self.cols,
-            rows: self.rows,
-            cells: &mut self.cells,
+    /// Move the cursor to 0-based (`row`, `col`), relative to the scroll
+    /// region top in origin mode and clamped to the screen (or region).
+    fn move_to(&mut self, row: usize, col: usize) {
+        let (top, bottom) = if self.modes.get(Mode::ORIGIN) {
+            (self.scroll_top, self.scroll_bottom)
+        } else {
+            (0, self.rows - 1)
         };
-        self.parser.advance(&mut performer, bytes);
+        self.cursor_y = (top + row).min(bottom);
+        self.cursor_x = col.min(self.cols - 1);
+        self.wrap_pending = false;
     }
 
-    /// Resize the emulator grid.
-    pub fn resize(&mut self, cols: usize, rows: usize) {
-        self.cols = cols;
-        self.rows = rows;
-        self.cells.resize(rows, vec![Cell::default(); cols]);
-        for row in self.cells.iter_mut() {
-            row.resize(cols, Cell::default());
-        }
-        if self.cursor_x >= cols {
-            self.cursor_x = cols - 1;
-        }
-        if self.cursor_y >= rows {
-            self.cursor_y = rows - 1;
+    /// Rows that CUU/CUD stop at: the scroll region in origin mode or while
+    /// the cursor is inside it, otherwise the whole screen.
+    fn vertical_margins(&self) -> (usize, usize) {
+        let inside = (self.scroll_top..=self.scroll_bottom).contains(&self.cursor_y);
+        if self.modes.get(Mode::ORIGIN) || inside {
+            (self.scroll_top, self.scroll_bottom)
+        } else {
+            (0, self.rows - 1)
         }
     }
 
-    /// Get the text content of a specific line.
-    pub fn get_line_text(&self, row: usize) -> String {
-        if row < self.cells.len() {
-            self.cells[row].iter().map(|c| c.ch).collect()
-        } else {
-            String::new()
+    /// Apply an SGR (Select Graphic Rendition) parameter list to the pen.
+    fn set_graphics_rendition(&mut self, params: &vte::Params) {
+        if params.is_empty() {
+            self.pen = Cell::default();
+            return;
+        }
+        let mut iter = params.iter();
+        while let Some(param) = iter.next() {
+            let code = param.first().copied().unwrap_or(0);
+            match code {
+                0 => self.pen = Cell::default(),
+                1 => self.pen.bold = true,
+                2 => self.pen.faint = true,
+                3 => self.pen.italic = true,
+                // `4:n` selects the underline style; plain 4 is a single line
+                4 => {
+                    let style = param.get(1).map_or(Some(UnderlineStyle::Single), |&n| UnderlineStyle::from_subparam(n));
+                    if let Some(style) = style {
+                        self.pen.underline = style;
+                    }
+                }
+                5 | 6 => self.pen.blink = true,
+                7 => self.pen.reverse = true,
+                8 => self.pen.invisible = true,
+                9 => self.pen.strikethrough = true,
+                21 => self.pen.underline = UnderlineStyle::Double,
+                22 => {
+                    self.pen.bold = false;
+                    self.pen.faint = false;
+                }
+                23 => self.pen.italic = false,
+                24 => self.pen.underline = UnderlineStyle::None,
+                25 => self.pen.blink = false,
+                27 => self.pen.reverse = false,
+                28 => self.pen.invisible = false,
+                29 => self.pen.strikethrough = false,
+                30..=37 => self.pen.fg = Color::Indexed((code - 30) as u8),
+                38 => {
+                    if let Some(color) = parse_extended_color(param, &mut iter) {
+                        self.pen.fg = color;
+                    }
+                }
+                39 => self.pen.fg = Color::Default,
+                40..=47 => self.pen.bg = Color::Indexed((code - 40) as u8),
+                48 => {
+                    if let Some(color) = parse_extended_color(param, &mut iter) {
+                        self.pen.bg = color;
+                    }
+                }
+                49 => self.pen.bg = Color::Default,
+                53 => self.pen.overline = true,
+                55 => self.pen.overline = false,
+                58 => {
+                    if let Some(color) = parse_extended_color(param, &mut iter) {
+                        self.pen.underline_color = color;
+                    }
+                }
+                59 => self.pen.underline_color = Color::Default,
+                90..=97 => self.pen.fg = Color::Indexed((code - 90 + 8) as u8),
+                100..=107 => self.pen.bg = Color::Indexed((code - 100 + 8) as u8),
+                _ => {}
+            }
         }
     }
 }
 
-/// Internal performer that implements vte::Perform.
-struct EmulatorPerformer<'a> {
-    cursor_x: &'a mut usize,
-    cursor_y: &'a mut usize,
-    cols: usize,
-    rows: usize,
-    cells: &'a mut Vec<Vec<Cell>>,
+/// Encode the reply to an OSC 52 clipboard read, to be written back to the PTY.
+pub fn osc52_response(selection: &str, data: &str) -> Vec<u8> {
+    format!(
+        "\x1b]52;{};{}\x07",
+        selection,
+        data_encoding::BASE64.encode(data.as_bytes())
+    )
+    .into_bytes()
 }
 
-impl<'a> EmulatorPerformer<'a> {
-    fn scroll_up(&mut self) {
-        self.cells.remove(0);
-        self.cells.push(vec![Cell::default(); self.cols]);
+/// Parse the color spec following SGR 38/48.
+///
+/// Accepts both the legacy semicolon form (`38;5;n`, `38;2;r;g;b`), where the
+/// components arrive as separate params, and the ITU T.416 colon form
+/// (`38:5:n`, `38:2::r:g:b`), where they arrive as subparams of `param`.
+fn parse_extended_color(param: &[u16], iter: &mut vte::ParamsIter) -> Option<Color> {
+    if param.len() > 1 {
+        return match param[1] {
+            5 => param.get(2).map(|&n| Color::Indexed(n as u8)),
+            // The colon form may carry a color-space id before r;g;b.
+            2 => {
+                let rgb = if param.len() >= 6 { &param[3..6] } else { param.get(2..5)? };
+                Some(Color::Rgb(rgb[0] as u8, rgb[1] as u8, rgb[2] as u8))
+            }
+            _ => None,
+        };
     }
 
-    fn newline(&mut self) {
-        *self.cursor_x = 0;
-        if *self.cursor_y + 1 >= self.rows {
-            self.scroll_up();
-        } else {
-            *self.cursor_y += 1;
+    let mut next = || iter.next().and_then(|p| p.first().copied());
+    match next()? {
+        5 => next().map(|n| Color::Indexed(n as u8)),
+        2 => {
+            let r = next()?;
+            let g = next()?;
+            let b = next()?;
+            Some(Color::Rgb(r as u8, g as u8, b as u8))
         }
+        _ => None,
     }
 }
 
 impl<'a> Perform for EmulatorPerformer<'a> {
     fn print(&mut self, ch: char) {
-        if *self.cursor_x >= self.cols {
-            self.newline();
+        let emu = &mut *self.emu;
+        let width = char_width(ch);
+        if width == 0 || emu.joins_previous(ch) {
+            emu.attach_to_previous(ch);
+            return;
+        }
+        if emu.cursor_y >= emu.cells.len() || width > emu.cols {
+            return;
+        }
+        if emu.wrap_pending {
+            emu.wrap_pending = false;
+            emu.newline();
         }
-        if *self.cursor_y < self.cells.len() && *self.cursor_x < self.cols {
-            self.cells[*self.cursor_y][*self.cursor_x].ch = ch;
-            *self.cursor_x += 1;
+        if emu.cursor_x + width > emu.line_width() {
+            if emu.modes.get(Mode::AUTOWRAP) {
+                // A wide glyph that doesn't fit in the last column wraps whole
+                let blank = emu.blank();
+                emu.put_cell(emu.cursor_x, blank);
+                emu.newline();
+            } else {
+                emu.cursor_x = emu.line_width().saturating_sub(width);
+            }
+        }
+        if emu.modes.get(Mode::INSERT) {
+            emu.insert_chars(width);
+        }
+        let x = emu.cursor_x;
+        emu.put_cell(x, Cell { ch, wide: width == 2, ..emu.pen.clone() });
+        emu.dirty[emu.cursor_y] = true;
+        let cols = emu.line_width();
+        if x + width < cols {
+            emu.cursor_x = x + width;
+        } else {
+            // Stay on the last column; without DECAWM further output overwrites it
+            emu.cursor_x = cols - 1;
+            emu.wrap_pending = emu.modes.get(Mode::AUTOWRAP);
         }
     }
 
     fn execute(&mut self, byte: u8) {
+        let emu = &mut *self.emu;
+        if byte != 0x07 {
+            emu.wrap_pending = false;
+        }
         match byte {
             // Newline (LF)
-            b'\n' | 0x0b | 0x0c => {
-                if *self.cursor_y + 1 >= self.rows {
-                    self.scroll_up();
-                } else {
-                    *self.cursor_y += 1;
-                }
-            }
+            b'\n' | 0x0b | 0x0c => emu.linefeed(),
             // Carriage return
             b'\r' => {
-                *self.cursor_x = 0;
+                emu.cursor_x = 0;
             }
             // Backspace
             0x08 => {
-                if *self.cursor_x > 0 {
-                    *self.cursor_x -= 1;
+                if emu.cursor_x > 0 {
+                    emu.cursor_x -= 1;
                 }
             }
             // Tab
-            b'\t' => {
-                let next_tab = (*self.cursor_x / 8 + 1) * 8;
-                *self.cursor_x = next_tab.min(self.cols - 1);
-            }
+            b'\t' => emu.tab_forward(1),
             // Bell
-            0x07 => { /* TODO: visual bell */ }
+            0x07 => emu.push_notification(NotificationKind::Bell),
+            // Enquiry (ENQ)
+            0x05 => emu.responses.extend_from_slice(emu.answerback.as_bytes()),
             _ => {}
         }
     }
@@ -164,107 +1559,309 @@ impl<'a> Perform for EmulatorPerformer<'a> {
     fn put(&mut self, _byte: u8) {}
     fn unhook(&mut self) {}
 
-    fn osc_dispatch(&mut self, _params: &[&[u8]], _bell_terminated: bool) {
-        // TODO: handle OSC sequences (window title, clipboard, etc.)
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        let emu = &mut *self.emu;
+        let Some(&command) = params.first() else { return };
+        // Replies end the way the query did
+        let terminator = if bell_terminated { "\x07" } else { "\x1b\\" };
+        match command {
+            // Palette colors
+            b"4" => emu.handle_palette_osc(params, terminator),
+            // Default foreground, background and cursor colors
+            b"10" => emu.handle_dynamic_color_osc(10, &params[1..], terminator),
+            b"11" => emu.handle_dynamic_color_osc(11, &params[1..], terminator),
+            b"12" => emu.handle_dynamic_color_osc(12, &params[1..], terminator),
+            b"104" | b"110" | b"111" | b"112" => emu.handle_color_reset_osc(params),
+            // Icon name and window title
+            b"0" | b"1" | b"2" => {
+                // vte splits on ';', so a title containing semicolons arrives in pieces.
+                let text = params[1..]
+                    .iter()
+                    .map(|p| String::from_utf8_lossy(p))
+                    .collect::<Vec<_>>()
+                    .join(";");
+                if command != b"2" {
+                    emu.icon_name = text.clone();
+                }
+                if command != b"1" {
+                    emu.title = text;
+                }
+            }
+            // Shell integration prompt marks
+            b"133" => emu.handle_prompt_mark(params),
+            // Clipboard manipulation
+            b"52" if params.len() >= 3 => emu.handle_clipboard_osc(params[1], params[2]),
+            // Desktop notifications
+            b"9" | b"777" if params.len() >= 2 => emu.handle_notification_osc(params),
+            // iTerm2 inline images
+            b"1337" if params.len() >= 2 => emu.handle_image_osc(params),
+            _ => {}
+        }
     }
 
-    fn csi_dispatch(&mut self, params: &vte::Params, _intermediates: &[u8], _ignore: bool, action: char) {
+    fn csi_dispatch(&mut self, params: &vte::Params, intermediates: &[u8], _ignore: bool, action: char) {
+        let emu = &mut *self.emu;
         let mut params_iter = params.iter();
         let first = params_iter.next().and_then(|p| p.first().copied()).unwrap_or(0);
         let second = params_iter.next().and_then(|p| p.first().copied()).unwrap_or(0);
 
+        // Cursor movement and line/character editing cancel a pending wrap
+        if matches!(action, 'A'..='H' | 'I' | 'L' | 'M' | 'P' | 'X' | 'Z' | '@' | 'f' | 'r') {
+            emu.wrap_pending = false;
+        }
+
         match action {
             // Cursor Up
             'A' => {
                 let n = if first == 0 { 1 } else { first as usize };
-                *self.cursor_y = self.cursor_y.saturating_sub(n);
+                let (top, _) = emu.vertical_margins();
+                emu.cursor_y = emu.cursor_y.saturating_sub(n).max(top);
             }
             // Cursor Down
             'B' => {
                 let n = if first == 0 { 1 } else { first as usize };
-                *self.cursor_y = (*self.cursor_y + n).min(self.rows - 1);
+                let (_, bottom) = emu.vertical_margins();
+                emu.cursor_y = emu.cursor_y.saturating_add(n).min(bottom);
             }
             // Cursor Forward
             'C' => {
                 let n = if first == 0 { 1 } else { first as usize };
-                *self.cursor_x = (*self.cursor_x + n).min(self.cols - 1);
+                emu.cursor_x = (emu.cursor_x + n).min(emu.cols - 1);
             }
             // Cursor Back
             'D' => {
                 let n = if first == 0 { 1 } else { first as usize };
-                *self.cursor_x = self.cursor_x.saturating_sub(n);
+                emu.cursor_x = emu.cursor_x.saturating_sub(n);
             }
             // Cursor Position (H or f)
             'H' | 'f' => {
                 let row = if first == 0 { 1 } else { first as usize };
                 let col = if second == 0 { 1 } else { second as usize };
-                *self.cursor_y = (row - 1).min(self.rows - 1);
-                *self.cursor_x = (col - 1).min(self.cols - 1);
+                emu.move_to(row - 1, col - 1);
             }
             // Erase in Display
             'J' => {
-                match first {
+                let (x, y, cols, rows) = (emu.cursor_x, emu.cursor_y, emu.cols, emu.rows);
+                // Lines erased entirely return to single width
+                let cleared = match first {
                     0 => {
                         // Clear from cursor to end of screen
-                        for x in *self.cursor_x..self.cols {
-                            self.cells[*self.cursor_y][x] = Cell::default();
-                        }
-                        for y in (*self.cursor_y + 1)..self.rows {
-                            for x in 0..self.cols {
-                                self.cells[y][x] = Cell::default();
-                            }
+                        emu.erase(y, x, cols);
+                        for row in (y + 1)..rows {
+                            emu.erase(row, 0, cols);
                         }
+                        (y + usize::from(x > 0))..rows
                     }
                     1 => {
                         // Clear from start to cursor
-                        for y in 0..*self.cursor_y {
-                            for x in 0..self.cols {
-                                self.cells[y][x] = Cell::default();
-                            }
-                        }
-                        for x in 0..=*self.cursor_x {
-                            self.cells[*self.cursor_y][x] = Cell::default();
+                        for row in 0..y {
+                            emu.erase(row, 0, cols);
                         }
+                        emu.erase(y, 0, x + 1);
+                        0..y
                     }
-                    2 | 3 => {
+                    2 => {
                         // Clear entire screen
-                        for y in 0..self.rows {
-                            for x in 0..self.cols {
-                                self.cells[y][x] = Cell::default();
-                            }
+                        for row in 0..rows {
+                            emu.erase(row, 0, cols);
                         }
+                        0..rows
                     }
-                    _ => {}
-                }
+                    3 => {
+                        // Clear scrollback only, the screen stays (xterm extension)
+                        emu.clear_scrollback();
+                        0..0
+                    }
+                    _ => 0..0,
+                };
+                emu.line_renditions[cleared].fill(LineRendition::Single);
             }
             // Erase in Line
             'K' => {
+                let (x, y, cols) = (emu.cursor_x, emu.cursor_y, emu.cols);
                 match first {
-                    0 => {
-                        for x in *self.cursor_x..self.cols {
-                            self.cells[*self.cursor_y][x] = Cell::default();
-                        }
-                    }
-                    1 => {
-                        for x in 0..=*self.cursor_x {
-                            self.cells[*self.cursor_y][x] = Cell::default();
-                        }
-                    }
-                    2 => {
-                        for x in 0..self.cols {
-                            self.cells[*self.cursor_y][x] = Cell::default();
-                        }
-                    }
+                    0 => emu.erase(y, x, cols),
+                    1 => emu.erase(y, 0, x + 1),
+                    2 => emu.erase(y, 0, cols),
                     _ => {}
                 }
             }
+            // Insert Lines (IL)
+            'L' if (emu.scroll_top..=emu.scroll_bottom).contains(&emu.cursor_y) => {
+                let n = if first == 0 { 1 } else { first as usize };
+                emu.scroll_region_down(emu.cursor_y, emu.scroll_bottom, n);
+                emu.cursor_x = 0;
+            }
+            // Delete Lines (DL)
+            'M' if (emu.scroll_top..=emu.scroll_bottom).contains(&emu.cursor_y) => {
+                let n = if first == 0 { 1 } else { first as usize };
+                emu.scroll_region_up(emu.cursor_y, emu.scroll_bottom, n);
+                emu.cursor_x = 0;
+            }
+            // Insert Characters (ICH)
+            '@' if intermediates.is_empty() => emu.insert_chars(first.max(1) as usize),
+            // Delete Characters (DCH)
+            'P' => emu.delete_chars(first.max(1) as usize),
+            // Erase Characters (ECH)
+            'X' => {
+                let x = emu.cursor_x.min(emu.cols.saturating_sub(1));
+                emu.erase(emu.cursor_y, x, x + first.max(1) as usize);
+                if let Some(line) = emu.cells.get_mut(emu.cursor_y) {
+                    repair_wide_cells(line);
+                }
+            }
+            // Set Top and Bottom Margins (DECSTBM)
+            'r' if intermediates.is_empty() => {
+                let top = if first == 0 { 1 } else { first as usize };
+                let bottom = if second == 0 { emu.rows } else { (second as usize).min(emu.rows) };
+                if top < bottom {
+                    emu.scroll_top = top - 1;
+                    emu.scroll_bottom = bottom - 1;
+                    emu.move_to(0, 0);
+                }
+            }
+            // Select Graphic Rendition
+            'm' if intermediates.is_empty() => emu.set_graphics_rendition(params),
+            // xterm modifyOtherKeys (`CSI > 4 ; Pv m`)
+            'm' if intermediates == [b'>'] && first == 4 => emu.modify_other_keys = second.min(2) as u8,
+            // Kitty keyboard protocol: push, pop, set and query flags
+            'u' if intermediates == [b'>'] => {
+                if emu.kitty_keyboard.len() >= MAX_KITTY_KEYBOARD_STACK {
+                    emu.kitty_keyboard.remove(0);
+                }
+                emu.kitty_keyboard.push(first as u32);
+            }
+            'u' if intermediates == [b'<'] => {
+                let n = (first.max(1) as usize).min(emu.kitty_keyboard.len());
+                emu.kitty_keyboard.truncate(emu.kitty_keyboard.len() - n);
+            }
+            'u' if intermediates == [b'='] => {
+                let flags = first as u32;
+                let current = emu.kitty_keyboard.last().copied().unwrap_or(0);
+                let updated = match second {
+                    2 => current | flags,
+                    3 => current & !flags,
+                    _ => flags,
+                };
+                match emu.kitty_keyboard.last_mut() {
+                    Some(top) => *top = updated,
+                    None => emu.kitty_keyboard.push(updated),
+                }
+            }
+            'u' if intermediates == [b'?'] => {
+                let flags = emu.kitty_keyboard.last().copied().unwrap_or(0);
+                emu.responses.extend_from_slice(format!("\x1b[?{flags}u").as_bytes());
+            }
+            // DEC private mode set/reset
+            'h' | 'l' if intermediates == [b'?'] => {
+                for param in params.iter() {
+                    emu.set_mode(Mode::Dec(param.first().copied().unwrap_or(0)), action == 'h');
+                }
+            }
+            // ANSI mode set/reset (SM/RM)
+            'h' | 'l' if intermediates.is_empty() => {
+                for param in params.iter() {
+                    emu.set_mode(Mode::Ansi(param.first().copied().unwrap_or(0)), action == 'h');
+                }
+            }
+            // Request mode (DECRQM)
+            'p' if intermediates == [b'$'] || intermediates == [b'?', b'$'] => {
+                let mode = if intermediates[0] == b'?' { Mode::Dec(first) } else { Mode::Ansi(first) };
+                let reply = emu.mode_report(mode);
+                emu.responses.extend_from_slice(reply.as_bytes());
+            }
+            // Cursor Horizontal Tab (CHT)
+            'I' => emu.tab_forward(first.max(1) as usize),
+            // Cursor Backward Tab (CBT)
+            'Z' => emu.tab_backward(first.max(1) as usize),
+            // Tab Clear (TBC): 0 = stop at cursor, 3 = all stops
+            'g' => match first {
+                0 => {
+                    if let Some(stop) = emu.tab_stops.get_mut(emu.cursor_x) {
+                        *stop = false;
+                    }
+                }
+                3 => emu.tab_stops.iter_mut().for_each(|stop| *stop = false),
+                _ => {}
+            },
+            // Device Status Report (DSR)
+            'n' if intermediates.is_empty() => match first {
+                // Operating status: OK
+                5 => emu.responses.extend_from_slice(b"\x1b[0n"),
+                // Cursor Position Report (CPR), 1-based
+                6 => {
+                    let reply = emu.cursor_position_report("");
+                    emu.responses.extend_from_slice(reply.as_bytes());
+                }
+                _ => {}
+            },
+            // DEC-specific CPR (DECXCPR)
+            'n' if intermediates == [b'?'] && first == 6 => {
+                let reply = emu.cursor_position_report("?");
+                emu.responses.extend_from_slice(reply.as_bytes());
+            }
+            // Primary Device Attributes (DA1): VT220 with ANSI color
+            'c' if intermediates.is_empty() && first == 0 => {
+                emu.responses.extend_from_slice(b"\x1b[?62;22c");
+            }
+            // Secondary Device Attributes (DA2): VT220, firmware 10, no ROM cartridge
+            'c' if intermediates == [b'>'] && first == 0 => {
+                emu.responses.extend_from_slice(b"\x1b[>1;10;0c");
+            }
+            // XTVERSION: report terminal name and version
+            'q' if intermediates == [b'>'] && first == 0 => {
+                let reply = format!("\x1bP>|Pier {}\x1b\\", env!("CARGO_PKG_VERSION"));
+                emu.responses.extend_from_slice(reply.as_bytes());
+            }
+            // DECSCUSR: set cursor style
+            'q' if intermediates == [b' '] => {
+                if let Some(style) = CursorStyle::from_decscusr(first) {
+                    emu.cursor_style = style;
+                    emu.mark_dirty(emu.cursor_y);
+                }
+            }
             _ => {
-                // TODO: handle more CSI sequences (SGR, scroll, etc.)
+                // TODO: handle more CSI sequences (scroll, etc.)
             }
         }
     }
 
-    fn esc_dispatch(&mut self, _intermediates: &[u8], _ignore: bool, _byte: u8) {}
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        let emu = &mut *self.emu;
+        if intermediates == [b'#'] {
+            let rendition = match byte {
+                b'3' => LineRendition::DoubleHeightTop,
+                b'4' => LineRendition::DoubleHeightBottom,
+                b'5' => LineRendition::Single,
+                b'6' => LineRendition::DoubleWidth,
+                _ => return,
+            };
+            emu.set_line_rendition(rendition);
+            return;
+        }
+        if !intermediates.is_empty() {
+            return;
+        }
+        match byte {
+            // Reverse Index (RI)
+            b'M' => {
+                emu.wrap_pending = false;
+                emu.reverse_index();
+            }
+            // Application / normal keypad (DECKPAM / DECKPNM)
+            b'=' => emu.modes.set(Mode::APP_KEYPAD, true),
+            b'>' => emu.modes.set(Mode::APP_KEYPAD, false),
+            // Identify Terminal (DECID), answered like DA1
+            b'Z' => emu.responses.extend_from_slice(b"\x1b[?62;22c"),
+            // Horizontal Tab Set (HTS)
+            b'H' => {
+                if let Some(stop) = emu.tab_stops.get_mut(emu.cursor_x) {
+                    *stop = true;
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
 #[cfg(test)]
@@ -303,4 +1900,578 @@ mod tests {
         emu.process(b"\x1b[2J");
         assert_eq!(emu.get_line_text(0).trim(), "");
     }
+
+    #[test]
+    fn test_sgr_attributes() {
+        let mut emu = VtEmulator::new(80, 24);
+        emu.process(b"\x1b[1;3;4;7;31;42mA\x1b[0mB");
+        let a = &emu.cells[0][0];
+        assert!(a.bold && a.italic && a.underline == UnderlineStyle::Single && a.reverse);
+        assert_eq!(a.fg, Color::Indexed(1));
+        assert_eq!(a.bg, Color::Indexed(2));
+        let b = &emu.cells[0][1];
+        assert!(!b.bold && !b.italic && b.underline == UnderlineStyle::None && !b.reverse);
+        assert_eq!(b.fg, Color::Default);
+    }
+
+    #[test]
+    fn test_sgr_bright_colors_and_partial_reset() {
+        let mut emu = VtEmulator::new(80, 24);
+        emu.process(b"\x1b[1;2;95;103mA\x1b[22;39mB");
+        assert!(emu.cells[0][0].bold && emu.cells[0][0].faint);
+        assert_eq!(emu.cells[0][0].fg, Color::Indexed(13));
+        assert_eq!(emu.cells[0][0].bg, Color::Indexed(11));
+        assert!(!emu.cells[0][1].bold && !emu.cells[0][1].faint);
+        assert_eq!(emu.cells[0][1].fg, Color::Default);
+        assert_eq!(emu.cells[0][1].bg, Color::Indexed(11));
+    }
+
+    #[test]
+    fn test_sgr_256_and_truecolor() {
+        let mut emu = VtEmulator::new(80, 24);
+        emu.process(b"\x1b[38;5;208;48;2;10;20;30mA");
+        emu.process(b"\x1b[38:2::1:2:3;48:5:17mB");
+        emu.process(b"\x1b[38:2:4:5:6mC");
+        assert_eq!(emu.cells[0][0].fg, Color::Indexed(208));
+        assert_eq!(emu.cells[0][0].bg, Color::Rgb(10, 20, 30));
+        assert_eq!(emu.cells[0][1].fg, Color::Rgb(1, 2, 3));
+        assert_eq!(emu.cells[0][1].bg, Color::Indexed(17));
+        assert_eq!(emu.cells[0][2].fg, Color::Rgb(4, 5, 6));
+    }
+
+    #[test]
+    fn test_alternate_screen_restores_primary() {
+        let mut emu = VtEmulator::new(80, 24);
+        emu.process(b"shell prompt");
+        emu.process(b"\x1b[?1049h");
+        assert!(emu.is_alternate_screen());
+        assert_eq!(emu.get_line_text(0).trim(), "");
+        emu.process(b"\x1b[5;1Hvim buffer");
+        emu.process(b"\x1b[?1049l");
+        assert!(!emu.is_alternate_screen());
+        assert_eq!(emu.get_line_text(0).trim(), "shell prompt");
+        assert_eq!(emu.get_line_text(4).trim(), "");
+        assert_eq!((emu.cursor_x, emu.cursor_y), (12, 0));
+    }
+
+    fn screen_lines(emu: &VtEmulator) -> Vec<String> {
+        (0..emu.rows).map(|r| emu.get_line_text(r).trim_end().to_string()).collect()
+    }
+
+    #[test]
+    fn test_scroll_region_linefeed() {
+        let mut emu = VtEmulator::new(10, 5);
+        emu.process(b"header\x1b[5;1Hfooter");
+        // Region rows 2..=4; output scrolls inside it only.
+        emu.process(b"\x1b[2;4r\x1b[2;1Ha\r\nb\r\nc\r\nd");
+        assert_eq!(screen_lines(&emu), ["header", "b", "c", "d", "footer"]);
+    }
+
+    #[test]
+    fn test_insert_delete_lines() {
+        let mut emu = VtEmulator::new(10, 4);
+        emu.process(b"1\r\n2\r\n3\r\n4");
+        emu.process(b"\x1b[2;1H\x1b[L");
+        assert_eq!(screen_lines(&emu), ["1", "", "2", "3"]);
+        emu.process(b"\x1b[1;1H\x1b[2M");
+        assert_eq!(screen_lines(&emu), ["2", "3", "", ""]);
+    }
+
+    #[test]
+    fn test_reverse_index_at_top_margin() {
+        let mut emu = VtEmulator::new(10, 3);
+        emu.process(b"1\r\n2\r\n3\x1b[1;1H\x1bM");
+        assert_eq!(screen_lines(&emu), ["", "1", "2"]);
+    }
+
+    #[test]
+    fn test_scrollback_captures_attributed_lines() {
+        let mut emu = VtEmulator::new(10, 2);
+        emu.process(b"\x1b[1mone\x1b[0m\r\ntwo\r\nthree\r\nfour");
+        assert_eq!(emu.scrollback_len(), 2);
+        let first = emu.scrollback_line(0).unwrap();
+        assert_eq!(first[0].ch, 'o');
+        assert!(first[0].bold);
+        assert_eq!(emu.scrollback_line(1).unwrap()[0].ch, 't');
+        assert_eq!(emu.get_line_text(0).trim(), "three");
+    }
+
+    #[test]
+    fn test_resize_to_zero() {
+        let mut emu = VtEmulator::new(10, 3);
+        emu.process(b"abc");
+        emu.resize(0, 0);
+        assert_eq!(emu.snapshot().cols, 1);
+        assert_eq!(emu.snapshot().rows, 1);
+        emu.process(b"xy\r\nz");
+        emu.resize(10, 3);
+        assert_eq!(emu.get_line_text(0).trim(), "z");
+    }
+
+    #[test]
+    fn test_scrollback_limit_and_alternate_screen() {
+        let mut emu = VtEmulator::new(10, 1);
+        emu.set_scrollback_limit(2);
+        emu.process(b"a\r\nb\r\nc\r\nd");
+        assert_eq!(emu.scrollback_len(), 2);
+        assert_eq!(emu.scrollback_line(0).unwrap()[0].ch, 'b');
+        emu.process(b"\x1b[?1049hx\r\ny\r\nz\x1b[?1049l");
+        assert_eq!(emu.scrollback_len(), 2);
+        emu.process(b"\x1b[3J");
+        assert_eq!(emu.scrollback_len(), 0);
+        assert_eq!(emu.get_line_text(0).trim(), "d");
+    }
+
+    #[test]
+    fn test_osc_title() {
+        let mut emu = VtEmulator::new(80, 24);
+        emu.process(b"\x1b]0;user@host: ~\x07");
+        assert_eq!(emu.title(), "user@host: ~");
+        assert_eq!(emu.icon_name(), "user@host: ~");
+        emu.process(b"\x1b]2;vim a;b.txt\x1b\\");
+        assert_eq!(emu.title(), "vim a;b.txt");
+        assert_eq!(emu.icon_name(), "user@host: ~");
+        emu.process(b"\x1b]1;icon\x07");
+        assert_eq!(emu.title(), "vim a;b.txt");
+        assert_eq!(emu.icon_name(), "icon");
+    }
+
+    #[test]
+    fn test_osc52_clipboard_write_and_read_policy() {
+        let mut emu = VtEmulator::new(80, 24);
+        emu.process(b"\x1b]52;c;aGVsbG8=\x07\x1b]52;c;?\x07");
+        assert_eq!(
+            emu.take_clipboard_events(),
+            vec![ClipboardEvent::Write { selection: "c".into(), data: "hello".into() }]
+        );
+
+        emu.set_clipboard_policy(ClipboardPolicy { allow_write: false, allow_read: true });
+        emu.process(b"\x1b]52;c;aGVsbG8=\x07\x1b]52;p;?\x07");
+        assert_eq!(
+            emu.take_clipboard_events(),
+            vec![ClipboardEvent::Read { selection: "p".into() }]
+        );
+        assert!(emu.take_clipboard_events().is_empty());
+        assert_eq!(osc52_response("c", "hello"), b"\x1b]52;c;aGVsbG8=\x07".to_vec());
+    }
+
+    #[test]
+    fn test_osc133_command_history() {
+        let mut emu = VtEmulator::new(20, 3);
+        emu.process(b"\x1b]133;A\x07$ \x1b]133;B\x07ls -l\r\n\x1b]133;C\x07");
+        emu.process(b"a\r\nb\r\nc\r\n\x1b]133;D;2\x07");
+        emu.process(b"\x1b]133;A\x07$ \x1b]133;B\x07\r\n\x1b]133;D\x07");
+        let history = emu.command_history();
+        assert_eq!(history.len(), 1);
+        let cmd = &history[0];
+        assert_eq!(cmd.command, "ls -l");
+        assert_eq!(cmd.exit_code, Some(2));
+        assert_eq!(cmd.prompt_row, 0);
+        assert_eq!(cmd.output_start_row, Some(1));
+        assert_eq!(cmd.output_end_row, Some(4));
+        // Rows stay addressable after scrolling into scrollback.
+        assert_eq!(emu.line_at(1).unwrap()[0].ch, 'a');
+        assert!(emu.screen_base_row() > 0);
+    }
+
+    #[test]
+    fn test_dirty_rows() {
+        let mut emu = VtEmulator::new(10, 4);
+        assert_eq!(emu.take_dirty_rows(), vec![0, 1, 2, 3]);
+        assert!(emu.take_dirty_rows().is_empty());
+        emu.process(b"\x1b[3;1Hx\x1b[1;1H\x1b[K");
+        assert_eq!(emu.take_dirty_rows(), vec![0, 2]);
+        emu.process(b"\x1b[2;3r\x1b[2;1H\x1b[L");
+        assert_eq!(emu.take_dirty_rows(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_take_updates() {
+        let mut emu = VtEmulator::new(10, 3);
+        let update = emu.take_updates();
+        assert_eq!((update.generation, update.lines.len()), (1, 3));
+        // Nothing changed: same generation, no rows.
+        let update = emu.take_updates();
+        assert_eq!((update.generation, update.lines.len()), (1, 0));
+
+        emu.process(b"ab");
+        let update = emu.take_updates();
+        assert_eq!(update.generation, 2);
+        assert_eq!(update.lines.iter().map(|l| l.row).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(update.cursor.x, 2);
+
+        // A cursor move alone is still a change.
+        emu.process(b"\x1b[H");
+        let update = emu.take_updates();
+        assert_eq!((update.generation, update.lines.len()), (3, 0));
+
+        emu.process(b"\r\n\n\n\n");
+        let update = emu.take_updates();
+        assert_eq!((update.scrolled, update.lines.len()), (2, 3));
+        let json = serde_json::to_value(emu.take_updates()).unwrap();
+        assert_eq!(json["generation"], 4);
+        assert_eq!(json["cursor"]["style"]["shape"], "block");
+    }
+
+    #[test]
+    fn test_snapshot_json() {
+        let mut emu = VtEmulator::new(3, 2);
+        emu.process(b"\x1b]2;t\x07\x1b[1;31mA\x1b[0mB");
+        let json = serde_json::to_value(emu.snapshot()).unwrap();
+        assert_eq!(json["cursor_x"], 2);
+        assert_eq!(json["title"], "t");
+        assert_eq!(json["alternate_screen"], false);
+        assert_eq!(json["lines"][0][0], serde_json::json!({"ch": "A", "fg": {"Indexed": 1}, "bold": true}));
+        assert_eq!(json["lines"][0][1], serde_json::json!({"ch": "B"}));
+    }
+
+    #[test]
+    fn test_wide_chars() {
+        let mut emu = VtEmulator::new(5, 2);
+        emu.process("中a".as_bytes());
+        assert_eq!(emu.cursor_x, 3);
+        assert!(emu.cells[0][0].wide);
+        assert!(emu.cells[0][1].wide_spacer);
+        assert_eq!(emu.get_line_text(0), "中a  ");
+        // A wide glyph that doesn't fit wraps to the next line.
+        emu.process("文字".as_bytes());
+        assert_eq!(emu.cells[0][3].ch, '文');
+        assert_eq!(emu.cells[1][0].ch, '字');
+        assert_eq!(emu.cursor_x, 2);
+        // Overwriting half of a wide glyph clears the other half.
+        emu.process(b"\x1b[1;2Hx");
+        assert!(!emu.cells[0][0].wide);
+        assert_eq!(emu.get_line_text(0), " xa文");
+    }
+
+    #[test]
+    fn test_combining_marks() {
+        let mut emu = VtEmulator::new(10, 2);
+        emu.process("e\u{301}x".as_bytes());
+        assert_eq!(emu.cursor_x, 2);
+        assert_eq!(emu.cells[0][0].combining.as_deref(), Some("\u{301}"));
+        assert_eq!(emu.get_line_text(0).trim_end(), "e\u{301}x");
+        // ZWJ sequences collapse into a single wide cell.
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        emu.process(format!("\r\n{family}!").as_bytes());
+        assert_eq!(emu.cursor_x, 3);
+        assert_eq!(emu.get_line_text(1).trim_end(), format!("{family}!"));
+    }
+
+    #[test]
+    fn test_cursor_visibility_and_style() {
+        let mut emu = VtEmulator::new(10, 2);
+        assert!(emu.cursor_visible());
+        assert_eq!(emu.cursor_style(), CursorStyle::default());
+        emu.process(b"\x1b[?25l\x1b[6 q");
+        assert!(!emu.cursor_visible());
+        assert_eq!(emu.cursor_style(), CursorStyle { shape: CursorShape::Bar, blinking: false });
+        emu.process(b"\x1b[?25h\x1b[3 q");
+        assert!(emu.cursor_visible());
+        assert_eq!(emu.cursor_style(), CursorStyle { shape: CursorShape::Underline, blinking: true });
+        // Without the space intermediate, `q` is not DECSCUSR.
+        emu.process(b"\x1b[2q");
+        assert_eq!(emu.cursor_style().shape, CursorShape::Underline);
+        let json = serde_json::to_value(emu.snapshot()).unwrap();
+        assert_eq!(json["cursor_visible"], true);
+        assert_eq!(json["cursor_style"], serde_json::json!({"shape": "underline", "blinking": true}));
+    }
+
+    #[test]
+    fn test_tab_stops() {
+        let mut emu = VtEmulator::new(30, 2);
+        emu.process(b"\t");
+        assert_eq!(emu.cursor_x, 8);
+        // Clear all stops, set custom ones at 3 and 10.
+        emu.process(b"\x1b[3g\x1b[1;4H\x1bH\x1b[1;11H\x1bH\r");
+        emu.process(b"\t");
+        assert_eq!(emu.cursor_x, 3);
+        emu.process(b"\x1b[I");
+        assert_eq!(emu.cursor_x, 10);
+        emu.process(b"\t");
+        assert_eq!(emu.cursor_x, 29);
+        emu.process(b"\x1b[2Z");
+        assert_eq!(emu.cursor_x, 3);
+        emu.process(b"\x1b[Z");
+        assert_eq!(emu.cursor_x, 0);
+        // Clear the stop under the cursor.
+        emu.process(b"\x1b[1;4H\x1b[g\r\t");
+        assert_eq!(emu.cursor_x, 10);
+    }
+
+    #[test]
+    fn test_insert_delete_erase_chars() {
+        let mut emu = VtEmulator::new(8, 2);
+        emu.process(b"abcdef\x1b[1;3H\x1b[2@");
+        assert_eq!(emu.get_line_text(0), "ab  cdef");
+        emu.process(b"\x1b[3P");
+        assert_eq!(emu.get_line_text(0), "abdef   ");
+        emu.process(b"\x1b[X");
+        assert_eq!(emu.get_line_text(0), "ab ef   ");
+        emu.process(b"\x1b[1;1H\x1b[99X");
+        assert_eq!(emu.get_line_text(0), "        ");
+        // Shifting a wide glyph off the margin drops both halves.
+        emu.process("\x1b[2;1Habcdef中".as_bytes());
+        emu.process(b"\x1b[2;1H\x1b[@");
+        assert_eq!(emu.get_line_text(1), " abcdef ");
+        assert!(!emu.cells[1][7].wide && !emu.cells[1][7].wide_spacer);
+    }
+
+    #[test]
+    fn test_device_reports() {
+        let mut emu = VtEmulator::new(10, 5);
+        emu.process(b"\x1b[3;4H\x1b[6n");
+        assert_eq!(emu.take_responses(), b"\x1b[3;4R");
+        assert!(emu.take_responses().is_empty());
+        emu.process(b"\x1b[5n\x1b[c\x1b[>c\x1b[?6n");
+        assert_eq!(emu.take_responses(), b"\x1b[0n\x1b[?62;22c\x1b[>1;10;0c\x1b[?3;4R");
+    }
+
+    #[test]
+    fn test_input_modes() {
+        let mut emu = VtEmulator::new(10, 2);
+        assert_eq!(emu.input_modes(), InputModes::default());
+        emu.process(b"\x1b[?1h\x1b=");
+        assert!(emu.input_modes().application_cursor);
+        assert!(emu.input_modes().application_keypad);
+        emu.process(b"\x1b[?1l\x1b>");
+        assert_eq!(emu.input_modes(), InputModes::default());
+    }
+
+    #[test]
+    fn test_deferred_wrap() {
+        let mut emu = VtEmulator::new(5, 3);
+        emu.process(b"abcde");
+        // Cursor stays on the last column until the next character arrives.
+        assert_eq!((emu.cursor_x, emu.cursor_y), (4, 0));
+        emu.process(b"\r\n");
+        assert_eq!((emu.cursor_x, emu.cursor_y), (0, 1));
+        emu.process(b"fghij\x1b[D1");
+        assert_eq!(emu.get_line_text(1), "fgh1j");
+        emu.process(b"\x1b[2;5Hxy");
+        assert_eq!(emu.get_line_text(2), "y    ");
+        emu.process(b"\x1b[1;5H\x1b[6n");
+        assert_eq!(emu.take_responses(), b"\x1b[1;5R");
+    }
+
+    #[test]
+    fn test_autowrap_disabled() {
+        let mut emu = VtEmulator::new(5, 2);
+        emu.process(b"\x1b[?7labcdefg");
+        assert_eq!(emu.get_line_text(0), "abcdg");
+        assert_eq!((emu.cursor_x, emu.cursor_y), (4, 0));
+        emu.process(b"\x1b[?7h\rabcdefg");
+        assert_eq!(emu.get_line_text(1), "fg   ");
+    }
+
+    #[test]
+    fn test_origin_mode() {
+        let mut emu = VtEmulator::new(10, 6);
+        emu.process(b"\x1b[2;4r\x1b[?6h");
+        assert_eq!((emu.cursor_x, emu.cursor_y), (0, 1));
+        // Addressing is relative to, and clamped within, the scroll region.
+        emu.process(b"\x1b[2;3H");
+        assert_eq!((emu.cursor_x, emu.cursor_y), (2, 2));
+        emu.process(b"\x1b[9;1H\x1b[6n");
+        assert_eq!(emu.cursor_y, 3);
+        assert_eq!(emu.take_responses(), b"\x1b[3;1R");
+        emu.process(b"\x1b[?6l");
+        assert_eq!((emu.cursor_x, emu.cursor_y), (0, 0));
+    }
+
+    #[test]
+    fn test_origin_mode_relative_moves_stay_in_region() {
+        let mut emu = VtEmulator::new(10, 24);
+        emu.process(b"\x1b[5;10r\x1b[?6h\x1b[3A\x1b[6n");
+        assert_eq!(emu.cursor_y, 4);
+        assert_eq!(emu.take_responses(), b"\x1b[1;1R");
+        emu.process(b"\x1b[20B\x1b[6n");
+        assert_eq!(emu.cursor_y, 9);
+        assert_eq!(emu.take_responses(), b"\x1b[6;1R");
+        // Outside the region without origin mode, moves use the whole screen.
+        emu.process(b"\x1b[?6l\x1b[2;1H\x1b[5A");
+        assert_eq!(emu.cursor_y, 0);
+        emu.process(b"\x1b[12;1H\x1b[30B");
+        assert_eq!(emu.cursor_y, 23);
+    }
+
+    #[test]
+    fn test_insert_mode_and_reverse_video() {
+        let mut emu = VtEmulator::new(6, 2);
+        emu.process(b"abc\r\x1b[4hX\x1b[4lY");
+        assert_eq!(emu.get_line_text(0), "XYbc  ");
+        emu.process(b"\r\x1b[4hZ");
+        assert_eq!(emu.get_line_text(0), "ZXYbc ");
+        emu.process(b"\x1b[?5h");
+        assert_eq!(serde_json::to_value(emu.snapshot()).unwrap()["reverse_video"], true);
+    }
+
+    #[test]
+    fn test_mode_queries() {
+        let mut emu = VtEmulator::new(10, 2);
+        emu.process(b"\x1b[?7$p\x1b[?6$p\x1b[?2004$p\x1b[4$p");
+        assert_eq!(emu.take_responses(), b"\x1b[?7;1$y\x1b[?6;2$y\x1b[?2004;0$y\x1b[4;2$y");
+        emu.process(b"\x1b[?2004h\x1b[?1049h\x1b[?2004$p\x1b[?1049$p");
+        assert_eq!(emu.take_responses(), b"\x1b[?2004;1$y\x1b[?1049;1$y");
+        assert_eq!(emu.mode(Mode::Dec(2004)), Some(true));
+    }
+
+    #[test]
+    fn test_keyboard_protocol_state() {
+        let mut emu = VtEmulator::new(10, 2);
+        emu.process(b"\x1b[>4;2m");
+        assert_eq!(emu.keyboard_state().modify_other_keys, 2);
+        // `CSI > 4 m` must not be taken as SGR 4 (underline).
+        emu.process(b"x");
+        assert_eq!(emu.cells[0][0].underline, UnderlineStyle::None);
+        emu.process(b"\x1b[>1u\x1b[>9u");
+        assert_eq!(emu.keyboard_state().kitty_flags, 9);
+        emu.process(b"\x1b[=8;3u\x1b[?u");
+        assert_eq!(emu.take_responses(), b"\x1b[?1u");
+        emu.process(b"\x1b[<u");
+        assert_eq!(emu.keyboard_state().kitty_flags, 1);
+        emu.process(b"\x1b[<5u\x1b[>4m");
+        assert_eq!(emu.keyboard_state(), KeyboardState::default());
+    }
+
+    #[test]
+    fn test_inline_image() {
+        let mut emu = VtEmulator::new(20, 5);
+        emu.process(b"ab\x1b]1337;File=name=YS5wbmc=;width=4;height=3;inline=1:AQID\x07");
+        assert_eq!((emu.cursor_x, emu.cursor_y), (6, 2));
+        emu.process(b"\x1b]1337;File=inline=1:AQID\x07\x1b]1337;File=inline=1:bad!\x07");
+        let images = emu.take_images();
+        assert_eq!(images.len(), 2);
+        assert_eq!((images[0].id, images[0].row, images[0].col), (1, 0, 2));
+        assert_eq!(images[0].name.as_deref(), Some("a.png"));
+        assert_eq!((images[1].id, images[1].row), (2, 2));
+        let json = serde_json::to_value(&images[0]).unwrap();
+        assert_eq!(json["data"], "AQID");
+        assert_eq!(json["width"], serde_json::json!({"cells": 4}));
+        assert!(emu.take_images().is_empty());
+    }
+
+    #[test]
+    fn test_bell_and_notifications() {
+        let mut emu = VtEmulator::new(20, 3);
+        emu.process(b"\x07\x1b]9;build done\x07\x1b]777;notify;make;ok; 0 errors\x1b\\");
+        emu.process(b"\x1b]9;4;1;50\x07");
+        let events = emu.take_notifications();
+        let kinds: Vec<_> = events.iter().map(|e| e.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                NotificationKind::Bell,
+                NotificationKind::Notify { title: String::new(), body: "build done".into() },
+                NotificationKind::Notify { title: "make".into(), body: "ok; 0 errors".into() },
+            ]
+        );
+        assert!(events[0].timestamp_ms > 0);
+        let json = serde_json::to_value(&events[0]).unwrap();
+        assert_eq!(json["kind"], "bell");
+        assert!(emu.take_notifications().is_empty());
+    }
+
+    #[test]
+    fn test_answerback_and_identification() {
+        let mut emu = VtEmulator::new(20, 3);
+        emu.process(b"\x05");
+        assert!(emu.take_responses().is_empty());
+        emu.set_answerback("pier-host".into());
+        emu.process(b"a\x05\x1bZ");
+        assert_eq!(emu.take_responses(), b"pier-host\x1b[?62;22c");
+        assert_eq!(emu.get_line_text(0).trim_end(), "a");
+        emu.process(b"\x1b[>q");
+        let reply = String::from_utf8(emu.take_responses()).unwrap();
+        assert!(reply.starts_with("\x1bP>|Pier ") && reply.ends_with("\x1b\\"));
+    }
+
+    #[test]
+    fn test_utf8_split_across_reads() {
+        let input = "a中😀e\u{301}\x1b[1m文\x1b[0mz".as_bytes();
+        let mut whole = VtEmulator::new(20, 2);
+        whole.process(input);
+        let expected = serde_json::to_string(&whole.snapshot()).unwrap();
+        for split in 1..input.len() {
+            let mut emu = VtEmulator::new(20, 2);
+            emu.process(&input[..split]);
+            emu.process(&input[split..]);
+            assert_eq!(serde_json::to_string(&emu.snapshot()).unwrap(), expected, "split at {split}");
+        }
+        let mut emu = VtEmulator::new(20, 2);
+        for byte in input {
+            emu.process(std::slice::from_ref(byte));
+        }
+        assert_eq!(emu.get_line_text(0).trim_end(), "a中😀e\u{301}文z");
+    }
+
+    #[test]
+    fn test_line_renditions() {
+        let mut emu = VtEmulator::new(10, 4);
+        emu.process(b"0123456789\x1b[1;8H\x1b#6");
+        // The right half is lost and the cursor kept within the usable width.
+        assert_eq!(emu.get_line_text(0), "01234     ");
+        assert_eq!(emu.cursor_x, 4);
+        emu.process(b"\r\nabcdefg");
+        assert_eq!(emu.get_line_text(1).trim_end(), "abcdefg");
+        emu.process(b"\x1b[3;1H\x1b#3BIG\r\n\x1b#4BIG");
+        assert_eq!(
+            emu.snapshot().line_renditions,
+            &[
+                LineRendition::DoubleWidth,
+                LineRendition::Single,
+                LineRendition::DoubleHeightTop,
+                LineRendition::DoubleHeightBottom,
+            ]
+        );
+        // Text wraps at the halved width.
+        emu.process(b"\x1b[3;1H\x1b#6abcdefg");
+        assert_eq!(emu.get_line_text(2).trim_end(), "abcde");
+
+        // Renditions scroll with their lines.
+        emu.process(b"\x1b[4;1H\n");
+        assert_eq!(emu.snapshot().line_renditions[2], LineRendition::DoubleHeightBottom);
+        emu.process(b"\x1b[2J");
+        assert!(emu.snapshot().line_renditions.iter().all(|r| *r == LineRendition::Single));
+    }
+
+    #[test]
+    fn test_extended_attributes() {
+        let mut emu = VtEmulator::new(10, 1);
+        emu.process(b"\x1b[4:3;58:2::255:0:0ma\x1b[21;58;5;4;9;53;5;8mb\x1b[24;59;29;55;25;28mc");
+        let (a, b, c) = (&emu.cells[0][0], &emu.cells[0][1], &emu.cells[0][2]);
+        assert_eq!(a.underline, UnderlineStyle::Curly);
+        assert_eq!(a.underline_color, Color::Rgb(255, 0, 0));
+        assert_eq!(b.underline, UnderlineStyle::Double);
+        assert_eq!(b.underline_color, Color::Indexed(4));
+        assert!(b.strikethrough && b.overline && b.blink && b.invisible);
+        assert_eq!(c.underline, UnderlineStyle::None);
+        assert_eq!(c.underline_color, Color::Default);
+        assert!(!c.strikethrough && !c.overline && !c.blink && !c.invisible);
+
+        let json = serde_json::to_value(a).unwrap();
+        assert_eq!(json["underline"], "curly");
+        assert!(serde_json::to_value(c).unwrap().get("underline").is_none());
+    }
+
+    #[test]
+    fn test_palette_osc() {
+        let mut emu = VtEmulator::new(10, 2);
+        emu.process(b"\x1b]4;1;#102030;300;#ffffff\x07\x1b]4;1;?\x07");
+        assert_eq!(emu.palette().colors[1], palette::Rgb(0x10, 0x20, 0x30));
+        assert_eq!(emu.take_responses(), b"\x1b]4;1;rgb:1010/2020/3030\x07");
+
+        emu.process(b"\x1b]11;?\x1b\\");
+        assert_eq!(emu.take_responses(), b"\x1b]11;rgb:0000/0000/0000\x1b\\");
+        emu.process(b"\x1b]10;rgb:ff/ff/ff;#202020\x07\x1b]12;?\x07");
+        assert_eq!(emu.palette().background, palette::Rgb(0x20, 0x20, 0x20));
+        // The cursor follows the foreground until set.
+        assert_eq!(emu.take_responses(), b"\x1b]12;rgb:ffff/ffff/ffff\x07");
+
+        let theme = Palette::default();
+        emu.process(b"\x1b]104\x07\x1b]111\x07");
+        assert_eq!(emu.palette().colors[1], theme.colors[1]);
+        assert_eq!(emu.palette().background, theme.background);
+        assert_eq!(emu.palette().foreground, palette::Rgb(255, 255, 255));
+    }
 }
@@ -0,0 +1,144 @@
+//! iTerm2 inline image protocol (`OSC 1337 ; File=[args] : base64 BEL`),
+//! as sent by `imgcat`.
+//!
+//! Images are decoded and queued with the cursor position where they were
+//! received; the renderer fetches them and draws them over the grid.
+
+use data_encoding::BASE64;
+
+/// Largest decoded image accepted, to bound memory use.
+pub const MAX_IMAGE_BYTES: usize = 32 * 1024 * 1024;
+
+/// Requested display width or height of an inline image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageSize {
+    /// Use the image's own size
+    #[default]
+    Auto,
+    /// A number of character cells
+    Cells(u32),
+    Pixels(u32),
+    /// Percentage of the terminal's width or height
+    Percent(u32),
+}
+
+impl ImageSize {
+    /// Parse `N`, `Npx`, `N%` or `auto`.
+    fn parse(value: &str) -> Option<Self> {
+        if value == "auto" {
+            Some(Self::Auto)
+        } else if let Some(px) = value.strip_suffix("px") {
+            px.parse().ok().map(Self::Pixels)
+        } else if let Some(pct) = value.strip_suffix('%') {
+            pct.parse().ok().map(Self::Percent)
+        } else {
+            value.parse().ok().map(Self::Cells)
+        }
+    }
+}
+
+/// An image received via OSC 1337.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct InlineImage {
+    /// Increasing per emulator, for the renderer to cache by
+    pub id: u64,
+    /// File name, if the sender provided one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub width: ImageSize,
+    pub height: ImageSize,
+    pub preserve_aspect_ratio: bool,
+    /// False for file transfers the user should be offered to save instead
+    pub inline: bool,
+    /// Absolute row and column of the image's top-left cell
+    pub row: u64,
+    pub col: usize,
+    /// Encoded file contents (PNG, JPEG, GIF, ...), base64 in JSON
+    #[serde(serialize_with = "serialize_base64")]
+    pub data: Vec<u8>,
+}
+
+fn serialize_base64<S: serde::Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE64.encode(data))
+}
+
+/// Parse the arguments and payload of `OSC 1337 ; File=...`, given the
+/// OSC text after `1337;`. Position and id are left for the caller.
+pub fn parse_file_payload(text: &[u8]) -> Option<InlineImage> {
+    let text = text.strip_prefix(b"File=")?;
+    let split = text.iter().position(|&b| b == b':')?;
+    let (args, payload) = (&text[..split], &text[split + 1..]);
+
+    let mut image = InlineImage {
+        id: 0,
+        name: None,
+        width: ImageSize::Auto,
+        height: ImageSize::Auto,
+        preserve_aspect_ratio: true,
+        inline: false,
+        row: 0,
+        col: 0,
+        data: Vec::new(),
+    };
+    let mut declared_size = None;
+    for arg in String::from_utf8_lossy(args).split(';') {
+        let Some((key, value)) = arg.split_once('=') else { continue };
+        match key {
+            "name" => {
+                image.name = BASE64
+                    .decode(value.as_bytes())
+                    .ok()
+                    .map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+            }
+            "size" => declared_size = value.parse::<usize>().ok(),
+            "width" => image.width = ImageSize::parse(value)?,
+            "height" => image.height = ImageSize::parse(value)?,
+            "preserveAspectRatio" => image.preserve_aspect_ratio = value != "0",
+            "inline" => image.inline = value == "1",
+            _ => {}
+        }
+    }
+
+    if declared_size.is_some_and(|size| size > MAX_IMAGE_BYTES)
+        || BASE64.decode_len(payload.len()).ok()? > MAX_IMAGE_BYTES
+    {
+        log::debug!("Ignoring oversized OSC 1337 image");
+        return None;
+    }
+    // Senders may wrap the base64 payload across lines
+    let payload: Vec<u8> = payload.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+    image.data = match BASE64.decode(&payload) {
+        Ok(data) => data,
+        Err(e) => {
+            log::debug!("Ignoring malformed OSC 1337 image: {}", e);
+            return None;
+        }
+    };
+    Some(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file_payload() {
+        let image = parse_file_payload(b"File=name=YS5wbmc=;size=3;width=10;height=50%;inline=1:AQID").unwrap();
+        assert_eq!(image.name.as_deref(), Some("a.png"));
+        assert_eq!(image.width, ImageSize::Cells(10));
+        assert_eq!(image.height, ImageSize::Percent(50));
+        assert!(image.inline);
+        assert!(image.preserve_aspect_ratio);
+        assert_eq!(image.data, vec![1, 2, 3]);
+
+        let image = parse_file_payload(b"File=width=20px;preserveAspectRatio=0:AQID").unwrap();
+        assert_eq!(image.width, ImageSize::Pixels(20));
+        assert!(!image.inline);
+        assert!(!image.preserve_aspect_ratio);
+
+        assert!(parse_file_payload(b"File=inline=1:!!!").is_none());
+        assert!(parse_file_payload(b"File=inline=1").is_none());
+        assert!(parse_file_payload(b"SetMark").is_none());
+    }
+}
@@ -0,0 +1,244 @@
+//! Encoding of key presses into the bytes a terminal program expects.
+//!
+//! Follows xterm for legacy sequences (including DECCKM and
+//! modifyOtherKeys) and the kitty keyboard protocol's "disambiguate" and
+//! "report all keys as escape codes" levels when the program enables them.
+
+use crate::terminal::emulator::InputModes;
+
+/// A key, independent of modifiers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    /// A text-producing key; the character already reflects Shift
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    /// Function key F1..=F12
+    F(u8),
+}
+
+/// Modifier bits, as used by xterm and kitty (sent as `1 + bits`).
+pub const MOD_SHIFT: u8 = 1;
+pub const MOD_ALT: u8 = 2;
+pub const MOD_CTRL: u8 = 4;
+pub const MOD_SUPER: u8 = 8;
+
+/// Kitty keyboard protocol flag: disambiguate escape codes.
+pub const KITTY_DISAMBIGUATE: u32 = 1;
+/// Kitty keyboard protocol flag: report all keys as escape codes.
+pub const KITTY_ALL_KEYS: u32 = 8;
+
+/// Keyboard-related state set by the running program.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct KeyboardState {
+    pub input_modes: InputModes,
+    /// xterm modifyOtherKeys level (`CSI > 4 ; n m`): 0, 1 or 2
+    pub modify_other_keys: u8,
+    /// Active kitty keyboard protocol flags (`CSI > flags u`)
+    pub kitty_flags: u32,
+}
+
+/// Encode a key press with `mods` (`MOD_*` bits). Returns an empty vector
+/// for keys with no encoding (e.g. F13+ or a bare Super press).
+pub fn encode_key(key: Key, mods: u8, state: &KeyboardState) -> Vec<u8> {
+    let kitty = state.kitty_flags & (KITTY_DISAMBIGUATE | KITTY_ALL_KEYS) != 0;
+    let all_keys = state.kitty_flags & KITTY_ALL_KEYS != 0;
+    let app_cursor = state.input_modes.application_cursor;
+
+    match key {
+        Key::Char(ch) => encode_char(ch, mods, state),
+        Key::Enter | Key::Tab | Key::Backspace | Key::Escape if all_keys || (kitty && mods != 0) => {
+            let code = match key {
+                Key::Enter => 13,
+                Key::Tab => 9,
+                Key::Backspace => 127,
+                _ => 27,
+            };
+            csi_u(code, mods)
+        }
+        // Kitty disambiguates a bare Escape from the start of a sequence
+        Key::Escape if kitty => csi_u(27, mods),
+        Key::Enter => alt_prefixed(b"\r", mods),
+        Key::Tab if mods & MOD_SHIFT != 0 => b"\x1b[Z".to_vec(),
+        Key::Tab => alt_prefixed(b"\t", mods),
+        Key::Backspace if mods & MOD_CTRL != 0 => alt_prefixed(b"\x08", mods),
+        Key::Backspace => alt_prefixed(b"\x7f", mods),
+        Key::Escape => alt_prefixed(b"\x1b", mods),
+        Key::Up => cursor_key('A', mods, app_cursor),
+        Key::Down => cursor_key('B', mods, app_cursor),
+        Key::Right => cursor_key('C', mods, app_cursor),
+        Key::Left => cursor_key('D', mods, app_cursor),
+        Key::Home => cursor_key('H', mods, app_cursor),
+        Key::End => cursor_key('F', mods, app_cursor),
+        // F1-F4 use SS3 like application-mode cursor keys
+        Key::F(n @ 1..=4) => cursor_key((b'P' + n - 1) as char, mods, true),
+        Key::Insert => tilde_key(2, mods),
+        Key::Delete => tilde_key(3, mods),
+        Key::PageUp => tilde_key(5, mods),
+        Key::PageDown => tilde_key(6, mods),
+        Key::F(n @ 5..=12) => tilde_key([15, 17, 18, 19, 20, 21, 23, 24][(n - 5) as usize], mods),
+        Key::F(_) => Vec::new(),
+    }
+}
+
+/// `CSI final` / `SS3 final`, or `CSI 1 ; mods final` when modified.
+fn cursor_key(final_byte: char, mods: u8, ss3: bool) -> Vec<u8> {
+    if mods != 0 {
+        format!("\x1b[1;{}{final_byte}", 1 + mods).into_bytes()
+    } else if ss3 {
+        format!("\x1bO{final_byte}").into_bytes()
+    } else {
+        format!("\x1b[{final_byte}").into_bytes()
+    }
+}
+
+/// `CSI code ~`, or `CSI code ; mods ~` when modified.
+fn tilde_key(code: u32, mods: u8) -> Vec<u8> {
+    if mods == 0 {
+        format!("\x1b[{code}~").into_bytes()
+    } else {
+        format!("\x1b[{code};{}~", 1 + mods).into_bytes()
+    }
+}
+
+/// `CSI code ; mods u`, omitting the modifier when there is none.
+fn csi_u(code: u32, mods: u8) -> Vec<u8> {
+    if mods == 0 {
+        format!("\x1b[{code}u").into_bytes()
+    } else {
+        format!("\x1b[{code};{}u", 1 + mods).into_bytes()
+    }
+}
+
+/// Legacy Alt handling: prefix with ESC.
+fn alt_prefixed(bytes: &[u8], mods: u8) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len() + 1);
+    if mods & MOD_ALT != 0 {
+        out.push(0x1b);
+    }
+    out.extend_from_slice(bytes);
+    out
+}
+
+/// Legacy C0 control for Ctrl+`ch`, if there is one.
+fn ctrl_code(ch: char) -> Option<u8> {
+    match ch {
+        'a'..='z' => Some(ch as u8 - b'a' + 1),
+        '@' | ' ' | '2' => Some(0),
+        '[' | '3' => Some(0x1b),
+        '\\' | '4' => Some(0x1c),
+        ']' | '5' => Some(0x1d),
+        '^' | '6' => Some(0x1e),
+        '_' | '/' | '7' => Some(0x1f),
+        '?' | '8' => Some(0x7f),
+        _ => None,
+    }
+}
+
+fn encode_char(ch: char, mods: u8, state: &KeyboardState) -> Vec<u8> {
+    let kitty = state.kitty_flags & (KITTY_DISAMBIGUATE | KITTY_ALL_KEYS) != 0;
+    let all_keys = state.kitty_flags & KITTY_ALL_KEYS != 0;
+    let ctrl_or_alt = mods & (MOD_CTRL | MOD_ALT | MOD_SUPER) != 0;
+
+    if all_keys || (kitty && ctrl_or_alt) {
+        // Kitty reports the unshifted key; Shift stays in the modifiers
+        let base = if mods & MOD_SHIFT != 0 { ch.to_ascii_lowercase() } else { ch };
+        return csi_u(base as u32, mods);
+    }
+
+    let lower = ch.to_ascii_lowercase();
+    let legacy_ctrl = if mods & MOD_CTRL != 0 { ctrl_code(lower) } else { None };
+    // Ctrl+Shift+letter and Ctrl+punctuation without a C0 code are
+    // indistinguishable in legacy encoding; modifyOtherKeys 1 fixes those,
+    // level 2 reports every modified key this way.
+    let ambiguous = mods & MOD_CTRL != 0 && (legacy_ctrl.is_none() || mods & MOD_SHIFT != 0 && ch.is_ascii_alphabetic());
+    let use_modify_other = match state.modify_other_keys {
+        1 => ambiguous,
+        2 => ctrl_or_alt,
+        _ => false,
+    };
+    if use_modify_other {
+        return format!("\x1b[27;{};{}~", 1 + mods, ch as u32).into_bytes();
+    }
+
+    let mut out = Vec::new();
+    if mods & MOD_ALT != 0 {
+        out.push(0x1b);
+    }
+    match legacy_ctrl {
+        Some(code) => out.push(code),
+        None => {
+            let mut buf = [0; 4];
+            out.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_encoding() {
+        let state = KeyboardState::default();
+        assert_eq!(encode_key(Key::Char('é'), 0, &state), "é".as_bytes());
+        assert_eq!(encode_key(Key::Char('c'), MOD_CTRL, &state), b"\x03");
+        assert_eq!(encode_key(Key::Char('x'), MOD_ALT, &state), b"\x1bx");
+        assert_eq!(encode_key(Key::Up, 0, &state), b"\x1b[A");
+        assert_eq!(encode_key(Key::Right, MOD_SHIFT | MOD_CTRL, &state), b"\x1b[1;6C");
+        assert_eq!(encode_key(Key::Tab, MOD_SHIFT, &state), b"\x1b[Z");
+        assert_eq!(encode_key(Key::F(1), 0, &state), b"\x1bOP");
+        assert_eq!(encode_key(Key::F(12), MOD_CTRL, &state), b"\x1b[24;5~");
+        assert_eq!(encode_key(Key::Delete, 0, &state), b"\x1b[3~");
+        assert_eq!(encode_key(Key::Backspace, 0, &state), b"\x7f");
+    }
+
+    #[test]
+    fn test_application_cursor() {
+        let state = KeyboardState {
+            input_modes: InputModes { application_cursor: true, application_keypad: false },
+            ..Default::default()
+        };
+        assert_eq!(encode_key(Key::Up, 0, &state), b"\x1bOA");
+        assert_eq!(encode_key(Key::Home, 0, &state), b"\x1bOH");
+        // Modified arrows always use the CSI form.
+        assert_eq!(encode_key(Key::Up, MOD_ALT, &state), b"\x1b[1;3A");
+    }
+
+    #[test]
+    fn test_modify_other_keys() {
+        let mut state = KeyboardState { modify_other_keys: 1, ..Default::default() };
+        assert_eq!(encode_key(Key::Char('A'), MOD_CTRL | MOD_SHIFT, &state), b"\x1b[27;6;65~");
+        assert_eq!(encode_key(Key::Char(';'), MOD_CTRL, &state), b"\x1b[27;5;59~");
+        // Keys with an unambiguous legacy encoding are unchanged at level 1.
+        assert_eq!(encode_key(Key::Char('a'), MOD_CTRL, &state), b"\x01");
+        state.modify_other_keys = 2;
+        assert_eq!(encode_key(Key::Char('a'), MOD_CTRL, &state), b"\x1b[27;5;97~");
+    }
+
+    #[test]
+    fn test_kitty_encoding() {
+        let mut state = KeyboardState { kitty_flags: KITTY_DISAMBIGUATE, ..Default::default() };
+        assert_eq!(encode_key(Key::Char('a'), 0, &state), b"a");
+        assert_eq!(encode_key(Key::Char('A'), MOD_CTRL | MOD_SHIFT, &state), b"\x1b[97;6u");
+        assert_eq!(encode_key(Key::Escape, 0, &state), b"\x1b[27u");
+        assert_eq!(encode_key(Key::Enter, 0, &state), b"\r");
+        assert_eq!(encode_key(Key::Enter, MOD_SHIFT, &state), b"\x1b[13;2u");
+        state.kitty_flags |= KITTY_ALL_KEYS;
+        assert_eq!(encode_key(Key::Char('a'), 0, &state), b"\x1b[97u");
+        assert_eq!(encode_key(Key::Enter, 0, &state), b"\x1b[13u");
+    }
+}
@@ -1,70 +1,957 @@
+pub mod copy_mode;
 pub mod emulator;
+pub mod images;
+pub mod keys;
+pub mod modes;
+pub mod palette;
+pub mod playback;
+pub mod process;
 pub mod pty;
+pub mod reaper;
+pub mod scrollback;
+pub mod search;
+pub mod selection;
+pub mod shell;
+pub mod triggers;
+pub mod utmp;
+pub mod width;
+pub mod writer;
 
-use std::sync::{Arc, Mutex};
-use crate::terminal::pty::PtyProcess;
+use std::collections::VecDeque;
+use std::ffi::{c_void, CString};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use crate::terminal::emulator::VtEmulator;
+use crate::terminal::keys::Key;
+use crate::terminal::copy_mode::CopyMode;
+use crate::terminal::process::ForegroundProcess;
+use crate::terminal::pty::{ExitStatus, InputMode, Packet, PtyProcess, PACKET_START, PACKET_STOP};
+use crate::terminal::utmp::UtmpRecord;
+use crate::terminal::triggers::{TriggerCallbackFn, TriggerMatch, TriggerSet};
+use crate::terminal::writer::{PastePacing, PtyWriter};
+use crate::ssh::shell::{RemoteShell, ShellOutput};
+
+/// How long the reader thread waits for output before checking for shutdown.
+const READ_POLL_INTERVAL_MS: u64 = 50;
+
+/// Cap on raw output kept for `take_output`; the oldest bytes are dropped
+/// when nobody drains it.
+const MAX_PENDING_OUTPUT: usize = 4 * 1024 * 1024;
+
+/// Callback invoked on the reader thread after new output was parsed, and
+/// once more when the PTY closes.
+pub type OutputCallbackFn = extern "C" fn(user_data: *mut c_void);
+
+/// Callback invoked once on the reader thread when the child process exits,
+/// with its exit code (-1 if killed) and terminating signal (0 if it exited).
+pub type ExitCallbackFn = extern "C" fn(user_data: *mut c_void, exit_code: i32, signal: i32);
+
+/// How long the reader waits for the child to be reapable after the PTY
+/// closes, so the exit status is known when the session reports closing.
+const EXIT_WAIT_MS: u64 = 1000;
+
+/// Software flow control characters (Ctrl-S, Ctrl-Q).
+const XOFF: u8 = 0x13;
+const XON: u8 = 0x11;
+
+/// Maximum number of undelivered foreground process changes.
+const MAX_PENDING_PROCESS_EVENTS: usize = 32;
+
+/// Opaque app context passed back to FFI callbacks.
+#[derive(Clone, Copy)]
+struct UserData(*mut c_void);
+
+// The app registers callbacks knowing they run on the reader thread, so the
+// context it hands us must be usable from there.
+unsafe impl Send for UserData {}
+
+/// Parsed terminal state, shared between the session and its reader thread.
+pub struct SessionState {
+    /// VT parser state fed with everything read from the PTY (screen + scrollback)
+    pub emulator: VtEmulator,
+    /// Regex triggers matched against new output
+    pub triggers: TriggerSet,
+    /// Receives trigger matches, with the app's opaque context pointer
+    trigger_callback: Option<(TriggerCallbackFn, UserData)>,
+    /// Notified when new output has been parsed
+    output_callback: Option<(OutputCallbackFn, UserData)>,
+    /// Notified when the child process exits
+    exit_callback: Option<(ExitCallbackFn, UserData)>,
+    /// Raw output not yet taken by `take_output`
+    output: Vec<u8>,
+    /// The PTY reached end of file or failed; the reader has stopped
+    pub closed: bool,
+    /// Current foreground job, refreshed by the reader thread
+    pub foreground: Option<ForegroundProcess>,
+    /// Foreground job changes not yet taken
+    process_events: VecDeque<ForegroundProcess>,
+    /// Keyboard selection state while copy mode is active
+    pub copy_mode: Option<CopyMode>,
+    /// The app paused output consumption; the reader stops reading and the
+    /// program blocks once the kernel buffer fills
+    pub output_paused: bool,
+    /// The user sent XOFF (Ctrl-S) with IXON on, so the line discipline is
+    /// holding the program's output until XON (Ctrl-Q)
+    pub xoff: bool,
+    /// Cap on bytes fed to the emulator per second; `None` is unlimited
+    pub output_throttle: Option<usize>,
+    /// Echo and line-editing settings of the terminal, refreshed by the
+    /// reader thread
+    pub input_mode: InputMode,
+}
+
+impl SessionState {
+    fn new(cols: u16, rows: u16) -> Self {
+        Self {
+            emulator: VtEmulator::new(cols as usize, rows as usize),
+            triggers: TriggerSet::default(),
+            trigger_callback: None,
+            output_callback: None,
+            exit_callback: None,
+            output: Vec::new(),
+            closed: false,
+            foreground: None,
+            process_events: VecDeque::new(),
+            copy_mode: None,
+            output_paused: false,
+            xoff: false,
+            output_throttle: None,
+            input_mode: InputMode::default(),
+        }
+    }
+
+    /// Drain foreground job changes since the last call, oldest first.
+    pub fn take_process_events(&mut self) -> Vec<ForegroundProcess> {
+        self.process_events.drain(..).collect()
+    }
+
+    /// A command other than the shell is in the foreground; closing the
+    /// session would kill it.
+    pub fn is_busy(&self) -> bool {
+        self.foreground.as_ref().is_some_and(|process| !process.is_shell)
+    }
+
+    /// Refresh `input_mode`; returns whether it changed.
+    fn update_input_mode(&mut self, pty: &PtyProcess) -> bool {
+        let Some(mode) = pty.input_mode() else { return false };
+        std::mem::replace(&mut self.input_mode, mode) != mode
+    }
+
+    /// Record the foreground process group, queueing an event on change.
+    fn update_foreground(&mut self, pty: &PtyProcess) {
+        let pgid = pty.foreground_pgid();
+        if self.foreground.as_ref().map(|process| process.pgid) == pgid {
+            return;
+        }
+        self.foreground = pty.foreground_process();
+        if let Some(process) = &self.foreground {
+            if self.process_events.len() >= MAX_PENDING_PROCESS_EVENTS {
+                self.process_events.pop_front();
+            }
+            self.process_events.push_back(process.clone());
+        }
+    }
+}
 
 /// Represents a terminal session with a PTY backend and VT parser.
+///
+/// A background thread drains the PTY into the emulator as output arrives;
+/// callers read parsed state through [`TerminalSession::lock`].
 pub struct TerminalSession {
-    /// The PTY process backing this terminal
-    pub pty: PtyProcess,
+    /// What the terminal is connected to
+    backend: Backend,
     /// Terminal grid dimensions
     pub cols: u16,
     pub rows: u16,
-    /// Scrollback buffer: each line is a string of rendered characters
-    pub scrollback: Arc<Mutex<Vec<String>>>,
-    /// Current screen buffer (rows x cols)
-    pub screen: Arc<Mutex<Vec<Vec<char>>>>,
+    state: Arc<Mutex<SessionState>>,
+    /// Signaled by the reader thread when output is buffered or the PTY closes
+    output_ready: Arc<Condvar>,
+    /// Tells the reader thread to exit
+    shutdown: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+    /// Login record listing the session in `who`, while registered
+    utmp: Option<UtmpRecord>,
+}
+
+/// The other end of a terminal session.
+enum Backend {
+    /// A local PTY, with a writer thread that queues input until the
+    /// program accepts it
+    Local { pty: Arc<PtyProcess>, writer: Arc<PtyWriter> },
+    /// An interactive shell channel on an SSH connection
+    Remote(Arc<RemoteShell>),
 }
 
 impl TerminalSession {
     /// Create a new terminal session with given dimensions.
+    /// The shell runs as a login shell and is registered in utmpx.
     pub fn new(cols: u16, rows: u16, shell: &str) -> Result<Self, std::io::Error> {
         let pty = PtyProcess::spawn(cols, rows, shell)?;
-        let screen = vec![vec![' '; cols as usize]; rows as usize];
+        let mut session = Self::start(pty, cols, rows)?;
+        session.set_login_record(true);
+        Ok(session)
+    }
+
+    /// Create a new terminal session running a specific command with arguments.
+    pub fn new_with_command(cols: u16, rows: u16, program: &str, args: &[&str]) -> Result<Self, std::io::Error> {
+        let pty = PtyProcess::spawn_command(cols, rows, program, args)?;
+        Self::start(pty, cols, rows)
+    }
+
+    /// Create a new terminal session running a command with extra
+    /// environment variables (`(name, value)` pairs).
+    pub fn new_with_env(
+        cols: u16,
+        rows: u16,
+        program: &str,
+        args: &[&str],
+        env: &[(&str, &str)],
+    ) -> Result<Self, std::io::Error> {
+        let pty = PtyProcess::spawn_with_env(cols, rows, program, args, env)?;
+        Self::start(pty, cols, rows)
+    }
+
+    /// Wrap a spawned PTY and start its reader thread.
+    fn start(pty: PtyProcess, cols: u16, rows: u16) -> Result<Self, std::io::Error> {
+        // Lets the reader see XOFF/XON and termios changes as they happen
+        if let Err(e) = pty.set_packet_mode(true) {
+            log::debug!("PTY packet mode unavailable: {}", e);
+        }
+        let pty = Arc::new(pty);
+        let mut initial = SessionState::new(cols, rows);
+        initial.input_mode = pty.input_mode().unwrap_or_default();
+        let state = Arc::new(Mutex::new(initial));
+        let writer = Arc::new(PtyWriter::start(pty.clone())?);
+        let output_ready = Arc::new(Condvar::new());
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let (pty, writer, state, output_ready, shutdown) =
+                (pty.clone(), writer.clone(), state.clone(), output_ready.clone(), shutdown.clone());
+            std::thread::Builder::new()
+                .name("pier-pty-reader".into())
+                .spawn(move || reader_loop(&pty, &writer, &state, &output_ready, &shutdown))?
+        };
         Ok(Self {
-            pty,
+            backend: Backend::Local { pty, writer },
             cols,
             rows,
-            scrollback: Arc::new(Mutex::new(Vec::new())),
-            screen: Arc::new(Mutex::new(screen)),
+            state,
+            output_ready,
+            shutdown,
+            reader: Some(reader),
+            utmp: None,
         })
     }
 
-    /// Create a new terminal session running a specific command with arguments.
-    pub fn new_with_command(cols: u16, rows: u16, program: &str, args: &[&str]) -> Result<Self, std::io::Error> {
-        let pty = PtyProcess::spawn_command(cols, rows, program, args)?;
-        let screen = vec![vec![' '; cols as usize]; rows as usize];
+    /// Create a terminal session for a remote shell channel, fed from its
+    /// output queue. It behaves like a local session, except that process
+    /// inspection, job control, utmpx records and paste pacing only apply
+    /// to local PTYs.
+    pub fn new_remote(cols: u16, rows: u16, shell: RemoteShell, output: ShellOutput) -> Result<Self, std::io::Error> {
+        let shell = Arc::new(shell);
+        let state = Arc::new(Mutex::new(SessionState::new(cols, rows)));
+        let output_ready = Arc::new(Condvar::new());
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let (shell, state, output_ready, shutdown) =
+                (shell.clone(), state.clone(), output_ready.clone(), shutdown.clone());
+            std::thread::Builder::new()
+                .name("pier-ssh-reader".into())
+                .spawn(move || remote_reader_loop(&shell, output, &state, &output_ready, &shutdown))?
+        };
         Ok(Self {
-            pty,
+            backend: Backend::Remote(shell),
             cols,
             rows,
-            scrollback: Arc::new(Mutex::new(Vec::new())),
-            screen: Arc::new(Mutex::new(screen)),
+            state,
+            output_ready,
+            shutdown,
+            reader: Some(reader),
+            utmp: None,
         })
     }
 
+    /// The local PTY, or an `Unsupported` error for remote sessions.
+    fn local_pty(&self) -> Result<&PtyProcess, std::io::Error> {
+        match &self.backend {
+            Backend::Local { pty, .. } => Ok(pty),
+            Backend::Remote(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "not available for remote sessions",
+            )),
+        }
+    }
+
+    /// Master file descriptor of a local session's PTY.
+    pub fn raw_fd(&self) -> Option<i32> {
+        self.local_pty().ok().map(PtyProcess::raw_fd)
+    }
+
+    /// Foreground process group of a local session's terminal.
+    pub fn foreground_pgid(&self) -> Option<i32> {
+        self.local_pty().ok()?.foreground_pgid()
+    }
+
+    /// Queue input for the program, in order with earlier input.
+    fn queue_input(&self, data: &[u8]) -> Result<(), std::io::Error> {
+        match &self.backend {
+            Backend::Local { writer, .. } => writer.write(data),
+            Backend::Remote(shell) => shell.write(data),
+        }
+    }
+
+    /// Lock the parsed state. Hold the guard briefly: the reader thread
+    /// blocks on it while output is waiting.
+    pub fn lock(&self) -> MutexGuard<'_, SessionState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Add or remove the session's utmpx login record, so `who`, `w` and
+    /// `last` list it. Login shells are registered by default and command
+    /// sessions are not. Best effort: without permission to write the
+    /// database nothing is recorded.
+    pub fn set_login_record(&mut self, registered: bool) {
+        if !registered {
+            self.utmp = None;
+        } else if let (None, Ok(pty)) = (&self.utmp, self.local_pty()) {
+            self.utmp = UtmpRecord::register(pty);
+        }
+    }
+
     /// Resize the terminal.
     pub fn resize(&mut self, cols: u16, rows: u16) -> Result<(), std::io::Error> {
         self.cols = cols;
         self.rows = rows;
-        self.pty.resize(cols, rows)?;
-        let mut screen = self.screen.lock().unwrap();
-        screen.resize(rows as usize, vec![' '; cols as usize]);
-        for row in screen.iter_mut() {
-            row.resize(cols as usize, ' ');
+        match &self.backend {
+            Backend::Local { pty, .. } => pty.resize(cols, rows)?,
+            Backend::Remote(shell) => shell.resize(cols, rows)?,
         }
+        self.lock().emulator.resize(cols as usize, rows as usize);
         Ok(())
     }
 
+    /// Set (or clear, with `None`) the callback that receives trigger matches.
+    /// It is invoked on the reader thread.
+    pub fn set_trigger_callback(&mut self, callback: Option<TriggerCallbackFn>, user_data: *mut c_void) {
+        self.lock().trigger_callback = callback.map(|func| (func, UserData(user_data)));
+    }
+
+    /// Set (or clear, with `None`) the callback notified after each chunk of
+    /// output is parsed. It is invoked on the reader thread without the
+    /// state lock held, so it may call back into the session.
+    pub fn set_output_callback(&mut self, callback: Option<OutputCallbackFn>, user_data: *mut c_void) {
+        self.lock().output_callback = callback.map(|func| (func, UserData(user_data)));
+    }
+
+    /// Set (or clear, with `None`) the callback notified when the child
+    /// process exits. It is invoked once, on the reader thread.
+    pub fn set_exit_callback(&mut self, callback: Option<ExitCallbackFn>, user_data: *mut c_void) {
+        self.lock().exit_callback = callback.map(|func| (func, UserData(user_data)));
+    }
+
+    /// How the child process (or remote command) ended, or `None` while it
+    /// is running.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        match &self.backend {
+            Backend::Local { pty, .. } => pty.try_wait(),
+            Backend::Remote(shell) => shell.exit_status(),
+        }
+    }
+
+    /// The child process is still running. A remote shell whose channel
+    /// closed without reporting a status counts as ended.
+    pub fn is_alive(&self) -> bool {
+        match &self.backend {
+            Backend::Local { .. } => self.exit_status().is_none(),
+            Backend::Remote(shell) => shell.exit_status().is_none() && !self.lock().closed,
+        }
+    }
+
+    /// Working directory of the foreground job (or the shell), read from
+    /// the OS. A fallback for shells that don't report OSC 7.
+    pub fn foreground_cwd(&self) -> Option<std::path::PathBuf> {
+        self.local_pty().ok()?.foreground_cwd()
+    }
+
+    /// Send a signal (SIGINT, SIGTERM, SIGTSTP, ...) to the foreground job.
+    /// Remote sessions ask the server to signal the remote command.
+    pub fn signal(&self, signal: i32) -> Result<(), std::io::Error> {
+        match &self.backend {
+            Backend::Local { pty, .. } => pty.signal(signal),
+            Backend::Remote(shell) => shell.signal(signal),
+        }
+    }
+
+    /// Stop or restart reading output. While paused, output stays in the
+    /// kernel buffer and the program blocks when it fills, so a runaway
+    /// command can't flood the renderer.
+    pub fn set_output_paused(&mut self, paused: bool) {
+        self.lock().output_paused = paused;
+    }
+
+    /// Limit output fed to the emulator to `bytes_per_second` (`None` for
+    /// no limit). Excess output backs up into the program, as with pausing.
+    pub fn set_output_throttle(&mut self, bytes_per_second: Option<usize>) {
+        self.lock().output_throttle = bytes_per_second.filter(|&limit| limit > 0);
+    }
+
+    /// The program turned echo off for a password prompt.
+    pub fn is_password_input(&self) -> bool {
+        self.lock().input_mode.is_password()
+    }
+
+    /// Output is held back, either paused by the app or stopped with XOFF.
+    pub fn is_output_stopped(&self) -> bool {
+        let state = self.lock();
+        state.output_paused || state.xoff
+    }
+
+    /// Stop the foreground job (SIGSTOP) until `resume`.
+    pub fn suspend(&self) -> Result<(), std::io::Error> {
+        self.local_pty()?.suspend()
+    }
+
+    /// Continue the job stopped by `suspend` (SIGCONT).
+    pub fn resume(&self) -> Result<(), std::io::Error> {
+        self.local_pty()?.resume()
+    }
+
+    /// A job stopped by `suspend` is waiting to be resumed.
+    pub fn is_suspended(&self) -> bool {
+        self.local_pty().is_ok_and(PtyProcess::is_suspended)
+    }
+
     /// Write input bytes to the PTY (user keystrokes).
+    /// Input is queued and written in order as the program reads it.
     pub fn write(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
-        self.pty.write(data)
+        self.queue_input(data)?;
+        // Mirror the line discipline's software flow control, so the UI can
+        // tell a stopped terminal from a hung program
+        if let Some(&control) = data.iter().rev().find(|&&b| b == XOFF || b == XON) {
+            if self.local_pty().is_ok_and(PtyProcess::flow_control_enabled) {
+                self.lock().xoff = control == XOFF;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode a key press for the program's current keyboard modes and
+    /// write it to the PTY.
+    pub fn send_key(&mut self, key: Key, mods: u8) -> Result<(), std::io::Error> {
+        let state = self.lock().emulator.keyboard_state();
+        let bytes = keys::encode_key(key, mods, &state);
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        self.queue_input(&bytes)
+    }
+
+    /// Queue pasted text, paced if `set_paste_pacing` is on.
+    pub fn paste(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+        match &self.backend {
+            Backend::Local { writer, .. } => writer.paste(data),
+            Backend::Remote(shell) => shell.write(data),
+        }
+    }
+
+    /// Send pastes in chunks with a pause after each, or all at once with
+    /// `None`. Remote sessions rely on SSH flow control instead.
+    pub fn set_paste_pacing(&mut self, pacing: Option<PastePacing>) {
+        if let Backend::Local { writer, .. } = &self.backend {
+            writer.set_paste_pacing(pacing);
+        }
+    }
+
+    /// Bytes of input the program hasn't accepted yet (always 0 for
+    /// remote sessions).
+    pub fn pending_input(&self) -> usize {
+        match &self.backend {
+            Backend::Local { writer, .. } => writer.pending(),
+            Backend::Remote(_) => 0,
+        }
+    }
+
+    /// Answer an OSC 52 clipboard read request with the given clipboard contents.
+    pub fn reply_clipboard(&mut self, selection: &str, data: &str) -> Result<(), std::io::Error> {
+        self.queue_input(&emulator::osc52_response(selection, data))
+    }
+
+    /// Take up to `max` bytes of raw output the reader thread has already
+    /// fed through the emulator, for callers that still render from the
+    /// byte stream themselves. A UTF-8 character that doesn't fit is left
+    /// for the next call rather than split.
+    pub fn take_output(&mut self, max: usize) -> Vec<u8> {
+        drain_output(&mut self.lock(), max)
+    }
+
+    /// Like `take_output`, but block up to `timeout` for output to arrive
+    /// instead of returning empty right away. Returns early if the PTY
+    /// closes.
+    pub fn take_output_timeout(&mut self, max: usize, timeout: Duration) -> Vec<u8> {
+        let (mut state, _) = self
+            .output_ready
+            .wait_timeout_while(self.lock(), timeout, |state| state.output.is_empty() && !state.closed)
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        drain_output(&mut state, max)
+    }
+}
+
+impl Drop for TerminalSession {
+    fn drop(&mut self) {
+        // Join before the PTY closes so the reader never polls a reused fd
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Backend::Remote(shell) = &self.backend {
+            // Ends the output queue the reader is blocked on
+            shell.close();
+        }
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+/// Take up to `max` bytes of buffered output, ending on a character boundary.
+fn drain_output(state: &mut SessionState, max: usize) -> Vec<u8> {
+    let len = if max >= state.output.len() {
+        state.output.len()
+    } else {
+        utf8_floor(&state.output, max)
+    };
+    state.output.drain(..len).collect()
+}
+
+/// Byte budget for one-second windows of output.
+struct OutputThrottle {
+    window_start: Instant,
+    bytes: usize,
+}
+
+impl OutputThrottle {
+    fn new() -> Self {
+        Self { window_start: Instant::now(), bytes: 0 }
     }
 
-    /// Read available output from the PTY.
-    /// Returns the raw bytes for VT parsing.
-    pub fn read(&mut self) -> Result<Vec<u8>, std::io::Error> {
-        self.pty.read()
+    /// The current window has used up `limit`; a new window starts once a
+    /// second has passed.
+    fn is_exhausted(&mut self, limit: Option<usize>) -> bool {
+        if self.window_start.elapsed() >= Duration::from_secs(1) {
+            self.window_start = Instant::now();
+            self.bytes = 0;
+        }
+        limit.is_some_and(|limit| self.bytes >= limit)
+    }
+
+    fn record(&mut self, bytes: usize) {
+        self.bytes += bytes;
+    }
+}
+
+/// Largest index `<= index` that doesn't fall inside a UTF-8 character.
+/// Invalid or non-UTF-8 data is never held back more than 3 bytes.
+fn utf8_floor(bytes: &[u8], index: usize) -> usize {
+    let is_continuation = |i: usize| bytes.get(i).is_some_and(|&b| b & 0xC0 == 0x80);
+    let mut i = index;
+    while i > 0 && index - i < 3 && is_continuation(i) {
+        i -= 1;
+    }
+    if is_continuation(i) {
+        index
+    } else {
+        i
+    }
+}
+
+/// Smallest index `>= index` that doesn't fall inside a UTF-8 character.
+fn utf8_ceil(bytes: &[u8], index: usize) -> usize {
+    let mut i = index;
+    while i < bytes.len() && i - index < 3 && bytes[i] & 0xC0 == 0x80 {
+        i += 1;
+    }
+    i
+}
+
+/// Drain PTY output into the emulator until the PTY closes or the session
+/// shuts down. Replies to terminal queries (DSR, DA) go straight back to
+/// the PTY; trigger callbacks run after the state lock is released. The
+/// foreground job is checked on every wakeup, so changes are noticed within
+/// `READ_POLL_INTERVAL_MS` even without output.
+fn reader_loop(
+    pty: &PtyProcess,
+    writer: &PtyWriter,
+    state: &Mutex<SessionState>,
+    output_ready: &Condvar,
+    shutdown: &AtomicBool,
+) {
+    let mut exit_reported = false;
+    let mut throttle = OutputThrottle::new();
+    while !shutdown.load(Ordering::Relaxed) {
+        let (paused, limit) = {
+            let state = state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            (state.output_paused, state.output_throttle)
+        };
+        let ready = if paused || throttle.is_exhausted(limit) {
+            // Leave output in the kernel buffer so the program blocks
+            std::thread::sleep(Duration::from_millis(READ_POLL_INTERVAL_MS));
+            Ok(false)
+        } else {
+            pty.wait_readable(Duration::from_millis(READ_POLL_INTERVAL_MS))
+        };
+        let input_changed = {
+            let mut state = state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            state.update_foreground(pty);
+            state.update_input_mode(pty)
+        };
+        if input_changed {
+            notify_output(state);
+        }
+        // Background jobs can keep the PTY open after the shell exits, so the
+        // exit is checked on every wake rather than only at end of file
+        if !exit_reported {
+            if let Some(status) = pty.try_wait() {
+                exit_reported = true;
+                fire_exit(state, status);
+            }
+        }
+        let data = match ready {
+            Ok(false) => continue,
+            Ok(true) => pty.read_packet(),
+            Err(e) => Err(e),
+        };
+        let data = match data {
+            // Readable but empty: the child side hung up
+            Ok(Packet::Data(data)) if data.is_empty() => break,
+            Ok(Packet::Data(data)) => data,
+            Ok(Packet::Status(flags)) => {
+                {
+                    let mut state = state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                    if flags & PACKET_STOP != 0 {
+                        state.xoff = true;
+                    } else if flags & PACKET_START != 0 {
+                        state.xoff = false;
+                    }
+                    state.update_input_mode(pty);
+                }
+                notify_output(state);
+                continue;
+            }
+            Err(e) => {
+                log::debug!("PTY reader stopping: {}", e);
+                break;
+            }
+        };
+        throttle.record(data.len());
+
+        let responses = feed_output(state, output_ready, &data);
+        if let Err(e) = writer.write(&responses) {
+            log::debug!("Failed to answer terminal query: {}", e);
+        }
+    }
+
+    // The slave side closes just before the child becomes reapable
+    let mut exit_status = pty.try_wait();
+    let deadline = Instant::now() + Duration::from_millis(EXIT_WAIT_MS);
+    while exit_status.is_none() && !shutdown.load(Ordering::Relaxed) && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+        exit_status = pty.try_wait();
+    }
+
+    finish(state, output_ready, exit_status.filter(|_| !exit_reported));
+}
+
+/// Reader thread of a remote session: feeds the shell channel's output to
+/// the emulator, honoring pause and throttle by leaving it queued.
+fn remote_reader_loop(
+    shell: &RemoteShell,
+    mut output: ShellOutput,
+    state: &Mutex<SessionState>,
+    output_ready: &Condvar,
+    shutdown: &AtomicBool,
+) {
+    let mut throttle = OutputThrottle::new();
+    while !shutdown.load(Ordering::Relaxed) {
+        let (paused, limit) = {
+            let state = state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            (state.output_paused, state.output_throttle)
+        };
+        if paused || throttle.is_exhausted(limit) {
+            std::thread::sleep(Duration::from_millis(READ_POLL_INTERVAL_MS));
+            continue;
+        }
+        let Some(data) = output.blocking_recv() else { break };
+        throttle.record(data.len());
+
+        let responses = feed_output(state, output_ready, &data);
+        if let Err(e) = shell.write(&responses) {
+            log::debug!("Failed to answer terminal query: {}", e);
+        }
+    }
+    finish(state, output_ready, shell.exit_status());
+}
+
+/// Parse a chunk of output, buffer it for `take_output`, and notify
+/// listeners. Returns the emulator's answers to terminal queries.
+fn feed_output(state: &Mutex<SessionState>, output_ready: &Condvar, data: &[u8]) -> Vec<u8> {
+    let (responses, matches, callback, output_callback) = {
+        let mut guard = state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let state = &mut *guard;
+        let from_row = state.emulator.screen_base_row() + state.emulator.cursor_y as u64;
+        state.emulator.process(data);
+        state.output.extend_from_slice(data);
+        if state.output.len() > MAX_PENDING_OUTPUT {
+            // Drop whole characters so the kept bytes still decode
+            let excess = utf8_ceil(&state.output, state.output.len() - MAX_PENDING_OUTPUT);
+            state.output.drain(..excess);
+        }
+        // Matching is skipped while nobody listens
+        let matches = match state.trigger_callback {
+            Some(_) if !state.triggers.is_empty() => state.triggers.scan(&state.emulator, from_row),
+            _ => Vec::new(),
+        };
+        (state.emulator.take_responses(), matches, state.trigger_callback, state.output_callback)
+    };
+    output_ready.notify_all();
+
+    if let Some((callback, user_data)) = callback {
+        fire_triggers(callback, user_data, &matches);
+    }
+    if let Some((callback, user_data)) = output_callback {
+        callback(user_data.0);
+    }
+    responses
+}
+
+/// Mark the session closed and notify listeners, then report `exit` if
+/// it wasn't yet.
+fn finish(state: &Mutex<SessionState>, output_ready: &Condvar, exit: Option<ExitStatus>) {
+    let output_callback = {
+        let mut state = state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.closed = true;
+        state.output_callback
+    };
+    output_ready.notify_all();
+    if let Some((callback, user_data)) = output_callback {
+        callback(user_data.0);
+    }
+    if let Some(status) = exit {
+        fire_exit(state, status);
+    }
+}
+
+/// Call the output callback, outside the state lock.
+fn notify_output(state: &Mutex<SessionState>) {
+    let callback = state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).output_callback;
+    if let Some((callback, user_data)) = callback {
+        callback(user_data.0);
+    }
+}
+
+fn fire_exit(state: &Mutex<SessionState>, status: ExitStatus) {
+    let callback = state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).exit_callback;
+    if let Some((callback, user_data)) = callback {
+        match status {
+            ExitStatus::Exited { code } => callback(user_data.0, code, 0),
+            ExitStatus::Signaled { signal } => callback(user_data.0, -1, signal),
+        }
+    }
+}
+
+fn fire_triggers(callback: TriggerCallbackFn, user_data: UserData, matches: &[TriggerMatch]) {
+    for found in matches {
+        if let Ok(json) = serde_json::to_string(found) {
+            let json = CString::new(json).unwrap_or_default();
+            callback(user_data.0, json.as_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reader_thread_feeds_emulator() {
+        let mut session = TerminalSession::new_with_command(40, 5, "/bin/sh", &["-c", "printf hello"]).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !session.lock().closed {
+            assert!(Instant::now() < deadline, "the PTY never closed");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(session.lock().emulator.get_line_text(0).trim_end(), "hello");
+        assert_eq!(session.take_output(64), b"hello");
+    }
+
+    #[test]
+    fn test_take_output_timeout() {
+        let mut session =
+            TerminalSession::new_with_command(40, 5, "/bin/sh", &["-c", "sleep 0.2; printf late"]).unwrap();
+        assert!(session.take_output_timeout(64, Duration::from_millis(10)).is_empty());
+        let start = Instant::now();
+        assert_eq!(session.take_output_timeout(64, Duration::from_secs(5)), b"late");
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_spawn_with_env() {
+        let env = [("PIER_TEST_VAR", "from pier"), ("TERM", "dumb")];
+        let session =
+            TerminalSession::new_with_env(40, 5, "/bin/sh", &["-c", "printf \"$PIER_TEST_VAR $TERM\""], &env).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !session.lock().closed {
+            assert!(Instant::now() < deadline, "the PTY never closed");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(session.lock().emulator.get_line_text(0).trim_end(), "from pier dumb");
+
+        assert!(TerminalSession::new_with_env(40, 5, "/bin/sh", &[], &[("A=B", "c")]).is_err());
+    }
+
+    static EXIT_STATUS: Mutex<Option<(i32, i32)>> = Mutex::new(None);
+
+    extern "C" fn record_exit(_user_data: *mut c_void, exit_code: i32, signal: i32) {
+        *EXIT_STATUS.lock().unwrap() = Some((exit_code, signal));
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let mut session = TerminalSession::new_with_command(40, 5, "/bin/sh", &["-c", "sleep 0.2; exit 3"]).unwrap();
+        session.set_exit_callback(Some(record_exit), std::ptr::null_mut());
+        assert!(session.is_alive());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while EXIT_STATUS.lock().unwrap().is_none() {
+            assert!(Instant::now() < deadline, "the exit was never reported");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(*EXIT_STATUS.lock().unwrap(), Some((3, 0)));
+        assert!(!session.is_alive());
+        assert_eq!(session.exit_status(), Some(ExitStatus::Exited { code: 3 }));
+
+        let session = TerminalSession::new_with_command(40, 5, "/bin/sh", &["-c", "kill -9 $$"]).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while session.is_alive() {
+            assert!(Instant::now() < deadline, "the child never exited");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(session.exit_status(), Some(ExitStatus::Signaled { signal: libc::SIGKILL }));
+    }
+
+    #[test]
+    fn test_signal_foreground_job() {
+        let session = TerminalSession::new_with_command(40, 5, "/bin/sh", &["-c", "sleep 5"]).unwrap();
+        session.signal(libc::SIGTERM).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while session.is_alive() {
+            assert!(Instant::now() < deadline, "the signal was not delivered");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(session.exit_status(), Some(ExitStatus::Signaled { signal: libc::SIGTERM }));
+        assert!(session.signal(libc::SIGTERM).is_err());
+    }
+
+    #[test]
+    fn test_suspend_and_resume() {
+        let session =
+            TerminalSession::new_with_command(40, 5, "/bin/sh", &["-c", "sleep 0.3; printf done"]).unwrap();
+        session.suspend().unwrap();
+        assert!(session.is_suspended());
+        std::thread::sleep(Duration::from_millis(600));
+        assert!(session.is_alive());
+        assert_eq!(session.lock().emulator.get_line_text(0).trim_end(), "");
+
+        session.resume().unwrap();
+        assert!(!session.is_suspended());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !session.lock().closed {
+            assert!(Instant::now() < deadline, "the job never resumed");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(session.lock().emulator.get_line_text(0).trim_end(), "done");
+    }
+
+    #[test]
+    fn test_password_input_detection() {
+        let session =
+            TerminalSession::new_with_command(40, 5, "/bin/sh", &["-c", "stty -echo; printf ok; sleep 1"]).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !session.is_password_input() {
+            assert!(Instant::now() < deadline, "echo off was never noticed");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        // Packet headers are stripped from the output
+        assert_eq!(session.lock().emulator.get_line_text(0).trim_end(), "ok");
+    }
+
+    #[test]
+    fn test_pause_output() {
+        let mut session =
+            TerminalSession::new_with_command(40, 5, "/bin/sh", &["-c", "sleep 0.2; printf held"]).unwrap();
+        session.set_output_paused(true);
+        assert!(session.is_output_stopped());
+        std::thread::sleep(Duration::from_millis(500));
+        assert_eq!(session.lock().emulator.get_line_text(0).trim_end(), "");
+
+        session.set_output_paused(false);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !session.lock().closed {
+            assert!(Instant::now() < deadline, "the PTY never closed");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(session.lock().emulator.get_line_text(0).trim_end(), "held");
+    }
+
+    #[test]
+    fn test_output_throttle() {
+        let mut throttle = OutputThrottle::new();
+        assert!(!throttle.is_exhausted(Some(10)));
+        throttle.record(10);
+        assert!(throttle.is_exhausted(Some(10)));
+        assert!(!throttle.is_exhausted(None));
+        throttle.window_start -= Duration::from_secs(1);
+        assert!(!throttle.is_exhausted(Some(10)));
+    }
+
+    #[test]
+    fn test_utf8_boundaries() {
+        let bytes = "a中b".as_bytes();
+        assert_eq!(utf8_floor(bytes, 2), 1);
+        assert_eq!(utf8_floor(bytes, 4), 4);
+        assert_eq!(utf8_ceil(bytes, 2), 4);
+        // Stray continuation bytes are not held back indefinitely.
+        assert_eq!(utf8_floor(&[0x80; 8], 6), 6);
+    }
+
+    extern "C" fn count_output(user_data: *mut c_void) {
+        let count = unsafe { &*(user_data as *const std::sync::atomic::AtomicUsize) };
+        count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_output_callback() {
+        static CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let mut session = TerminalSession::new_with_command(40, 5, "/bin/sh", &["-c", "sleep 0.1; printf hi"]).unwrap();
+        session.set_output_callback(Some(count_output), &CALLS as *const _ as *mut c_void);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !session.lock().closed {
+            assert!(Instant::now() < deadline, "the PTY never closed");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        // At least one output chunk, plus the close notification
+        assert!(CALLS.load(Ordering::SeqCst) >= 2);
+    }
+
+    #[test]
+    fn test_foreground_process_events() {
+        let session = TerminalSession::new_with_command(40, 5, "/bin/sh", &["-c", "sleep 0.3"]).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while session.lock().foreground.is_none() {
+            assert!(Instant::now() < deadline, "no foreground process seen");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let mut state = session.lock();
+        let events = state.take_process_events();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].is_shell);
+        assert!(!state.is_busy());
     }
 }
@@ -0,0 +1,93 @@
+//! Terminal mode state (SM/RM and DECSET/DECRST).
+//!
+//! Every mode a program sets is recorded here, whether or not the emulator
+//! acts on it, so behavior for new modes can be added without new plumbing
+//! and programs can query state via DECRQM.
+
+use std::collections::HashMap;
+
+/// A settable terminal mode: ANSI (`CSI Pm h`) or DEC private (`CSI ? Pm h`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Ansi(u16),
+    Dec(u16),
+}
+
+impl Mode {
+    /// IRM: printed characters shift the rest of the line right
+    pub const INSERT: Mode = Mode::Ansi(4);
+    /// DECCKM: arrow keys send application sequences
+    pub const APP_CURSOR: Mode = Mode::Dec(1);
+    /// DECSCNM: whole screen drawn in reverse video
+    pub const REVERSE_VIDEO: Mode = Mode::Dec(5);
+    /// DECOM: cursor addressing is relative to the scroll region
+    pub const ORIGIN: Mode = Mode::Dec(6);
+    /// DECAWM: printing past the last column wraps to the next line
+    pub const AUTOWRAP: Mode = Mode::Dec(7);
+    /// DECTCEM: cursor is shown
+    pub const CURSOR_VISIBLE: Mode = Mode::Dec(25);
+    /// DECNKM: keypad sends application sequences (also DECKPAM/DECKPNM)
+    pub const APP_KEYPAD: Mode = Mode::Dec(66);
+
+    /// State before any program touches the mode.
+    fn default_state(self) -> bool {
+        matches!(self, Mode::AUTOWRAP | Mode::CURSOR_VISIBLE)
+    }
+
+    /// Modes the emulator implements, reported as such by DECRQM even if
+    /// never set.
+    fn is_recognized(self) -> bool {
+        matches!(
+            self,
+            Mode::INSERT
+                | Mode::APP_CURSOR
+                | Mode::REVERSE_VIDEO
+                | Mode::ORIGIN
+                | Mode::AUTOWRAP
+                | Mode::CURSOR_VISIBLE
+                | Mode::APP_KEYPAD
+                | Mode::Dec(47 | 1047 | 1049)
+        )
+    }
+}
+
+/// Current state of every mode, defaulting to each mode's reset state.
+#[derive(Debug, Default)]
+pub struct ModeTable {
+    states: HashMap<Mode, bool>,
+}
+
+impl ModeTable {
+    pub fn get(&self, mode: Mode) -> bool {
+        self.states.get(&mode).copied().unwrap_or_else(|| mode.default_state())
+    }
+
+    pub fn set(&mut self, mode: Mode, enabled: bool) {
+        self.states.insert(mode, enabled);
+    }
+
+    /// State of `mode` if the emulator knows it (recognized, or set by the
+    /// program at some point); `None` otherwise.
+    pub fn query(&self, mode: Mode) -> Option<bool> {
+        (mode.is_recognized() || self.states.contains_key(&mode)).then(|| self.get(mode))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_table() {
+        let mut modes = ModeTable::default();
+        assert!(modes.get(Mode::AUTOWRAP));
+        assert!(!modes.get(Mode::ORIGIN));
+        assert_eq!(modes.query(Mode::Dec(2004)), None);
+        modes.set(Mode::Dec(2004), true);
+        assert_eq!(modes.query(Mode::Dec(2004)), Some(true));
+        modes.set(Mode::AUTOWRAP, false);
+        assert_eq!(modes.query(Mode::AUTOWRAP), Some(false));
+        // ANSI and DEC namespaces are distinct.
+        assert!(!modes.get(Mode::Ansi(7)));
+    }
+}
@@ -0,0 +1,189 @@
+//! Per-session color palette and the OSC sequences that change it.
+//!
+//! Programs may redefine the 256 indexed colors (OSC 4) and the default
+//! foreground, background and cursor colors (OSC 10/11/12), and query them
+//! to adapt to the theme (vim's `background` detection asks OSC 11). The app
+//! sets the theme; OSC 104/110/111/112 restore it.
+
+/// An RGB color, serialized as `"#rrggbb"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl serde::Serialize for Rgb {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("#{:02x}{:02x}{:02x}", self.0, self.1, self.2))
+    }
+}
+
+/// The sixteen ANSI colors as xterm draws them.
+const ANSI_COLORS: [Rgb; 16] = [
+    Rgb(0x00, 0x00, 0x00),
+    Rgb(0xcd, 0x00, 0x00),
+    Rgb(0x00, 0xcd, 0x00),
+    Rgb(0xcd, 0xcd, 0x00),
+    Rgb(0x00, 0x00, 0xee),
+    Rgb(0xcd, 0x00, 0xcd),
+    Rgb(0x00, 0xcd, 0xcd),
+    Rgb(0xe5, 0xe5, 0xe5),
+    Rgb(0x7f, 0x7f, 0x7f),
+    Rgb(0xff, 0x00, 0x00),
+    Rgb(0x00, 0xff, 0x00),
+    Rgb(0xff, 0xff, 0x00),
+    Rgb(0x5c, 0x5c, 0xff),
+    Rgb(0xff, 0x00, 0xff),
+    Rgb(0x00, 0xff, 0xff),
+    Rgb(0xff, 0xff, 0xff),
+];
+
+/// Default value of indexed color `index`: ANSI colors, the 6x6x6 cube,
+/// then the 24-step gray ramp.
+fn default_indexed(index: u8) -> Rgb {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    match index {
+        0..=15 => ANSI_COLORS[index as usize],
+        16..=231 => {
+            let i = index - 16;
+            Rgb(LEVELS[(i / 36) as usize], LEVELS[(i / 6 % 6) as usize], LEVELS[(i % 6) as usize])
+        }
+        _ => {
+            let level = 8 + 10 * (index - 232);
+            Rgb(level, level, level)
+        }
+    }
+}
+
+/// Colors used to draw a session.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct Palette {
+    #[serde(serialize_with = "serialize_colors")]
+    pub colors: [Rgb; 256],
+    pub foreground: Rgb,
+    pub background: Rgb,
+    /// Cursor color; `None` draws the cursor in the foreground color
+    pub cursor: Option<Rgb>,
+}
+
+fn serialize_colors<S: serde::Serializer>(colors: &[Rgb; 256], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(colors.iter())
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            colors: std::array::from_fn(|i| default_indexed(i as u8)),
+            foreground: ANSI_COLORS[7],
+            background: ANSI_COLORS[0],
+            cursor: None,
+        }
+    }
+}
+
+/// A theme as supplied by the app; missing entries keep their defaults.
+/// Colors use any form `parse_color_spec` accepts.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct ThemeSpec {
+    /// Replacements for the first `colors.len()` indexed colors
+    pub colors: Vec<String>,
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+    pub cursor: Option<String>,
+}
+
+impl ThemeSpec {
+    /// Build the palette, failing on the first unparseable color.
+    pub fn to_palette(&self) -> Result<Palette, String> {
+        let parse = |spec: &str| parse_color_spec(spec).ok_or_else(|| format!("Invalid color: {spec}"));
+        let mut palette = Palette::default();
+        if self.colors.len() > 256 {
+            return Err("More than 256 palette colors".to_string());
+        }
+        for (slot, spec) in palette.colors.iter_mut().zip(&self.colors) {
+            *slot = parse(spec)?;
+        }
+        if let Some(spec) = &self.foreground {
+            palette.foreground = parse(spec)?;
+        }
+        if let Some(spec) = &self.background {
+            palette.background = parse(spec)?;
+        }
+        palette.cursor = self.cursor.as_deref().map(parse).transpose()?;
+        Ok(palette)
+    }
+}
+
+/// Parse an X11 color spec as sent in OSC 4/10/11/12: `rgb:r/g/b` with 1-4
+/// hex digits per component, or `#rgb`, `#rrggbb`, `#rrrgggbbb`,
+/// `#rrrrggggbbbb`.
+pub fn parse_color_spec(spec: &str) -> Option<Rgb> {
+    // Scale an n-digit hex component to 8 bits
+    let component = |hex: &str| -> Option<u8> {
+        if hex.is_empty() || hex.len() > 4 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        let max = (1u32 << (4 * hex.len())) - 1;
+        Some((value * 255 / max) as u8)
+    };
+
+    if let Some(rgb) = spec.strip_prefix("rgb:") {
+        let mut parts = rgb.split('/');
+        let color = Rgb(component(parts.next()?)?, component(parts.next()?)?, component(parts.next()?)?);
+        return parts.next().is_none().then_some(color);
+    }
+    let hex = spec.strip_prefix('#')?;
+    if hex.is_empty() || hex.len() % 3 != 0 || hex.len() > 12 || !hex.is_ascii() {
+        return None;
+    }
+    let n = hex.len() / 3;
+    // The `#` forms give the most significant digits, not a scaled value
+    let component = |hex: &str| u8::from_str_radix(&format!("{:0<2}", &hex[..n.min(2)]), 16).ok();
+    Some(Rgb(component(&hex[..n])?, component(&hex[n..2 * n])?, component(&hex[2 * n..])?))
+}
+
+/// Format a color for a query reply, as xterm does: `rgb:rrrr/gggg/bbbb`.
+pub fn format_color_spec(color: Rgb) -> String {
+    let wide = |c: u8| u16::from(c) * 0x101;
+    format!("rgb:{:04x}/{:04x}/{:04x}", wide(color.0), wide(color.1), wide(color.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_palette() {
+        let palette = Palette::default();
+        assert_eq!(palette.colors[1], Rgb(0xcd, 0, 0));
+        assert_eq!(palette.colors[16], Rgb(0, 0, 0));
+        assert_eq!(palette.colors[196], Rgb(255, 0, 0));
+        assert_eq!(palette.colors[231], Rgb(255, 255, 255));
+        assert_eq!(palette.colors[232], Rgb(8, 8, 8));
+        assert_eq!(palette.colors[255], Rgb(238, 238, 238));
+    }
+
+    #[test]
+    fn test_color_specs() {
+        assert_eq!(parse_color_spec("rgb:ff/80/00"), Some(Rgb(255, 128, 0)));
+        assert_eq!(parse_color_spec("rgb:ffff/0/8"), Some(Rgb(255, 0, 136)));
+        assert_eq!(parse_color_spec("#1e1e2e"), Some(Rgb(0x1e, 0x1e, 0x2e)));
+        assert_eq!(parse_color_spec("#f00"), Some(Rgb(0xf0, 0, 0)));
+        assert_eq!(parse_color_spec("#123456789abc"), Some(Rgb(0x12, 0x56, 0x9a)));
+        assert_eq!(parse_color_spec("rgb:ff/80"), None);
+        assert_eq!(parse_color_spec("red"), None);
+        assert_eq!(format_color_spec(Rgb(0x1e, 0, 0xff)), "rgb:1e1e/0000/ffff");
+    }
+
+    #[test]
+    fn test_theme_spec() {
+        let spec: ThemeSpec = serde_json::from_str(r##"{"colors": ["#111111"], "background": "#1e1e2e"}"##).unwrap();
+        let palette = spec.to_palette().unwrap();
+        assert_eq!(palette.colors[0], Rgb(0x11, 0x11, 0x11));
+        assert_eq!(palette.colors[1], Palette::default().colors[1]);
+        assert_eq!(palette.background, Rgb(0x1e, 0x1e, 0x2e));
+        assert_eq!(palette.cursor, None);
+
+        let spec: ThemeSpec = serde_json::from_str(r#"{"foreground": "blue"}"#).unwrap();
+        assert!(spec.to_palette().is_err());
+    }
+}
@@ -0,0 +1,229 @@
+//! Replay of recorded sessions in asciinema v2 (`.cast`) format.
+//!
+//! A cast file is a JSON header line followed by one event per line:
+//! `[seconds, "o", "output"]` for output and `[seconds, "r", "COLSxROWS"]`
+//! for resizes. The player feeds events through its own [`VtEmulator`] as
+//! playback time advances, so the UI renders replays with the same snapshot
+//! API as live sessions.
+
+use crate::terminal::emulator::VtEmulator;
+
+/// Header line of a cast file.
+#[derive(Debug, serde::Deserialize)]
+struct CastHeader {
+    version: u32,
+    width: usize,
+    height: usize,
+    /// Gaps between events longer than this are shortened to it
+    idle_time_limit: Option<f64>,
+    title: Option<String>,
+}
+
+#[derive(Debug)]
+enum CastEvent {
+    Output(String),
+    Resize(usize, usize),
+}
+
+/// Parse a resize event's `COLSxROWS` payload.
+fn parse_size(data: &str) -> Option<(usize, usize)> {
+    let (cols, rows) = data.split_once('x')?;
+    let (cols, rows) = (cols.parse().ok()?, rows.parse().ok()?);
+    (cols > 0 && rows > 0).then_some((cols, rows))
+}
+
+/// Plays a cast recording into an emulator with pause, speed and seek.
+pub struct CastPlayer {
+    emulator: VtEmulator,
+    width: usize,
+    height: usize,
+    title: String,
+    /// (time in seconds, event), ascending
+    events: Vec<(f64, CastEvent)>,
+    /// Index of the next event to apply
+    next: usize,
+    /// Current playback position in recording seconds
+    position: f64,
+    speed: f64,
+    paused: bool,
+}
+
+impl CastPlayer {
+    /// Open and parse a cast file.
+    pub fn open(path: &str) -> Result<Self, anyhow::Error> {
+        let content = std::fs::read_to_string(path)?;
+        Self::parse(&content)
+    }
+
+    /// Parse cast file contents. Unknown event types (input, markers) are
+    /// skipped.
+    pub fn parse(content: &str) -> Result<Self, anyhow::Error> {
+        let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+        let header_line = lines.next().ok_or_else(|| anyhow::anyhow!("Empty cast file"))?;
+        let header: CastHeader = serde_json::from_str(header_line)?;
+        if header.version != 2 {
+            return Err(anyhow::anyhow!("Unsupported cast version {}", header.version));
+        }
+        if header.width == 0 || header.height == 0 {
+            return Err(anyhow::anyhow!("Invalid cast dimensions {}x{}", header.width, header.height));
+        }
+
+        let mut events = Vec::new();
+        let (mut last_raw, mut shift) = (0.0, 0.0);
+        for line in lines {
+            let (time, kind, data): (f64, String, String) = serde_json::from_str(line)?;
+            // Compress idle gaps, shifting all later events earlier
+            if let Some(limit) = header.idle_time_limit {
+                shift += (time - last_raw - limit).max(0.0);
+            }
+            last_raw = time;
+            let event = match kind.as_str() {
+                "o" => CastEvent::Output(data),
+                "r" => match parse_size(&data) {
+                    Some((cols, rows)) => CastEvent::Resize(cols, rows),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            events.push((time - shift, event));
+        }
+
+        Ok(Self {
+            emulator: VtEmulator::new(header.width, header.height),
+            width: header.width,
+            height: header.height,
+            title: header.title.unwrap_or_default(),
+            events,
+            next: 0,
+            position: 0.0,
+            speed: 1.0,
+            paused: false,
+        })
+    }
+
+    /// Emulator holding the replayed screen at the current position.
+    pub fn emulator(&self) -> &VtEmulator {
+        &self.emulator
+    }
+
+    /// Title from the cast header, or empty.
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// Length of the recording in seconds (time of the last event).
+    pub fn duration(&self) -> f64 {
+        self.events.last().map_or(0.0, |(time, _)| *time)
+    }
+
+    /// Current playback position in seconds.
+    pub fn position(&self) -> f64 {
+        self.position
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.events.len()
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Playback rate multiplier; 1.0 is the original speed.
+    pub fn set_speed(&mut self, speed: f64) {
+        if speed.is_finite() && speed > 0.0 {
+            self.speed = speed;
+        }
+    }
+
+    /// Advance playback by `elapsed` wall-clock seconds (scaled by speed),
+    /// applying every event that falls due. Does nothing while paused.
+    /// Returns true if the screen changed.
+    pub fn advance(&mut self, elapsed: f64) -> bool {
+        if self.paused || elapsed.is_nan() || elapsed <= 0.0 {
+            return false;
+        }
+        let target = (self.position + elapsed * self.speed).min(self.duration());
+        self.play_until(target)
+    }
+
+    /// Jump to `time` seconds. Seeking backwards replays from the start.
+    pub fn seek(&mut self, time: f64) {
+        let time = time.clamp(0.0, self.duration());
+        if time < self.position {
+            self.emulator = VtEmulator::new(self.width, self.height);
+            self.next = 0;
+        }
+        self.play_until(time);
+    }
+
+    fn play_until(&mut self, time: f64) -> bool {
+        let start = self.next;
+        while let Some((at, event)) = self.events.get(self.next) {
+            if *at > time {
+                break;
+            }
+            match event {
+                CastEvent::Output(data) => {
+                    self.emulator.process(data.as_bytes());
+                    // Nobody is listening for replies to recorded queries
+                    self.emulator.take_responses();
+                }
+                CastEvent::Resize(cols, rows) => self.emulator.resize(*cols, *rows),
+            }
+            self.next += 1;
+        }
+        self.position = time;
+        self.next > start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAST: &str = r#"{"version": 2, "width": 10, "height": 3, "idle_time_limit": 2.0, "title": "demo"}
+[0.5, "o", "hello"]
+[1.0, "i", "typed"]
+[1.5, "o", "\r\nworld"]
+[10.0, "o", "\r\nlate"]
+[10.5, "r", "12x4"]
+"#;
+
+    #[test]
+    fn test_parse_and_play() {
+        let mut player = CastPlayer::parse(CAST).unwrap();
+        assert_eq!(player.title(), "demo");
+        // The 8.5s idle gap is compressed to 2s.
+        assert_eq!(player.duration(), 4.0);
+        assert!(!player.advance(0.2));
+        assert!(player.advance(0.4));
+        assert_eq!(player.emulator().get_line_text(0).trim_end(), "hello");
+        player.set_speed(4.0);
+        player.advance(1.0);
+        assert_eq!(player.position(), 4.0);
+        assert!(player.is_finished());
+        assert_eq!(player.emulator().get_line_text(2).trim_end(), "late");
+        assert_eq!(player.emulator().cols, 12);
+    }
+
+    #[test]
+    fn test_pause_and_seek() {
+        let mut player = CastPlayer::parse(CAST).unwrap();
+        player.set_paused(true);
+        assert!(!player.advance(5.0));
+        assert_eq!(player.position(), 0.0);
+        player.seek(2.0);
+        assert_eq!(player.emulator().get_line_text(1).trim_end(), "world");
+        // Seeking back rebuilds the screen from the start.
+        player.seek(0.6);
+        assert_eq!(player.emulator().get_line_text(1).trim_end(), "");
+        assert_eq!(player.emulator().get_line_text(0).trim_end(), "hello");
+    }
+
+    #[test]
+    fn test_rejects_bad_header() {
+        assert!(CastPlayer::parse("").is_err());
+        assert!(CastPlayer::parse(r#"{"version": 1, "width": 80, "height": 24}"#).is_err());
+    }
+}
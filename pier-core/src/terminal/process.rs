@@ -0,0 +1,172 @@
+//! Which program is running in the foreground of a PTY.
+//!
+//! The foreground process group of the terminal (`tcgetpgrp` on the master)
+//! is the shell itself while it sits at the prompt, and the job's group while
+//! a command runs. The group leader's name is what the UI shows as
+//! "running: cargo".
+//!
+//! The job's working directory is read the same way, for following the
+//! terminal's directory when the shell doesn't report it with OSC 7.
+
+use std::path::PathBuf;
+
+use crate::terminal::pty::PtyProcess;
+
+/// The foreground job of a terminal session.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct ForegroundProcess {
+    /// Foreground process group id (also the group leader's pid)
+    pub pgid: i32,
+    /// Executable name of the group leader, empty if it couldn't be read
+    pub name: String,
+    /// Command line of the group leader (`argv`), empty if it couldn't be read
+    pub args: Vec<String>,
+    /// The shell itself is in the foreground, i.e. idle at its prompt
+    pub is_shell: bool,
+}
+
+impl PtyProcess {
+    /// Foreground process group of the terminal, if it can be determined.
+    pub fn foreground_pgid(&self) -> Option<i32> {
+        let pgid = unsafe { libc::tcgetpgrp(self.raw_fd()) };
+        (pgid > 0).then_some(pgid)
+    }
+
+    /// The terminal's foreground job, with its name.
+    pub fn foreground_process(&self) -> Option<ForegroundProcess> {
+        let pgid = self.foreground_pgid()?;
+        Some(ForegroundProcess {
+            pgid,
+            name: process_name(pgid).unwrap_or_default(),
+            args: process_args(pgid).unwrap_or_default(),
+            is_shell: pgid == self.child_pid,
+        })
+    }
+
+    /// Working directory of the foreground job, or of the shell if the
+    /// foreground group can't be determined or read.
+    pub fn foreground_cwd(&self) -> Option<PathBuf> {
+        self.foreground_pgid()
+            .and_then(process_cwd)
+            .or_else(|| process_cwd(self.child_pid))
+    }
+}
+
+/// Executable name of process `pid`.
+#[cfg(target_os = "macos")]
+pub fn process_name(pid: i32) -> Option<String> {
+    let mut buf = [0u8; 256];
+    let len = unsafe { libc::proc_name(pid, buf.as_mut_ptr() as *mut libc::c_void, buf.len() as u32) };
+    (len > 0).then(|| String::from_utf8_lossy(&buf[..len as usize]).into_owned())
+}
+
+/// Executable name of process `pid`.
+#[cfg(not(target_os = "macos"))]
+pub fn process_name(pid: i32) -> Option<String> {
+    let comm = std::fs::read_to_string(format!("/proc/{pid}/comm")).ok()?;
+    Some(comm.trim_end().to_string())
+}
+
+/// Command line (`argv`) of process `pid`, from `KERN_PROCARGS2`.
+#[cfg(target_os = "macos")]
+pub fn process_args(pid: i32) -> Option<Vec<String>> {
+    let mut argmax: libc::c_int = 0;
+    let mut size = std::mem::size_of::<libc::c_int>();
+    let mut mib = [libc::CTL_KERN, libc::KERN_ARGMAX];
+    let result = unsafe {
+        libc::sysctl(mib.as_mut_ptr(), 2, &mut argmax as *mut _ as *mut libc::c_void, &mut size, std::ptr::null_mut(), 0)
+    };
+    if result < 0 || argmax <= 0 {
+        return None;
+    }
+
+    let mut buf = vec![0u8; argmax as usize];
+    let mut size = buf.len();
+    let mut mib = [libc::CTL_KERN, libc::KERN_PROCARGS2, pid];
+    let result = unsafe {
+        libc::sysctl(mib.as_mut_ptr(), 3, buf.as_mut_ptr() as *mut libc::c_void, &mut size, std::ptr::null_mut(), 0)
+    };
+    if result < 0 {
+        return None;
+    }
+    buf.truncate(size);
+
+    // Layout: argc, the executable path, NUL padding, then argc strings
+    let argc = i32::from_ne_bytes(buf.get(..4)?.try_into().ok()?);
+    let rest = &buf[4..];
+    let rest = &rest[rest.iter().position(|&b| b == 0)?..];
+    let rest = &rest[rest.iter().position(|&b| b != 0)?..];
+    Some(
+        rest.split(|&b| b == 0)
+            .take(argc.max(0) as usize)
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect(),
+    )
+}
+
+/// Command line (`argv`) of process `pid`.
+#[cfg(not(target_os = "macos"))]
+pub fn process_args(pid: i32) -> Option<Vec<String>> {
+    let cmdline = std::fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+    let cmdline = cmdline.strip_suffix(&[0]).unwrap_or(&cmdline);
+    if cmdline.is_empty() {
+        return Some(Vec::new());
+    }
+    Some(cmdline.split(|&b| b == 0).map(|arg| String::from_utf8_lossy(arg).into_owned()).collect())
+}
+
+/// Current working directory of process `pid`.
+#[cfg(target_os = "macos")]
+pub fn process_cwd(pid: i32) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut info: libc::proc_vnodepathinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_vnodepathinfo>() as libc::c_int;
+    let written = unsafe {
+        libc::proc_pidinfo(pid, libc::PROC_PIDVNODEPATHINFO, 0, &mut info as *mut _ as *mut libc::c_void, size)
+    };
+    if written != size {
+        return None;
+    }
+    // `vip_path` is a NUL-terminated MAXPATHLEN buffer split into rows
+    let path = unsafe {
+        std::slice::from_raw_parts(
+            info.pvi_cdir.vip_path.as_ptr() as *const u8,
+            std::mem::size_of_val(&info.pvi_cdir.vip_path),
+        )
+    };
+    let len = path.iter().position(|&b| b == 0)?;
+    (len > 0).then(|| PathBuf::from(std::ffi::OsStr::from_bytes(&path[..len])))
+}
+
+/// Current working directory of process `pid`.
+#[cfg(not(target_os = "macos"))]
+pub fn process_cwd(pid: i32) -> Option<PathBuf> {
+    std::fs::read_link(format!("/proc/{pid}/cwd")).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_name_of_self() {
+        let name = process_name(std::process::id() as i32).unwrap();
+        assert!(!name.is_empty());
+        assert!(process_name(-1).is_none());
+    }
+
+    #[test]
+    fn test_process_cwd_of_self() {
+        let cwd = process_cwd(std::process::id() as i32).unwrap();
+        assert_eq!(cwd, std::env::current_dir().unwrap());
+        assert!(process_cwd(-1).is_none());
+    }
+
+    #[test]
+    fn test_process_args_of_self() {
+        let args = process_args(std::process::id() as i32).unwrap();
+        assert_eq!(args.len(), std::env::args().count());
+        assert!(process_args(-1).is_none());
+    }
+}
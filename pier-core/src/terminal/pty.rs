@@ -1,4 +1,121 @@
 use std::os::fd::{FromRawFd, OwnedFd, AsRawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::terminal::reaper::{self, ExitSlot};
+
+/// How the child process ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ExitStatus {
+    /// Exited normally with this status code
+    Exited { code: i32 },
+    /// Killed by this signal
+    Signaled { signal: i32 },
+}
+
+impl ExitStatus {
+    pub(crate) fn from_wait_status(status: libc::c_int) -> Self {
+        if libc::WIFSIGNALED(status) {
+            ExitStatus::Signaled { signal: libc::WTERMSIG(status) }
+        } else {
+            ExitStatus::Exited { code: libc::WEXITSTATUS(status) }
+        }
+    }
+}
+
+/// Packet-mode status bits (`TIOCPKT_*`), the same on macOS and Linux.
+/// Output was stopped with XOFF (Ctrl-S).
+pub const PACKET_STOP: u8 = 0x04;
+/// Output was restarted with XON (Ctrl-Q).
+pub const PACKET_START: u8 = 0x08;
+/// The termios settings changed (where the platform reports it).
+pub const PACKET_IOCTL: u8 = 0x40;
+
+/// One read from the master in packet mode.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Packet {
+    /// Output from the program; empty on end of file or nothing available
+    Data(Vec<u8>),
+    /// `PACKET_*` status bits with no data
+    Status(u8),
+}
+
+/// How the slave's line discipline treats input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct InputMode {
+    /// Typed characters are echoed back
+    pub echo: bool,
+    /// Input is line-buffered and edited by the line discipline
+    pub canonical: bool,
+}
+
+impl InputMode {
+    /// Echo off with line editing on: what `read -s`, `sudo` and `ssh`
+    /// password prompts set, so the UI can flag secure input.
+    pub fn is_password(&self) -> bool {
+        !self.echo && self.canonical
+    }
+}
+
+/// UTF-8 locale set for the child. `C.UTF-8` is the one guaranteed to exist
+/// on Linux; macOS has no `C.UTF-8`.
+#[cfg(target_os = "macos")]
+const DEFAULT_LOCALE: &str = "en_US.UTF-8";
+#[cfg(not(target_os = "macos"))]
+const DEFAULT_LOCALE: &str = "C.UTF-8";
+
+/// Fork a child whose controlling terminal is a new PTY sized `win_size`.
+/// Returns the child's pid (0 in the child) and, in the parent, the master
+/// fd. macOS uses `forkpty`.
+#[cfg(target_os = "macos")]
+unsafe fn fork_with_pty(win_size: &libc::winsize) -> Result<(libc::pid_t, libc::c_int), std::io::Error> {
+    let mut master_fd: libc::c_int = -1;
+    let mut win_size = *win_size;
+    let child_pid = libc::forkpty(&mut master_fd, std::ptr::null_mut(), std::ptr::null_mut(), &mut win_size);
+    if child_pid < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok((child_pid, master_fd))
+}
+
+/// Fork a child whose controlling terminal is a new PTY sized `win_size`.
+/// Returns the child's pid (0 in the child) and, in the parent, the master
+/// fd. Elsewhere the pair comes from `openpty` and the child sets up its
+/// session itself, which doesn't rely on BSD `forkpty` semantics.
+#[cfg(not(target_os = "macos"))]
+unsafe fn fork_with_pty(win_size: &libc::winsize) -> Result<(libc::pid_t, libc::c_int), std::io::Error> {
+    let (mut master_fd, mut slave_fd): (libc::c_int, libc::c_int) = (-1, -1);
+    if libc::openpty(&mut master_fd, &mut slave_fd, std::ptr::null_mut(), std::ptr::null(), win_size) < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // Keep the master out of other children spawned by the app
+    libc::fcntl(master_fd, libc::F_SETFD, libc::FD_CLOEXEC);
+
+    let child_pid = libc::fork();
+    if child_pid < 0 {
+        let err = std::io::Error::last_os_error();
+        libc::close(master_fd);
+        libc::close(slave_fd);
+        return Err(err);
+    }
+    if child_pid == 0 {
+        // New session with the slave as controlling terminal and stdio
+        libc::close(master_fd);
+        libc::setsid();
+        libc::ioctl(slave_fd, libc::TIOCSCTTY as _, 0);
+        for fd in 0..=2 {
+            libc::dup2(slave_fd, fd);
+        }
+        if slave_fd > 2 {
+            libc::close(slave_fd);
+        }
+        return Ok((0, -1));
+    }
+    libc::close(slave_fd);
+    Ok((child_pid, master_fd))
+}
 
 /// Manages a pseudo-terminal (PTY) process on macOS/Unix.
 pub struct PtyProcess {
@@ -6,6 +123,13 @@ pub struct PtyProcess {
     master_fd: OwnedFd,
     /// Child process ID
     pub child_pid: libc::pid_t,
+    /// Filled in by the reaper once the child is gone; its pid may be
+    /// reused after that
+    exit_status: Arc<ExitSlot>,
+    /// Process group stopped by `suspend`, until `resume`
+    suspended: Mutex<Option<libc::pid_t>>,
+    /// Reads start with a `TIOCPKT` status byte
+    packet_mode: AtomicBool,
 }
 
 impl PtyProcess {
@@ -16,10 +140,37 @@ impl PtyProcess {
 
     /// Spawn a new PTY process running the given command with explicit arguments.
     pub fn spawn_command(cols: u16, rows: u16, program: &str, args: &[&str]) -> Result<Self, std::io::Error> {
-        let mut master_fd: libc::c_int = 0;
+        Self::spawn_with_env(cols, rows, program, args, &[])
+    }
+
+    /// Spawn a new PTY process running the given command with extra
+    /// environment variables. `env` entries are applied after Pier's own
+    /// defaults (TERM, LANG, ...), so they can override them.
+    pub fn spawn_with_env(
+        cols: u16,
+        rows: u16,
+        program: &str,
+        args: &[&str],
+        env: &[(&str, &str)],
+    ) -> Result<Self, std::io::Error> {
+        // Built before forking: the child only hands these to putenv, and the
+        // fork's copy of the heap keeps them alive until execvp.
+        let env_c = env
+            .iter()
+            .map(|(key, value)| {
+                if key.is_empty() || key.contains('=') {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Invalid environment variable name: {key:?}"),
+                    ));
+                }
+                std::ffi::CString::new(format!("{key}={value}"))
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
         // Set up terminal size
-        let mut win_size = libc::winsize {
+        let win_size = libc::winsize {
             ws_row: rows,
             ws_col: cols,
             ws_xpixel: 0,
@@ -27,16 +178,7 @@ impl PtyProcess {
         };
 
         unsafe {
-            let child_pid = libc::forkpty(
-                &mut master_fd,
-                std::ptr::null_mut(),
-                std::ptr::null_mut(),
-                &mut win_size,
-            );
-
-            if child_pid < 0 {
-                return Err(std::io::Error::last_os_error());
-            }
+            let (child_pid, master_fd) = fork_with_pty(&win_size)?;
 
             if child_pid == 0 {
                 // Child process: exec the command with given args
@@ -57,9 +199,9 @@ impl PtyProcess {
                 libc::putenv(term.as_ptr() as *mut _);
 
                 // Set locale for proper UTF-8 handling (prevents <0080> artifacts)
-                let lang = std::ffi::CString::new("LANG=en_US.UTF-8").unwrap();
+                let lang = std::ffi::CString::new(format!("LANG={DEFAULT_LOCALE}")).unwrap();
                 libc::putenv(lang.as_ptr() as *mut _);
-                let lc_all = std::ffi::CString::new("LC_ALL=en_US.UTF-8").unwrap();
+                let lc_all = std::ffi::CString::new(format!("LC_ALL={DEFAULT_LOCALE}")).unwrap();
                 libc::putenv(lc_all.as_ptr() as *mut _);
 
                 // Use ZDOTDIR to inject a custom prompt AFTER /etc/zshrc.
@@ -95,6 +237,10 @@ impl PtyProcess {
                     }
                 }
 
+                for entry in &env_c {
+                    libc::putenv(entry.as_ptr() as *mut _);
+                }
+
                 // Change to user's home directory (default working directory)
                 // Without this, the terminal inherits the CWD of the launching process.
                 if let Ok(home) = std::env::var("HOME") {
@@ -115,6 +261,9 @@ impl PtyProcess {
             Ok(Self {
                 master_fd: OwnedFd::from_raw_fd(master_fd),
                 child_pid,
+                exit_status: reaper::watch(child_pid),
+                suspended: Mutex::new(None),
+                packet_mode: AtomicBool::new(false),
             })
         }
     }
@@ -139,16 +288,34 @@ impl PtyProcess {
         }
     }
 
-    /// Write data to the PTY master (sends input to the shell).
-    pub fn write(&self, data: &[u8]) -> Result<(), std::io::Error> {
+    /// Write data to the PTY master (sends input to the shell), waiting
+    /// while the program's input buffer is full. Sessions queue input
+    /// through their writer thread instead.
+    pub fn write(&self, mut data: &[u8]) -> Result<(), std::io::Error> {
+        while !data.is_empty() {
+            let written = self.write_some(data)?;
+            if written == 0 {
+                self.wait_writable(Duration::from_millis(50))?;
+            }
+            data = &data[written..];
+        }
+        Ok(())
+    }
+
+    /// Write as much of `data` as the PTY accepts without blocking. Returns
+    /// the number of bytes written, 0 if its input buffer is full.
+    pub fn write_some(&self, data: &[u8]) -> Result<usize, std::io::Error> {
         let fd = self.master_fd.as_raw_fd();
         let result = unsafe {
             libc::write(fd, data.as_ptr() as *const libc::c_void, data.len())
         };
-        if result < 0 {
-            Err(std::io::Error::last_os_error())
-        } else {
-            Ok(())
+        if result >= 0 {
+            return Ok(result as usize);
+        }
+        let err = std::io::Error::last_os_error();
+        match err.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted => Ok(0),
+            _ => Err(err),
         }
     }
 
@@ -174,6 +341,191 @@ impl PtyProcess {
         }
     }
 
+    /// Reap the child if it has exited, without blocking. Returns its exit
+    /// status, or `None` while it is still running.
+    pub fn try_wait(&self) -> Option<ExitStatus> {
+        reaper::try_reap(self.child_pid, &self.exit_status)
+    }
+
+    /// Send `signal` to the terminal's foreground process group (the running
+    /// job, or the shell at its prompt), falling back to the shell's own
+    /// group. Fails once the child has exited, since its pid may be reused.
+    pub fn signal(&self, signal: i32) -> Result<(), std::io::Error> {
+        if self.try_wait().is_some() {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "Process has exited"));
+        }
+        self.signal_group(self.foreground_pgid().unwrap_or(self.child_pid), signal)
+    }
+
+    fn signal_group(&self, pgid: libc::pid_t, signal: i32) -> Result<(), std::io::Error> {
+        if unsafe { libc::killpg(pgid, signal) } == 0 {
+            return Ok(());
+        }
+        let err = std::io::Error::last_os_error();
+        // Right after the fork the child may not have called setsid yet, so
+        // its group doesn't exist; signal the child itself instead
+        if err.raw_os_error() == Some(libc::ESRCH) && unsafe { libc::kill(self.child_pid, signal) } == 0 {
+            return Ok(());
+        }
+        Err(err)
+    }
+
+    /// Wait up to `timeout` for output to become readable, or for the child
+    /// side to hang up. Returns false on timeout or if a signal interrupted
+    /// the wait.
+    pub fn wait_readable(&self, timeout: Duration) -> Result<bool, std::io::Error> {
+        self.poll(libc::POLLIN, timeout)
+    }
+
+    /// Wait up to `timeout` for room to write input. Returns whether there
+    /// is, or the PTY hung up (so the next write reports the error).
+    pub fn wait_writable(&self, timeout: Duration) -> Result<bool, std::io::Error> {
+        self.poll(libc::POLLOUT, timeout)
+    }
+
+    fn poll(&self, events: libc::c_short, timeout: Duration) -> Result<bool, std::io::Error> {
+        let mut fds = libc::pollfd {
+            fd: self.master_fd.as_raw_fd(),
+            events,
+            revents: 0,
+        };
+        // Round up so a sub-millisecond timeout still waits
+        let timeout_ms = timeout.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32;
+        let result = unsafe { libc::poll(&mut fds, 1, timeout_ms) };
+        if result < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            return Err(err);
+        }
+        Ok(result > 0)
+    }
+
+    /// Block up to `timeout` for output, then read what is available. Like
+    /// `read`, returns an empty buffer if nothing arrived in time.
+    pub fn read_timeout(&self, timeout: Duration) -> Result<Vec<u8>, std::io::Error> {
+        if self.wait_readable(timeout)? {
+            self.read()
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Path of the slave side, e.g. `/dev/ttys003`.
+    #[cfg(target_os = "macos")]
+    pub fn tty_name(&self) -> Option<String> {
+        let mut buf = [0 as libc::c_char; 128];
+        let result = unsafe { libc::ioctl(self.master_fd.as_raw_fd(), libc::TIOCPTYGNAME as _, buf.as_mut_ptr()) };
+        if result < 0 {
+            return None;
+        }
+        let name = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+        Some(name.to_string_lossy().into_owned())
+    }
+
+    /// Path of the slave side, e.g. `/dev/pts/3`.
+    #[cfg(not(target_os = "macos"))]
+    pub fn tty_name(&self) -> Option<String> {
+        let mut buf = [0 as libc::c_char; 128];
+        let result = unsafe { libc::ptsname_r(self.master_fd.as_raw_fd(), buf.as_mut_ptr(), buf.len()) };
+        if result != 0 {
+            return None;
+        }
+        let name = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+        Some(name.to_string_lossy().into_owned())
+    }
+
+    /// Stop the foreground job with SIGSTOP, which programs can't ignore.
+    /// A shell with job control notices and reports the job as stopped.
+    pub fn suspend(&self) -> Result<(), std::io::Error> {
+        if self.try_wait().is_some() {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "Process has exited"));
+        }
+        let mut suspended = self.suspended.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if suspended.is_some() {
+            return Ok(());
+        }
+        let pgid = self.foreground_pgid().unwrap_or(self.child_pid);
+        self.signal_group(pgid, libc::SIGSTOP)?;
+        *suspended = Some(pgid);
+        Ok(())
+    }
+
+    /// Continue the job stopped by `suspend`. Does nothing if none is.
+    pub fn resume(&self) -> Result<(), std::io::Error> {
+        let mut suspended = self.suspended.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Some(pgid) = suspended.take() else { return Ok(()) };
+        if let Err(err) = self.signal_group(pgid, libc::SIGCONT) {
+            // The group is gone, so there is nothing left to resume
+            if err.raw_os_error() != Some(libc::ESRCH) {
+                *suspended = Some(pgid);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// A job stopped by `suspend` is waiting to be resumed.
+    pub fn is_suspended(&self) -> bool {
+        let mut suspended = self.suspended.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Forget groups that exited (e.g. killed) while stopped
+        if let Some(pgid) = *suspended {
+            if self.signal_group(pgid, 0).is_err() {
+                *suspended = None;
+            }
+        }
+        suspended.is_some()
+    }
+
+    /// Turn packet mode (TIOCPKT) on or off. While on, `read_packet`
+    /// separates output from the status changes the line discipline reports
+    /// (`PACKET_*`). `read` returns the raw packets.
+    pub fn set_packet_mode(&self, enabled: bool) -> Result<(), std::io::Error> {
+        let flag: libc::c_int = enabled.into();
+        if unsafe { libc::ioctl(self.master_fd.as_raw_fd(), libc::TIOCPKT as _, &flag) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        self.packet_mode.store(enabled, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Read like `read`, splitting off packet-mode status reports.
+    pub fn read_packet(&self) -> Result<Packet, std::io::Error> {
+        let mut data = self.read()?;
+        if !self.packet_mode.load(Ordering::Relaxed) || data.is_empty() {
+            return Ok(Packet::Data(data));
+        }
+        match data[0] {
+            0 => {
+                data.remove(0);
+                Ok(Packet::Data(data))
+            }
+            flags => Ok(Packet::Status(flags)),
+        }
+    }
+
+    /// Current echo and line-editing settings of the slave side.
+    pub fn input_mode(&self) -> Option<InputMode> {
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(self.master_fd.as_raw_fd(), &mut termios) } < 0 {
+            return None;
+        }
+        Some(InputMode {
+            echo: termios.c_lflag & libc::ECHO != 0,
+            canonical: termios.c_lflag & libc::ICANON != 0,
+        })
+    }
+
+    /// Whether the line discipline honors XON/XOFF (IXON) for output.
+    pub fn flow_control_enabled(&self) -> bool {
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(self.master_fd.as_raw_fd(), &mut termios) } < 0 {
+            return false;
+        }
+        termios.c_iflag & libc::IXON != 0
+    }
+
     /// Get the raw file descriptor for polling/select.
     pub fn raw_fd(&self) -> i32 {
         self.master_fd.as_raw_fd()
@@ -182,27 +534,8 @@ impl PtyProcess {
 
 impl Drop for PtyProcess {
     fn drop(&mut self) {
-        unsafe {
-            // Send SIGTERM for graceful shutdown
-            libc::kill(self.child_pid, libc::SIGTERM);
-
-            // Non-blocking reap loop — avoids blocking the caller's thread.
-            // Try up to 5 times with 50ms sleeps before sending SIGKILL.
-            let mut status: libc::c_int = 0;
-            for attempt in 0..5 {
-                let waited = libc::waitpid(self.child_pid, &mut status, libc::WNOHANG);
-                if waited != 0 {
-                    return; // Reaped (or error — either way, done)
-                }
-                if attempt == 2 {
-                    // After 150ms of SIGTERM, escalate to SIGKILL
-                    libc::kill(self.child_pid, libc::SIGKILL);
-                }
-                std::thread::sleep(std::time::Duration::from_millis(50));
-            }
-            // Final non-blocking attempt — if still not reaped, the OS will
-            // clean up the zombie when the parent process exits.
-            libc::waitpid(self.child_pid, &mut status, libc::WNOHANG);
-        }
+        // SIGTERM, then SIGKILL after a grace period, reaped in the
+        // background so dropping never blocks
+        reaper::release(self.child_pid);
     }
 }
@@ -0,0 +1,275 @@
+//! Central reaping of PTY children.
+//!
+//! One supervisor thread does every `waitpid` on PTY children. A SIGCHLD
+//! handler wakes it through a self-pipe, and it also rescans periodically in
+//! case a signal is missed. Sessions read their child's status from a shared
+//! slot, and a dropped session hands its child over to be terminated and
+//! reaped in the background instead of sleeping on the dropping thread.
+//!
+//! Only registered pids are waited for, so children the app spawns by other
+//! means are left to their owners. A SIGCHLD handler installed before ours is
+//! still called.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::terminal::pty::ExitStatus;
+
+/// How long a released child gets after SIGTERM before SIGKILL.
+const KILL_GRACE: Duration = Duration::from_millis(150);
+
+/// Rescan interval when no SIGCHLD arrives.
+const SCAN_INTERVAL_MS: i32 = 1000;
+
+/// Rescan interval while a released child is waiting to be killed.
+const KILL_POLL_MS: i32 = 25;
+
+/// Where the supervisor records a child's exit status.
+pub type ExitSlot = Mutex<Option<ExitStatus>>;
+
+struct Child {
+    slot: Arc<ExitSlot>,
+    /// Set when the owner released the child: SIGKILL it at this time
+    kill_at: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Supervisor {
+    children: Mutex<HashMap<libc::pid_t, Child>>,
+}
+
+impl Supervisor {
+    fn children(&self) -> MutexGuard<'_, HashMap<libc::pid_t, Child>> {
+        self.children.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Reap exited children and kill released ones past their grace period.
+    fn scan(&self) {
+        let now = Instant::now();
+        self.children().retain(|&pid, child| {
+            if reap(pid, &child.slot) {
+                return false;
+            }
+            if child.kill_at.is_some_and(|kill_at| now >= kill_at) {
+                unsafe { libc::kill(pid, libc::SIGKILL) };
+                child.kill_at = None;
+            }
+            true
+        });
+    }
+}
+
+static SUPERVISOR: OnceLock<Supervisor> = OnceLock::new();
+
+/// Write end of the self-pipe, for the signal handler.
+static WAKE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// SIGCHLD disposition before ours, chained from our handler.
+static PREVIOUS_ACTION: OnceLock<libc::sigaction> = OnceLock::new();
+
+fn supervisor() -> &'static Supervisor {
+    SUPERVISOR.get_or_init(|| {
+        let mut fds = [-1; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } == 0 {
+            for fd in fds {
+                unsafe {
+                    libc::fcntl(fd, libc::F_SETFL, libc::fcntl(fd, libc::F_GETFL) | libc::O_NONBLOCK);
+                    libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                }
+            }
+            WAKE_FD.store(fds[1], Ordering::Relaxed);
+            install_handler();
+        } else {
+            log::warn!("Child reaper falling back to polling: {}", std::io::Error::last_os_error());
+        }
+        let wake_read = fds[0];
+        if let Err(e) = std::thread::Builder::new()
+            .name("pier-child-reaper".into())
+            .spawn(move || run(wake_read))
+        {
+            log::error!("Failed to start child reaper: {}", e);
+        }
+        Supervisor::default()
+    })
+}
+
+fn install_handler() {
+    unsafe {
+        let mut previous: libc::sigaction = std::mem::zeroed();
+        libc::sigaction(libc::SIGCHLD, std::ptr::null(), &mut previous);
+        let _ = PREVIOUS_ACTION.set(previous);
+
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_sigchld as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART | libc::SA_NOCLDSTOP;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGCHLD, &action, std::ptr::null_mut());
+    }
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__error()
+}
+
+#[cfg(not(target_os = "macos"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno_location()
+}
+
+/// Wake the supervisor, then pass the signal on to whoever had SIGCHLD
+/// before. Only async-signal-safe calls; errno is preserved.
+extern "C" fn on_sigchld(signal: libc::c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+    unsafe {
+        let saved_errno = *errno_location();
+        let fd = WAKE_FD.load(Ordering::Relaxed);
+        if fd >= 0 {
+            let byte = 0u8;
+            libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+        }
+        *errno_location() = saved_errno;
+
+        if let Some(previous) = PREVIOUS_ACTION.get() {
+            let handler = previous.sa_sigaction;
+            if handler == libc::SIG_DFL || handler == libc::SIG_IGN {
+                return;
+            }
+            if previous.sa_flags & libc::SA_SIGINFO != 0 {
+                let handler: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) =
+                    std::mem::transmute(handler);
+                handler(signal, info, context);
+            } else {
+                let handler: extern "C" fn(libc::c_int) = std::mem::transmute(handler);
+                handler(signal);
+            }
+        }
+    }
+}
+
+fn run(wake_read: libc::c_int) {
+    let mut buf = [0u8; 64];
+    loop {
+        let pending_kill = supervisor().children().values().any(|child| child.kill_at.is_some());
+        let timeout = if pending_kill { KILL_POLL_MS } else { SCAN_INTERVAL_MS };
+        let mut fds = libc::pollfd { fd: wake_read, events: libc::POLLIN, revents: 0 };
+        unsafe {
+            libc::poll(&mut fds, 1, timeout);
+            while wake_read >= 0 && libc::read(wake_read, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) > 0 {}
+        }
+        supervisor().scan();
+    }
+}
+
+fn wake() {
+    let fd = WAKE_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = 0u8;
+        unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+    }
+}
+
+/// `waitpid` `pid` without blocking, recording its status in `slot`.
+/// Returns true once the pid is gone.
+fn reap(pid: libc::pid_t, slot: &ExitSlot) -> bool {
+    let mut status: libc::c_int = 0;
+    let waited = unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) };
+    if waited == pid && (libc::WIFEXITED(status) || libc::WIFSIGNALED(status)) {
+        *slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(ExitStatus::from_wait_status(status));
+        true
+    } else if waited < 0 {
+        // Reaped by someone else (e.g. a `waitpid(-1)` in the app); the
+        // status is lost
+        log::debug!("Lost track of child {}: {}", pid, std::io::Error::last_os_error());
+        true
+    } else {
+        false
+    }
+}
+
+/// Start supervising child `pid`. Its exit status appears in the returned
+/// slot once it has been reaped.
+pub fn watch(pid: libc::pid_t) -> Arc<ExitSlot> {
+    let slot = Arc::new(ExitSlot::default());
+    supervisor().children().insert(pid, Child { slot: slot.clone(), kill_at: None });
+    slot
+}
+
+/// Reap `pid` now if it has exited, rather than waiting for the supervisor.
+pub fn try_reap(pid: libc::pid_t, slot: &ExitSlot) -> Option<ExitStatus> {
+    let mut children = supervisor().children();
+    if children.contains_key(&pid) && reap(pid, slot) {
+        children.remove(&pid);
+    }
+    *slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The owner is done with `pid`: send it SIGTERM, and SIGKILL after a grace
+/// period if it's still running. It is reaped in the background.
+pub fn release(pid: libc::pid_t) {
+    let mut children = supervisor().children();
+    let Some(child) = children.get_mut(&pid) else { return };
+    if reap(pid, &child.slot) {
+        children.remove(&pid);
+        return;
+    }
+    // Still ours and unreaped, so the pid can't have been reused
+    unsafe { libc::kill(pid, libc::SIGTERM) };
+    child.kill_at = Some(Instant::now() + KILL_GRACE);
+    drop(children);
+    wake();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(Instant::now() < deadline, "{what}");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_exited_child_is_reaped() {
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            unsafe { libc::_exit(7) };
+        }
+        let slot = watch(pid);
+        wait_for("the child was never reaped", || slot.lock().unwrap().is_some());
+        assert_eq!(*slot.lock().unwrap(), Some(ExitStatus::Exited { code: 7 }));
+        assert!(!supervisor().children().contains_key(&pid));
+    }
+
+    #[test]
+    fn test_released_child_is_killed() {
+        let mut ready = [-1; 2];
+        assert_eq!(unsafe { libc::pipe(ready.as_mut_ptr()) }, 0);
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            unsafe {
+                // Ignore SIGTERM so only the SIGKILL escalation ends it
+                libc::signal(libc::SIGTERM, libc::SIG_IGN);
+                libc::write(ready[1], b"x".as_ptr() as *const libc::c_void, 1);
+                loop {
+                    libc::pause();
+                }
+            }
+        }
+        let slot = watch(pid);
+        let mut byte = 0u8;
+        unsafe {
+            libc::read(ready[0], &mut byte as *mut u8 as *mut libc::c_void, 1);
+            libc::close(ready[0]);
+            libc::close(ready[1]);
+        }
+        release(pid);
+        wait_for("the child was never killed", || slot.lock().unwrap().is_some());
+        assert_eq!(*slot.lock().unwrap(), Some(ExitStatus::Signaled { signal: libc::SIGKILL }));
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+    }
+}
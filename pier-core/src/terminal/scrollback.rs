@@ -0,0 +1,292 @@
+//! Scrollback history stored as compacted lines in a bounded ring buffer.
+//!
+//! A screen line is a `Vec<Cell>` of several dozen bytes per cell. Lines that
+//! scroll off are stored instead as one `char` per cell plus runs of shared
+//! attributes, with trailing blanks dropped, which is typically 5-10x
+//! smaller. The buffer is bounded both by line count and by an estimate of
+//! its heap use; the oldest lines are evicted first.
+
+use std::collections::VecDeque;
+use std::mem::size_of;
+
+use crate::terminal::emulator::{Cell, Color, UnderlineStyle};
+
+/// Default number of lines retained in the scrollback buffer.
+pub const DEFAULT_SCROLLBACK_LIMIT: usize = 10_000;
+
+/// Default memory budget for scrollback, in bytes.
+pub const DEFAULT_SCROLLBACK_BYTES: usize = 64 * 1024 * 1024;
+
+/// Stands in for the spacer cell after a wide glyph in `CompactLine::chars`.
+const SPACER: char = '\0';
+
+/// Attributes shared by a run of cells.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Style {
+    fg: Color,
+    bg: Color,
+    bold: bool,
+    faint: bool,
+    italic: bool,
+    underline: UnderlineStyle,
+    underline_color: Color,
+    blink: bool,
+    reverse: bool,
+    invisible: bool,
+    strikethrough: bool,
+    overline: bool,
+}
+
+impl Style {
+    fn of(cell: &Cell) -> Self {
+        Self {
+            fg: cell.fg,
+            bg: cell.bg,
+            bold: cell.bold,
+            faint: cell.faint,
+            italic: cell.italic,
+            underline: cell.underline,
+            underline_color: cell.underline_color,
+            blink: cell.blink,
+            reverse: cell.reverse,
+            invisible: cell.invisible,
+            strikethrough: cell.strikethrough,
+            overline: cell.overline,
+        }
+    }
+
+    fn cell(&self, ch: char) -> Cell {
+        Cell {
+            ch,
+            fg: self.fg,
+            bg: self.bg,
+            bold: self.bold,
+            faint: self.faint,
+            italic: self.italic,
+            underline: self.underline,
+            underline_color: self.underline_color,
+            blink: self.blink,
+            reverse: self.reverse,
+            invisible: self.invisible,
+            strikethrough: self.strikethrough,
+            overline: self.overline,
+            ..Cell::default()
+        }
+    }
+}
+
+/// A line in compacted form.
+struct CompactLine {
+    /// Base character of each stored cell; `SPACER` marks wide-glyph spacers
+    chars: Box<[char]>,
+    /// (cell count, style) runs covering `chars`
+    runs: Box<[(u32, Style)]>,
+    /// Combining marks by column
+    combining: Box<[(u32, Box<str>)]>,
+    /// Width of the line when it scrolled off; blanks pad `chars` up to it
+    width: usize,
+}
+
+impl CompactLine {
+    fn new(line: &[Cell]) -> Self {
+        let blank = Cell::default();
+        let stored = line
+            .iter()
+            .rposition(|cell| !is_blank(cell, &blank))
+            .map_or(0, |last| last + 1);
+
+        let mut chars = Vec::with_capacity(stored);
+        let mut runs: Vec<(u32, Style)> = Vec::new();
+        let mut combining = Vec::new();
+        for (col, cell) in line[..stored].iter().enumerate() {
+            chars.push(if cell.wide_spacer { SPACER } else { cell.ch });
+            let style = Style::of(cell);
+            match runs.last_mut() {
+                Some((count, last)) if *last == style => *count += 1,
+                _ => runs.push((1, style)),
+            }
+            if let Some(marks) = &cell.combining {
+                combining.push((col as u32, marks.clone()));
+            }
+        }
+
+        Self {
+            chars: chars.into_boxed_slice(),
+            runs: runs.into_boxed_slice(),
+            combining: combining.into_boxed_slice(),
+            width: line.len(),
+        }
+    }
+
+    fn expand(&self) -> Vec<Cell> {
+        let mut line = Vec::with_capacity(self.width);
+        let mut chars = self.chars.iter().copied().peekable();
+        for &(count, style) in self.runs.iter() {
+            for _ in 0..count {
+                let Some(ch) = chars.next() else { break };
+                let mut cell = style.cell(ch);
+                if ch == SPACER {
+                    cell.ch = ' ';
+                    cell.wide_spacer = true;
+                } else {
+                    cell.wide = chars.peek() == Some(&SPACER);
+                }
+                line.push(cell);
+            }
+        }
+        for (col, marks) in self.combining.iter() {
+            if let Some(cell) = line.get_mut(*col as usize) {
+                cell.combining = Some(marks.clone());
+            }
+        }
+        line.resize(self.width, Cell::default());
+        line
+    }
+
+    /// Approximate heap plus inline size, for the memory budget.
+    fn size(&self) -> usize {
+        size_of::<Self>()
+            + self.chars.len() * size_of::<char>()
+            + self.runs.len() * size_of::<(u32, Style)>()
+            + self
+                .combining
+                .iter()
+                .map(|(_, marks)| size_of::<(u32, Box<str>)>() + marks.len())
+                .sum::<usize>()
+    }
+}
+
+/// A cell that padding restores exactly: a default-styled space.
+fn is_blank(cell: &Cell, blank: &Cell) -> bool {
+    cell.ch == ' '
+        && !cell.wide
+        && !cell.wide_spacer
+        && cell.combining.is_none()
+        && Style::of(cell) == Style::of(blank)
+}
+
+/// Bounded history of lines scrolled off the top of the screen.
+pub struct Scrollback {
+    lines: VecDeque<CompactLine>,
+    max_lines: usize,
+    max_bytes: usize,
+    /// Sum of `CompactLine::size` over `lines`
+    bytes: usize,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Self::new(DEFAULT_SCROLLBACK_LIMIT, DEFAULT_SCROLLBACK_BYTES)
+    }
+}
+
+impl Scrollback {
+    pub fn new(max_lines: usize, max_bytes: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            max_lines,
+            max_bytes,
+            bytes: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Approximate memory held by stored lines, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.bytes
+    }
+
+    /// Change the line and memory limits, evicting the oldest excess.
+    pub fn set_limits(&mut self, max_lines: usize, max_bytes: usize) {
+        self.max_lines = max_lines;
+        self.max_bytes = max_bytes;
+        self.evict();
+    }
+
+    pub fn max_lines(&self) -> usize {
+        self.max_lines
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// Append a line that scrolled off the screen.
+    pub fn push(&mut self, line: &[Cell]) {
+        if self.max_lines == 0 {
+            return;
+        }
+        let line = CompactLine::new(line);
+        self.bytes += line.size();
+        self.lines.push_back(line);
+        self.evict();
+    }
+
+    /// Line `index` (0 = oldest), expanded back into cells.
+    pub fn get(&self, index: usize) -> Option<Vec<Cell>> {
+        self.lines.get(index).map(CompactLine::expand)
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.bytes = 0;
+    }
+
+    fn evict(&mut self) {
+        while self.lines.len() > self.max_lines || self.bytes > self.max_bytes {
+            let Some(line) = self.lines.pop_front() else { break };
+            self.bytes -= line.size();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_round_trip() {
+        let mut line = vec![Cell::default(); 10];
+        line[0] = Cell { ch: 'a', bold: true, underline: UnderlineStyle::Curly, ..Cell::default() };
+        line[1] = Cell { ch: 'e', combining: Some("\u{301}".into()), ..Cell::default() };
+        line[2] = Cell { ch: '中', wide: true, fg: Color::Indexed(1), ..Cell::default() };
+        line[3] = Cell { ch: ' ', wide_spacer: true, fg: Color::Indexed(1), ..Cell::default() };
+        line[5] = Cell { ch: ' ', bg: Color::Rgb(1, 2, 3), ..Cell::default() };
+
+        let compact = CompactLine::new(&line);
+        assert_eq!(compact.chars.len(), 6);
+        let expanded = compact.expand();
+        assert_eq!(expanded.len(), 10);
+        for (a, b) in line.iter().zip(&expanded) {
+            assert_eq!(format!("{a:?}"), format!("{b:?}"));
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let line: Vec<Cell> = "hello".chars().map(|ch| Cell { ch, ..Cell::default() }).collect();
+        let mut scrollback = Scrollback::new(3, usize::MAX);
+        for _ in 0..5 {
+            scrollback.push(&line);
+        }
+        assert_eq!(scrollback.len(), 3);
+        let per_line = scrollback.memory_usage() / 3;
+
+        scrollback.set_limits(100, per_line * 2);
+        assert_eq!(scrollback.len(), 2);
+        assert_eq!(scrollback.memory_usage(), per_line * 2);
+        assert_eq!(scrollback.get(1).unwrap()[4].ch, 'o');
+
+        scrollback.set_limits(0, usize::MAX);
+        scrollback.push(&line);
+        assert!(scrollback.is_empty());
+        assert_eq!(scrollback.memory_usage(), 0);
+    }
+}
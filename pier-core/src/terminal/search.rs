@@ -0,0 +1,113 @@
+//! Find-in-terminal over the screen and scrollback.
+//!
+//! Matches are reported as [`SelectionRange`]s in absolute rows so the app
+//! can highlight them and step through with find next/previous.
+
+use regex::{Regex, RegexBuilder};
+
+use crate::terminal::emulator::{Cell, VtEmulator};
+use crate::terminal::selection::SelectionRange;
+
+/// Upper bound on reported matches; the newest are kept.
+pub const MAX_SEARCH_MATCHES: usize = 10_000;
+
+/// How a search pattern is interpreted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchOptions {
+    /// Treat the pattern as a regular expression instead of literal text
+    pub regex: bool,
+    pub case_insensitive: bool,
+}
+
+/// Compile `pattern` according to `options`.
+pub fn build_pattern(pattern: &str, options: SearchOptions) -> Result<Regex, regex::Error> {
+    let source = if options.regex {
+        pattern.to_string()
+    } else {
+        regex::escape(pattern)
+    };
+    RegexBuilder::new(&source)
+        .case_insensitive(options.case_insensitive)
+        .build()
+}
+
+impl VtEmulator {
+    /// Find all non-empty matches of `pattern`, oldest row first. Matches do
+    /// not span lines. A match ending on a wide glyph covers its spacer cell.
+    pub fn search(&self, pattern: &Regex) -> Vec<SelectionRange> {
+        let end = self.screen_base_row() + self.rows as u64;
+        let start = self.screen_base_row() - self.scrollback_len() as u64;
+        let mut matches = Vec::new();
+        for row in start..end {
+            let Some(line) = self.line_at(row) else { continue };
+            matches.extend(line_matches(&line, row, pattern).into_iter().map(|(range, _)| range));
+        }
+        if matches.len() > MAX_SEARCH_MATCHES {
+            matches.drain(..matches.len() - MAX_SEARCH_MATCHES);
+        }
+        matches
+    }
+}
+
+/// Non-empty matches of `pattern` in one line, with the matched text.
+pub(crate) fn line_matches(line: &[Cell], row: u64, pattern: &Regex) -> Vec<(SelectionRange, String)> {
+    let mut text = String::new();
+    // (byte offset in `text`, column) of each cell's first byte
+    let mut offsets = Vec::new();
+    for (col, cell) in line.iter().enumerate() {
+        if !cell.wide_spacer {
+            offsets.push((text.len(), col));
+            cell.push_text(&mut text);
+        }
+    }
+    let column_of = |byte: usize| {
+        let i = offsets.partition_point(|&(offset, _)| offset <= byte);
+        offsets[i - 1].1
+    };
+    pattern
+        .find_iter(&text)
+        .filter(|found| !found.is_empty())
+        .map(|found| {
+            let last = column_of(found.end() - 1);
+            let range = SelectionRange {
+                start_row: row,
+                start_col: column_of(found.start()),
+                end_row: row,
+                end_col: if line[last].wide { last + 1 } else { last },
+            };
+            (range, found.as_str().to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_literal_and_regex() {
+        let mut emu = VtEmulator::new(12, 2);
+        emu.process(b"error: a.b\r\nError a+b\r\nerr");
+        let literal = build_pattern("a.b", SearchOptions::default()).unwrap();
+        let found = emu.search(&literal);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].start_row, found[0].start_col, found[0].end_col), (0, 7, 9));
+
+        let options = SearchOptions { regex: true, case_insensitive: true };
+        let found = emu.search(&build_pattern("^err(or)?", options).unwrap());
+        // Row 0 is in scrollback; rows stay absolute.
+        let rows: Vec<u64> = found.iter().map(|m| m.start_row).collect();
+        assert_eq!(rows, vec![0, 1, 2]);
+        assert!(build_pattern("(", options).is_err());
+    }
+
+    #[test]
+    fn test_search_wide_columns() {
+        let mut emu = VtEmulator::new(12, 1);
+        emu.process("ab中文cd".as_bytes());
+        let found = emu.search(&build_pattern("文c", SearchOptions::default()).unwrap());
+        assert_eq!((found[0].start_col, found[0].end_col), (4, 6));
+        let found = emu.search(&build_pattern("中", SearchOptions::default()).unwrap());
+        assert_eq!((found[0].start_col, found[0].end_col), (2, 3));
+    }
+}
@@ -0,0 +1,147 @@
+//! Text selection over the screen and scrollback.
+//!
+//! Points are `(row, col)` with absolute rows (see
+//! [`VtEmulator::screen_base_row`]), so a selection stays valid while
+//! output scrolls.
+
+use crate::terminal::emulator::{Cell, VtEmulator};
+
+/// How the cells between two selection points are chosen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectionMode {
+    /// Running text from start to end, wrapping across lines
+    Stream,
+    /// The rectangle with start and end as opposite corners
+    Block,
+}
+
+/// Inclusive range of cells, start before end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct SelectionRange {
+    pub start_row: u64,
+    pub start_col: usize,
+    pub end_row: u64,
+    pub end_col: usize,
+}
+
+/// Characters that end a word for double-click selection, besides whitespace.
+const WORD_DELIMITERS: &str = "'\"`()[]{}<>|;,";
+
+/// Whether the cell at `x` belongs to a word. The spacer half of a wide
+/// glyph takes the class of its leading half.
+pub(crate) fn is_word_cell(line: &[Cell], x: usize) -> bool {
+    let cell = if line[x].wide_spacer && x > 0 { &line[x - 1] } else { &line[x] };
+    !cell.ch.is_whitespace() && !WORD_DELIMITERS.contains(cell.ch)
+}
+
+/// Text of `line[from..=to]` with trailing blanks trimmed.
+fn cells_text(line: &[Cell], from: usize, to: usize) -> String {
+    let mut text = String::new();
+    if from < line.len() {
+        for cell in &line[from..=to.min(line.len() - 1)] {
+            cell.push_text(&mut text);
+        }
+    }
+    text.truncate(text.trim_end().len());
+    text
+}
+
+impl VtEmulator {
+    /// Extract the text between two points (inclusive). Points may be given
+    /// in either order; lines are joined with `\n` and trailing blanks are
+    /// dropped. Rows no longer in scrollback are skipped.
+    pub fn selection_text(&self, start: (u64, usize), end: (u64, usize), mode: SelectionMode) -> String {
+        let (start, end) = match mode {
+            SelectionMode::Stream => (start.min(end), start.max(end)),
+            SelectionMode::Block => (
+                (start.0.min(end.0), start.1.min(end.1)),
+                (start.0.max(end.0), start.1.max(end.1)),
+            ),
+        };
+        let mut lines = Vec::new();
+        for row in start.0..=end.0 {
+            let Some(line) = self.line_at(row) else { continue };
+            let (mut from, to) = match mode {
+                SelectionMode::Stream => (
+                    if row == start.0 { start.1 } else { 0 },
+                    if row == end.0 { end.1 } else { usize::MAX },
+                ),
+                SelectionMode::Block => (start.1, end.1),
+            };
+            // Starting on the spacer of a wide glyph selects the whole glyph
+            if line.get(from).is_some_and(|cell| cell.wide_spacer) && from > 0 {
+                from -= 1;
+            }
+            lines.push(cells_text(&line, from, to));
+        }
+        lines.join("\n")
+    }
+
+    /// Range of the word under (`row`, `col`), for double-click selection.
+    /// A blank or delimiter cell selects just itself. `None` if the row is
+    /// gone or `col` is off the line.
+    pub fn word_at(&self, row: u64, col: usize) -> Option<SelectionRange> {
+        let line = self.line_at(row)?;
+        if col >= line.len() {
+            return None;
+        }
+        let (mut start, mut end) = (col, col);
+        if is_word_cell(&line, col) {
+            while start > 0 && is_word_cell(&line, start - 1) {
+                start -= 1;
+            }
+            while end + 1 < line.len() && is_word_cell(&line, end + 1) {
+                end += 1;
+            }
+        }
+        Some(SelectionRange { start_row: row, start_col: start, end_row: row, end_col: end })
+    }
+
+    /// Range covering the whole of `row`, for triple-click selection.
+    pub fn line_range(&self, row: u64) -> Option<SelectionRange> {
+        let line = self.line_at(row)?;
+        Some(SelectionRange {
+            start_row: row,
+            start_col: 0,
+            end_row: row,
+            end_col: line.len().saturating_sub(1),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_and_block_selection() {
+        let mut emu = VtEmulator::new(8, 3);
+        emu.process(b"one two\r\nthree\r\nfour");
+        assert_eq!(emu.selection_text((0, 4), (1, 2), SelectionMode::Stream), "two\nthr");
+        // Reversed points give the same text.
+        assert_eq!(emu.selection_text((1, 2), (0, 4), SelectionMode::Stream), "two\nthr");
+        assert_eq!(emu.selection_text((0, 1), (2, 3), SelectionMode::Block), "ne\nhre\nour");
+    }
+
+    #[test]
+    fn test_selection_spans_scrollback() {
+        let mut emu = VtEmulator::new(6, 2);
+        emu.process(b"a\r\nb\r\nc\r\nd");
+        let base = emu.screen_base_row();
+        assert_eq!(base, 2);
+        assert_eq!(emu.selection_text((0, 0), (base + 1, 5), SelectionMode::Stream), "a\nb\nc\nd");
+    }
+
+    #[test]
+    fn test_word_and_line_ranges() {
+        let mut emu = VtEmulator::new(20, 2);
+        emu.process("ls (/tmp/中文) x".as_bytes());
+        let word = emu.word_at(0, 6).unwrap();
+        assert_eq!((word.start_col, word.end_col), (4, 12));
+        assert_eq!(emu.selection_text((0, word.start_col), (0, word.end_col), SelectionMode::Stream), "/tmp/中文");
+        let paren = emu.word_at(0, 3).unwrap();
+        assert_eq!((paren.start_col, paren.end_col), (3, 3));
+        assert_eq!(emu.line_range(0).unwrap().end_col, 19);
+        assert!(emu.word_at(5, 0).is_none());
+    }
+}
@@ -0,0 +1,111 @@
+//! The user's login shell.
+//!
+//! New sessions should start the shell the user chose with `chsh`, not a
+//! hardcoded one. The passwd entry is the source of truth; on macOS accounts
+//! from a directory service may only be visible through `dscl`, and `$SHELL`
+//! is a last resort since it's inherited and may be stale. Whatever is found
+//! must be an executable listed in `/etc/shells`, like login(1) requires.
+
+use std::ffi::CStr;
+use std::path::Path;
+
+/// Where the list of permitted login shells lives.
+const SHELLS_FILE: &str = "/etc/shells";
+
+/// Shell used when nothing valid is configured.
+#[cfg(target_os = "macos")]
+const FALLBACK_SHELL: &str = "/bin/zsh";
+#[cfg(not(target_os = "macos"))]
+const FALLBACK_SHELL: &str = "/bin/sh";
+
+/// Path of the current user's login shell, falling back to the platform
+/// default (`/bin/zsh` on macOS, `/bin/sh` elsewhere).
+pub fn default_shell() -> String {
+    let allowed = std::fs::read_to_string(SHELLS_FILE).ok();
+    [passwd_shell(), directory_shell(), std::env::var("SHELL").ok()]
+        .into_iter()
+        .flatten()
+        .find(|shell| is_valid_shell(shell, allowed.as_deref()))
+        .unwrap_or_else(|| FALLBACK_SHELL.to_string())
+}
+
+/// An absolute path to an executable, listed in the `/etc/shells` contents
+/// if they could be read.
+fn is_valid_shell(shell: &str, allowed: Option<&str>) -> bool {
+    let listed = allowed.is_none_or(|list| {
+        list.lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with('#'))
+            .any(|line| line == shell)
+    });
+    listed && Path::new(shell).is_absolute() && is_executable(shell)
+}
+
+fn is_executable(path: &str) -> bool {
+    let Ok(path) = std::ffi::CString::new(path) else { return false };
+    unsafe { libc::access(path.as_ptr(), libc::X_OK) == 0 }
+}
+
+/// `pw_shell` of the current user's passwd entry.
+fn passwd_shell() -> Option<String> {
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let rc = unsafe { libc::getpwuid_r(libc::getuid(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc != 0 || result.is_null() || entry.pw_shell.is_null() {
+        return None;
+    }
+    let shell = unsafe { CStr::from_ptr(entry.pw_shell) }.to_str().ok()?;
+    (!shell.is_empty()).then(|| shell.to_string())
+}
+
+/// `UserShell` from Directory Services, for accounts getpwuid doesn't see.
+#[cfg(target_os = "macos")]
+fn directory_shell() -> Option<String> {
+    let user = std::env::var("USER").ok()?;
+    let output = std::process::Command::new("/usr/bin/dscl")
+        .args([".", "-read", &format!("/Users/{}", user), "UserShell"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_dscl_shell(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn directory_shell() -> Option<String> {
+    None
+}
+
+/// The value from `dscl` output of the form `UserShell: /bin/zsh`.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_dscl_shell(output: &str) -> Option<String> {
+    let shell = output.trim().strip_prefix("UserShell:")?.trim();
+    (!shell.is_empty()).then(|| shell.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_shell_is_valid() {
+        let shell = default_shell();
+        assert!(Path::new(&shell).is_absolute(), "{shell}");
+        assert!(is_executable(&shell), "{shell}");
+    }
+
+    #[test]
+    fn test_shell_validation() {
+        let list = "# comment\n/bin/sh\n  /bin/bash  \n";
+        assert!(is_valid_shell("/bin/sh", Some(list)));
+        assert!(!is_valid_shell("/bin/ls", Some(list)));
+        assert!(!is_valid_shell("/nonexistent/shell", None));
+        assert!(!is_valid_shell("sh", None));
+        assert!(is_valid_shell("/bin/sh", None));
+
+        assert_eq!(parse_dscl_shell("UserShell: /bin/zsh\n").as_deref(), Some("/bin/zsh"));
+        assert_eq!(parse_dscl_shell("No such key: UserShell\n"), None);
+    }
+}
@@ -0,0 +1,126 @@
+//! Regex triggers on terminal output.
+//!
+//! After each chunk of output the rows it touched (from where the cursor
+//! was to where it ended up) are matched against the registered patterns.
+//! The cursor row is rescanned as it fills in, so a prompt like
+//! `password:` fires as soon as it appears; each match position is reported
+//! once.
+
+use std::collections::HashSet;
+use std::ffi::c_void;
+use std::os::raw::c_char;
+
+use regex::Regex;
+
+use crate::terminal::emulator::VtEmulator;
+use crate::terminal::search::line_matches;
+use crate::terminal::selection::SelectionRange;
+
+/// Callback invoked for each trigger match with a JSON event
+/// `{trigger_id, text, start_row, start_col, end_row, end_col}`.
+/// The string is only valid for the duration of the call.
+pub type TriggerCallbackFn = extern "C" fn(user_data: *mut c_void, event_json: *const c_char);
+
+/// A match of a trigger pattern in new output.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct TriggerMatch {
+    pub trigger_id: u32,
+    /// The matched text
+    pub text: String,
+    #[serde(flatten)]
+    pub range: SelectionRange,
+}
+
+struct Trigger {
+    id: u32,
+    pattern: Regex,
+}
+
+/// Registered triggers for one session.
+#[derive(Default)]
+pub struct TriggerSet {
+    triggers: Vec<Trigger>,
+    next_id: u32,
+    /// (trigger id, row, column) of matches already reported on rows that
+    /// may still be rescanned
+    reported: HashSet<(u32, u64, usize)>,
+}
+
+impl TriggerSet {
+    /// Register a pattern; returns its id (never 0).
+    pub fn add(&mut self, pattern: Regex) -> u32 {
+        self.next_id += 1;
+        self.triggers.push(Trigger { id: self.next_id, pattern });
+        self.next_id
+    }
+
+    /// Remove a trigger. Returns false if no trigger has that id.
+    pub fn remove(&mut self, id: u32) -> bool {
+        let before = self.triggers.len();
+        self.triggers.retain(|trigger| trigger.id != id);
+        self.reported.retain(|&(trigger_id, _, _)| trigger_id != id);
+        self.triggers.len() != before
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triggers.is_empty()
+    }
+
+    /// Match the rows between absolute row `from_row` (the cursor row before
+    /// the output was processed) and the current cursor row, returning
+    /// matches not reported before.
+    pub fn scan(&mut self, emu: &VtEmulator, from_row: u64) -> Vec<TriggerMatch> {
+        let cursor_row = emu.screen_base_row() + emu.cursor_y as u64;
+        let (first, last) = (from_row.min(cursor_row), from_row.max(cursor_row));
+        // Rows above the scanned range are never looked at again
+        self.reported.retain(|&(_, row, _)| row >= first);
+
+        let mut found = Vec::new();
+        for row in first..=last {
+            let Some(line) = emu.line_at(row) else { continue };
+            for trigger in &self.triggers {
+                for (range, text) in line_matches(&line, row, &trigger.pattern) {
+                    if self.reported.insert((trigger.id, row, range.start_col)) {
+                        found.push(TriggerMatch { trigger_id: trigger.id, text, range });
+                    }
+                }
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trigger_scan() {
+        let mut emu = VtEmulator::new(20, 3);
+        let mut triggers = TriggerSet::default();
+        let id = triggers.add(Regex::new("[Pp]assword:").unwrap());
+
+        emu.process(b"Passw");
+        assert!(triggers.scan(&emu, 0).is_empty());
+        emu.process(b"ord:");
+        let found = triggers.scan(&emu, 0);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].trigger_id, id);
+        assert_eq!(found[0].text, "Password:");
+        assert_eq!((found[0].range.start_col, found[0].range.end_col), (0, 8));
+        // Rescanning the same row doesn't report the match again.
+        emu.process(b" ");
+        assert!(triggers.scan(&emu, 0).is_empty());
+
+        // Output spanning several rows (and scrolling) is fully scanned.
+        let from = emu.screen_base_row() + emu.cursor_y as u64;
+        emu.process(b"\r\nx\r\npassword: \r\ny\r\n");
+        let found = triggers.scan(&emu, from);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].range.start_row, 2);
+
+        assert!(triggers.remove(id));
+        assert!(!triggers.remove(id));
+        assert!(triggers.is_empty());
+    }
+}
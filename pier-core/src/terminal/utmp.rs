@@ -0,0 +1,96 @@
+//! utmpx login records for terminal sessions.
+//!
+//! Terminal.app and xterm add a `USER_PROCESS` entry for each login shell so
+//! `who`, `w` and `last` list the terminal, and mark it `DEAD_PROCESS` when
+//! the session ends. Writing the database needs privileges on some systems,
+//! so registration is best effort: failures are logged and ignored.
+
+use std::ffi::c_char;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::terminal::pty::PtyProcess;
+
+/// A registered utmpx entry; dropping it records the logout.
+pub struct UtmpRecord {
+    /// Terminal line without `/dev/`, e.g. `ttys003` or `pts/3`
+    line: String,
+    pid: libc::pid_t,
+}
+
+impl UtmpRecord {
+    /// Record a login for the session's shell on its terminal line.
+    /// Returns `None` if the line is unknown or the entry can't be written.
+    pub fn register(pty: &PtyProcess) -> Option<Self> {
+        let line = pty.tty_name()?.trim_start_matches("/dev/").to_string();
+        let user = std::env::var("USER").or_else(|_| std::env::var("LOGNAME")).unwrap_or_default();
+        let record = Self { line, pid: pty.child_pid };
+        record.write(libc::USER_PROCESS, &user).then_some(record)
+    }
+
+    fn write(&self, ut_type: libc::c_short, user: &str) -> bool {
+        let mut entry: libc::utmpx = unsafe { std::mem::zeroed() };
+        entry.ut_type = ut_type;
+        entry.ut_pid = self.pid;
+        copy_field(&mut entry.ut_line, &self.line);
+        copy_field(&mut entry.ut_id, utmp_id(&self.line));
+        copy_field(&mut entry.ut_user, user);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        entry.ut_tv.tv_sec = now.as_secs() as _;
+        entry.ut_tv.tv_usec = now.subsec_micros() as _;
+
+        let written = unsafe {
+            libc::setutxent();
+            let written = !libc::pututxline(&entry).is_null();
+            libc::endutxent();
+            written
+        };
+        if !written {
+            log::debug!("Failed to update utmpx for {}: {}", self.line, std::io::Error::last_os_error());
+        }
+        written
+    }
+}
+
+impl Drop for UtmpRecord {
+    fn drop(&mut self) {
+        self.write(libc::DEAD_PROCESS, "");
+    }
+}
+
+/// The `ut_id` for a line: its last four characters (`s003` for
+/// `ttys003`), which is what login(1) and Terminal.app use.
+fn utmp_id(line: &str) -> &str {
+    let start = line.len().saturating_sub(4);
+    line.get(start..).unwrap_or(line)
+}
+
+/// Copy `value` into a fixed-size utmpx field, truncating if needed. The
+/// fields need not be NUL terminated when full.
+fn copy_field(field: &mut [c_char], value: &str) {
+    for (slot, byte) in field.iter_mut().zip(value.bytes()) {
+        *slot = byte as c_char;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utmp_fields() {
+        assert_eq!(utmp_id("ttys003"), "s003");
+        assert_eq!(utmp_id("pts/3"), "ts/3");
+        assert_eq!(utmp_id("p1"), "p1");
+
+        let mut field = [0 as c_char; 4];
+        copy_field(&mut field, "pts/12");
+        assert_eq!(field.map(|c| c as u8), *b"pts/");
+    }
+
+    #[test]
+    fn test_tty_name() {
+        let pty = PtyProcess::spawn_command(40, 5, "/bin/sh", &["-c", "exit"]).unwrap();
+        let name = pty.tty_name().unwrap();
+        assert!(name.starts_with("/dev/"), "{name}");
+    }
+}
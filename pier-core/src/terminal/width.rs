@@ -0,0 +1,61 @@
+//! Display width of characters in terminal cells.
+//!
+//! Widths come from `unicode-width` (East Asian Width, combining marks,
+//! emoji presentation) and grapheme clusters from `unicode-segmentation`.
+//! A character that continues the previous cluster without widening it,
+//! like a combining mark or the rest of an emoji ZWJ sequence, shares that
+//! cluster's cells; anything else gets cells of its own, as `wcwidth` says.
+
+use unicode_segmentation::GraphemeCursor;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Number of cells `ch` occupies: 0 for combining marks, 2 for wide glyphs, else 1.
+pub fn char_width(ch: char) -> usize {
+    ch.width().unwrap_or(0)
+}
+
+/// Whether `ch` belongs to the grapheme cluster `cluster` ends with and
+/// adds no cells to it, so it goes in the same cell.
+pub fn joins_cluster(cluster: &str, ch: char) -> bool {
+    // ASCII never extends an ASCII cluster; the common case stays cheap
+    if ch.is_ascii() && cluster.is_ascii() {
+        return false;
+    }
+    let mut text = String::with_capacity(cluster.len() + ch.len_utf8());
+    text.push_str(cluster);
+    text.push(ch);
+    let continues = GraphemeCursor::new(cluster.len(), text.len(), true).is_boundary(&text, 0) == Ok(false);
+    continues && text.width() <= cluster.width()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_width() {
+        assert_eq!(char_width('a'), 1);
+        assert_eq!(char_width('é'), 1);
+        assert_eq!(char_width('\u{0301}'), 0);
+        assert_eq!(char_width('\u{200D}'), 0);
+        assert_eq!(char_width('中'), 2);
+        assert_eq!(char_width('ｱ'), 1);
+        assert_eq!(char_width('Ａ'), 2);
+        assert_eq!(char_width('😀'), 2);
+        // Combining marks of Indic, Tibetan and Southeast Asian scripts
+        for mark in ['\u{0981}', '\u{09BC}', '\u{0A3C}', '\u{0BCD}', '\u{0F71}', '\u{102D}', '\u{17B7}'] {
+            assert_eq!(char_width(mark), 0, "U+{:04X}", mark as u32);
+        }
+    }
+
+    #[test]
+    fn test_joins_cluster() {
+        assert!(!joins_cluster("a", 'b'));
+        assert!(joins_cluster("e", '\u{0301}'));
+        assert!(joins_cluster("\u{1F468}\u{200D}", '\u{1F469}'));
+        assert!(joins_cluster("\u{1F44D}", '\u{1F3FD}'));
+        assert!(!joins_cluster("\u{1F468}", '\u{1F469}'));
+        // A spacing vowel sign extends the cluster but takes a cell of its own
+        assert!(!joins_cluster("\u{0915}", '\u{093F}'));
+    }
+}
@@ -0,0 +1,263 @@
+//! Queued input to the PTY.
+//!
+//! The master is non-blocking, and the kernel only buffers a few KB of input
+//! the program hasn't read yet, so a large paste gets EAGAIN or a partial
+//! write part way through. A writer thread per session owns all input: it
+//! writes queued bytes in order as the PTY accepts them, so callers never
+//! block on a program that's slow to read, and the reader thread can answer
+//! terminal queries without risking a deadlock.
+//!
+//! Pastes can optionally be paced, sent in chunks with a pause between them,
+//! for programs that drop input arriving faster than they process it (serial
+//! consoles, some remote shells).
+
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::terminal::pty::PtyProcess;
+use crate::terminal::utf8_floor;
+
+/// Cap on input waiting for the program; writes beyond it are refused.
+const MAX_PENDING_INPUT: usize = 16 * 1024 * 1024;
+
+/// How long the writer waits for the PTY to accept input before checking
+/// for shutdown.
+const WRITE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Chunked sending of pastes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PastePacing {
+    /// Bytes per chunk (split on character boundaries)
+    pub chunk: usize,
+    /// Pause after each chunk
+    pub interval: Duration,
+}
+
+struct Segment {
+    data: Vec<u8>,
+    /// Pause for the pacing interval after writing it
+    paced: bool,
+}
+
+#[derive(Default)]
+struct Queue {
+    segments: VecDeque<Segment>,
+    /// Bytes queued or being written
+    pending: usize,
+    pacing: Option<PastePacing>,
+    /// Set when the PTY stopped accepting input; nothing more is written
+    failed: Option<ErrorKind>,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    /// Signaled when input is queued, drained, or the writer closes
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The input side of a terminal session.
+pub struct PtyWriter {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PtyWriter {
+    /// Start the writer thread for `pty`.
+    pub fn start(pty: Arc<PtyProcess>) -> Result<Self, std::io::Error> {
+        let shared = Arc::new(Shared::default());
+        let thread = {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name("pier-pty-writer".into())
+                .spawn(move || writer_loop(&pty, &shared))?
+        };
+        Ok(Self { shared, thread: Some(thread) })
+    }
+
+    /// Queue `data` to be written after everything queued before it.
+    /// Fails once the PTY stopped accepting input, or with `WouldBlock` if
+    /// the program has fallen too far behind.
+    pub fn write(&self, data: &[u8]) -> Result<(), std::io::Error> {
+        self.enqueue(data, false)
+    }
+
+    /// Queue pasted text, split into paced chunks if pacing is set.
+    pub fn paste(&self, data: &[u8]) -> Result<(), std::io::Error> {
+        self.enqueue(data, true)
+    }
+
+    fn enqueue(&self, data: &[u8], paste: bool) -> Result<(), std::io::Error> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let mut queue = self.shared.lock();
+        if let Some(kind) = queue.failed {
+            return Err(std::io::Error::new(kind, "PTY no longer accepts input"));
+        }
+        if queue.pending + data.len() > MAX_PENDING_INPUT {
+            return Err(std::io::Error::new(ErrorKind::WouldBlock, "too much input pending"));
+        }
+        queue.pending += data.len();
+        match queue.pacing.filter(|_| paste) {
+            Some(pacing) => {
+                let mut rest = data;
+                while !rest.is_empty() {
+                    let mut len = rest.len().min(pacing.chunk.max(1));
+                    if len < rest.len() {
+                        len = match utf8_floor(rest, len) {
+                            0 => len,
+                            floor => floor,
+                        };
+                    }
+                    let (chunk, tail) = rest.split_at(len);
+                    queue.segments.push_back(Segment { data: chunk.to_vec(), paced: true });
+                    rest = tail;
+                }
+            }
+            None => queue.segments.push_back(Segment { data: data.to_vec(), paced: false }),
+        }
+        drop(queue);
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// Send pastes in chunks, or all at once with `None`. Applies to pastes
+    /// queued from now on.
+    pub fn set_paste_pacing(&self, pacing: Option<PastePacing>) {
+        self.shared.lock().pacing = pacing;
+    }
+
+    /// Bytes queued that the program hasn't accepted yet.
+    pub fn pending(&self) -> usize {
+        self.shared.lock().pending
+    }
+
+    /// Wait up to `timeout` for the queue to drain. Returns whether it did.
+    pub fn flush(&self, timeout: Duration) -> bool {
+        let queue = self.shared.lock();
+        let (queue, _) = self
+            .shared
+            .changed
+            .wait_timeout_while(queue, timeout, |queue| queue.pending > 0 && queue.failed.is_none())
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        queue.pending == 0
+    }
+}
+
+impl Drop for PtyWriter {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.changed.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn writer_loop(pty: &PtyProcess, shared: &Shared) {
+    loop {
+        let segment = {
+            let queue = shared.lock();
+            let mut queue = shared
+                .changed
+                .wait_while(queue, |queue| queue.segments.is_empty() && !queue.closed)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if queue.closed {
+                return;
+            }
+            queue.segments.pop_front()
+        };
+        let Some(segment) = segment else { continue };
+
+        if let Err(e) = write_all(pty, shared, &segment.data) {
+            log::debug!("PTY input failed: {}", e);
+            let mut queue = shared.lock();
+            queue.failed = Some(e.kind());
+            queue.segments.clear();
+            queue.pending = 0;
+            drop(queue);
+            shared.changed.notify_all();
+            return;
+        }
+
+        let mut queue = shared.lock();
+        queue.pending -= segment.data.len();
+        let pause = queue.pacing.filter(|_| segment.paced).map(|pacing| pacing.interval);
+        shared.changed.notify_all();
+        if let Some(pause) = pause {
+            // Closing cuts the pause short
+            let _ = shared.changed.wait_timeout_while(queue, pause, |queue| !queue.closed);
+        }
+    }
+}
+
+/// Write all of `data`, waiting whenever the PTY's input buffer is full.
+/// Gives up quietly if the writer is closed meanwhile.
+fn write_all(pty: &PtyProcess, shared: &Shared, mut data: &[u8]) -> Result<(), std::io::Error> {
+    while !data.is_empty() {
+        let written = pty.write_some(data)?;
+        data = &data[written..];
+        if written == 0 {
+            if shared.lock().closed {
+                return Ok(());
+            }
+            pty.wait_writable(WRITE_POLL_INTERVAL)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_large_write_is_not_truncated() {
+        // Raw mode, as line editing caps the length of a line; the program
+        // starts reading late, so the PTY's input buffer fills up
+        let pty = Arc::new(
+            PtyProcess::spawn_command(80, 24, "/bin/sh", &["-c", "stty raw -echo; sleep 0.3; head -c 262144 | wc -c"])
+                .unwrap(),
+        );
+        std::thread::sleep(Duration::from_millis(100));
+        let writer = PtyWriter::start(pty.clone()).unwrap();
+        writer.write(&vec![b'x'; 256 * 1024]).unwrap();
+        assert!(writer.flush(Duration::from_secs(10)), "{} bytes still pending", writer.pending());
+
+        let mut output = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !String::from_utf8_lossy(&output).contains("262144") {
+            assert!(Instant::now() < deadline, "unexpected output: {:?}", String::from_utf8_lossy(&output));
+            output.extend(pty.read_timeout(Duration::from_millis(50)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_paste_pacing() {
+        let pty = Arc::new(PtyProcess::spawn_command(80, 24, "/bin/sh", &["-c", "cat > /dev/null"]).unwrap());
+        let writer = PtyWriter::start(pty).unwrap();
+        writer.set_paste_pacing(Some(PastePacing { chunk: 4, interval: Duration::from_millis(30) }));
+        let started = Instant::now();
+        writer.paste("ab€cdefgh".as_bytes()).unwrap();
+        {
+            let queue = writer.shared.lock();
+            // The euro sign isn't split across chunks
+            let lens: Vec<usize> = queue.segments.iter().map(|s| s.data.len()).collect();
+            assert!(lens.iter().all(|&len| len <= 4), "{lens:?}");
+        }
+        assert!(writer.flush(Duration::from_secs(5)));
+        assert!(started.elapsed() >= Duration::from_millis(60));
+    }
+}